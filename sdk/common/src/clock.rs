@@ -0,0 +1,182 @@
+use crate::timestamp::Timestamp;
+use core::cell::Cell;
+
+pub const AZ_ERROR_CLOCK_UNAVAILABLE: &str = "The current time is not available.";
+
+// Source of the current time. Embedded targets without std wrap their RTC or
+// NTP client in a FnClock.
+pub trait Clock {
+    fn now(&self) -> Result<Timestamp, &'static str>;
+
+    fn epoch_time(&self) -> Result<u64, &'static str> {
+        self.now()?.epoch_time().ok_or(AZ_ERROR_CLOCK_UNAVAILABLE)
+    }
+}
+
+impl<C> Clock for &C
+where
+    C: Clock + ?Sized,
+{
+    fn now(&self) -> Result<Timestamp, &'static str> {
+        (**self).now()
+    }
+}
+
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now(&self) -> Result<Timestamp, &'static str> {
+        let elapsed = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .map_err(|_| AZ_ERROR_CLOCK_UNAVAILABLE)?;
+        Timestamp::from_unix_seconds(elapsed.as_secs() as i64, elapsed.subsec_nanos())
+    }
+}
+
+// Only moves when told to, for deterministic tests.
+#[derive(Debug, Default)]
+pub struct ManualClock {
+    now: Cell<Timestamp>,
+}
+
+impl ManualClock {
+    pub fn new(now: Timestamp) -> ManualClock {
+        ManualClock {
+            now: Cell::new(now),
+        }
+    }
+
+    pub fn from_epoch_time(epoch_time: u64) -> ManualClock {
+        ManualClock::new(Timestamp::from_epoch_time(epoch_time))
+    }
+
+    pub fn set(&self, now: Timestamp) {
+        self.now.set(now);
+    }
+
+    pub fn advance(&self, seconds: u64) {
+        let now = self.now.get();
+        self.now
+            .set(now.checked_add_seconds(seconds).unwrap_or(now));
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> Result<Timestamp, &'static str> {
+        Ok(self.now.get())
+    }
+}
+
+// Adapts any time source, e.g. `FnClock(|| rtc.read())`. Return
+// AZ_ERROR_CLOCK_UNAVAILABLE until the source has been synchronized.
+pub struct FnClock<F>(pub F);
+
+impl<F> Clock for FnClock<F>
+where
+    F: Fn() -> Result<Timestamp, &'static str>,
+{
+    fn now(&self) -> Result<Timestamp, &'static str> {
+        (self.0)()
+    }
+}
+
+// A point in time an operation has to finish by.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Deadline {
+    expires: Timestamp,
+}
+
+impl Deadline {
+    pub fn at(expires: Timestamp) -> Deadline {
+        Deadline { expires }
+    }
+
+    pub fn after<C>(clock: &C, seconds: u64) -> Result<Deadline, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        clock
+            .now()?
+            .checked_add_seconds(seconds)
+            .map(Deadline::at)
+            .ok_or(AZ_ERROR_CLOCK_UNAVAILABLE)
+    }
+
+    pub fn expires(&self) -> Timestamp {
+        self.expires
+    }
+
+    pub fn is_expired<C>(&self, clock: &C) -> Result<bool, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        Ok(clock.now()? >= self.expires)
+    }
+
+    // Whole seconds left, rounded down; zero once expired.
+    pub fn remaining_seconds<C>(&self, clock: &C) -> Result<u64, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        let now = clock.now()?;
+        if now >= self.expires {
+            return Ok(0);
+        }
+        let mut seconds = self.expires.unix_seconds() - now.unix_seconds();
+        if self.expires.nanoseconds() < now.nanoseconds() {
+            seconds -= 1;
+        }
+        Ok(seconds as u64)
+    }
+}
+
+#[cfg(test)]
+mod tests_clock {
+    use super::*;
+
+    #[test]
+    fn manual_clock_only_moves_when_advanced() {
+        let clock = ManualClock::from_epoch_time(100);
+        assert_eq!(clock.epoch_time(), Ok(100));
+        clock.advance(5);
+        assert_eq!(clock.epoch_time(), Ok(105));
+        clock.set(Timestamp::from_epoch_time(10));
+        assert_eq!(clock.epoch_time(), Ok(10));
+    }
+
+    #[test]
+    fn fn_clock_reports_an_unsynchronized_source() {
+        let clock = FnClock(|| Err(AZ_ERROR_CLOCK_UNAVAILABLE));
+        assert_eq!(clock.epoch_time(), Err(AZ_ERROR_CLOCK_UNAVAILABLE));
+        let clock = FnClock(|| Ok(Timestamp::from_epoch_time(42)));
+        assert_eq!(clock.epoch_time(), Ok(42));
+    }
+
+    #[test]
+    fn times_before_the_epoch_have_no_epoch_time() {
+        let clock = ManualClock::new(Timestamp::from_unix_seconds(-1, 0).unwrap());
+        assert_eq!(clock.epoch_time(), Err(AZ_ERROR_CLOCK_UNAVAILABLE));
+    }
+
+    #[test]
+    fn deadlines_expire() {
+        let clock = ManualClock::from_epoch_time(100);
+        let deadline = Deadline::after(&clock, 30).unwrap();
+        assert_eq!(deadline.remaining_seconds(&clock), Ok(30));
+        assert_eq!(deadline.is_expired(&clock), Ok(false));
+        clock.advance(29);
+        assert_eq!(deadline.remaining_seconds(&clock), Ok(1));
+        clock.advance(1);
+        assert_eq!(deadline.is_expired(&clock), Ok(true));
+        assert_eq!(deadline.remaining_seconds(&clock), Ok(0));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn system_clock_is_past_2020() {
+        assert!(SystemClock.epoch_time().unwrap() > 1_577_836_800);
+    }
+}
@@ -1,5 +1,43 @@
 pub const AZ_ERROR_INSUFFICIENT_SPAN_SIZE: &str = "The size of the provided span is too small";
 
-use core::fmt::{Debug, Display};
+use core::fmt::{self, Debug, Display};
 
 pub trait Error: Debug + Display {}
+
+// Error of the APIs writing into a caller provided buffer
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SpanError {
+    // The buffer must be at least `required` bytes long
+    InsufficientSize { required: usize },
+    Other(&'static str),
+}
+
+impl SpanError {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SpanError::InsufficientSize { .. } => AZ_ERROR_INSUFFICIENT_SPAN_SIZE,
+            SpanError::Other(error) => error,
+        }
+    }
+}
+
+impl From<&'static str> for SpanError {
+    fn from(error: &'static str) -> SpanError {
+        SpanError::Other(error)
+    }
+}
+
+impl Display for SpanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpanError::InsufficientSize { required } => write!(
+                f,
+                "{} ({} bytes are required)",
+                AZ_ERROR_INSUFFICIENT_SPAN_SIZE, required
+            ),
+            SpanError::Other(error) => f.write_str(error),
+        }
+    }
+}
+
+impl Error for SpanError {}
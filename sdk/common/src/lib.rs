@@ -1,8 +1,15 @@
 #![no_std]
 #![allow(dead_code)]
 
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod clock;
 pub mod common;
 pub mod error;
+pub mod log;
+pub mod span;
+pub mod timestamp;
 
 #[cfg(test)]
 mod tests {
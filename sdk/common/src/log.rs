@@ -0,0 +1,270 @@
+// Logging modelled on az_log from the C SDK. Nothing is formatted unless a
+// message callback is set or the `log` or `defmt` feature forwards messages,
+// and the classification filter lets callers pick the events they care about.
+use core::fmt::{self, Write};
+
+use spin::RwLock;
+
+// Longer messages are cut short, as are messages that needed redacting.
+pub const MAX_MESSAGE_LENGTH: usize = 512;
+
+// Stands in for secrets in log messages and Debug output.
+pub const REDACTED: &str = "***";
+// The values following these are secrets: SAS signatures and connection
+// string keys.
+const SECRET_MARKERS: [&str; 2] = ["sig=", "SharedAccessKey="];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Classification {
+    ProvisioningRequest,
+    ProvisioningResponse,
+    ProvisioningResponseError,
+    Retry,
+    HubRequest,
+    HubMessage,
+    SasToken,
+}
+
+impl Classification {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Classification::ProvisioningRequest => "provisioning request",
+            Classification::ProvisioningResponse => "provisioning response",
+            Classification::ProvisioningResponseError => "provisioning response error",
+            Classification::Retry => "retry",
+            Classification::HubRequest => "hub request",
+            Classification::HubMessage => "hub message",
+            Classification::SasToken => "sas token",
+        }
+    }
+}
+
+impl fmt::Display for Classification {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+pub type MessageCallback = fn(Classification, &str);
+pub type ClassificationFilter = fn(Classification) -> bool;
+
+// The callbacks are copied out before they are called, so a callback may
+// replace itself.
+static MESSAGE_CALLBACK: RwLock<Option<MessageCallback>> = RwLock::new(None);
+static CLASSIFICATION_FILTER: RwLock<Option<ClassificationFilter>> = RwLock::new(None);
+
+pub fn set_message_callback(callback: Option<MessageCallback>) {
+    *MESSAGE_CALLBACK.write() = callback;
+}
+
+// Without a filter every classification is written.
+pub fn set_classification_filter(filter: Option<ClassificationFilter>) {
+    *CLASSIFICATION_FILTER.write() = filter;
+}
+
+fn message_callback() -> Option<MessageCallback> {
+    *MESSAGE_CALLBACK.read()
+}
+
+fn classification_filter() -> Option<ClassificationFilter> {
+    *CLASSIFICATION_FILTER.read()
+}
+
+pub fn should_write(classification: Classification) -> bool {
+    let listening =
+        cfg!(feature = "log") || cfg!(feature = "defmt") || message_callback().is_some();
+    listening && classification_filter().map_or(true, |filter| filter(classification))
+}
+
+pub fn write(classification: Classification, message: &str) {
+    if !should_write(classification) {
+        return;
+    }
+    if SECRET_MARKERS.iter().any(|marker| message.contains(marker)) {
+        let mut redacted = MessageBuffer::new();
+        redact_into(&mut redacted, message);
+        emit(classification, redacted.as_str());
+    } else {
+        emit(classification, message);
+    }
+}
+
+// Formats the message only when it will be written, e.g.
+// log::write_fmt(Classification::Retry, format_args!("waiting {} seconds", delay))
+pub fn write_fmt(classification: Classification, args: fmt::Arguments<'_>) {
+    if !should_write(classification) {
+        return;
+    }
+    let mut message = MessageBuffer::new();
+    // a message that doesn't fit is written cut short
+    let _ = message.write_fmt(args);
+    write(classification, message.as_str());
+}
+
+fn emit(classification: Classification, message: &str) {
+    if let Some(callback) = message_callback() {
+        callback(classification, message);
+    }
+    #[cfg(feature = "log")]
+    {
+        let level = match classification {
+            Classification::ProvisioningResponseError => ::log::Level::Warn,
+            Classification::Retry => ::log::Level::Info,
+            _ => ::log::Level::Debug,
+        };
+        ::log::log!(target: "azure_iot", level, "{}: {}", classification, message);
+    }
+    #[cfg(feature = "defmt")]
+    match classification {
+        Classification::ProvisioningResponseError => {
+            defmt::warn!("{=str}: {=str}", classification.as_str(), message)
+        }
+        Classification::Retry => defmt::info!("{=str}: {=str}", classification.as_str(), message),
+        _ => defmt::debug!("{=str}: {=str}", classification.as_str(), message),
+    }
+}
+
+// Copies the message with the value after each secret marker replaced, up to
+// the next '&', ';', '"' or whitespace.
+pub fn redact_into<W>(writer: &mut W, message: &str)
+where
+    W: Write,
+{
+    let mut rest = message;
+    while let Some((index, marker)) = SECRET_MARKERS
+        .iter()
+        .filter_map(|marker| rest.find(marker).map(|index| (index, marker)))
+        .min_by_key(|(index, _)| *index)
+    {
+        let value_start = index + marker.len();
+        let value_length = rest[value_start..]
+            .find(|c: char| c == '&' || c == ';' || c == '"' || c.is_whitespace())
+            .unwrap_or(rest.len() - value_start);
+        if writer.write_str(&rest[..value_start]).is_err() || writer.write_str(REDACTED).is_err() {
+            return;
+        }
+        rest = &rest[value_start + value_length..];
+    }
+    let _ = writer.write_str(rest);
+}
+
+// Fixed size buffer that keeps whatever fits, on a character boundary.
+struct MessageBuffer {
+    bytes: [u8; MAX_MESSAGE_LENGTH],
+    length: usize,
+}
+
+impl MessageBuffer {
+    fn new() -> MessageBuffer {
+        MessageBuffer {
+            bytes: [0; MAX_MESSAGE_LENGTH],
+            length: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        // only whole characters are ever copied in
+        core::str::from_utf8(&self.bytes[..self.length]).unwrap_or_default()
+    }
+}
+
+impl Write for MessageBuffer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        let available = MAX_MESSAGE_LENGTH - self.length;
+        let mut length = s.len().min(available);
+        while !s.is_char_boundary(length) {
+            length -= 1;
+        }
+        self.bytes[self.length..self.length + length].copy_from_slice(&s.as_bytes()[..length]);
+        self.length += length;
+        if length < s.len() {
+            Err(fmt::Error)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_log {
+    use super::*;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static WRITTEN: AtomicUsize = AtomicUsize::new(0);
+
+    fn count(classification: Classification, message: &str) {
+        assert_ne!(classification, Classification::Retry);
+        assert!(!message.contains("c2lnbmF0dXJl"));
+        WRITTEN.fetch_add(message.len(), Ordering::SeqCst);
+    }
+
+    fn no_retries(classification: Classification) -> bool {
+        classification != Classification::Retry
+    }
+
+    fn redact(message: &str) -> MessageBuffer {
+        let mut redacted = MessageBuffer::new();
+        redact_into(&mut redacted, message);
+        redacted
+    }
+
+    // the callbacks are global, so everything that sets them is in one test
+    #[test]
+    fn callback_receives_filtered_and_redacted_messages() {
+        set_message_callback(Some(count));
+        set_classification_filter(Some(no_retries));
+        assert!(should_write(Classification::ProvisioningRequest));
+        assert!(!should_write(Classification::Retry));
+
+        write(Classification::Retry, "waiting");
+        assert_eq!(WRITTEN.load(Ordering::SeqCst), 0);
+        write_fmt(
+            Classification::ProvisioningResponse,
+            format_args!("status {}", 200),
+        );
+        assert_eq!(WRITTEN.load(Ordering::SeqCst), 10);
+        write(
+            Classification::SasToken,
+            "SharedAccessSignature sr=a&sig=c2lnbmF0dXJl&se=1",
+        );
+        assert_eq!(WRITTEN.load(Ordering::SeqCst), 10 + 39);
+
+        set_message_callback(None);
+        set_classification_filter(None);
+        write(Classification::HubMessage, "unheard");
+        assert_eq!(WRITTEN.load(Ordering::SeqCst), 49);
+    }
+
+    #[test]
+    fn secrets_are_redacted() {
+        assert_eq!(
+            redact("SharedAccessSignature sr=a%2fb&sig=c2ln%3D&se=1&skn=key").as_str(),
+            "SharedAccessSignature sr=a%2fb&sig=***&se=1&skn=key"
+        );
+        assert_eq!(
+            redact("HostName=h;DeviceId=d;SharedAccessKey=a2V5").as_str(),
+            "HostName=h;DeviceId=d;SharedAccessKey=***"
+        );
+        assert_eq!(
+            redact("{\"password\": \"SharedAccessSignature sig=abc\"}").as_str(),
+            "{\"password\": \"SharedAccessSignature sig=***\"}"
+        );
+        assert_eq!(redact("no secrets").as_str(), "no secrets");
+    }
+
+    #[test]
+    fn long_messages_are_cut_short() {
+        let mut message = MessageBuffer::new();
+        for _ in 0..MAX_MESSAGE_LENGTH / 2 {
+            let _ = message.write_str("é");
+        }
+        assert!(message.write_str("é").is_err());
+        assert_eq!(message.as_str().len(), MAX_MESSAGE_LENGTH);
+        let mut odd = MessageBuffer::new();
+        let _ = odd.write_str("a");
+        for _ in 0..MAX_MESSAGE_LENGTH {
+            let _ = odd.write_str("é");
+        }
+        assert_eq!(odd.as_str().len(), MAX_MESSAGE_LENGTH - 1);
+    }
+}
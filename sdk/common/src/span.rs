@@ -0,0 +1,95 @@
+// Writes into a caller provided buffer. Like the C SDK's az_span out-parameters,
+// writing never stops at the end of the buffer: the required length keeps being
+// counted so that the caller can retry with a buffer of the right size.
+use core::fmt;
+
+use crate::error::SpanError;
+
+pub struct SpanWriter<'a> {
+    buffer: &'a mut [u8],
+    required: usize,
+}
+
+impl<'a> SpanWriter<'a> {
+    pub fn new(buffer: &'a mut [u8]) -> SpanWriter<'a> {
+        SpanWriter {
+            buffer,
+            required: 0,
+        }
+    }
+
+    pub fn push_bytes(&mut self, bytes: &[u8]) {
+        let start = self.required;
+        self.required += bytes.len();
+        if self.required <= self.buffer.len() {
+            self.buffer[start..self.required].copy_from_slice(bytes);
+        }
+    }
+
+    pub fn push_str(&mut self, value: &str) {
+        self.push_bytes(value.as_bytes());
+    }
+
+    pub fn push(&mut self, c: char) {
+        let mut encoded = [0_u8; 4];
+        self.push_str(c.encode_utf8(&mut encoded));
+    }
+
+    // The number of bytes needed so far, which may exceed the buffer length
+    pub fn required(&self) -> usize {
+        self.required
+    }
+
+    pub fn fits(&self) -> bool {
+        self.required <= self.buffer.len()
+    }
+
+    // Ok with the number of bytes written, or the length the buffer needs to be.
+    pub fn finish(self) -> Result<usize, SpanError> {
+        if self.fits() {
+            Ok(self.required)
+        } else {
+            Err(SpanError::InsufficientSize {
+                required: self.required,
+            })
+        }
+    }
+}
+
+impl<'a> fmt::Write for SpanWriter<'a> {
+    fn write_str(&mut self, value: &str) -> fmt::Result {
+        self.push_str(value);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::fmt::Write;
+
+    #[test]
+    fn writes_when_the_buffer_is_large_enough() {
+        let mut buffer = [0_u8; 8];
+        let mut writer = SpanWriter::new(&mut buffer);
+        writer.push_str("ab");
+        writer.push('c');
+        write!(writer, "{}", 12).unwrap();
+        assert_eq!(writer.finish(), Ok(5));
+        assert_eq!(&buffer[..5], b"abc12");
+    }
+
+    #[test]
+    fn reports_the_required_length() {
+        let mut buffer = [0_u8; 4];
+        let mut writer = SpanWriter::new(&mut buffer);
+        writer.push_str("abc");
+        writer.push_str("defg");
+        writer.push_str("h");
+        assert_eq!(
+            writer.finish(),
+            Err(SpanError::InsufficientSize { required: 8 })
+        );
+        assert_eq!(&buffer[..3], b"abc");
+    }
+}
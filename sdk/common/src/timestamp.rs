@@ -0,0 +1,345 @@
+use core::cmp::Ordering;
+use core::convert::TryFrom;
+use core::fmt;
+use core::str::FromStr;
+
+pub const AZ_ERROR_INVALID_TIMESTAMP: &str = "The given value is not a valid RFC 3339 timestamp.";
+
+// A point in time as seconds and nanoseconds since the Unix epoch. Compares
+// directly against the u64 epoch times used for SAS token expiry.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    seconds: i64,
+    nanoseconds: u32,
+}
+
+impl Timestamp {
+    pub const UNIX_EPOCH: Timestamp = Timestamp {
+        seconds: 0,
+        nanoseconds: 0,
+    };
+
+    // Epoch times past i64::MAX seconds saturate.
+    pub fn from_epoch_time(epoch_time: u64) -> Timestamp {
+        Timestamp {
+            seconds: i64::try_from(epoch_time).unwrap_or(i64::MAX),
+            nanoseconds: 0,
+        }
+    }
+
+    pub fn from_unix_seconds(seconds: i64, nanoseconds: u32) -> Result<Timestamp, &'static str> {
+        if nanoseconds >= NANOSECONDS_PER_SECOND {
+            return Err(AZ_ERROR_INVALID_TIMESTAMP);
+        }
+        Ok(Timestamp {
+            seconds,
+            nanoseconds,
+        })
+    }
+
+    // Accepts `YYYY-MM-DDTHH:MM:SS[.fraction](Z|+HH:MM|-HH:MM)`. The service
+    // sends 7 fractional digits; anything past nanoseconds is dropped.
+    pub fn parse(value: &str) -> Result<Timestamp, &'static str> {
+        let bytes = value.as_bytes();
+        if bytes.len() < 20
+            || bytes[4] != b'-'
+            || bytes[7] != b'-'
+            || !matches!(bytes[10], b'T' | b't' | b' ')
+            || bytes[13] != b':'
+            || bytes[16] != b':'
+        {
+            return Err(AZ_ERROR_INVALID_TIMESTAMP);
+        }
+        let year = digits(&bytes[0..4])?;
+        let month = digits(&bytes[5..7])?;
+        let day = digits(&bytes[8..10])?;
+        let hour = digits(&bytes[11..13])?;
+        let minute = digits(&bytes[14..16])?;
+        // 60 is a leap second, which rolls over into the next minute
+        let second = digits(&bytes[17..19])?;
+        if !(1..=12).contains(&month)
+            || day < 1
+            || day > days_in_month(year, month)
+            || hour > 23
+            || minute > 59
+            || second > 60
+        {
+            return Err(AZ_ERROR_INVALID_TIMESTAMP);
+        }
+
+        let mut rest = &bytes[19..];
+        let mut nanoseconds = 0_u32;
+        if rest[0] == b'.' {
+            let count = rest[1..].iter().take_while(|b| b.is_ascii_digit()).count();
+            if count == 0 {
+                return Err(AZ_ERROR_INVALID_TIMESTAMP);
+            }
+            let mut scale = NANOSECONDS_PER_SECOND;
+            for b in &rest[1..=count] {
+                scale /= 10;
+                nanoseconds += u32::from(b - b'0') * scale;
+            }
+            rest = &rest[1 + count..];
+        }
+
+        let offset = match rest {
+            b"Z" | b"z" => 0,
+            [sign, h1, h2, b':', m1, m2] if *sign == b'+' || *sign == b'-' => {
+                let hours = digits(&[*h1, *h2])?;
+                let minutes = digits(&[*m1, *m2])?;
+                if hours > 23 || minutes > 59 {
+                    return Err(AZ_ERROR_INVALID_TIMESTAMP);
+                }
+                let offset = hours * 3600 + minutes * 60;
+                if *sign == b'-' {
+                    -offset
+                } else {
+                    offset
+                }
+            }
+            _ => return Err(AZ_ERROR_INVALID_TIMESTAMP),
+        };
+
+        let seconds =
+            days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset;
+        Ok(Timestamp {
+            seconds,
+            nanoseconds,
+        })
+    }
+
+    pub fn unix_seconds(&self) -> i64 {
+        self.seconds
+    }
+
+    pub fn nanoseconds(&self) -> u32 {
+        self.nanoseconds
+    }
+
+    // Whole seconds since the epoch, None before 1970.
+    pub fn epoch_time(&self) -> Option<u64> {
+        if self.seconds < 0 {
+            None
+        } else {
+            Some(self.seconds as u64)
+        }
+    }
+
+    pub fn checked_add_seconds(&self, seconds: u64) -> Option<Timestamp> {
+        if seconds > i64::MAX as u64 {
+            return None;
+        }
+        self.seconds
+            .checked_add(seconds as i64)
+            .map(|seconds| Timestamp {
+                seconds,
+                nanoseconds: self.nanoseconds,
+            })
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = &'static str;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Timestamp::parse(value)
+    }
+}
+
+// Formats as UTC with the service's 7 fractional digits when there is a fraction.
+impl fmt::Display for Timestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let days = self.seconds.div_euclid(86400);
+        let time = self.seconds.rem_euclid(86400);
+        let (year, month, day) = civil_from_days(days);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            time / 3600,
+            time % 3600 / 60,
+            time % 60
+        )?;
+        // below 100 nanoseconds nothing shows in 7 digits
+        if self.nanoseconds / 100 != 0 {
+            write!(f, ".{:07}", self.nanoseconds / 100)?;
+        }
+        f.write_str("Z")
+    }
+}
+
+impl PartialEq<u64> for Timestamp {
+    fn eq(&self, other: &u64) -> bool {
+        self.partial_cmp(other) == Some(Ordering::Equal)
+    }
+}
+
+// Compared as whole seconds so that epoch times past i64::MAX still order.
+impl PartialOrd<u64> for Timestamp {
+    fn partial_cmp(&self, other: &u64) -> Option<Ordering> {
+        Some(match self.epoch_time() {
+            None => Ordering::Less,
+            Some(seconds) if self.nanoseconds == 0 => seconds.cmp(other),
+            Some(seconds) => seconds.cmp(other).then(Ordering::Greater),
+        })
+    }
+}
+
+const NANOSECONDS_PER_SECOND: u32 = 1_000_000_000;
+
+fn digits(bytes: &[u8]) -> Result<i64, &'static str> {
+    let mut value = 0_i64;
+    for b in bytes {
+        if !b.is_ascii_digit() {
+            return Err(AZ_ERROR_INVALID_TIMESTAMP);
+        }
+        value = value * 10 + i64::from(b - b'0');
+    }
+    Ok(value)
+}
+
+fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01 of a proleptic Gregorian date.
+// http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month_index = (month + 9) % 12;
+    let day_of_year = (153 * month_index + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 {
+        month_index + 3
+    } else {
+        month_index - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests_timestamp {
+    use super::*;
+
+    #[test]
+    fn service_timestamps_parse() {
+        let timestamp = Timestamp::parse("2020-08-04T21:39:08.4834929Z").unwrap();
+        assert_eq!(timestamp.unix_seconds(), 1_596_577_148);
+        assert_eq!(timestamp.nanoseconds(), 483_492_900);
+        assert_eq!(timestamp.epoch_time(), Some(1_596_577_148));
+    }
+
+    #[test]
+    fn offsets_are_applied() {
+        let utc = Timestamp::parse("2020-08-04T21:39:08Z").unwrap();
+        assert_eq!(Timestamp::parse("2020-08-04T23:39:08+02:00").unwrap(), utc);
+        assert_eq!(Timestamp::parse("2020-08-04T16:09:08-05:30").unwrap(), utc);
+    }
+
+    #[test]
+    fn compares_against_epoch_times() {
+        let timestamp = Timestamp::parse("2020-08-04T21:39:08.5Z").unwrap();
+        assert!(timestamp > 1_596_577_148_u64);
+        assert!(timestamp < 1_596_577_149_u64);
+        assert!(Timestamp::parse("2020-08-04T21:39:08Z").unwrap() == 1_596_577_148_u64);
+        assert!(Timestamp::parse("1969-12-31T23:59:59Z").unwrap() < 0_u64);
+    }
+
+    #[test]
+    fn large_epoch_times_saturate() {
+        let latest = Timestamp::from_epoch_time(u64::MAX);
+        assert_eq!(latest.unix_seconds(), i64::MAX);
+        assert!(latest < u64::MAX);
+        assert!(latest == i64::MAX as u64);
+        assert_eq!(latest.checked_add_seconds(1), None);
+    }
+
+    #[test]
+    fn leap_days_and_the_epoch() {
+        assert_eq!(
+            Timestamp::parse("1970-01-01T00:00:00Z").unwrap(),
+            Timestamp::UNIX_EPOCH
+        );
+        assert!(Timestamp::parse("2020-02-29T00:00:00Z").is_ok());
+        assert!(Timestamp::parse("2019-02-29T00:00:00Z").is_err());
+        assert!(Timestamp::parse("1900-02-29T00:00:00Z").is_err());
+        assert_eq!(
+            Timestamp::parse("1969-12-31T23:59:59Z")
+                .unwrap()
+                .epoch_time(),
+            None
+        );
+    }
+
+    #[test]
+    fn invalid_values_are_rejected() {
+        for value in &[
+            "",
+            "2020-08-04",
+            "2020-08-04T21:39:08",
+            "2020-13-04T21:39:08Z",
+            "2020-08-04T24:00:00Z",
+            "2020-08-04T21:39:08.Z",
+            "2020-08-04T21:39:08+0200",
+            "2020-08-04T21:39:08ZZ",
+            "2020/08/04T21:39:08Z",
+        ] {
+            assert_eq!(Timestamp::parse(value), Err(AZ_ERROR_INVALID_TIMESTAMP));
+        }
+    }
+
+    #[test]
+    fn display_round_trips() {
+        use crate::span::SpanWriter;
+        use core::fmt::Write;
+        for value in &[
+            "2020-08-04T21:39:08.4834929Z",
+            "2020-08-04T21:39:08Z",
+            "1969-07-20T20:17:40Z",
+        ] {
+            let mut buffer = [0_u8; 32];
+            let mut writer = SpanWriter::new(&mut buffer);
+            write!(writer, "{}", Timestamp::parse(value).unwrap()).unwrap();
+            let length = writer.finish().unwrap();
+            assert_eq!(&buffer[..length], value.as_bytes());
+        }
+    }
+
+    #[test]
+    fn fractions_below_the_precision_are_not_shown() {
+        use crate::span::SpanWriter;
+        use core::fmt::Write;
+        let mut buffer = [0_u8; 32];
+        let mut writer = SpanWriter::new(&mut buffer);
+        write!(writer, "{}", Timestamp::from_unix_seconds(0, 99).unwrap()).unwrap();
+        let length = writer.finish().unwrap();
+        assert_eq!(&buffer[..length], b"1970-01-01T00:00:00Z");
+    }
+}
@@ -3,7 +3,9 @@ use std::time::Duration;
 use std::time::{SystemTime, UNIX_EPOCH};
 pub const DEFAULT_MQTT_CONNECT_KEEPALIVE_SECONDS: u64 = 240;
 
+use azure_sdk_for_rust_common::clock::{FnClock, AZ_ERROR_CLOCK_UNAVAILABLE};
 use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+use azure_sdk_for_rust_common::timestamp::Timestamp;
 use heapless::consts::U256;
 use heapless::String;
 
@@ -35,11 +37,10 @@ pub fn subscribe_client_to_provisioning_service_topics(mqtt_client: &mqtt::Clien
 
 // $dps/registrations/PUT/iotdps-register/?$rid=%s
 pub fn register_client_with_provisioning_service(mqtt_client: &mqtt::Client) {
-    let topic = "$dps/registrations/PUT/iotdps-register/?$rid=1";
+    let topic = client::Client::get_register_publish_topic().unwrap();
     let payload = Vec::new();
     let qos = 1;
-    println!("Sending: {}", &topic);
-    let message = paho_mqtt::message::Message::new(topic, payload, qos);
+    let message = paho_mqtt::message::Message::new(topic.as_str(), payload, qos);
     if let Err(response) = mqtt_client.publish(message) {
         panic!("{:?}", response);
     }
@@ -151,7 +152,6 @@ fn az_iot_provisioning_client_query_status_get_publish_topic(
 
     let payload = Vec::new();
     let qos = 1;
-    println!("Sending: {}", &topic);
     let message = paho_mqtt::message::Message::new(topic.as_str(), payload, qos);
 
     if mqtt_client.publish(message).is_err() {
@@ -207,11 +207,12 @@ fn parse_topic(topic: &str) -> (&str, Option<u64>) {
     return (topic, None);
 }
 
-pub fn get_epoch_expiration_time_from_hours(hours: u64) -> u64 {
-    match SystemTime::now().duration_since(UNIX_EPOCH) {
-        Ok(n) => return n.as_secs() + hours * 60 * 60,
-        Err(_) => panic!(),
-    }
+// Stands in for the RTC or NTP source of an embedded device.
+fn system_time() -> Result<Timestamp, &'static str> {
+    let elapsed = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_err(|_| AZ_ERROR_CLOCK_UNAVAILABLE)?;
+    Timestamp::from_unix_seconds(elapsed.as_secs() as i64, elapsed.subsec_nanos())
 }
 
 pub fn connect_client_to_provisioning_service(
@@ -221,9 +222,9 @@ pub fn connect_client_to_provisioning_service(
 ) -> mqtt::client::Client {
     let client_id = client.get_client_id();
     let user_name = client.get_user_name().unwrap();
-    let sas_duration = get_epoch_expiration_time_from_hours(1);
-
-    let sas_password = sas::get_password(&client, &sas_key, sas_duration, None).unwrap();
+    let clock = FnClock(system_time);
+    let (sas_password, _) =
+        sas::get_password_with_clock(&client, &sas_key, &clock, 60 * 60, 5 * 60).unwrap();
 
     let create_opts = mqtt::CreateOptionsBuilder::new()
         .client_id(client_id)
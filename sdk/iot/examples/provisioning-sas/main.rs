@@ -4,6 +4,7 @@ pub const DEFAULT_MQTT_CONNECT_PORT: i32 = 8883;
 pub const DEFAULT_MQTT_CONNECT_KEEPALIVE_SECONDS: u64 = 240;
 
 extern crate azure_sdk_for_rust_iot;
+use azure_sdk_for_rust_common::log::{self, Classification};
 use azure_sdk_for_rust_iot::provisioning::client;
 
 mod lib;
@@ -15,7 +16,13 @@ macro_rules! env_require {
     }};
 }
 
+fn print_log_message(classification: Classification, message: &str) {
+    println!("[{}] {}", classification, message);
+}
+
 fn main() {
+    log::set_message_callback(Some(print_log_message));
+
     let global_provisioning_endpoint = option_env!("ENV_GLOBAL_PROVISIONING_ENDPOINT")
         .unwrap_or("ssl://global.azure-devices-provisioning.net:8883");
 
@@ -49,7 +56,8 @@ fn main() {
         &id_scope,
         &registration_id,
         Some(options),
-    );
+    )
+    .expect("Invalid ID scope or registration ID");
     //client
     let mut mqtt_client = connect_client_to_provisioning_service(&client, &sas_key, trust_store);
 
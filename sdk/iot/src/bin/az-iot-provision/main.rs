@@ -0,0 +1,67 @@
+// Provisions a device with the Device Provisioning Service and prints the
+// registration result as JSON, for scripts such as manufacturing lines. Also
+// generates and checks SAS tokens offline. See `az-iot-provision --help`.
+mod options;
+mod register;
+mod tokens;
+mod transport;
+
+use std::env;
+use std::process;
+
+use azure_sdk_for_rust_common::clock::SystemClock;
+
+use options::{Command, Options, USAGE};
+use register::Outcome;
+
+const EXIT_SUCCESS: i32 = 0;
+const EXIT_FAILURE: i32 = 1;
+const EXIT_USAGE: i32 = 2;
+
+fn main() {
+    let code = match options::parse(env::args().skip(1)) {
+        Ok(Command::Help) => {
+            print!("{}", USAGE);
+            EXIT_SUCCESS
+        }
+        Ok(Command::Register(options)) => run(&options),
+        Ok(Command::Token(command)) => match tokens::run(&command, &SystemClock) {
+            Ok(report) => {
+                println!("{}", report.output);
+                if report.success {
+                    EXIT_SUCCESS
+                } else {
+                    EXIT_FAILURE
+                }
+            }
+            Err(error) => {
+                eprintln!("error: {}", error);
+                EXIT_FAILURE
+            }
+        },
+        Err(error) => {
+            eprintln!("error: {}\n\n{}", error, USAGE);
+            EXIT_USAGE
+        }
+    };
+    process::exit(code);
+}
+
+fn run(options: &Options) -> i32 {
+    let outcome =
+        transport::connect(options).and_then(|stream| register::register(stream, options, None));
+    match outcome {
+        Ok(Outcome::Assigned(state)) => {
+            println!("{}", state);
+            EXIT_SUCCESS
+        }
+        Ok(Outcome::Failed(details)) => {
+            println!("{}", details);
+            EXIT_FAILURE
+        }
+        Err(error) => {
+            eprintln!("error: {}", error);
+            EXIT_FAILURE
+        }
+    }
+}
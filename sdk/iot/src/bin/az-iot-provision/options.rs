@@ -0,0 +1,549 @@
+// Command line and config file handling. Values given on the command line win
+// over the config file.
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use azure_sdk_for_rust_iot::identifiers::{IdScope, RegistrationId};
+use azure_sdk_for_rust_iot::provisioning::api_version::ApiVersion;
+
+use crate::tokens::{self, TokenCommand};
+
+pub const DEFAULT_ENDPOINT: &str = "ssl://global.azure-devices-provisioning.net:8883";
+pub const DEFAULT_TIMEOUT_SECONDS: u64 = 120;
+
+pub const USAGE: &str = "\
+Provisions a device with the Azure IoT Hub Device Provisioning Service and
+prints the registration result as JSON.
+
+USAGE:
+    az-iot-provision [register] [OPTIONS]
+    az-iot-provision sas dps --id-scope <SCOPE> --registration-id <ID> --symmetric-key <KEY>
+                             [--expiry <EPOCH> | --ttl <SECONDS>] [--key-name <NAME>]
+    az-iot-provision sas hub --hostname <HOST> --device-id <ID> [--module-id <ID>]
+                             --symmetric-key <KEY> [--expiry <EPOCH> | --ttl <SECONDS>]
+                             [--key-name <NAME>]
+    az-iot-provision derive-key --group-key <KEY> --registration-id <ID>
+    az-iot-provision decode-sas <TOKEN>
+    az-iot-provision verify-sas <TOKEN> --symmetric-key <KEY>
+
+The sas, derive-key, decode-sas and verify-sas commands work offline. sas
+prints a token valid for --ttl seconds [default: 3600] or until the --expiry
+epoch time, derive-key prints the device key of an enrollment group member and
+decode-sas and verify-sas print the parts of an existing token as JSON.
+
+REGISTER OPTIONS:
+    --config <FILE>             File with any of the options below, one per line:
+                                id-scope = \"0ne00000001\"
+    --endpoint <URI>            Global device endpoint, wss:// for MQTT over WebSocket on 443
+                                [default: ssl://global.azure-devices-provisioning.net:8883]
+    --id-scope <SCOPE>          ID scope of the DPS instance
+    --registration-id <ID>      Registration id of the device
+    --symmetric-key <KEY>       Base64 encoded enrollment or device key
+    --certificate <FILE>        PEM device certificate chain for X.509 attestation
+    --private-key <FILE>        PEM private key of the device certificate
+    --trust-bundle <FILE>       PEM CA certificates to trust instead of the built-in roots
+    --payload <JSON>            Custom allocation payload
+    --api-version <VERSION>     DPS API version, 2019-03-31 or 2021-06-01 [default: 2019-03-31]
+    --model-id <DTMI>           Plug and Play model ID of the device, needs 2021-06-01
+    --timeout <SECONDS>         Give up after this long [default: 120]
+    -h, --help                  Print this message
+
+EXIT STATUS:
+    0  the device was assigned, or the command succeeded
+    1  provisioning failed; the service error details are printed when known.
+       verify-sas also fails when the token is expired or the key does not match
+    2  the options are invalid
+";
+
+#[derive(Debug, Default, PartialEq)]
+pub struct Config {
+    pub endpoint: Option<String>,
+    pub id_scope: Option<String>,
+    pub registration_id: Option<String>,
+    pub symmetric_key: Option<String>,
+    pub certificate: Option<PathBuf>,
+    pub private_key: Option<PathBuf>,
+    pub trust_bundle: Option<PathBuf>,
+    pub payload: Option<String>,
+    pub api_version: Option<String>,
+    pub model_id: Option<String>,
+    pub timeout: Option<u64>,
+}
+
+impl Config {
+    // The config file is flat TOML: `<option> = "<value>"` lines, with the
+    // option names of the command line and # comments.
+    pub fn from_file(path: &Path) -> Result<Config, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|error| format!("could not read {}: {}", path.display(), error))?;
+        let mut config = Config::default();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(2, '=');
+            let name = parts.next().unwrap_or_default().trim();
+            parts
+                .next()
+                .ok_or_else(|| "expected <option> = <value>".to_string())
+                .and_then(|value| unquote(value.trim()))
+                .and_then(|value| config.set(name, value))
+                .map_err(|error| format!("{}:{}: {}", path.display(), number + 1, error))?;
+        }
+        // files named in the config are relative to it
+        if let Some(directory) = path.parent() {
+            for file in &mut [
+                &mut config.certificate,
+                &mut config.private_key,
+                &mut config.trust_bundle,
+            ] {
+                if let Some(file) = file.as_mut() {
+                    if file.is_relative() {
+                        *file = directory.join(&file);
+                    }
+                }
+            }
+        }
+        Ok(config)
+    }
+
+    fn set(&mut self, name: &str, value: String) -> Result<(), String> {
+        match name {
+            "endpoint" => self.endpoint = Some(value),
+            "id-scope" => self.id_scope = Some(value),
+            "registration-id" => self.registration_id = Some(value),
+            "symmetric-key" => self.symmetric_key = Some(value),
+            "certificate" => self.certificate = Some(PathBuf::from(value)),
+            "private-key" => self.private_key = Some(PathBuf::from(value)),
+            "trust-bundle" => self.trust_bundle = Some(PathBuf::from(value)),
+            "payload" => self.payload = Some(value),
+            "api-version" => self.api_version = Some(value),
+            "model-id" => self.model_id = Some(value),
+            "timeout" => {
+                self.timeout =
+                    Some(value.parse().map_err(|_| {
+                        format!("timeout must be a number of seconds, not '{}'", value)
+                    })?)
+            }
+            _ => return Err(format!("unknown option '{}'", name)),
+        }
+        Ok(())
+    }
+
+    // Values set in `other` replace ours.
+    fn merge(self, other: Config) -> Config {
+        Config {
+            endpoint: other.endpoint.or(self.endpoint),
+            id_scope: other.id_scope.or(self.id_scope),
+            registration_id: other.registration_id.or(self.registration_id),
+            symmetric_key: other.symmetric_key.or(self.symmetric_key),
+            certificate: other.certificate.or(self.certificate),
+            private_key: other.private_key.or(self.private_key),
+            trust_bundle: other.trust_bundle.or(self.trust_bundle),
+            payload: other.payload.or(self.payload),
+            api_version: other.api_version.or(self.api_version),
+            model_id: other.model_id.or(self.model_id),
+            timeout: other.timeout.or(self.timeout),
+        }
+    }
+}
+
+#[derive(PartialEq)]
+pub enum Credential {
+    SymmetricKey(String),
+    X509 {
+        certificate: PathBuf,
+        private_key: PathBuf,
+    },
+}
+
+// The key is left out.
+impl fmt::Debug for Credential {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Credential::SymmetricKey(_) => f.write_str("SymmetricKey(..)"),
+            Credential::X509 {
+                certificate,
+                private_key,
+            } => f
+                .debug_struct("X509")
+                .field("certificate", certificate)
+                .field("private_key", private_key)
+                .finish(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Options {
+    pub endpoint: String,
+    pub id_scope: String,
+    pub registration_id: String,
+    pub credential: Credential,
+    pub trust_bundle: Option<PathBuf>,
+    pub payload: Option<serde_json::Value>,
+    pub api_version: ApiVersion,
+    pub model_id: Option<String>,
+    pub timeout_seconds: u64,
+}
+
+impl Options {
+    fn from_config(config: Config) -> Result<Options, String> {
+        let credential = match (config.symmetric_key, config.certificate, config.private_key) {
+            (Some(key), None, None) => Credential::SymmetricKey(key),
+            (None, Some(certificate), Some(private_key)) => Credential::X509 {
+                certificate,
+                private_key,
+            },
+            (None, None, None) => {
+                return Err(
+                    "either --symmetric-key or --certificate and --private-key are required"
+                        .to_string(),
+                )
+            }
+            (None, _, _) => {
+                return Err("--certificate and --private-key must be given together".to_string())
+            }
+            (Some(_), _, _) => {
+                return Err(
+                    "--symmetric-key can't be combined with --certificate or --private-key"
+                        .to_string(),
+                )
+            }
+        };
+        let payload = match config.payload {
+            Some(payload) => Some(
+                serde_json::from_str(&payload)
+                    .map_err(|error| format!("--payload is not valid JSON: {}", error))?,
+            ),
+            None => None,
+        };
+        let api_version = match config.api_version {
+            Some(version) => version
+                .parse::<ApiVersion>()
+                .map_err(|error| format!("--api-version: {}", error))?,
+            None => ApiVersion::default(),
+        };
+        if config.model_id.is_some() && !api_version.supports_model_id() {
+            return Err(format!(
+                "--model-id is not supported by API version {}",
+                api_version
+            ));
+        }
+        let id_scope = config.id_scope.ok_or("--id-scope is required")?;
+        IdScope::new(&id_scope).map_err(|error| format!("--id-scope: {}", error))?;
+        let registration_id = config
+            .registration_id
+            .ok_or("--registration-id is required")?;
+        RegistrationId::new(&registration_id)
+            .map_err(|error| format!("--registration-id: {}", error))?;
+        Ok(Options {
+            endpoint: config
+                .endpoint
+                .unwrap_or_else(|| DEFAULT_ENDPOINT.to_string()),
+            id_scope,
+            registration_id,
+            credential,
+            trust_bundle: config.trust_bundle,
+            payload,
+            api_version,
+            model_id: config.model_id,
+            timeout_seconds: config.timeout.unwrap_or(DEFAULT_TIMEOUT_SECONDS),
+        })
+    }
+}
+
+// "basic" strings take \\ and \" escapes, 'literal' strings are taken as is
+// and anything else is a bare value such as a number. Only a # comment may
+// follow the value.
+fn unquote(value: &str) -> Result<String, String> {
+    let (unquoted, rest) = if let Some(literal) = value.strip_prefix('\'') {
+        let end = literal.find('\'').ok_or("unterminated string")?;
+        (literal[..end].to_string(), &literal[end + 1..])
+    } else if let Some(basic) = value.strip_prefix('"') {
+        unescape(basic)?
+    } else {
+        let end = value.find('#').unwrap_or(value.len());
+        return Ok(value[..end].trim_end().to_string());
+    };
+    let rest = rest.trim_start();
+    if rest.is_empty() || rest.starts_with('#') {
+        Ok(unquoted)
+    } else {
+        Err(format!("unexpected '{}' after the value", rest))
+    }
+}
+
+// The value of a basic string up to its closing quote and what follows it.
+fn unescape(value: &str) -> Result<(String, &str), String> {
+    let mut unquoted = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok((unquoted, chars.as_str())),
+            '\\' => match chars.next() {
+                Some(c @ '"') | Some(c @ '\\') => unquoted.push(c),
+                _ => return Err("unsupported escape sequence".to_string()),
+            },
+            _ => unquoted.push(c),
+        }
+    }
+    Err("unterminated string".to_string())
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Command {
+    Help,
+    Register(Options),
+    Token(TokenCommand),
+}
+
+pub fn is_help(arg: &str) -> bool {
+    arg == "-h" || arg == "--help"
+}
+
+// Splits --name value or --name=value into the name without dashes and the
+// value.
+pub fn split_option<I>(arg: &str, args: &mut I) -> Result<(String, String), String>
+where
+    I: Iterator<Item = String>,
+{
+    let (name, value) = match arg.find('=') {
+        Some(index) => (&arg[..index], Some(arg[index + 1..].to_string())),
+        None => (arg, None),
+    };
+    match value.or_else(|| args.next()) {
+        Some(value) => Ok((name.trim_start_matches("--").to_string(), value)),
+        None => Err(format!("{} needs a value", name)),
+    }
+}
+
+pub fn parse<I>(args: I) -> Result<Command, String>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut args = args.into_iter().peekable();
+    match args.peek().map(String::as_str) {
+        Some("register") => {
+            args.next();
+        }
+        Some(command) if tokens::is_command(command) => return tokens::parse(args),
+        _ => {}
+    }
+    let mut config_file = None;
+    let mut config = Config::default();
+    while let Some(arg) = args.next() {
+        if is_help(&arg) {
+            return Ok(Command::Help);
+        }
+        if !arg.starts_with("--") {
+            return Err(format!("unexpected argument '{}'", arg));
+        }
+        let (name, value) = split_option(&arg, &mut args)?;
+        if name == "config" {
+            config_file = Some(PathBuf::from(value));
+        } else {
+            config.set(&name, value)?;
+        }
+    }
+    if let Some(path) = config_file {
+        config = Config::from_file(&path)?.merge(config);
+    }
+    Options::from_config(config).map(Command::Register)
+}
+
+#[cfg(test)]
+mod tests_options {
+    use super::*;
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn options(args: &[&str]) -> Options {
+        match parse_args(args).unwrap() {
+            Command::Register(options) => options,
+            command => panic!("unexpected {:?}", command),
+        }
+    }
+
+    #[test]
+    fn symmetric_key_on_the_command_line() {
+        let options = options(&[
+            "--id-scope",
+            "0ne00000001",
+            "--registration-id=device-1",
+            "--symmetric-key",
+            "a2V5",
+            "--payload",
+            "{\"modelId\":\"dtmi:example;1\"}",
+        ]);
+        assert_eq!(options.endpoint, DEFAULT_ENDPOINT);
+        assert_eq!(options.id_scope, "0ne00000001");
+        assert_eq!(options.registration_id, "device-1");
+        assert_eq!(
+            options.credential,
+            Credential::SymmetricKey("a2V5".to_string())
+        );
+        assert_eq!(
+            options.payload.unwrap()["modelId"],
+            serde_json::Value::from("dtmi:example;1")
+        );
+        assert_eq!(options.api_version, ApiVersion::V2019_03_31);
+        assert_eq!(options.model_id, None);
+        assert_eq!(options.timeout_seconds, DEFAULT_TIMEOUT_SECONDS);
+    }
+
+    #[test]
+    fn model_id_needs_a_newer_api_version() {
+        let args = [
+            "--id-scope",
+            "0ne00000001",
+            "--registration-id",
+            "device-1",
+            "--symmetric-key",
+            "a2V5",
+            "--model-id",
+            "dtmi:com:example:Thermostat;1",
+        ];
+        assert!(parse_args(&args).unwrap_err().contains("--model-id"));
+        let mut newer = args.to_vec();
+        newer.extend_from_slice(&["--api-version", "2021-06-01"]);
+        let options = options(&newer);
+        assert_eq!(options.api_version, ApiVersion::V2021_06_01);
+        assert_eq!(
+            options.model_id.as_deref(),
+            Some("dtmi:com:example:Thermostat;1")
+        );
+        newer.extend_from_slice(&["--api-version", "2020-09-01"]);
+        assert!(parse_args(&newer)
+            .unwrap_err()
+            .starts_with("--api-version: "));
+    }
+
+    #[test]
+    fn register_is_the_default_command() {
+        let args = [
+            "--id-scope",
+            "0ne00000001",
+            "--registration-id",
+            "device-1",
+            "--symmetric-key",
+            "a2V5",
+        ];
+        let mut register = vec!["register"];
+        register.extend_from_slice(&args);
+        assert_eq!(parse_args(&register), parse_args(&args));
+    }
+
+    #[test]
+    fn command_line_wins_over_the_config_file() {
+        let directory =
+            std::env::temp_dir().join(format!("az-iot-provision-options-{}", std::process::id()));
+        fs::create_dir_all(&directory).unwrap();
+        let file = directory.join("device.toml");
+        fs::write(
+            &file,
+            "# station 7\n\
+             id-scope = \"0ne00000001\"\n\
+             registration-id = \"device-1\"\n\
+             payload = '{\"station\": 7}'\n\
+             certificate = \"device.pem\"\n\
+             private-key = \"/keys/device.key\"\n\
+             api-version = \"2021-06-01\"\n\
+             timeout = 30\n",
+        )
+        .unwrap();
+        let options = options(&[
+            "--config",
+            file.to_str().unwrap(),
+            "--registration-id",
+            "device-2",
+        ]);
+        assert_eq!(options.id_scope, "0ne00000001");
+        assert_eq!(options.registration_id, "device-2");
+        assert_eq!(
+            options.credential,
+            Credential::X509 {
+                certificate: directory.join("device.pem"),
+                private_key: PathBuf::from("/keys/device.key"),
+            }
+        );
+        assert_eq!(options.api_version, ApiVersion::V2021_06_01);
+        assert_eq!(options.timeout_seconds, 30);
+        assert_eq!(options.payload.unwrap()["station"], 7);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn config_values_are_unquoted() {
+        assert_eq!(
+            unquote("\"a \\\"b\\\" \\\\c\""),
+            Ok("a \"b\" \\c".to_string())
+        );
+        assert_eq!(unquote("'C:\\certs'"), Ok("C:\\certs".to_string()));
+        assert_eq!(unquote("30"), Ok("30".to_string()));
+        assert!(unquote("\"open").is_err());
+        assert!(unquote("\"a\\nb\"").is_err());
+        assert!(unquote("'open").is_err());
+    }
+
+    #[test]
+    fn only_comments_may_follow_config_values() {
+        assert_eq!(unquote("\"a\" # note"), Ok("a".to_string()));
+        assert_eq!(unquote("'a'#note"), Ok("a".to_string()));
+        assert_eq!(unquote("30 # seconds"), Ok("30".to_string()));
+        assert!(unquote("\"a\" b").is_err());
+        assert!(unquote("\"a\"\"b\"").is_err());
+        assert!(unquote("'a' 'b'").is_err());
+    }
+
+    #[test]
+    fn keys_are_not_printed() {
+        let command = parse_args(&[
+            "--id-scope",
+            "0ne00000001",
+            "--registration-id",
+            "device-1",
+            "--symmetric-key",
+            "c2VjcmV0",
+        ])
+        .unwrap();
+        let printed = format!("{:?}", command);
+        assert!(printed.contains("device-1"), "{}", printed);
+        assert!(!printed.contains("c2VjcmV0"), "{}", printed);
+    }
+
+    #[test]
+    fn invalid_options_are_reported() {
+        let base = ["--id-scope", "0ne00000001", "--registration-id", "device-1"];
+        assert!(parse_args(&base).unwrap_err().contains("--symmetric-key"));
+        let mut args = base.to_vec();
+        args.extend_from_slice(&["--certificate", "device.pem"]);
+        assert!(parse_args(&args).unwrap_err().contains("together"));
+        args.extend_from_slice(&["--private-key", "device.key", "--symmetric-key", "a2V5"]);
+        assert!(parse_args(&args).unwrap_err().contains("can't be combined"));
+        assert!(parse_args(&["--id-scope"])
+            .unwrap_err()
+            .contains("needs a value"));
+        assert!(parse_args(&[
+            "--id-scope",
+            "0ne00000001",
+            "--registration-id",
+            "Device 1",
+            "--symmetric-key",
+            "a2V5"
+        ])
+        .unwrap_err()
+        .starts_with("--registration-id: "));
+        assert!(parse_args(&["--color", "red"])
+            .unwrap_err()
+            .contains("unknown option"));
+        assert!(parse_args(&["provision"])
+            .unwrap_err()
+            .contains("unexpected argument"));
+        assert_eq!(parse_args(&["--id-scope", "x", "-h"]), Ok(Command::Help));
+    }
+}
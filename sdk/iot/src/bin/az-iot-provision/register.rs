@@ -0,0 +1,461 @@
+// The DPS register/poll flow over MQTT, using the crate's own codec.
+use std::thread;
+use std::time::Duration;
+
+use azure_sdk_for_rust_common::clock::{Deadline, SystemClock};
+use azure_sdk_for_rust_iot::mqtt::{
+    decode, encode_connect, encode_disconnect, encode_publish, encode_subscribe, Connect,
+    ConnectReturnCode, Packet, Publish, QoS,
+};
+use azure_sdk_for_rust_iot::provisioning::client::{Client, ClientOptions};
+use azure_sdk_for_rust_iot::provisioning::retry::{Backoff, RetryOptions};
+use azure_sdk_for_rust_iot::provisioning::sas;
+use azure_sdk_for_rust_iot::provisioning::serialization::owned;
+use azure_sdk_for_rust_iot::provisioning::serialization::ProvisioningServiceErrorDetails;
+use azure_sdk_for_rust_iot::statuscode::{ErrorAction, ExtendedErrorCode, StatusCode};
+use heapless::consts::{U1024, U128, U256, U4096, U512};
+use serde_json::Value;
+
+use crate::options::{Credential, Options};
+use crate::transport::{io_error, Stream};
+
+const SAS_LIFETIME_SECONDS: u64 = 3600;
+// Used when an assigning response has no retry-after
+const DEFAULT_POLL_SECONDS: u64 = 3;
+
+#[derive(Debug, PartialEq)]
+pub enum Outcome {
+    // The registration state of the assigned device
+    Assigned(Value),
+    // ProvisioningServiceErrorDetails of the failure
+    Failed(Value),
+}
+
+enum Request {
+    Register,
+    OperationStatus(String),
+}
+
+struct Session<S> {
+    stream: S,
+    buffer: Vec<u8>,
+    packet_id: u16,
+}
+
+impl<S> Session<S>
+where
+    S: Stream,
+{
+    fn send(&mut self, packet: &[u8]) -> Result<(), String> {
+        self.stream
+            .write_all(packet)
+            .and_then(|_| self.stream.flush())
+            .map_err(io_error)
+    }
+
+    fn publish(&mut self, topic: &str, payload: &[u8]) -> Result<(), String> {
+        self.packet_id = self.packet_id.wrapping_add(1).max(1);
+        let packet = encode_publish::<U4096>(&Publish::new(
+            topic,
+            payload,
+            QoS::AtLeastOnce,
+            self.packet_id,
+        ))?;
+        self.send(&packet)
+    }
+
+    fn receive(&mut self) -> Result<Packet<U256, U4096>, String> {
+        loop {
+            if let Some((packet, consumed)) = decode(&self.buffer)? {
+                self.buffer.drain(..consumed);
+                return Ok(packet);
+            }
+            let mut chunk = [0_u8; 1024];
+            let read = self.stream.read(&mut chunk).map_err(io_error)?;
+            if read == 0 {
+                return Err("the service closed the connection".to_string());
+            }
+            self.buffer.extend_from_slice(&chunk[..read]);
+        }
+    }
+}
+
+pub fn register<S>(
+    stream: S,
+    options: &Options,
+    retry: Option<RetryOptions>,
+) -> Result<Outcome, String>
+where
+    S: Stream,
+{
+    let clock = SystemClock;
+    let deadline = Deadline::after(&clock, options.timeout_seconds)?;
+    let client = Client::new(
+        &options.endpoint,
+        &options.id_scope,
+        &options.registration_id,
+        Some(ClientOptions {
+            api_version: options.api_version,
+            ..ClientOptions::default()
+        }),
+    )?;
+    let user_name = client.get_user_name_with_capacity::<U512>()?;
+    let password = match &options.credential {
+        Credential::SymmetricKey(key) => {
+            Some(sas::get_password_with_clock(&client, key, &clock, SAS_LIFETIME_SECONDS, 0)?.0)
+        }
+        // the TLS client certificate authenticates the device
+        Credential::X509 { .. } => None,
+    };
+    let mut connect = Connect::new(client.get_client_id(), &user_name, "");
+    connect.password = password.as_ref().map(|password| password.as_bytes());
+
+    let mut session = Session {
+        stream,
+        buffer: Vec::new(),
+        packet_id: 0,
+    };
+    session.send(&encode_connect::<U1024>(&connect)?)?;
+    match session.receive()? {
+        Packet::Connack(connack) if connack.return_code == ConnectReturnCode::Accepted => {}
+        Packet::Connack(connack) => {
+            return Err(format!(
+                "the service refused the connection: {:?}",
+                connack.return_code
+            ))
+        }
+        packet => return Err(format!("expected CONNACK, got {:?}", packet)),
+    }
+
+    session.packet_id += 1;
+    let subscribe = encode_subscribe::<U128>(
+        session.packet_id,
+        &[(Client::get_provisioning_service_topics(), QoS::AtLeastOnce)],
+    )?;
+    session.send(&subscribe)?;
+    loop {
+        match session.receive()? {
+            Packet::Suback(suback) if suback.is_success() => break,
+            Packet::Suback(_) => return Err("the service rejected the subscription".to_string()),
+            _ => {}
+        }
+    }
+
+    let mut registration = client.registration(options.payload.as_ref());
+    registration.model_id = options.model_id.as_deref();
+    client.check_registration(&registration)?;
+    // serde-json-core can't write an arbitrary JSON payload
+    let body = serde_json::to_string(&registration).map_err(|error| error.to_string())?;
+    let mut backoff = Backoff::new(retry);
+    let mut request = Request::Register;
+    send_request(&mut session, &request, &body)?;
+
+    let outcome = loop {
+        let message = match session.receive()? {
+            Packet::Publish(message) => message,
+            _ => continue,
+        };
+        let response = Client::parse_received_topic(&message.topic)?;
+        let delay = if response.status < StatusCode::BAD_REQUEST.as_u16() {
+            let status: owned::RegistrationOperationStatus<Value> =
+                serde_json::from_slice(&message.payload)
+                    .map_err(|error| format!("invalid response from the service: {}", error))?;
+            match status.status.as_str() {
+                "assigning" => {
+                    request = Request::OperationStatus(status.operation_id);
+                    response.retry_after_seconds.unwrap_or(DEFAULT_POLL_SECONDS)
+                }
+                "assigned" => {
+                    let state = status
+                        .registration_state
+                        .ok_or("the service did not return a registration state")?;
+                    break Outcome::Assigned(to_value(
+                        &state.for_api_version(options.api_version),
+                    )?);
+                }
+                _ => break Outcome::Failed(registration_error(status)?),
+            }
+        } else {
+            let details = owned::ProvisioningServiceErrorDetails::from_slice(&message.payload)
+                .map_err(|error| format!("invalid error response from the service: {}", error))?;
+            let delay = match error_code(&details, response.status).action() {
+                ErrorAction::Retry => backoff.next_delay(response.retry_after_seconds),
+                // the SAS token was just made from the key, a new one won't help
+                ErrorAction::RefreshCredentials | ErrorAction::Terminal => None,
+            };
+            match delay {
+                Some(delay) => delay,
+                None => break Outcome::Failed(to_value(&details)?),
+            }
+        };
+        if deadline.remaining_seconds(&clock)? < delay {
+            return Err("timed out waiting for the device to be assigned".to_string());
+        }
+        thread::sleep(Duration::from_secs(delay));
+        send_request(&mut session, &request, &body)?;
+    };
+    // the outcome is known, a failed disconnect doesn't change it
+    let _ = session.send(&encode_disconnect());
+    Ok(outcome)
+}
+
+fn send_request<S>(session: &mut Session<S>, request: &Request, body: &str) -> Result<(), String>
+where
+    S: Stream,
+{
+    match request {
+        Request::Register => {
+            let topic = Client::get_register_publish_topic()?;
+            session.publish(&topic, body.as_bytes())
+        }
+        Request::OperationStatus(operation_id) => {
+            let topic = Client::query_status_get_publish_topic(operation_id)?;
+            session.publish(&topic, b"")
+        }
+    }
+}
+
+// A failed, disabled or unassigned registration carries its error in the
+// registration state rather than in an error response.
+fn registration_error(status: owned::RegistrationOperationStatus<Value>) -> Result<Value, String> {
+    let state = status.registration_state.as_ref();
+    let message = match state {
+        Some(state) if !state.error_message.is_empty() => state.error_message.clone(),
+        _ => format!("The registration is {}.", status.status),
+    };
+    to_value(&ProvisioningServiceErrorDetails {
+        error_code: state
+            .and_then(|state| state.error_code.parse().ok())
+            .unwrap_or_default(),
+        info: None,
+        message: &message,
+        timestamp_utc: state.map_or("", |state| state.last_updated_date_time_utc.as_str()),
+        tracking_id: "",
+    })
+}
+
+// The errorCode decides what to do about an error response; the status of
+// the topic stands in when the body has none.
+fn error_code(details: &owned::ProvisioningServiceErrorDetails, status: u16) -> ExtendedErrorCode {
+    match details.error_code {
+        0 => ExtendedErrorCode::from_u32(u32::from(status)),
+        _ => details.code(),
+    }
+}
+
+fn to_value<T>(value: &T) -> Result<Value, String>
+where
+    T: serde::Serialize,
+{
+    serde_json::to_value(value).map_err(|error| error.to_string())
+}
+
+#[cfg(all(test, feature = "test-support"))]
+mod tests_register {
+    use super::*;
+    use crate::transport::connect;
+    use azure_sdk_for_rust_iot::provisioning::api_version::ApiVersion;
+    use azure_sdk_for_rust_iot::test_support::dps::{
+        DpsRequest, DpsSimulator, DpsSimulatorOptions, Enrollment, ScriptedResponse,
+    };
+    use azure_sdk_for_rust_iot::test_support::websocket::WebSocketGateway;
+    use serde_json::json;
+    use std::net::TcpStream;
+
+    const ID_SCOPE: &str = "0ne00000001";
+    const REGISTRATION_ID: &str = "device-1";
+    const SAS_KEY: &str = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+
+    fn start(responses: Vec<ScriptedResponse>) -> DpsSimulator {
+        DpsSimulator::start(DpsSimulatorOptions {
+            id_scope: ID_SCOPE.to_string(),
+            enrollments: vec![Enrollment::new(REGISTRATION_ID, SAS_KEY)],
+            responses,
+            #[cfg(feature = "csr")]
+            certificate_issuer: None,
+        })
+        .unwrap()
+    }
+
+    fn options(endpoint: String, sas_key: &str) -> Options {
+        Options {
+            endpoint,
+            id_scope: ID_SCOPE.to_string(),
+            registration_id: REGISTRATION_ID.to_string(),
+            credential: Credential::SymmetricKey(sas_key.to_string()),
+            trust_bundle: None,
+            payload: Some(json!({ "modelId": "dtmi:example;1" })),
+            api_version: ApiVersion::default(),
+            model_id: None,
+            timeout_seconds: 10,
+        }
+    }
+
+    fn retry_options() -> Option<RetryOptions> {
+        Some(RetryOptions {
+            initial_delay_seconds: 0,
+            max_delay_seconds: 0,
+            max_attempts: Some(3),
+        })
+    }
+
+    fn run(simulator: &DpsSimulator, sas_key: &str) -> Result<Outcome, String> {
+        let options = options(simulator.endpoint(), sas_key);
+        let stream = TcpStream::connect(simulator.local_addr()).unwrap();
+        register(stream, &options, retry_options())
+    }
+
+    fn assigned() -> ScriptedResponse {
+        ScriptedResponse::Assigned {
+            assigned_hub: "example.azure-devices.net".to_string(),
+            device_id: REGISTRATION_ID.to_string(),
+            substatus: "initialAssignment".to_string(),
+        }
+    }
+
+    #[test]
+    fn device_is_assigned_after_polling() {
+        let simulator = start(vec![
+            ScriptedResponse::Assigning {
+                retry_after: Some(0),
+            },
+            ScriptedResponse::Throttled { retry_after: 0 },
+            assigned(),
+        ]);
+        let state = match run(&simulator, SAS_KEY).unwrap() {
+            Outcome::Assigned(state) => state,
+            outcome => panic!("unexpected {:?}", outcome),
+        };
+        assert_eq!(state["assignedHub"], "example.azure-devices.net");
+        assert_eq!(state["deviceId"], REGISTRATION_ID);
+        assert_eq!(state["status"], "assigned");
+
+        let requests = simulator.requests();
+        assert_eq!(
+            requests[1],
+            DpsRequest::Register {
+                registration_id: REGISTRATION_ID.to_string(),
+                payload:
+                    "{\"payload\":{\"modelId\":\"dtmi:example;1\"},\"registrationId\":\"device-1\"}"
+                        .to_string(),
+            }
+        );
+        let operation = DpsRequest::OperationStatus {
+            operation_id: "4.0000000000000000.device-1".to_string(),
+        };
+        assert_eq!(&requests[2..], &[operation.clone(), operation]);
+    }
+
+    #[test]
+    fn model_id_is_sent_for_the_api_version() {
+        let simulator = start(vec![assigned()]);
+        let mut options = options(simulator.endpoint(), SAS_KEY);
+        options.api_version = ApiVersion::V2021_06_01;
+        options.model_id = Some("dtmi:com:example:Thermostat;1".to_string());
+        options.payload = Some(json!({ "station": 7 }));
+        let stream = TcpStream::connect(simulator.local_addr()).unwrap();
+        match register(stream, &options, retry_options()).unwrap() {
+            Outcome::Assigned(state) => assert_eq!(state["deviceId"], REGISTRATION_ID),
+            outcome => panic!("unexpected {:?}", outcome),
+        }
+        let requests = simulator.requests();
+        match &requests[0] {
+            DpsRequest::Connect { user_name, .. } => {
+                assert!(
+                    user_name.ends_with("/api-version=2021-06-01"),
+                    "{}",
+                    user_name
+                )
+            }
+            request => panic!("unexpected {:?}", request),
+        }
+        assert_eq!(
+            requests[1],
+            DpsRequest::Register {
+                registration_id: REGISTRATION_ID.to_string(),
+                payload: "{\"payload\":{\"modelId\":\"dtmi:com:example:Thermostat;1\",\"station\":7},\"registrationId\":\"device-1\"}"
+                    .to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn device_is_assigned_over_websocket() {
+        let simulator = start(vec![assigned()]);
+        let gateway = WebSocketGateway::start(simulator.local_addr()).unwrap();
+        let options = options(gateway.endpoint(), SAS_KEY);
+        let stream = connect(&options).unwrap();
+        match register(stream, &options, retry_options()).unwrap() {
+            Outcome::Assigned(state) => assert_eq!(state["deviceId"], REGISTRATION_ID),
+            outcome => panic!("unexpected {:?}", outcome),
+        }
+    }
+
+    #[test]
+    fn failed_registration_is_reported_as_error_details() {
+        let simulator = start(vec![ScriptedResponse::Failed {
+            error_code: "400209".to_string(),
+            error_message: "Custom allocation failed".to_string(),
+        }]);
+        let details = match run(&simulator, SAS_KEY).unwrap() {
+            Outcome::Failed(details) => details,
+            outcome => panic!("unexpected {:?}", outcome),
+        };
+        assert_eq!(details["errorCode"], 400_209);
+        assert_eq!(details["message"], "Custom allocation failed");
+    }
+
+    #[test]
+    fn service_errors_are_not_retried_unless_transient() {
+        let simulator = start(vec![ScriptedResponse::Unauthorized, assigned()]);
+        let details = match run(&simulator, SAS_KEY).unwrap() {
+            Outcome::Failed(details) => details,
+            outcome => panic!("unexpected {:?}", outcome),
+        };
+        assert_eq!(details["errorCode"], 401_002);
+        assert_eq!(details["message"], "Unauthorized");
+    }
+
+    #[test]
+    fn throttling_gives_up_after_the_retries() {
+        let throttled = ScriptedResponse::Throttled { retry_after: 0 };
+        let simulator = start(vec![throttled; 4]);
+        let details = match run(&simulator, SAS_KEY).unwrap() {
+            Outcome::Failed(details) => details,
+            outcome => panic!("unexpected {:?}", outcome),
+        };
+        assert_eq!(details["errorCode"], 429_001);
+    }
+
+    #[test]
+    fn error_codes_decide_on_retries() {
+        let details = |json: &str| -> owned::ProvisioningServiceErrorDetails {
+            owned::ProvisioningServiceErrorDetails::from_slice(json.as_bytes()).unwrap()
+        };
+        let throttled = details("{\"errorCode\":429001,\"message\":\"Throttled\"}");
+        assert_eq!(error_code(&throttled, 429).action(), ErrorAction::Retry);
+        // the code wins over the status of the topic
+        let invalid = details("{\"errorCode\":400004,\"message\":\"Invalid\"}");
+        assert_eq!(error_code(&invalid, 503).action(), ErrorAction::Terminal);
+        let unavailable = details("{\"message\":\"Unavailable\"}");
+        assert_eq!(error_code(&unavailable, 503).action(), ErrorAction::Retry);
+    }
+
+    #[test]
+    fn error_info_is_passed_through() {
+        let details = owned::ProvisioningServiceErrorDetails::from_slice(
+            b"{\"errorCode\":400209,\"message\":\"Failed\",\"info\":{\"reasons\":[\"a\",1]}}",
+        )
+        .unwrap();
+        let value = to_value(&details).unwrap();
+        assert_eq!(value["errorCode"], 400_209);
+        assert_eq!(value["info"], json!({ "reasons": ["a", 1] }));
+    }
+
+    #[test]
+    fn wrong_key_is_refused() {
+        let simulator = start(vec![]);
+        let error = run(&simulator, "QW5vdGhlciBrZXkgdGhhdCBpcyBub3QgZW5yb2xsZWQ=").unwrap_err();
+        assert!(error.contains("NotAuthorized"), "{}", error);
+    }
+}
@@ -0,0 +1,480 @@
+// Offline credential commands: SAS tokens for DPS and IoT Hub, device keys of
+// enrollment group members, and decoding or verifying existing tokens.
+use std::fmt;
+
+use azure_sdk_for_rust_common::clock::Clock;
+use azure_sdk_for_rust_common::timestamp::Timestamp;
+use azure_sdk_for_rust_iot::hub;
+use azure_sdk_for_rust_iot::identifiers::ModuleId;
+use azure_sdk_for_rust_iot::provisioning;
+use azure_sdk_for_rust_iot::provisioning::sas::{self, SasToken};
+use serde_json::json;
+use zeroize::Zeroize;
+
+use crate::options::{is_help, split_option, Command};
+
+pub const DEFAULT_TTL_SECONDS: u64 = 3600;
+
+#[derive(Debug, PartialEq)]
+pub enum Expiry {
+    At(u64),
+    After(u64),
+}
+
+#[derive(PartialEq)]
+pub enum TokenCommand {
+    DpsSas {
+        id_scope: String,
+        registration_id: String,
+        symmetric_key: String,
+        expiry: Expiry,
+        key_name: Option<String>,
+    },
+    HubSas {
+        hostname: String,
+        device_id: String,
+        module_id: Option<String>,
+        symmetric_key: String,
+        expiry: Expiry,
+        key_name: Option<String>,
+    },
+    DeriveKey {
+        group_key: String,
+        registration_id: String,
+    },
+    DecodeSas(String),
+    VerifySas {
+        token: String,
+        symmetric_key: String,
+    },
+}
+
+// Keys and tokens are left out.
+impl fmt::Debug for TokenCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TokenCommand::DpsSas {
+                id_scope,
+                registration_id,
+                expiry,
+                key_name,
+                ..
+            } => f
+                .debug_struct("DpsSas")
+                .field("id_scope", id_scope)
+                .field("registration_id", registration_id)
+                .field("expiry", expiry)
+                .field("key_name", key_name)
+                .finish_non_exhaustive(),
+            TokenCommand::HubSas {
+                hostname,
+                device_id,
+                module_id,
+                expiry,
+                key_name,
+                ..
+            } => f
+                .debug_struct("HubSas")
+                .field("hostname", hostname)
+                .field("device_id", device_id)
+                .field("module_id", module_id)
+                .field("expiry", expiry)
+                .field("key_name", key_name)
+                .finish_non_exhaustive(),
+            TokenCommand::DeriveKey {
+                registration_id, ..
+            } => f
+                .debug_struct("DeriveKey")
+                .field("registration_id", registration_id)
+                .finish_non_exhaustive(),
+            TokenCommand::DecodeSas(_) => f.write_str("DecodeSas(..)"),
+            TokenCommand::VerifySas { .. } => f.write_str("VerifySas { .. }"),
+        }
+    }
+}
+
+// What a command prints to stdout and whether it succeeded.
+#[derive(Debug, PartialEq)]
+pub struct Report {
+    pub output: String,
+    pub success: bool,
+}
+
+// The output can be a token or a derived key.
+impl Drop for Report {
+    fn drop(&mut self) {
+        self.output.zeroize();
+    }
+}
+
+pub fn is_command(arg: &str) -> bool {
+    matches!(arg, "sas" | "derive-key" | "decode-sas" | "verify-sas")
+}
+
+// The options given to a command; each is taken out as the command reads it
+// so that anything left over can be reported.
+struct Arguments {
+    options: Vec<(String, String)>,
+    positional: Vec<String>,
+}
+
+impl Arguments {
+    fn take(&mut self, name: &str) -> Option<String> {
+        let index = self
+            .options
+            .iter()
+            .rposition(|(option, _)| option == name)?;
+        let value = self.options.remove(index).1;
+        // repeated options: the last one wins
+        self.options.retain(|(option, _)| option != name);
+        Some(value)
+    }
+
+    fn require(&mut self, name: &str) -> Result<String, String> {
+        self.take(name)
+            .ok_or_else(|| format!("--{} is required", name))
+    }
+
+    fn take_positional(&mut self, description: &str) -> Result<String, String> {
+        if self.positional.is_empty() {
+            return Err(format!("{} is required", description));
+        }
+        Ok(self.positional.remove(0))
+    }
+
+    fn take_expiry(&mut self) -> Result<Expiry, String> {
+        let seconds = |name: &str, value: String| {
+            value
+                .parse()
+                .map_err(|_| format!("--{} must be a number of seconds, not '{}'", name, value))
+        };
+        match (self.take("expiry"), self.take("ttl")) {
+            (Some(_), Some(_)) => Err("--expiry and --ttl can't be combined".to_string()),
+            (Some(epoch), None) => seconds("expiry", epoch).map(Expiry::At),
+            (None, Some(ttl)) => seconds("ttl", ttl).map(Expiry::After),
+            (None, None) => Ok(Expiry::After(DEFAULT_TTL_SECONDS)),
+        }
+    }
+
+    fn finish(self, command: TokenCommand) -> Result<Command, String> {
+        if let Some((name, _)) = self.options.first() {
+            return Err(format!("unknown option '{}'", name));
+        }
+        if let Some(arg) = self.positional.first() {
+            return Err(format!("unexpected argument '{}'", arg));
+        }
+        Ok(Command::Token(command))
+    }
+}
+
+pub fn parse<I>(mut args: I) -> Result<Command, String>
+where
+    I: Iterator<Item = String>,
+{
+    let command = args.next().unwrap_or_default();
+    let mut arguments = Arguments {
+        options: Vec::new(),
+        positional: Vec::new(),
+    };
+    while let Some(arg) = args.next() {
+        if is_help(&arg) {
+            return Ok(Command::Help);
+        }
+        if arg.starts_with("--") {
+            arguments.options.push(split_option(&arg, &mut args)?);
+        } else {
+            arguments.positional.push(arg);
+        }
+    }
+    let command = match command.as_str() {
+        "sas" => match arguments.take_positional("sas dps or sas hub")?.as_str() {
+            "dps" => TokenCommand::DpsSas {
+                id_scope: arguments.require("id-scope")?,
+                registration_id: arguments.require("registration-id")?,
+                symmetric_key: arguments.require("symmetric-key")?,
+                expiry: arguments.take_expiry()?,
+                key_name: arguments.take("key-name"),
+            },
+            "hub" => TokenCommand::HubSas {
+                hostname: arguments.require("hostname")?,
+                device_id: arguments.require("device-id")?,
+                module_id: arguments.take("module-id"),
+                symmetric_key: arguments.require("symmetric-key")?,
+                expiry: arguments.take_expiry()?,
+                key_name: arguments.take("key-name"),
+            },
+            service => return Err(format!("unknown service '{}', use dps or hub", service)),
+        },
+        "derive-key" => TokenCommand::DeriveKey {
+            group_key: arguments.require("group-key")?,
+            registration_id: arguments.require("registration-id")?,
+        },
+        "decode-sas" => TokenCommand::DecodeSas(arguments.take_positional("the token")?),
+        "verify-sas" => TokenCommand::VerifySas {
+            token: arguments.take_positional("the token")?,
+            symmetric_key: arguments.require("symmetric-key")?,
+        },
+        command => return Err(format!("unknown command '{}'", command)),
+    };
+    arguments.finish(command)
+}
+
+pub fn run<C>(command: &TokenCommand, clock: &C) -> Result<Report, String>
+where
+    C: Clock + ?Sized,
+{
+    match command {
+        TokenCommand::DpsSas {
+            id_scope,
+            registration_id,
+            symmetric_key,
+            expiry,
+            key_name,
+        } => {
+            let client = provisioning::client::Client::new("", id_scope, registration_id, None)?;
+            let token = sas::get_password_with_capacity::<heapless::consts::U512>(
+                &client,
+                symmetric_key,
+                expiration_epoch_time(expiry, clock)?,
+                key_name.as_deref(),
+            )?;
+            Ok(success(token.to_string()))
+        }
+        TokenCommand::HubSas {
+            hostname,
+            device_id,
+            module_id,
+            symmetric_key,
+            expiry,
+            key_name,
+        } => {
+            let options = hub::client::ClientOptions {
+                module_id: module_id.as_deref().map(ModuleId::new).transpose()?,
+                ..hub::client::ClientOptions::default()
+            };
+            let client = hub::client::Client::new(hostname, device_id, Some(options))?;
+            let token = hub::sas::get_password(
+                &client,
+                symmetric_key,
+                expiration_epoch_time(expiry, clock)?,
+                key_name.as_deref(),
+            )?;
+            Ok(success(token.to_string()))
+        }
+        TokenCommand::DeriveKey {
+            group_key,
+            registration_id,
+        } => Ok(success(
+            sas::derive_device_key(group_key, registration_id)?
+                .as_str()
+                .to_string(),
+        )),
+        TokenCommand::DecodeSas(token) => {
+            let token = SasToken::parse(token)?;
+            let report = describe(&token, clock)?;
+            Ok(success(report.to_string()))
+        }
+        TokenCommand::VerifySas {
+            token,
+            symmetric_key,
+        } => {
+            let token = SasToken::parse(token)?;
+            let mut report = describe(&token, clock)?;
+            let valid = token.verify(symmetric_key)?;
+            report["valid"] = json!(valid);
+            Ok(Report {
+                success: valid && report["expired"] == false,
+                output: report.to_string(),
+            })
+        }
+    }
+}
+
+fn success(output: String) -> Report {
+    Report {
+        output,
+        success: true,
+    }
+}
+
+fn expiration_epoch_time<C>(expiry: &Expiry, clock: &C) -> Result<u64, String>
+where
+    C: Clock + ?Sized,
+{
+    match expiry {
+        Expiry::At(epoch_time) => Ok(*epoch_time),
+        Expiry::After(seconds) => Ok(clock.epoch_time()?.saturating_add(*seconds)),
+    }
+}
+
+fn describe<C>(token: &SasToken<'_>, clock: &C) -> Result<serde_json::Value, String>
+where
+    C: Clock + ?Sized,
+{
+    Ok(json!({
+        "resource": token.decode_resource()?.as_str(),
+        "signature": token.signature,
+        "expiry": token.expiration_epoch_time,
+        "expiresAt": Timestamp::from_epoch_time(token.expiration_epoch_time).to_string(),
+        "keyName": token.key_name,
+        "expired": token.is_expired(clock)?,
+    }))
+}
+
+#[cfg(test)]
+mod tests_tokens {
+    use super::*;
+    use azure_sdk_for_rust_common::clock::ManualClock;
+
+    const KEY: &str = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+
+    fn parse_args(args: &[&str]) -> Result<Command, String> {
+        parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    fn command(args: &[&str]) -> TokenCommand {
+        match parse_args(args).unwrap() {
+            Command::Token(command) => command,
+            command => panic!("unexpected {:?}", command),
+        }
+    }
+
+    #[test]
+    fn keys_and_tokens_are_not_printed() {
+        let printed = format!(
+            "{:?} {:?} {:?}",
+            command(&[
+                "derive-key",
+                "--group-key",
+                KEY,
+                "--registration-id",
+                "device-1"
+            ]),
+            command(&["decode-sas", "sr=a&sig=c2lnbmF0dXJl&se=1"]),
+            command(&[
+                "verify-sas",
+                "sr=a&sig=c2lnbmF0dXJl&se=1",
+                "--symmetric-key",
+                KEY
+            ]),
+        );
+        assert!(printed.contains("device-1"), "{}", printed);
+        assert!(!printed.contains(KEY), "{}", printed);
+        assert!(!printed.contains("c2lnbmF0dXJl"), "{}", printed);
+    }
+
+    #[test]
+    fn dps_token_is_generated_for_the_ttl() {
+        let command = command(&[
+            "sas",
+            "dps",
+            "--id-scope",
+            "eight675309",
+            "--registration-id",
+            "1-1-2-3-5-8-13-21",
+            "--symmetric-key",
+            KEY,
+            "--ttl=3600",
+        ]);
+        let clock = ManualClock::from_epoch_time(1_596_893_939);
+        let report = run(&command, &clock).unwrap();
+        assert!(report.success);
+        assert_eq!(
+            report.output,
+            "SharedAccessSignature sr=eight675309%2fregistrations%2f1-1-2-3-5-8-13-21\
+             &sig=npj3I09%2BJtl6VYHZM%2FH5mKMG8jn4Y3zty3dMjMkMMDs%3D&se=1596897539"
+        );
+    }
+
+    #[test]
+    fn hub_token_verifies_until_it_expires() {
+        let clock = ManualClock::from_epoch_time(1_600_000_000);
+        let token = run(
+            &command(&[
+                "sas",
+                "hub",
+                "--hostname",
+                "hub.azure-devices.net",
+                "--device-id",
+                "dev1",
+                "--module-id",
+                "edge",
+                "--symmetric-key",
+                KEY,
+                "--expiry",
+                "1600000060",
+            ]),
+            &clock,
+        )
+        .unwrap()
+        .output
+        .clone();
+        let verify = command(&["verify-sas", &token, "--symmetric-key", KEY]);
+        let report = run(&verify, &clock).unwrap();
+        assert!(report.success);
+        let details: serde_json::Value = serde_json::from_str(&report.output).unwrap();
+        assert_eq!(
+            details["resource"],
+            "hub.azure-devices.net/devices/dev1/modules/edge"
+        );
+        assert_eq!(details["expiresAt"], "2020-09-13T12:27:40Z");
+        assert_eq!(details["valid"], true);
+
+        clock.advance(60);
+        assert!(!run(&verify, &clock).unwrap().success);
+        clock.set(Timestamp::from_epoch_time(1_600_000_000));
+        let wrong_key = command(&["verify-sas", &token, "--symmetric-key", "b3RoZXIga2V5"]);
+        let report = run(&wrong_key, &clock).unwrap();
+        assert!(!report.success);
+        assert!(report.output.contains("\"valid\":false"));
+    }
+
+    #[test]
+    fn group_member_key_is_derived() {
+        let command = command(&[
+            "derive-key",
+            "--group-key",
+            "8isrFI1sGsIlvvFSSFRiMfCNzv21fjbE/+ah/lSh3lF8e2YG1Te7w1KpZhJFFXJrqYKi9yegxkqIChbqOS9Egw==",
+            "--registration-id",
+            "sn-007-888-abc-mac-a1-b2-c3-d4-e5-f6",
+        ]);
+        let report = run(&command, &ManualClock::from_epoch_time(0)).unwrap();
+        assert_eq!(
+            report.output,
+            "Jsm0lyGpjaVYVP2g3FnmnmG9dI/9qU24wNoykUmermc="
+        );
+    }
+
+    #[test]
+    fn invalid_commands_are_reported() {
+        assert!(parse_args(&["sas", "storage"])
+            .unwrap_err()
+            .contains("unknown service"));
+        assert!(parse_args(&["sas", "dps", "--id-scope", "x"])
+            .unwrap_err()
+            .contains("--registration-id is required"));
+        assert!(parse_args(&[
+            "derive-key",
+            "--group-key",
+            "a2V5",
+            "--registration-id",
+            "device-1",
+            "--ttl",
+            "60",
+        ])
+        .unwrap_err()
+        .contains("unknown option 'ttl'"));
+        assert!(parse_args(&["decode-sas"])
+            .unwrap_err()
+            .contains("the token is required"));
+        assert!(parse_args(&["decode-sas", "a", "b"])
+            .unwrap_err()
+            .contains("unexpected argument 'b'"));
+        assert_eq!(parse_args(&["verify-sas", "--help"]), Ok(Command::Help));
+        assert!(run(
+            &TokenCommand::DecodeSas("sr=a&sig=b&se=1".to_string()),
+            &ManualClock::from_epoch_time(0)
+        )
+        .is_err());
+    }
+}
@@ -0,0 +1,138 @@
+// Byte stream to the service: TLS for the real service, plain TCP for local
+// stand-ins such as test_support::dps, either of them optionally carrying
+// MQTT over WebSocket.
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use azure_sdk_for_rust_iot::endpoint::{Endpoint, Scheme};
+use azure_sdk_for_rust_iot::tls::{self, ClientIdentity, TrustBundle};
+use azure_sdk_for_rust_iot::websocket::WebSocketStream;
+use rustls::{ClientConfig, StreamOwned};
+
+use crate::options::{Credential, Options};
+
+pub trait Stream: Read + Write {}
+
+impl<T> Stream for T where T: Read + Write {}
+
+// MQTT endpoints: ssl, mqtts (the default), tcp or mqtt, or wss or ws for MQTT
+// over WebSocket.
+pub fn parse_endpoint(uri: &str) -> Result<Endpoint<'_>, String> {
+    let endpoint =
+        Endpoint::parse(uri).map_err(|error| format!("invalid endpoint '{}': {}", uri, error))?;
+    match endpoint.scheme {
+        Scheme::Mqtts | Scheme::Mqtt | Scheme::Wss | Scheme::Ws => Ok(endpoint),
+        scheme => Err(format!(
+            "unsupported endpoint scheme '{}' in '{}'",
+            scheme.as_str(),
+            uri
+        )),
+    }
+}
+
+pub fn connect(options: &Options) -> Result<Box<dyn Stream>, String> {
+    let endpoint = parse_endpoint(&options.endpoint)?;
+    let tcp = TcpStream::connect((endpoint.host, endpoint.port))
+        .map_err(|error| format!("could not connect to {}: {}", options.endpoint, error))?;
+    // a stalled service must not hang the caller past the timeout
+    tcp.set_read_timeout(Some(Duration::from_secs(options.timeout_seconds.max(1))))
+        .map_err(|error| error.to_string())?;
+    let stream: Box<dyn Stream> = if endpoint.scheme.is_tls() {
+        let config = tls_config(&options.credential, options.trust_bundle.as_deref())?;
+        let connection = tls::rustls::client_connection(Arc::new(config), endpoint.host)
+            .map_err(|error| format!("could not start TLS: {}", error))?;
+        Box::new(StreamOwned::new(connection, tcp))
+    } else {
+        Box::new(tcp)
+    };
+    if !endpoint.scheme.is_websocket() {
+        return Ok(stream);
+    }
+    let websocket = WebSocketStream::connect(stream, &endpoint).map_err(|error| {
+        format!(
+            "could not open a WebSocket to {}: {}",
+            options.endpoint,
+            io_error(error)
+        )
+    })?;
+    Ok(Box::new(websocket))
+}
+
+fn tls_config(
+    credential: &Credential,
+    trust_bundle: Option<&Path>,
+) -> Result<ClientConfig, String> {
+    let trust = match trust_bundle {
+        Some(path) => TrustBundle::Pem(read(path)?),
+        None => TrustBundle::AzureRoots,
+    };
+    let identity = match credential {
+        Credential::SymmetricKey(_) => None,
+        Credential::X509 {
+            certificate,
+            private_key,
+        } => Some(
+            ClientIdentity::from_pem(&read(certificate)?, &read(private_key)?)
+                .map_err(|error| format!("invalid device certificate: {}", error))?,
+        ),
+    };
+    tls::rustls::client_config(identity.as_ref(), &trust)
+        .map_err(|error| format!("invalid TLS configuration: {}", error))
+}
+
+fn read(path: &Path) -> Result<Vec<u8>, String> {
+    fs::read(path).map_err(|error| format!("could not read {}: {}", path.display(), error))
+}
+
+pub(crate) fn io_error(error: io::Error) -> String {
+    match error.kind() {
+        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+            "timed out waiting for the service".to_string()
+        }
+        _ => format!("connection error: {}", error),
+    }
+}
+
+#[cfg(test)]
+mod tests_transport {
+    use super::*;
+
+    #[test]
+    fn endpoints_are_parsed() {
+        let endpoint = parse_endpoint("ssl://global.azure-devices-provisioning.net:8883").unwrap();
+        assert_eq!(endpoint.scheme, Scheme::Mqtts);
+        assert_eq!(endpoint.host, "global.azure-devices-provisioning.net");
+        let endpoint = parse_endpoint("tcp://127.0.0.1:4567").unwrap();
+        assert!(!endpoint.scheme.is_tls());
+        assert_eq!(endpoint.port, 4567);
+        assert_eq!(
+            parse_endpoint("global.azure-devices-provisioning.net").map(|endpoint| endpoint.port),
+            Ok(8883)
+        );
+        let endpoint = parse_endpoint("wss://global.azure-devices-provisioning.net").unwrap();
+        assert_eq!(endpoint.port, 443);
+        assert_eq!(endpoint.path, "");
+        assert!(parse_endpoint("https://example.net")
+            .unwrap_err()
+            .contains("unsupported endpoint scheme 'https'"));
+        assert!(parse_endpoint("ssl://example.net:port").is_err());
+        assert!(parse_endpoint("ssl://:8883")
+            .unwrap_err()
+            .contains("ssl://:8883"));
+    }
+
+    #[test]
+    fn missing_credential_files_are_reported() {
+        let credential = Credential::X509 {
+            certificate: "/nonexistent/device.pem".into(),
+            private_key: "/nonexistent/device.key".into(),
+        };
+        assert!(tls_config(&credential, None)
+            .unwrap_err()
+            .contains("/nonexistent/device.pem"));
+    }
+}
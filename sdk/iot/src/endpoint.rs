@@ -0,0 +1,302 @@
+// DPS and IoT Hub endpoints, e.g. ssl://global.azure-devices-provisioning.net:8883.
+// The same host serves MQTT on 8883, MQTT over WebSocket on 443 and REST, so
+// the other variants are derived from whichever one was configured; this keeps
+// private link and sovereign cloud hosts working without string surgery.
+use core::fmt::{self, Write};
+
+use heapless::{ArrayLength, String};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+pub const AZ_ERROR_ENDPOINT_UNSUPPORTED_SCHEME: &str = "The endpoint scheme is not supported.";
+pub const AZ_ERROR_ENDPOINT_INVALID_HOST: &str = "The endpoint does not contain a valid host name.";
+pub const AZ_ERROR_ENDPOINT_INVALID_PORT: &str = "The endpoint port is not valid.";
+pub const AZ_ERROR_ENDPOINT_INVALID_PATH: &str = "The endpoint path is not valid for its scheme.";
+
+pub const WEBSOCKET_PATH: &str = "/$iothub/websocket";
+
+const SCHEME_SEPARATOR: &str = "://";
+const MAX_HOST_LENGTH: usize = 253;
+const MAX_LABEL_LENGTH: usize = 63;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Scheme {
+    // ssl:// is accepted as paho's name for it
+    Mqtts,
+    // tcp:// is accepted as paho's name for it; only for local stand-ins
+    Mqtt,
+    Wss,
+    Ws,
+    Https,
+}
+
+impl Scheme {
+    fn parse(scheme: &str) -> Option<Scheme> {
+        match scheme {
+            "mqtts" | "ssl" => Some(Scheme::Mqtts),
+            "mqtt" | "tcp" => Some(Scheme::Mqtt),
+            "wss" => Some(Scheme::Wss),
+            "ws" => Some(Scheme::Ws),
+            "https" => Some(Scheme::Https),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Scheme::Mqtts => "mqtts",
+            Scheme::Mqtt => "mqtt",
+            Scheme::Wss => "wss",
+            Scheme::Ws => "ws",
+            Scheme::Https => "https",
+        }
+    }
+
+    pub fn default_port(self) -> u16 {
+        match self {
+            Scheme::Mqtts => 8883,
+            Scheme::Mqtt => 1883,
+            Scheme::Wss | Scheme::Https => 443,
+            Scheme::Ws => 80,
+        }
+    }
+
+    pub fn is_tls(self) -> bool {
+        match self {
+            Scheme::Mqtts | Scheme::Wss | Scheme::Https => true,
+            Scheme::Mqtt | Scheme::Ws => false,
+        }
+    }
+
+    pub fn is_websocket(self) -> bool {
+        self == Scheme::Wss || self == Scheme::Ws
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Endpoint<'a> {
+    pub scheme: Scheme,
+    // Also the name sent as SNI and the REST host.
+    pub host: &'a str,
+    pub port: u16,
+    // Empty except for WebSocket endpoints.
+    pub path: &'a str,
+}
+
+impl<'a> Endpoint<'a> {
+    // [<scheme>://]<host>[:<port>][<path>]; without a scheme the endpoint is
+    // MQTT over TLS, so a bare hub host name is accepted too.
+    pub fn parse(uri: &'a str) -> Result<Endpoint<'a>, &'static str> {
+        let (scheme, rest) = match uri.find(SCHEME_SEPARATOR) {
+            Some(index) => (
+                Scheme::parse(&uri[..index]).ok_or(AZ_ERROR_ENDPOINT_UNSUPPORTED_SCHEME)?,
+                &uri[index + SCHEME_SEPARATOR.len()..],
+            ),
+            None => (Scheme::Mqtts, uri),
+        };
+        let (authority, path) = rest
+            .find('/')
+            .map_or((rest, ""), |index| rest.split_at(index));
+        let (host, port) = match authority.rfind(':') {
+            Some(index) => (
+                &authority[..index],
+                match authority[index + 1..].parse() {
+                    Ok(port) if port != 0 => port,
+                    _ => return Err(AZ_ERROR_ENDPOINT_INVALID_PORT),
+                },
+            ),
+            None => (authority, scheme.default_port()),
+        };
+        if !is_valid_host(host) {
+            return Err(AZ_ERROR_ENDPOINT_INVALID_HOST);
+        }
+        let path = if path == "/" { "" } else { path };
+        if (!path.is_empty() && !scheme.is_websocket())
+            || path.contains(|c: char| c == '?' || c == '#' || c.is_whitespace())
+        {
+            return Err(AZ_ERROR_ENDPOINT_INVALID_PATH);
+        }
+        Ok(Endpoint {
+            scheme,
+            host,
+            port,
+            path,
+        })
+    }
+
+    // mqtts://<host>:8883
+    #[must_use]
+    pub fn mqtts(&self) -> Endpoint<'a> {
+        self.with_scheme(Scheme::Mqtts, "")
+    }
+
+    // wss://<host>:443/$iothub/websocket
+    #[must_use]
+    pub fn websocket(&self) -> Endpoint<'a> {
+        self.with_scheme(Scheme::Wss, WEBSOCKET_PATH)
+    }
+
+    // https://<host>:443
+    #[must_use]
+    pub fn https(&self) -> Endpoint<'a> {
+        self.with_scheme(Scheme::Https, "")
+    }
+
+    pub fn uri<N>(&self) -> Result<String<N>, &'static str>
+    where
+        N: ArrayLength<u8>,
+    {
+        let mut uri = String::new();
+        write!(uri, "{}", self).map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+        Ok(uri)
+    }
+
+    fn with_scheme(&self, scheme: Scheme, path: &'a str) -> Endpoint<'a> {
+        Endpoint {
+            scheme,
+            host: self.host,
+            port: scheme.default_port(),
+            path,
+        }
+    }
+}
+
+impl<'a> fmt::Display for Endpoint<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}://{}:{}{}",
+            self.scheme.as_str(),
+            self.host,
+            self.port,
+            self.path
+        )
+    }
+}
+
+// DNS names and IPv4 addresses; labels are letters, digits and hyphens.
+fn is_valid_host(host: &str) -> bool {
+    !host.is_empty()
+        && host.len() <= MAX_HOST_LENGTH
+        && host.split('.').all(|label| {
+            !label.is_empty()
+                && label.len() <= MAX_LABEL_LENGTH
+                && !label.starts_with('-')
+                && !label.ends_with('-')
+                && label.chars().all(|c| c.is_ascii_alphanumeric() || c == '-')
+        })
+}
+
+#[cfg(test)]
+mod tests_endpoint {
+    use super::*;
+    use heapless::consts::U128;
+
+    #[test]
+    fn endpoints_are_parsed() {
+        assert_eq!(
+            Endpoint::parse("ssl://global.azure-devices-provisioning.net:8883"),
+            Ok(Endpoint {
+                scheme: Scheme::Mqtts,
+                host: "global.azure-devices-provisioning.net",
+                port: 8883,
+                path: "",
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("tcp://127.0.0.1:4567"),
+            Ok(Endpoint {
+                scheme: Scheme::Mqtt,
+                host: "127.0.0.1",
+                port: 4567,
+                path: "",
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("contoso.azure-devices.net"),
+            Ok(Endpoint {
+                scheme: Scheme::Mqtts,
+                host: "contoso.azure-devices.net",
+                port: 8883,
+                path: "",
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("wss://contoso.azure-devices.net/$iothub/websocket"),
+            Ok(Endpoint {
+                scheme: Scheme::Wss,
+                host: "contoso.azure-devices.net",
+                port: 443,
+                path: WEBSOCKET_PATH,
+            })
+        );
+        assert_eq!(
+            Endpoint::parse("mqtts://hub.privatelink.azure-devices.net/"),
+            Ok(Endpoint {
+                scheme: Scheme::Mqtts,
+                host: "hub.privatelink.azure-devices.net",
+                port: 8883,
+                path: "",
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_endpoints_are_rejected() {
+        assert_eq!(
+            Endpoint::parse("amqps://example.net"),
+            Err(AZ_ERROR_ENDPOINT_UNSUPPORTED_SCHEME)
+        );
+        assert_eq!(
+            Endpoint::parse("ssl://example.net:port"),
+            Err(AZ_ERROR_ENDPOINT_INVALID_PORT)
+        );
+        assert_eq!(
+            Endpoint::parse("ssl://example.net:0"),
+            Err(AZ_ERROR_ENDPOINT_INVALID_PORT)
+        );
+        assert_eq!(
+            Endpoint::parse("ssl://:8883"),
+            Err(AZ_ERROR_ENDPOINT_INVALID_HOST)
+        );
+        assert_eq!(
+            Endpoint::parse("ssl://user@example.net"),
+            Err(AZ_ERROR_ENDPOINT_INVALID_HOST)
+        );
+        assert_eq!(
+            Endpoint::parse("ssl://example..net"),
+            Err(AZ_ERROR_ENDPOINT_INVALID_HOST)
+        );
+        assert_eq!(
+            Endpoint::parse("ssl://example.net/$iothub/websocket"),
+            Err(AZ_ERROR_ENDPOINT_INVALID_PATH)
+        );
+        assert_eq!(
+            Endpoint::parse("wss://example.net/ws?token=1"),
+            Err(AZ_ERROR_ENDPOINT_INVALID_PATH)
+        );
+    }
+
+    #[test]
+    fn variants_keep_the_host() {
+        let endpoint = Endpoint::parse("ssl://contoso.azure-devices.us:8883").unwrap();
+        assert_eq!(
+            endpoint.websocket().uri::<U128>().unwrap(),
+            "wss://contoso.azure-devices.us:443/$iothub/websocket"
+        );
+        assert_eq!(
+            endpoint.websocket().mqtts().uri::<U128>().unwrap(),
+            "mqtts://contoso.azure-devices.us:8883"
+        );
+        assert_eq!(
+            endpoint.https().uri::<U128>().unwrap(),
+            "https://contoso.azure-devices.us:443"
+        );
+        assert_eq!(
+            endpoint.uri::<heapless::consts::U8>(),
+            Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE)
+        );
+        let websocket = endpoint.websocket().uri::<U128>().unwrap();
+        assert_eq!(Endpoint::parse(&websocket), Ok(endpoint.websocket()));
+    }
+}
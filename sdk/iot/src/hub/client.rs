@@ -0,0 +1,406 @@
+pub struct ClientOptions<'a> {
+    pub module_id: Option<ModuleId<'a>>,
+    pub user_agent: &'a str,
+}
+
+pub struct Client<'a> {
+    pub hub_hostname: &'a str,
+    pub device_id: DeviceId<'a>,
+    pub options: ClientOptions<'a>,
+}
+
+impl<'a> Default for ClientOptions<'a> {
+    #[inline]
+    fn default() -> ClientOptions<'a> {
+        ClientOptions {
+            module_id: None,
+            user_agent: "",
+        }
+    }
+}
+
+impl<'a> Client<'a> {
+    pub fn new(
+        hub_hostname: &'a str,
+        device_id: &'a str,
+        options: Option<ClientOptions<'a>>,
+    ) -> Result<Client<'a>, &'static str> {
+        Ok(Client {
+            hub_hostname,
+            device_id: DeviceId::new(device_id)?,
+            options: options.unwrap_or_default(),
+        })
+    }
+
+    // MQTT over TLS to the hub host; see Endpoint for the other variants.
+    pub fn endpoint(&self) -> Result<Endpoint<'a>, &'static str> {
+        Endpoint::parse(self.hub_hostname)
+    }
+}
+
+use super::properties::Properties;
+use super::{METHODS_RESPONSE_TOPIC, SERVICE_VERSION, TWIN_GET_TOPIC, TWIN_PATCH_REPORTED_TOPIC};
+
+use heapless::consts::{U128, U256};
+use heapless::String;
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+use azure_sdk_for_rust_common::log::{self, Classification};
+
+use crate::endpoint::Endpoint;
+use crate::identifiers::{DeviceId, ModuleId};
+use crate::provisioning::util::u64_to_string;
+
+pub const AZ_ERROR_IOT_TOPIC_NO_MATCH: &str = "The topic does not match the expected format.";
+
+const DEVICES: &str = "devices/";
+const MODULES: &str = "/modules/";
+const MESSAGES_EVENTS: &str = "/messages/events/";
+const MESSAGES_DEVICEBOUND: &str = "/messages/devicebound/";
+const METHODS_REQUEST_PREFIX: &str = "$iothub/methods/POST/";
+const TWIN_RESPONSE_PREFIX: &str = "$iothub/twin/res/";
+const TWIN_DESIRED_PREFIX: &str = "$iothub/twin/PATCH/properties/desired/";
+const REQUEST_ID: &str = "$rid";
+const VERSION: &str = "$version";
+
+// A cloud-to-device message received on devices/<device_id>/messages/devicebound/<properties>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct C2dRequest<'a> {
+    pub properties: Properties<'a>,
+}
+
+// A direct method call received on $iothub/methods/POST/<name>/?$rid=<request_id>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MethodRequest<'a> {
+    pub name: &'a str,
+    pub request_id: &'a str,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TwinResponseType {
+    Get,
+    DesiredProperties,
+    ReportedProperties,
+}
+
+// A twin document, desired patch or reported acknowledgement received on
+// $iothub/twin/res/<status>/?$rid=<request_id>[&$version=<version>] or
+// $iothub/twin/PATCH/properties/desired/?$version=<version>
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TwinResponse<'a> {
+    pub response_type: TwinResponseType,
+    pub status: u16,
+    pub request_id: Option<&'a str>,
+    pub version: Option<&'a str>,
+}
+
+impl<'a> Client<'a> {
+    // <device_id>[/<module_id>]
+    pub fn get_client_id(&self) -> Result<String<U128>, &'static str> {
+        let mut res: String<U128> = String::new();
+        if res.push_str(self.device_id.as_str()).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        if let Some(module_id) = self.options.module_id {
+            if res.push('/').is_err() || res.push_str(module_id.as_str()).is_err() {
+                return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+            }
+        }
+        Ok(res)
+    }
+
+    // <hub_hostname>/<client_id>/?api-version=<service_version>[&DeviceClientType=<user_agent>]
+    pub fn get_user_name(&self) -> Result<String<U256>, &'static str> {
+        let client_id = self.get_client_id()?;
+        let mut res: String<U256> = String::new();
+        if res.push_str(self.hub_hostname).is_err()
+            || res.push('/').is_err()
+            || res.push_str(client_id.as_str()).is_err()
+            || res.push_str("/?api-version=").is_err()
+            || res.push_str(SERVICE_VERSION).is_err()
+        {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        if !self.options.user_agent.is_empty()
+            && (res.push_str("&DeviceClientType=").is_err()
+                || res.push_str(self.options.user_agent).is_err())
+        {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        Ok(res)
+    }
+
+    // Topic: devices/<device_id>[/modules/<module_id>]/messages/events/[<properties>]
+    pub fn telemetry_publish_topic(
+        &self,
+        properties: Option<&Properties<'_>>,
+    ) -> Result<String<U256>, &'static str> {
+        let mut topic: String<U256> = String::new();
+        if topic.push_str(DEVICES).is_err() || topic.push_str(self.device_id.as_str()).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        if let Some(module_id) = self.options.module_id {
+            if topic.push_str(MODULES).is_err() || topic.push_str(module_id.as_str()).is_err() {
+                return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+            }
+        }
+        if topic.push_str(MESSAGES_EVENTS).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        if let Some(properties) = properties {
+            if topic.push_str(properties.as_str()).is_err() {
+                return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+            }
+        }
+        log::write(Classification::HubRequest, &topic);
+        Ok(topic)
+    }
+
+    // Topic: devices/<device_id>/messages/devicebound/#
+    pub fn c2d_subscribe_topic(&self) -> Result<String<U128>, &'static str> {
+        let mut topic: String<U128> = String::new();
+        if topic.push_str(DEVICES).is_err()
+            || topic.push_str(self.device_id.as_str()).is_err()
+            || topic.push_str(MESSAGES_DEVICEBOUND).is_err()
+            || topic.push('#').is_err()
+        {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        Ok(topic)
+    }
+
+    pub fn c2d_parse_received_topic<'b>(
+        &self,
+        topic: &'b str,
+    ) -> Result<C2dRequest<'b>, &'static str> {
+        let properties = topic
+            .strip_prefix(DEVICES)
+            .and_then(|rest| rest.strip_prefix(self.device_id.as_str()))
+            .and_then(|rest| rest.strip_prefix(MESSAGES_DEVICEBOUND))
+            .ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?;
+        log::write(Classification::HubMessage, topic);
+        Ok(C2dRequest {
+            properties: Properties::new(properties),
+        })
+    }
+
+    // Topic: $iothub/methods/res/<status>/?$rid=<request_id>
+    pub fn methods_response_publish_topic(
+        request_id: &str,
+        status: u16,
+    ) -> Result<String<U128>, &'static str> {
+        let mut topic: String<U128> = String::new();
+        let status = u64_to_string(u64::from(status));
+        if topic.push_str(METHODS_RESPONSE_TOPIC).is_err()
+            || topic.push_str(status.as_str()).is_err()
+            || topic.push_str("/?$rid=").is_err()
+            || topic.push_str(request_id).is_err()
+        {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        log::write(Classification::HubRequest, &topic);
+        Ok(topic)
+    }
+
+    pub fn methods_parse_received_topic(topic: &str) -> Result<MethodRequest<'_>, &'static str> {
+        let rest = topic
+            .strip_prefix(METHODS_REQUEST_PREFIX)
+            .ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?;
+        let mut parts = rest.splitn(2, "/?");
+        let name = parts.next().unwrap_or("");
+        let properties = Properties::new(parts.next().ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?);
+        let request_id = properties
+            .get(REQUEST_ID)
+            .ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?;
+        if name.is_empty() {
+            return Err(AZ_ERROR_IOT_TOPIC_NO_MATCH);
+        }
+        log::write(Classification::HubMessage, topic);
+        Ok(MethodRequest { name, request_id })
+    }
+
+    // Topic: $iothub/twin/GET/?$rid=<request_id>
+    pub fn twin_document_get_publish_topic(request_id: &str) -> Result<String<U128>, &'static str> {
+        let mut topic: String<U128> = String::new();
+        if topic.push_str(TWIN_GET_TOPIC).is_err() || topic.push_str(request_id).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        log::write(Classification::HubRequest, &topic);
+        Ok(topic)
+    }
+
+    // Topic: $iothub/twin/PATCH/properties/reported/?$rid=<request_id>
+    pub fn twin_patch_publish_topic(request_id: &str) -> Result<String<U128>, &'static str> {
+        let mut topic: String<U128> = String::new();
+        if topic.push_str(TWIN_PATCH_REPORTED_TOPIC).is_err() || topic.push_str(request_id).is_err()
+        {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        log::write(Classification::HubRequest, &topic);
+        Ok(topic)
+    }
+
+    pub fn twin_parse_received_topic(topic: &str) -> Result<TwinResponse<'_>, &'static str> {
+        if let Some(rest) = topic.strip_prefix(TWIN_DESIRED_PREFIX) {
+            log::write(Classification::HubMessage, topic);
+            let properties = Properties::new(rest.strip_prefix('?').unwrap_or(rest));
+            return Ok(TwinResponse {
+                response_type: TwinResponseType::DesiredProperties,
+                status: 200,
+                request_id: None,
+                version: properties.get(VERSION),
+            });
+        }
+        let rest = topic
+            .strip_prefix(TWIN_RESPONSE_PREFIX)
+            .ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?;
+        let mut parts = rest.splitn(2, "/?");
+        let status: u16 = parts
+            .next()
+            .and_then(|status| status.parse().ok())
+            .ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?;
+        let properties = Properties::new(parts.next().unwrap_or(""));
+        log::write(Classification::HubMessage, topic);
+        let response_type = if status == 204 {
+            TwinResponseType::ReportedProperties
+        } else {
+            TwinResponseType::Get
+        };
+        Ok(TwinResponse {
+            response_type,
+            status,
+            request_id: properties.get(REQUEST_ID),
+            version: properties.get(VERSION),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests_hub_client {
+    use super::*;
+
+    #[test]
+    fn client_id_includes_module() {
+        let device = Client::new("hub.azure-devices.net", "dev1", None).unwrap();
+        assert_eq!(device.get_client_id().unwrap().as_str(), "dev1");
+        let module = Client::new(
+            "hub.azure-devices.net",
+            "dev1",
+            Some(ClientOptions {
+                module_id: Some(ModuleId::new("mod1").unwrap()),
+                user_agent: "",
+            }),
+        )
+        .unwrap();
+        assert_eq!(module.get_client_id().unwrap().as_str(), "dev1/mod1");
+    }
+
+    #[test]
+    fn websocket_endpoint_is_derived_from_hostname() {
+        let client = Client::new("hub.azure-devices.net", "dev1", None).unwrap();
+        let endpoint = client.endpoint().unwrap();
+        assert_eq!(endpoint.port, 8883);
+        assert_eq!(
+            endpoint.websocket().uri::<U128>().unwrap(),
+            "wss://hub.azure-devices.net:443/$iothub/websocket"
+        );
+    }
+
+    #[test]
+    fn invalid_device_ids_are_rejected() {
+        assert_eq!(
+            Client::new("hub.azure-devices.net", "dev/1", None).err(),
+            Some(crate::identifiers::AZ_ERROR_INVALID_DEVICE_ID)
+        );
+    }
+
+    #[test]
+    fn user_name_is_built() {
+        let client = Client::new(
+            "hub.azure-devices.net",
+            "dev1",
+            Some(ClientOptions {
+                module_id: None,
+                user_agent: "rust/1.0",
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            client.get_user_name().unwrap().as_str(),
+            "hub.azure-devices.net/dev1/?api-version=2018-06-30&DeviceClientType=rust/1.0"
+        );
+    }
+
+    #[test]
+    fn telemetry_topic_includes_properties() {
+        let client = Client::new("hub.azure-devices.net", "dev1", None).unwrap();
+        assert_eq!(
+            client.telemetry_publish_topic(None).unwrap().as_str(),
+            "devices/dev1/messages/events/"
+        );
+        let properties = Properties::new("a=1&b=2");
+        assert_eq!(
+            client
+                .telemetry_publish_topic(Some(&properties))
+                .unwrap()
+                .as_str(),
+            "devices/dev1/messages/events/a=1&b=2"
+        );
+    }
+
+    #[test]
+    fn c2d_topic_is_parsed() {
+        let client = Client::new("hub.azure-devices.net", "dev1", None).unwrap();
+        assert_eq!(
+            client.c2d_subscribe_topic().unwrap().as_str(),
+            "devices/dev1/messages/devicebound/#"
+        );
+        let request = client
+            .c2d_parse_received_topic("devices/dev1/messages/devicebound/%24.mid=1&k=v")
+            .unwrap();
+        assert_eq!(request.properties.get("k"), Some("v"));
+        assert!(client
+            .c2d_parse_received_topic("devices/dev2/messages/devicebound/")
+            .is_err());
+    }
+
+    #[test]
+    fn method_topic_is_parsed() {
+        let request =
+            Client::methods_parse_received_topic("$iothub/methods/POST/reboot/?$rid=7").unwrap();
+        assert_eq!(
+            request,
+            MethodRequest {
+                name: "reboot",
+                request_id: "7"
+            }
+        );
+        assert!(Client::methods_parse_received_topic("$iothub/methods/POST/reboot").is_err());
+        assert_eq!(
+            Client::methods_response_publish_topic("7", 200)
+                .unwrap()
+                .as_str(),
+            "$iothub/methods/res/200/?$rid=7"
+        );
+    }
+
+    #[test]
+    fn twin_topics_are_parsed() {
+        let get = Client::twin_parse_received_topic("$iothub/twin/res/200/?$rid=1").unwrap();
+        assert_eq!(get.response_type, TwinResponseType::Get);
+        assert_eq!(get.request_id, Some("1"));
+
+        let reported =
+            Client::twin_parse_received_topic("$iothub/twin/res/204/?$rid=2&$version=5").unwrap();
+        assert_eq!(reported.response_type, TwinResponseType::ReportedProperties);
+        assert_eq!(reported.version, Some("5"));
+
+        let desired =
+            Client::twin_parse_received_topic("$iothub/twin/PATCH/properties/desired/?$version=3")
+                .unwrap();
+        assert_eq!(desired.response_type, TwinResponseType::DesiredProperties);
+        assert_eq!(desired.version, Some("3"));
+
+        assert!(Client::twin_parse_received_topic("$iothub/twin/res/abc/?$rid=1").is_err());
+    }
+}
@@ -0,0 +1,11 @@
+pub mod client;
+pub mod properties;
+pub mod sas;
+
+pub const SERVICE_VERSION: &str = "2018-06-30";
+pub const METHODS_SUBSCRIBE_TOPIC: &str = "$iothub/methods/POST/#";
+pub const METHODS_RESPONSE_TOPIC: &str = "$iothub/methods/res/";
+pub const TWIN_RESPONSE_SUBSCRIBE_TOPIC: &str = "$iothub/twin/res/#";
+pub const TWIN_PATCH_SUBSCRIBE_TOPIC: &str = "$iothub/twin/PATCH/properties/desired/#";
+pub const TWIN_GET_TOPIC: &str = "$iothub/twin/GET/?$rid=";
+pub const TWIN_PATCH_REPORTED_TOPIC: &str = "$iothub/twin/PATCH/properties/reported/?$rid=";
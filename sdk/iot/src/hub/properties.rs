@@ -0,0 +1,129 @@
+use heapless::{ArrayLength, String};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use crate::provisioning::percent_encode;
+
+const PROPERTY_SEPARATOR: char = '&';
+const VALUE_SEPARATOR: char = '=';
+
+// A message property bag as it appears in a topic: <name>=<value>&<name>=<value>
+// Names and values are url-encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Properties<'a> {
+    raw: &'a str,
+}
+
+impl<'a> Properties<'a> {
+    pub fn new(raw: &'a str) -> Properties<'a> {
+        Properties { raw }
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.raw
+    }
+
+    // Yields the (still url-encoded) name and value of each property
+    pub fn iter(&self) -> impl Iterator<Item = (&'a str, &'a str)> {
+        self.raw
+            .split(PROPERTY_SEPARATOR)
+            .filter(|pair| !pair.is_empty())
+            .map(|pair| {
+                let mut parts = pair.splitn(2, VALUE_SEPARATOR);
+                (parts.next().unwrap_or(""), parts.next().unwrap_or(""))
+            })
+    }
+
+    pub fn get(&self, name: &str) -> Option<&'a str> {
+        self.iter()
+            .find(|(property, _)| *property == name)
+            .map(|(_, value)| value)
+    }
+}
+
+// Builds a url-encoded property bag for outgoing messages.
+pub struct PropertiesBuilder<B>
+where
+    B: ArrayLength<u8>,
+{
+    value: String<B>,
+}
+
+impl<B> PropertiesBuilder<B>
+where
+    B: ArrayLength<u8>,
+{
+    pub fn new() -> PropertiesBuilder<B> {
+        PropertiesBuilder {
+            value: String::new(),
+        }
+    }
+
+    pub fn append(&mut self, name: &str, value: &str) -> Result<(), &'static str> {
+        if !self.value.is_empty() && self.value.push(PROPERTY_SEPARATOR).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        percent_encode::encode_into(&mut self.value, name)?;
+        if self.value.push(VALUE_SEPARATOR).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        percent_encode::encode_into(&mut self.value, value)
+    }
+
+    pub fn as_properties(&self) -> Properties<'_> {
+        Properties::new(self.value.as_str())
+    }
+}
+
+impl<B> Default for PropertiesBuilder<B>
+where
+    B: ArrayLength<u8>,
+{
+    fn default() -> PropertiesBuilder<B> {
+        PropertiesBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests_properties {
+    use super::*;
+    use heapless::consts::{U4, U64};
+    #[test]
+    fn properties_are_iterated_in_order() {
+        let properties = Properties::new("a=1&b=two&c=");
+        let mut iter = properties.iter();
+        assert_eq!(iter.next(), Some(("a", "1")));
+        assert_eq!(iter.next(), Some(("b", "two")));
+        assert_eq!(iter.next(), Some(("c", "")));
+        assert_eq!(iter.next(), None);
+    }
+
+    #[test]
+    fn properties_can_be_found_by_name() {
+        let properties = Properties::new("$rid=1&$version=4");
+        assert_eq!(properties.get("$version"), Some("4"));
+        assert_eq!(properties.get("missing"), None);
+    }
+
+    #[test]
+    fn empty_bag_has_no_properties() {
+        assert_eq!(Properties::new("").iter().count(), 0);
+    }
+
+    #[test]
+    fn builder_encodes_names_and_values() {
+        let mut builder: PropertiesBuilder<U64> = PropertiesBuilder::new();
+        builder.append("key", "value").unwrap();
+        builder.append("content type", "a/b").unwrap();
+        assert_eq!(
+            builder.as_properties().as_str(),
+            "key=value&content%20type=a%2Fb"
+        );
+    }
+
+    #[test]
+    fn builder_fails_when_full() {
+        let mut builder: PropertiesBuilder<U4> = PropertiesBuilder::new();
+        assert!(builder.append("key", "value").is_err());
+    }
+}
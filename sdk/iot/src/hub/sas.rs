@@ -0,0 +1,88 @@
+use heapless::consts::{U128, U256};
+use heapless::String;
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use crate::provisioning::percent_encode;
+use crate::provisioning::sas::get_sas_token;
+use crate::provisioning::signer::{SasSigner, SymmetricKeySigner};
+
+const DEVICES: &str = "/devices/";
+const MODULES: &str = "/modules/";
+
+// "SharedAccessSignature sr=<url-encoded(resource-string)>&sig=<signature>&se=<expiration-time>[&skn=<key-name>]"
+//
+// Where:
+// resource-string: <hub_hostname>/devices/<device_id>[/modules/<module_id>]
+pub fn get_password(
+    client: &super::client::Client<'_>,
+    sas_key: &str,
+    token_expiration_epoch_time: u64,
+    key_name: Option<&str>,
+) -> Result<String<U256>, &'static str> {
+    get_password_with_signer(
+        client,
+        &SymmetricKeySigner::new(sas_key)?,
+        token_expiration_epoch_time,
+        key_name,
+    )
+}
+
+// Same as get_password with the signature computed by the signer.
+pub fn get_password_with_signer<S>(
+    client: &super::client::Client<'_>,
+    signer: &S,
+    token_expiration_epoch_time: u64,
+    key_name: Option<&str>,
+) -> Result<String<U256>, &'static str>
+where
+    S: SasSigner + ?Sized,
+{
+    let mut resource: String<U128> = String::new();
+    if resource.push_str(client.hub_hostname).is_err()
+        || resource.push_str(DEVICES).is_err()
+        || resource.push_str(client.device_id.as_str()).is_err()
+    {
+        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    if let Some(module_id) = client.options.module_id {
+        if resource.push_str(MODULES).is_err() || resource.push_str(module_id.as_str()).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+    }
+    let mut encoded_resource: String<U256> = String::new();
+    percent_encode::encode_into(&mut encoded_resource, resource.as_str())?;
+    get_sas_token(
+        encoded_resource.as_str(),
+        signer,
+        token_expiration_epoch_time,
+        key_name,
+    )
+}
+
+#[cfg(test)]
+mod tests_hub_sas {
+    use super::*;
+    use crate::hub::client::Client;
+    use crate::provisioning::sas::SasToken;
+
+    #[test]
+    fn password_signs_device_resource() {
+        let client = Client::new("hub.azure-devices.net", "dev1", None).unwrap();
+        let password = get_password(
+            &client,
+            "dGVzdGtleXRlc3RrZXl0ZXN0a2V5dGVzdGtleQ==",
+            1_600_000_000,
+            None,
+        )
+        .unwrap();
+        let token = SasToken::parse(password.as_str()).unwrap();
+        assert_eq!(token.resource, "hub.azure-devices.net%2Fdevices%2Fdev1");
+        assert_eq!(token.expiration_epoch_time, 1_600_000_000);
+        assert_eq!(token.key_name, None);
+        assert_eq!(
+            token.verify("dGVzdGtleXRlc3RrZXl0ZXN0a2V5dGVzdGtleQ=="),
+            Ok(true)
+        );
+    }
+}
@@ -0,0 +1,144 @@
+// Identifiers checked against the service rules when the client is configured,
+// rather than failing later as a buffer overflow or a 400 from the service.
+use core::convert::TryFrom;
+use core::fmt;
+
+pub const AZ_ERROR_INVALID_ID_SCOPE: &str = "The ID scope must be 1 to 64 alphanumeric characters.";
+pub const AZ_ERROR_INVALID_REGISTRATION_ID: &str =
+    "The registration ID must be 1 to 128 lowercase alphanumeric characters or '-', '.', '_', ':', ending in an alphanumeric character or '-'.";
+pub const AZ_ERROR_INVALID_DEVICE_ID: &str =
+    "The device ID must be 1 to 128 ASCII alphanumeric characters or one of -.%_*?!(),:=@$'.";
+pub const AZ_ERROR_INVALID_MODULE_ID: &str =
+    "The module ID must be 1 to 128 ASCII alphanumeric characters or one of -.%_*?!(),:=@$'.";
+
+// Scopes are 0ne followed by eight hex digits today; the limit leaves room.
+const MAX_ID_SCOPE_LENGTH: usize = 64;
+const MAX_ID_LENGTH: usize = 128;
+const REGISTRATION_ID_SYMBOLS: &str = "-._:";
+const DEVICE_ID_SYMBOLS: &str = "-.%_*?!(),:=@$'";
+
+macro_rules! identifier {
+    ($name:ident, $is_valid:ident, $error:ident) => {
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub struct $name<'a>(&'a str);
+
+        impl<'a> $name<'a> {
+            pub fn new(value: &'a str) -> Result<Self, &'static str> {
+                if $is_valid(value) {
+                    Ok($name(value))
+                } else {
+                    Err($error)
+                }
+            }
+
+            pub fn as_str(&self) -> &'a str {
+                self.0
+            }
+        }
+
+        impl<'a> TryFrom<&'a str> for $name<'a> {
+            type Error = &'static str;
+
+            fn try_from(value: &'a str) -> Result<Self, Self::Error> {
+                $name::new(value)
+            }
+        }
+
+        impl<'a> AsRef<str> for $name<'a> {
+            fn as_ref(&self) -> &str {
+                self.0
+            }
+        }
+
+        impl<'a> fmt::Display for $name<'a> {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.0)
+            }
+        }
+    };
+}
+
+// The DPS ID scope, e.g. 0ne00000A0A.
+identifier!(IdScope, is_valid_id_scope, AZ_ERROR_INVALID_ID_SCOPE);
+// Identifies the device to DPS; also the device ID it is assigned by default.
+identifier!(
+    RegistrationId,
+    is_valid_registration_id,
+    AZ_ERROR_INVALID_REGISTRATION_ID
+);
+identifier!(DeviceId, is_valid_device_id, AZ_ERROR_INVALID_DEVICE_ID);
+identifier!(ModuleId, is_valid_device_id, AZ_ERROR_INVALID_MODULE_ID);
+
+fn is_valid_id_scope(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_ID_SCOPE_LENGTH
+        && value.chars().all(|c| c.is_ascii_alphanumeric())
+}
+
+fn is_valid_registration_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_ID_LENGTH
+        && value.chars().all(|c| {
+            c.is_ascii_lowercase() || c.is_ascii_digit() || REGISTRATION_ID_SYMBOLS.contains(c)
+        })
+        && value.ends_with(|c: char| c.is_ascii_alphanumeric() || c == '-')
+}
+
+fn is_valid_device_id(value: &str) -> bool {
+    !value.is_empty()
+        && value.len() <= MAX_ID_LENGTH
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || DEVICE_ID_SYMBOLS.contains(c))
+}
+
+#[cfg(test)]
+mod tests_identifiers {
+    use super::*;
+
+    #[test]
+    fn id_scopes_are_alphanumeric() {
+        assert_eq!(IdScope::new("0ne00000A0A").unwrap().as_str(), "0ne00000A0A");
+        assert_eq!(IdScope::new(""), Err(AZ_ERROR_INVALID_ID_SCOPE));
+        assert_eq!(
+            IdScope::new("global.azure-devices-provisioning.net"),
+            Err(AZ_ERROR_INVALID_ID_SCOPE)
+        );
+    }
+
+    #[test]
+    fn registration_ids_follow_the_dps_rules() {
+        let mut longest: heapless::String<heapless::consts::U256> = heapless::String::new();
+        while longest.len() < MAX_ID_LENGTH {
+            longest.push('a').unwrap();
+        }
+        for valid in &[
+            "sn-007-888-abc-mac-a1-b2-c3-d4-e5-f6",
+            "a.b_c:d",
+            "device-",
+            longest.as_str(),
+        ] {
+            assert!(RegistrationId::new(valid).is_ok(), "{}", valid);
+        }
+        for invalid in &["", "Device1", "device 1", "device/1", "device.", "device_"] {
+            assert_eq!(
+                RegistrationId::new(invalid),
+                Err(AZ_ERROR_INVALID_REGISTRATION_ID),
+                "{}",
+                invalid
+            );
+        }
+        longest.push('a').unwrap();
+        assert!(RegistrationId::try_from(longest.as_str()).is_err());
+    }
+
+    #[test]
+    fn device_and_module_ids_follow_the_hub_rules() {
+        assert!(DeviceId::new("Thermostat-1@building(2)").is_ok());
+        assert!(ModuleId::new("$edgeAgent").is_ok());
+        assert_eq!(DeviceId::new("dev/1"), Err(AZ_ERROR_INVALID_DEVICE_ID));
+        assert_eq!(DeviceId::new("dev#1"), Err(AZ_ERROR_INVALID_DEVICE_ID));
+        assert_eq!(ModuleId::new(""), Err(AZ_ERROR_INVALID_MODULE_ID));
+        assert_eq!(ModuleId::new("mödule"), Err(AZ_ERROR_INVALID_MODULE_ID));
+    }
+}
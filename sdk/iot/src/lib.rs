@@ -16,7 +16,23 @@
 #![allow(dead_code)]
 #![allow(clippy::large_enum_variant)]
 
+#[cfg(feature = "alloc")]
+extern crate alloc;
+#[cfg(feature = "std")]
+extern crate std;
+
+pub mod endpoint;
+pub mod hub;
+pub mod identifiers;
+pub mod mqtt;
 pub mod provisioning;
+pub mod statuscode;
+#[cfg(feature = "test-support")]
+pub mod test_support;
+#[cfg(any(feature = "rustls", feature = "native-tls"))]
+pub mod tls;
+#[cfg(feature = "websocket")]
+pub mod websocket;
 
 #[cfg(test)]
 mod tests {
@@ -0,0 +1,379 @@
+use heapless::consts::U8;
+use heapless::{ArrayLength, String, Vec};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use super::{
+    QoS, AZ_ERROR_MQTT_MALFORMED_PACKET, AZ_ERROR_MQTT_UNSUPPORTED_PACKET, CONNACK, PINGRESP,
+    PUBACK, PUBLISH, SUBACK,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectReturnCode {
+    Accepted = 0,
+    UnacceptableProtocolVersion = 1,
+    IdentifierRejected = 2,
+    ServerUnavailable = 3,
+    BadUserNameOrPassword = 4,
+    NotAuthorized = 5,
+}
+
+impl ConnectReturnCode {
+    fn from_u8(value: u8) -> Result<ConnectReturnCode, &'static str> {
+        match value {
+            0 => Ok(ConnectReturnCode::Accepted),
+            1 => Ok(ConnectReturnCode::UnacceptableProtocolVersion),
+            2 => Ok(ConnectReturnCode::IdentifierRejected),
+            3 => Ok(ConnectReturnCode::ServerUnavailable),
+            4 => Ok(ConnectReturnCode::BadUserNameOrPassword),
+            5 => Ok(ConnectReturnCode::NotAuthorized),
+            _ => Err(AZ_ERROR_MQTT_MALFORMED_PACKET),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Connack {
+    pub session_present: bool,
+    pub return_code: ConnectReturnCode,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Suback {
+    pub packet_id: u16,
+    // One entry per requested topic filter: the granted QoS, or 0x80 on failure.
+    pub return_codes: Vec<u8, U8>,
+}
+
+impl Suback {
+    pub fn is_success(&self) -> bool {
+        self.return_codes.iter().all(|code| *code != 0x80)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct IncomingPublish<T, P>
+where
+    T: ArrayLength<u8>,
+    P: ArrayLength<u8>,
+{
+    pub topic: String<T>,
+    pub payload: Vec<u8, P>,
+    pub qos: QoS,
+    // Zero for QoS::AtMostOnce
+    pub packet_id: u16,
+    pub retain: bool,
+    pub dup: bool,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Packet<T, P>
+where
+    T: ArrayLength<u8>,
+    P: ArrayLength<u8>,
+{
+    Connack(Connack),
+    Suback(Suback),
+    Publish(IncomingPublish<T, P>),
+    Puback(u16),
+    Pingresp,
+}
+
+// A decoded packet and the number of bytes it consumed from the buffer
+pub type Decoded<T, P> = Option<(Packet<T, P>, usize)>;
+
+// Returns the total length of the packet at the start of the buffer, or None
+// if not enough bytes have been received to know it yet.
+pub fn packet_length(buffer: &[u8]) -> Result<Option<usize>, &'static str> {
+    match parse_fixed_header(buffer)? {
+        Some((_, header_length, remaining_length)) => Ok(Some(header_length + remaining_length)),
+        None => Ok(None),
+    }
+}
+
+// Decodes the packet at the start of the buffer. Returns the packet and the number
+// of bytes consumed, or None if the packet has not been completely received.
+pub fn decode<T, P>(buffer: &[u8]) -> Result<Decoded<T, P>, &'static str>
+where
+    T: ArrayLength<u8>,
+    P: ArrayLength<u8>,
+{
+    let (header, header_length, remaining_length) = match parse_fixed_header(buffer)? {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    let total_length = header_length + remaining_length;
+    if buffer.len() < total_length {
+        return Ok(None);
+    }
+    let body = &buffer[header_length..total_length];
+    let packet = match header >> 4 {
+        CONNACK => Packet::Connack(decode_connack(body)?),
+        SUBACK => Packet::Suback(decode_suback(body)?),
+        PUBLISH => Packet::Publish(decode_publish(header, body)?),
+        PUBACK => Packet::Puback(decode_packet_id_only(body)?),
+        PINGRESP => {
+            if !body.is_empty() {
+                return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+            }
+            Packet::Pingresp
+        }
+        _ => return Err(AZ_ERROR_MQTT_UNSUPPORTED_PACKET),
+    };
+    Ok(Some((packet, total_length)))
+}
+
+pub(super) fn parse_fixed_header(
+    buffer: &[u8],
+) -> Result<Option<(u8, usize, usize)>, &'static str> {
+    if buffer.is_empty() {
+        return Ok(None);
+    }
+    let mut remaining_length = 0;
+    let mut multiplier = 1;
+    for (index, byte) in buffer.iter().skip(1).take(4).enumerate() {
+        remaining_length += (*byte & 0x7F) as usize * multiplier;
+        if *byte & 0x80 == 0 {
+            return Ok(Some((buffer[0], index + 2, remaining_length)));
+        }
+        multiplier *= 128;
+    }
+    if buffer.len() > 4 {
+        // the fifth byte still had the continuation bit set
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    Ok(None)
+}
+
+pub(super) fn read_u16(body: &[u8], offset: usize) -> Result<u16, &'static str> {
+    if body.len() < offset + 2 {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    Ok(u16::from_be_bytes([body[offset], body[offset + 1]]))
+}
+
+pub(super) fn read_str(body: &[u8], offset: usize) -> Result<(&str, usize), &'static str> {
+    let length = read_u16(body, offset)? as usize;
+    let start = offset + 2;
+    if body.len() < start + length {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    match core::str::from_utf8(&body[start..start + length]) {
+        Ok(value) => Ok((value, start + length)),
+        Err(_) => Err(AZ_ERROR_MQTT_MALFORMED_PACKET),
+    }
+}
+
+fn decode_connack(body: &[u8]) -> Result<Connack, &'static str> {
+    if body.len() != 2 || body[0] & 0xFE != 0 {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    Ok(Connack {
+        session_present: body[0] == 1,
+        return_code: ConnectReturnCode::from_u8(body[1])?,
+    })
+}
+
+fn decode_suback(body: &[u8]) -> Result<Suback, &'static str> {
+    let packet_id = read_u16(body, 0)?;
+    if body.len() < 3 {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    let mut return_codes = Vec::new();
+    if return_codes.extend_from_slice(&body[2..]).is_err() {
+        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    Ok(Suback {
+        packet_id,
+        return_codes,
+    })
+}
+
+pub(super) fn decode_publish<T, P>(
+    header: u8,
+    body: &[u8],
+) -> Result<IncomingPublish<T, P>, &'static str>
+where
+    T: ArrayLength<u8>,
+    P: ArrayLength<u8>,
+{
+    let qos = QoS::from_u8((header >> 1) & 0x03)?;
+    let (topic_name, mut offset) = read_str(body, 0)?;
+    let mut packet_id = 0;
+    if qos == QoS::AtLeastOnce {
+        packet_id = read_u16(body, offset)?;
+        // 0 is not a valid packet identifier [MQTT-2.3.1-1]
+        if packet_id == 0 {
+            return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+        }
+        offset += 2;
+    }
+    let mut topic = String::new();
+    if topic.push_str(topic_name).is_err() {
+        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    let rest = body.get(offset..).ok_or(AZ_ERROR_MQTT_MALFORMED_PACKET)?;
+    let mut payload = Vec::new();
+    if payload.extend_from_slice(rest).is_err() {
+        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    Ok(IncomingPublish {
+        topic,
+        payload,
+        qos,
+        packet_id,
+        retain: header & 0x01 != 0,
+        dup: header & 0x08 != 0,
+    })
+}
+
+pub(super) fn decode_packet_id_only(body: &[u8]) -> Result<u16, &'static str> {
+    if body.len() != 2 {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    read_u16(body, 0)
+}
+
+#[cfg(test)]
+mod tests_packet_length {
+    use super::*;
+    #[test]
+    fn empty_buffer_is_incomplete() {
+        assert_eq!(packet_length(&[]).unwrap(), None);
+    }
+
+    #[test]
+    fn partial_remaining_length_is_incomplete() {
+        assert_eq!(packet_length(&[0x30, 0xCB]).unwrap(), None);
+    }
+
+    #[test]
+    fn length_includes_fixed_header() {
+        assert_eq!(packet_length(&[0x20, 2]).unwrap(), Some(4));
+        assert_eq!(packet_length(&[0x30, 0xCB, 0x01]).unwrap(), Some(206));
+    }
+
+    #[test]
+    fn more_than_four_length_bytes_is_malformed() {
+        assert!(packet_length(&[0x30, 0xFF, 0xFF, 0xFF, 0xFF, 0x01]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod tests_decode {
+    use super::*;
+    use crate::mqtt::{encode_publish, Publish};
+    use heapless::consts::{U128, U32, U4, U64};
+
+    type TestPacket = Packet<U64, U128>;
+
+    #[test]
+    fn connack_is_decoded() {
+        let (packet, consumed) = decode::<U64, U128>(&[0x20, 2, 0, 0]).unwrap().unwrap();
+        assert_eq!(consumed, 4);
+        assert_eq!(
+            packet,
+            TestPacket::Connack(Connack {
+                session_present: false,
+                return_code: ConnectReturnCode::Accepted
+            })
+        );
+    }
+
+    #[test]
+    fn connack_rejection_is_decoded() {
+        let (packet, _) = decode::<U64, U128>(&[0x20, 2, 1, 5]).unwrap().unwrap();
+        assert_eq!(
+            packet,
+            TestPacket::Connack(Connack {
+                session_present: true,
+                return_code: ConnectReturnCode::NotAuthorized
+            })
+        );
+    }
+
+    #[test]
+    fn suback_is_decoded() {
+        let (packet, _) = decode::<U64, U128>(&[0x90, 4, 0, 9, 1, 0x80])
+            .unwrap()
+            .unwrap();
+        match packet {
+            Packet::Suback(suback) => {
+                assert_eq!(suback.packet_id, 9);
+                assert_eq!(&suback.return_codes[..], &[1, 0x80]);
+                assert!(!suback.is_success());
+            }
+            _ => panic!("expected suback"),
+        }
+    }
+
+    #[test]
+    fn puback_is_decoded() {
+        let (packet, _) = decode::<U64, U128>(&[0x40, 2, 1, 2]).unwrap().unwrap();
+        assert_eq!(packet, TestPacket::Puback(0x0102));
+    }
+
+    #[test]
+    fn pingresp_is_decoded() {
+        let (packet, _) = decode::<U64, U128>(&[0xD0, 0]).unwrap().unwrap();
+        assert_eq!(packet, TestPacket::Pingresp);
+    }
+
+    #[test]
+    fn publish_round_trips_through_the_encoder() {
+        let topic = "$dps/registrations/res/202/?$rid=1&retry-after=3";
+        let payload = b"{\"operationId\":\"4.abc\",\"status\":\"assigning\"}";
+        let publish = Publish::new(topic, payload, QoS::AtLeastOnce, 42);
+        let encoded: Vec<u8, U128> = encode_publish(&publish).unwrap();
+
+        let (packet, consumed) = decode::<U64, U128>(&encoded).unwrap().unwrap();
+        assert_eq!(consumed, encoded.len());
+        match packet {
+            Packet::Publish(incoming) => {
+                assert_eq!(incoming.topic.as_str(), topic);
+                assert_eq!(&incoming.payload[..], &payload[..]);
+                assert_eq!(incoming.qos, QoS::AtLeastOnce);
+                assert_eq!(incoming.packet_id, 42);
+            }
+            _ => panic!("expected publish"),
+        }
+    }
+
+    #[test]
+    fn only_the_first_packet_is_consumed() {
+        let (packet, consumed) = decode::<U64, U128>(&[0x40, 2, 0, 1, 0xD0, 0])
+            .unwrap()
+            .unwrap();
+        assert_eq!(packet, TestPacket::Puback(1));
+        assert_eq!(consumed, 4);
+    }
+
+    #[test]
+    fn incomplete_packet_is_none() {
+        assert!(decode::<U64, U128>(&[0x40, 2, 0]).unwrap().is_none());
+    }
+
+    #[test]
+    fn qos_2_publish_is_unsupported() {
+        let result = decode::<U64, U128>(&[0x34, 5, 0, 1, b'a', 0, 1]);
+        assert_eq!(result.unwrap_err(), AZ_ERROR_MQTT_UNSUPPORTED_PACKET);
+    }
+
+    #[test]
+    fn qos_1_publish_without_packet_id_is_malformed() {
+        let result = decode::<U64, U128>(&[0x32, 5, 0, 1, b'a', 0, 0]);
+        assert_eq!(result.unwrap_err(), AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+
+    #[test]
+    fn topic_larger_than_the_buffer_fails() {
+        let result = decode::<U4, U32>(&[0x30, 7, 0, 5, b'a', b'b', b'c', b'd', b'e']);
+        assert_eq!(result.unwrap_err(), AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+
+    #[test]
+    fn client_packets_are_unsupported() {
+        let result = decode::<U64, U128>(&[0xC0, 0]);
+        assert_eq!(result.unwrap_err(), AZ_ERROR_MQTT_UNSUPPORTED_PACKET);
+    }
+}
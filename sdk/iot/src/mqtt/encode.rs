@@ -0,0 +1,322 @@
+use heapless::{ArrayLength, Vec};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use super::{
+    QoS, AZ_ERROR_MQTT_MALFORMED_PACKET, CONNECT, DISCONNECT, MAX_REMAINING_LENGTH, PINGREQ,
+    PROTOCOL_LEVEL, PROTOCOL_NAME, PUBACK, PUBLISH, SUBSCRIBE,
+};
+
+const CONNECT_FLAG_USER_NAME: u8 = 0x80;
+const CONNECT_FLAG_PASSWORD: u8 = 0x40;
+const CONNECT_FLAG_CLEAN_SESSION: u8 = 0x02;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Connect<'a> {
+    pub client_id: &'a str,
+    pub user_name: Option<&'a str>,
+    pub password: Option<&'a [u8]>,
+    pub keep_alive_seconds: u16,
+    pub clean_session: bool,
+}
+
+impl<'a> Connect<'a> {
+    // For DPS: user_name from Client::get_user_name, password from sas::get_password
+    pub fn new(client_id: &'a str, user_name: &'a str, password: &'a str) -> Connect<'a> {
+        Connect {
+            client_id,
+            user_name: Some(user_name),
+            password: Some(password.as_bytes()),
+            keep_alive_seconds: 240,
+            clean_session: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Publish<'a> {
+    pub topic: &'a str,
+    pub payload: &'a [u8],
+    pub qos: QoS,
+    // Required when qos is QoS::AtLeastOnce
+    pub packet_id: u16,
+    pub retain: bool,
+    pub dup: bool,
+}
+
+impl<'a> Publish<'a> {
+    pub fn new(topic: &'a str, payload: &'a [u8], qos: QoS, packet_id: u16) -> Publish<'a> {
+        Publish {
+            topic,
+            payload,
+            qos,
+            packet_id,
+            retain: false,
+            dup: false,
+        }
+    }
+}
+
+pub fn encode_connect<B>(connect: &Connect<'_>) -> Result<Vec<u8, B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    let mut flags = 0;
+    let mut remaining_length = 2 + PROTOCOL_NAME.len() + 1 + 1 + 2 + 2 + connect.client_id.len();
+    if let Some(user_name) = connect.user_name {
+        flags |= CONNECT_FLAG_USER_NAME;
+        remaining_length += 2 + user_name.len();
+    }
+    if let Some(password) = connect.password {
+        flags |= CONNECT_FLAG_PASSWORD;
+        remaining_length += 2 + password.len();
+    }
+    if connect.clean_session {
+        flags |= CONNECT_FLAG_CLEAN_SESSION;
+    }
+
+    let mut buffer: Vec<u8, B> = Vec::new();
+    push_fixed_header(&mut buffer, CONNECT << 4, remaining_length)?;
+    push_bytes_with_length(&mut buffer, PROTOCOL_NAME.as_bytes())?;
+    push_bytes(&mut buffer, &[PROTOCOL_LEVEL, flags])?;
+    push_u16(&mut buffer, connect.keep_alive_seconds)?;
+    push_bytes_with_length(&mut buffer, connect.client_id.as_bytes())?;
+    if let Some(user_name) = connect.user_name {
+        push_bytes_with_length(&mut buffer, user_name.as_bytes())?;
+    }
+    if let Some(password) = connect.password {
+        push_bytes_with_length(&mut buffer, password)?;
+    }
+    Ok(buffer)
+}
+
+pub fn encode_subscribe<B>(
+    packet_id: u16,
+    topics: &[(&str, QoS)],
+) -> Result<Vec<u8, B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    if topics.is_empty() {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    let remaining_length = topics
+        .iter()
+        .fold(2, |length, (topic, _)| length + 2 + topic.len() + 1);
+
+    let mut buffer: Vec<u8, B> = Vec::new();
+    // Bits 3,2,1 and 0 of the fixed header of the SUBSCRIBE packet are reserved and MUST be 0,0,1 and 0.
+    push_fixed_header(&mut buffer, SUBSCRIBE << 4 | 0x02, remaining_length)?;
+    push_u16(&mut buffer, packet_id)?;
+    for (topic, qos) in topics {
+        push_bytes_with_length(&mut buffer, topic.as_bytes())?;
+        push_bytes(&mut buffer, &[*qos as u8])?;
+    }
+    Ok(buffer)
+}
+
+pub fn encode_publish<B>(publish: &Publish<'_>) -> Result<Vec<u8, B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    let mut header = PUBLISH << 4 | (publish.qos as u8) << 1;
+    if publish.dup {
+        header |= 0x08;
+    }
+    if publish.retain {
+        header |= 0x01;
+    }
+    let mut remaining_length = 2 + publish.topic.len() + publish.payload.len();
+    if publish.qos == QoS::AtLeastOnce {
+        remaining_length += 2;
+    }
+
+    let mut buffer: Vec<u8, B> = Vec::new();
+    push_fixed_header(&mut buffer, header, remaining_length)?;
+    push_bytes_with_length(&mut buffer, publish.topic.as_bytes())?;
+    if publish.qos == QoS::AtLeastOnce {
+        push_u16(&mut buffer, publish.packet_id)?;
+    }
+    push_bytes(&mut buffer, publish.payload)?;
+    Ok(buffer)
+}
+
+pub fn encode_puback(packet_id: u16) -> [u8; 4] {
+    let id = packet_id.to_be_bytes();
+    [PUBACK << 4, 2, id[0], id[1]]
+}
+
+pub fn encode_pingreq() -> [u8; 2] {
+    [PINGREQ << 4, 0]
+}
+
+pub fn encode_disconnect() -> [u8; 2] {
+    [DISCONNECT << 4, 0]
+}
+
+pub(super) fn push_fixed_header<B>(
+    buffer: &mut Vec<u8, B>,
+    header: u8,
+    remaining_length: usize,
+) -> Result<(), &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    if remaining_length > MAX_REMAINING_LENGTH {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    push_bytes(buffer, &[header])?;
+    let mut length = remaining_length;
+    loop {
+        let mut encoded = (length % 128) as u8;
+        length /= 128;
+        if length > 0 {
+            encoded |= 0x80;
+        }
+        push_bytes(buffer, &[encoded])?;
+        if length == 0 {
+            return Ok(());
+        }
+    }
+}
+
+pub(super) fn push_u16<B>(buffer: &mut Vec<u8, B>, value: u16) -> Result<(), &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    push_bytes(buffer, &value.to_be_bytes())
+}
+
+pub(super) fn push_bytes_with_length<B>(
+    buffer: &mut Vec<u8, B>,
+    bytes: &[u8],
+) -> Result<(), &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    if bytes.len() > u16::MAX as usize {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    push_u16(buffer, bytes.len() as u16)?;
+    push_bytes(buffer, bytes)
+}
+
+pub(super) fn push_bytes<B>(buffer: &mut Vec<u8, B>, bytes: &[u8]) -> Result<(), &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    if buffer.extend_from_slice(bytes).is_err() {
+        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests_encode_connect {
+    use super::*;
+    use heapless::consts::{U16, U64};
+    #[test]
+    fn connect_with_credentials_is_encoded() {
+        let connect = Connect::new("rid", "user", "pass");
+        let packet: Vec<u8, U64> = encode_connect(&connect).unwrap();
+        let expected: &[u8] = &[
+            0x10, 27, 0, 4, b'M', b'Q', b'T', b'T', 4, 0xC0, 0, 240, 0, 3, b'r', b'i', b'd', 0, 4,
+            b'u', b's', b'e', b'r', 0, 4, b'p', b'a', b's', b's',
+        ];
+        assert_eq!(&packet[..], expected);
+    }
+
+    #[test]
+    fn connect_without_credentials_sets_clean_session() {
+        let connect = Connect {
+            client_id: "a",
+            user_name: None,
+            password: None,
+            keep_alive_seconds: 10,
+            clean_session: true,
+        };
+        let packet: Vec<u8, U64> = encode_connect(&connect).unwrap();
+        let expected: &[u8] = &[
+            0x10, 13, 0, 4, b'M', b'Q', b'T', b'T', 4, 0x02, 0, 10, 0, 1, b'a',
+        ];
+        assert_eq!(&packet[..], expected);
+    }
+
+    #[test]
+    fn connect_fails_when_the_buffer_is_too_small() {
+        let connect = Connect::new("rid", "user", "pass");
+        let packet = encode_connect::<U16>(&connect);
+        assert_eq!(packet.unwrap_err(), AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+}
+
+#[cfg(test)]
+mod tests_encode_subscribe {
+    use super::*;
+    use heapless::consts::U64;
+    #[test]
+    fn subscribe_is_encoded() {
+        let packet: Vec<u8, U64> =
+            encode_subscribe(10, &[("$dps/registrations/res/#", QoS::AtLeastOnce)]).unwrap();
+        assert_eq!(&packet[..5], &[0x82, 29, 0, 10, 0]);
+        assert_eq!(packet[5], 24);
+        assert_eq!(&packet[6..30], b"$dps/registrations/res/#");
+        assert_eq!(packet[30], 1);
+        assert_eq!(packet.len(), 31);
+    }
+
+    #[test]
+    fn subscribe_requires_a_topic() {
+        assert!(encode_subscribe::<U64>(1, &[]).is_err());
+    }
+}
+
+#[cfg(test)]
+mod tests_encode_publish {
+    use super::*;
+    use heapless::consts::{U256, U512};
+    #[test]
+    fn qos_0_publish_has_no_packet_id() {
+        let publish = Publish::new("a/b", b"hi", QoS::AtMostOnce, 7);
+        let packet: Vec<u8, U256> = encode_publish(&publish).unwrap();
+        assert_eq!(&packet[..], &[0x30, 7, 0, 3, b'a', b'/', b'b', b'h', b'i']);
+    }
+
+    #[test]
+    fn qos_1_publish_has_packet_id() {
+        let mut publish = Publish::new("a/b", b"", QoS::AtLeastOnce, 0x0102);
+        publish.dup = true;
+        publish.retain = true;
+        let packet: Vec<u8, U256> = encode_publish(&publish).unwrap();
+        assert_eq!(&packet[..], &[0x3B, 7, 0, 3, b'a', b'/', b'b', 1, 2]);
+    }
+
+    #[test]
+    fn large_payloads_use_multi_byte_remaining_length() {
+        let payload = [b'x'; 200];
+        let publish = Publish::new("t", &payload, QoS::AtMostOnce, 0);
+        let packet: Vec<u8, U512> = encode_publish(&publish).unwrap();
+        // 2 + 1 + 200 = 203 = 0x4B + 1 * 128
+        assert_eq!(&packet[..3], &[0x30, 0xCB, 0x01]);
+        assert_eq!(packet.len(), 206);
+    }
+}
+
+#[cfg(test)]
+mod tests_encode_fixed {
+    use super::*;
+    #[test]
+    fn puback_is_encoded() {
+        assert_eq!(encode_puback(0x1234), [0x40, 2, 0x12, 0x34]);
+    }
+
+    #[test]
+    fn pingreq_is_encoded() {
+        assert_eq!(encode_pingreq(), [0xC0, 0]);
+    }
+
+    #[test]
+    fn disconnect_is_encoded() {
+        assert_eq!(encode_disconnect(), [0xE0, 0]);
+    }
+}
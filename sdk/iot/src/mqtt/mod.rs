@@ -0,0 +1,58 @@
+// Minimal allocation-free MQTT 3.1.1 packet codec.
+// http://docs.oasis-open.org/mqtt/mqtt/v3.1.1/os/mqtt-v3.1.1-os.html
+//
+// Only the packets needed to talk to DPS and IoT Hub are supported.
+// QoS 2 is not supported by either service.
+mod decode;
+mod encode;
+#[cfg(any(test, feature = "test-support"))]
+mod server;
+
+pub use decode::{
+    decode, packet_length, Connack, ConnectReturnCode, Decoded, IncomingPublish, Packet, Suback,
+};
+pub use encode::{
+    encode_connect, encode_disconnect, encode_pingreq, encode_puback, encode_publish,
+    encode_subscribe, Connect, Publish,
+};
+#[cfg(any(test, feature = "test-support"))]
+pub use server::{
+    decode_client_packet, encode_connack, encode_pingresp, encode_suback, ClientPacket,
+};
+
+pub const AZ_ERROR_MQTT_MALFORMED_PACKET: &str = "The MQTT packet is malformed.";
+pub const AZ_ERROR_MQTT_UNSUPPORTED_PACKET: &str = "The MQTT packet type is not supported.";
+
+pub const PROTOCOL_NAME: &str = "MQTT";
+pub const PROTOCOL_LEVEL: u8 = 4;
+
+// Fixed header control packet types (upper nibble of the first byte)
+pub const CONNECT: u8 = 1;
+pub const CONNACK: u8 = 2;
+pub const PUBLISH: u8 = 3;
+pub const PUBACK: u8 = 4;
+pub const SUBSCRIBE: u8 = 8;
+pub const SUBACK: u8 = 9;
+pub const PINGREQ: u8 = 12;
+pub const PINGRESP: u8 = 13;
+pub const DISCONNECT: u8 = 14;
+
+// The remaining length is encoded in at most four bytes.
+pub const MAX_REMAINING_LENGTH: usize = 268_435_455;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QoS {
+    AtMostOnce = 0,
+    AtLeastOnce = 1,
+}
+
+impl QoS {
+    pub fn from_u8(value: u8) -> Result<QoS, &'static str> {
+        match value {
+            0 => Ok(QoS::AtMostOnce),
+            1 => Ok(QoS::AtLeastOnce),
+            2 => Err(AZ_ERROR_MQTT_UNSUPPORTED_PACKET),
+            _ => Err(AZ_ERROR_MQTT_MALFORMED_PACKET),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+// Service side of the codec, used by the local DPS and IoT Hub stand-ins.
+// Client packets are decoded in place and borrow from the receive buffer.
+use heapless::consts::U8;
+use heapless::{ArrayLength, Vec};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use super::decode::{decode_packet_id_only, parse_fixed_header, read_str, read_u16};
+use super::encode::{push_bytes, push_fixed_header, push_u16};
+use super::{
+    Connect, ConnectReturnCode, Publish, QoS, AZ_ERROR_MQTT_MALFORMED_PACKET,
+    AZ_ERROR_MQTT_UNSUPPORTED_PACKET, CONNACK, CONNECT, DISCONNECT, PINGREQ, PINGRESP,
+    PROTOCOL_LEVEL, PROTOCOL_NAME, PUBACK, PUBLISH, SUBACK, SUBSCRIBE,
+};
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ClientPacket<'a> {
+    Connect(Connect<'a>),
+    Subscribe {
+        packet_id: u16,
+        topics: Vec<(&'a str, QoS), U8>,
+    },
+    Publish(Publish<'a>),
+    Puback(u16),
+    Pingreq,
+    Disconnect,
+}
+
+pub fn decode_client_packet(
+    buffer: &[u8],
+) -> Result<Option<(ClientPacket<'_>, usize)>, &'static str> {
+    let (header, header_length, remaining_length) = match parse_fixed_header(buffer)? {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    let total_length = header_length + remaining_length;
+    if buffer.len() < total_length {
+        return Ok(None);
+    }
+    let body = &buffer[header_length..total_length];
+    let packet = match header >> 4 {
+        CONNECT => ClientPacket::Connect(decode_connect(body)?),
+        SUBSCRIBE => decode_subscribe(body)?,
+        PUBLISH => ClientPacket::Publish(decode_publish(header, body)?),
+        PUBACK => ClientPacket::Puback(decode_packet_id_only(body)?),
+        PINGREQ => ClientPacket::Pingreq,
+        DISCONNECT => ClientPacket::Disconnect,
+        _ => return Err(AZ_ERROR_MQTT_UNSUPPORTED_PACKET),
+    };
+    Ok(Some((packet, total_length)))
+}
+
+pub fn encode_connack(session_present: bool, return_code: ConnectReturnCode) -> [u8; 4] {
+    [
+        CONNACK << 4,
+        2,
+        u8::from(session_present),
+        return_code as u8,
+    ]
+}
+
+pub fn encode_suback<B>(packet_id: u16, return_codes: &[u8]) -> Result<Vec<u8, B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    let mut buffer: Vec<u8, B> = Vec::new();
+    push_fixed_header(&mut buffer, SUBACK << 4, 2 + return_codes.len())?;
+    push_u16(&mut buffer, packet_id)?;
+    push_bytes(&mut buffer, return_codes)?;
+    Ok(buffer)
+}
+
+pub fn encode_pingresp() -> [u8; 2] {
+    [PINGRESP << 4, 0]
+}
+
+fn decode_connect(body: &[u8]) -> Result<Connect<'_>, &'static str> {
+    let (protocol_name, offset) = read_str(body, 0)?;
+    if protocol_name != PROTOCOL_NAME || body.len() < offset + 4 {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    if body[offset] != PROTOCOL_LEVEL {
+        return Err(AZ_ERROR_MQTT_UNSUPPORTED_PACKET);
+    }
+    let flags = body[offset + 1];
+    let keep_alive_seconds = read_u16(body, offset + 2)?;
+    let (client_id, mut offset) = read_str(body, offset + 4)?;
+    if flags & 0x04 != 0 {
+        // will topic and message are not used by the services
+        let (_, after_topic) = read_str(body, offset)?;
+        let will_length = read_u16(body, after_topic)? as usize;
+        offset = after_topic + 2 + will_length;
+        if body.len() < offset {
+            return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+        }
+    }
+    let mut user_name = None;
+    if flags & 0x80 != 0 {
+        let (value, next) = read_str(body, offset)?;
+        user_name = Some(value);
+        offset = next;
+    }
+    let mut password = None;
+    if flags & 0x40 != 0 {
+        let length = read_u16(body, offset)? as usize;
+        let start = offset + 2;
+        password = Some(
+            body.get(start..start + length)
+                .ok_or(AZ_ERROR_MQTT_MALFORMED_PACKET)?,
+        );
+    }
+    Ok(Connect {
+        client_id,
+        user_name,
+        password,
+        keep_alive_seconds,
+        clean_session: flags & 0x02 != 0,
+    })
+}
+
+fn decode_subscribe(body: &[u8]) -> Result<ClientPacket<'_>, &'static str> {
+    let packet_id = read_u16(body, 0)?;
+    let mut topics = Vec::new();
+    let mut offset = 2;
+    while offset < body.len() {
+        let (topic, next) = read_str(body, offset)?;
+        if body.len() <= next {
+            return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+        }
+        if topics.push((topic, QoS::from_u8(body[next])?)).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        offset = next + 1;
+    }
+    if topics.is_empty() {
+        return Err(AZ_ERROR_MQTT_MALFORMED_PACKET);
+    }
+    Ok(ClientPacket::Subscribe { packet_id, topics })
+}
+
+fn decode_publish(header: u8, body: &[u8]) -> Result<Publish<'_>, &'static str> {
+    let qos = QoS::from_u8((header >> 1) & 0x03)?;
+    let (topic, mut offset) = read_str(body, 0)?;
+    let mut packet_id = 0;
+    if qos == QoS::AtLeastOnce {
+        packet_id = read_u16(body, offset)?;
+        offset += 2;
+    }
+    Ok(Publish {
+        topic,
+        payload: &body[offset..],
+        qos,
+        packet_id,
+        retain: header & 0x01 != 0,
+        dup: header & 0x08 != 0,
+    })
+}
+
+#[cfg(test)]
+mod tests_client_packets {
+    use super::*;
+    use crate::mqtt::{
+        encode_connect, encode_disconnect, encode_pingreq, encode_puback, encode_publish,
+        encode_subscribe,
+    };
+    use heapless::consts::U128;
+
+    #[test]
+    fn connect_round_trips() {
+        let connect = Connect::new("rid", "scope/registrations/rid", "SharedAccessSignature");
+        let encoded: Vec<u8, U128> = encode_connect(&connect).unwrap();
+        let (decoded, consumed) = decode_client_packet(&encoded).unwrap().unwrap();
+        assert_eq!(consumed, encoded.len());
+        assert_eq!(decoded, ClientPacket::Connect(connect));
+    }
+
+    #[test]
+    fn connect_with_truncated_will_is_malformed() {
+        let packet = [
+            CONNECT << 4,
+            18,
+            0,
+            4,
+            b'M',
+            b'Q',
+            b'T',
+            b'T',
+            PROTOCOL_LEVEL,
+            0x06,
+            0,
+            60,
+            0,
+            1,
+            b'c',
+            0,
+            1,
+            b't',
+            0,
+            9,
+        ];
+        assert_eq!(
+            decode_client_packet(&packet),
+            Err(AZ_ERROR_MQTT_MALFORMED_PACKET)
+        );
+    }
+
+    #[test]
+    fn subscribe_round_trips() {
+        let encoded: Vec<u8, U128> =
+            encode_subscribe(3, &[("a/#", QoS::AtLeastOnce), ("b", QoS::AtMostOnce)]).unwrap();
+        let (decoded, _) = decode_client_packet(&encoded).unwrap().unwrap();
+        match decoded {
+            ClientPacket::Subscribe { packet_id, topics } => {
+                assert_eq!(packet_id, 3);
+                assert_eq!(
+                    &topics[..],
+                    &[("a/#", QoS::AtLeastOnce), ("b", QoS::AtMostOnce)]
+                );
+            }
+            _ => panic!("expected subscribe"),
+        }
+    }
+
+    #[test]
+    fn publish_round_trips() {
+        let publish = Publish::new("topic", b"{}", QoS::AtLeastOnce, 5);
+        let encoded: Vec<u8, U128> = encode_publish(&publish).unwrap();
+        let (decoded, _) = decode_client_packet(&encoded).unwrap().unwrap();
+        assert_eq!(decoded, ClientPacket::Publish(publish));
+    }
+
+    #[test]
+    fn fixed_packets_round_trip() {
+        let puback = encode_puback(9);
+        let (decoded, _) = decode_client_packet(&puback).unwrap().unwrap();
+        assert_eq!(decoded, ClientPacket::Puback(9));
+        let pingreq = encode_pingreq();
+        let (decoded, _) = decode_client_packet(&pingreq).unwrap().unwrap();
+        assert_eq!(decoded, ClientPacket::Pingreq);
+        let disconnect = encode_disconnect();
+        let (decoded, _) = decode_client_packet(&disconnect).unwrap().unwrap();
+        assert_eq!(decoded, ClientPacket::Disconnect);
+    }
+}
+
+#[cfg(test)]
+mod tests_server_packets {
+    use super::*;
+    use crate::mqtt::{decode, Connack, Packet};
+    use heapless::consts::{U16, U64};
+
+    #[test]
+    fn connack_round_trips() {
+        let encoded = encode_connack(false, ConnectReturnCode::BadUserNameOrPassword);
+        let (decoded, _) = decode::<U64, U64>(&encoded).unwrap().unwrap();
+        assert_eq!(
+            decoded,
+            Packet::Connack(Connack {
+                session_present: false,
+                return_code: ConnectReturnCode::BadUserNameOrPassword
+            })
+        );
+    }
+
+    #[test]
+    fn suback_round_trips() {
+        let encoded: Vec<u8, U16> = encode_suback(7, &[1]).unwrap();
+        let (decoded, _) = decode::<U64, U64>(&encoded).unwrap().unwrap();
+        match decoded {
+            Packet::Suback(suback) => {
+                assert_eq!(suback.packet_id, 7);
+                assert!(suback.is_success());
+            }
+            _ => panic!("expected suback"),
+        }
+    }
+
+    #[test]
+    fn pingresp_round_trips() {
+        let (decoded, _) = decode::<U64, U64>(&encode_pingresp()).unwrap().unwrap();
+        assert_eq!(decoded, Packet::Pingresp);
+    }
+}
@@ -0,0 +1,82 @@
+// DPS service API versions. The version is sent in the MQTT user name and
+// decides which request fields the service understands; newer fields are only
+// written for versions that have them.
+use core::fmt;
+use core::str::FromStr;
+
+use super::SERVICE_VERSION;
+
+pub const AZ_ERROR_UNSUPPORTED_API_VERSION: &str = "The DPS API version is not supported.";
+pub const AZ_ERROR_NOT_SUPPORTED_BY_API_VERSION: &str =
+    "The request uses a field that the selected DPS API version does not support.";
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ApiVersion {
+    #[default]
+    V2019_03_31,
+    // Adds Plug and Play model IDs, client certificate issuance and trust
+    // bundles.
+    V2021_06_01,
+}
+
+impl ApiVersion {
+    pub const LATEST: ApiVersion = ApiVersion::V2021_06_01;
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ApiVersion::V2019_03_31 => SERVICE_VERSION,
+            ApiVersion::V2021_06_01 => "2021-06-01",
+        }
+    }
+
+    // {"payload": {"modelId": "dtmi:..."}} in the register request
+    pub fn supports_model_id(self) -> bool {
+        self >= ApiVersion::V2021_06_01
+    }
+
+    // certificateSigningRequest in the request, issuedCertificateChain in the
+    // registration state
+    pub fn supports_certificate_issuance(self) -> bool {
+        self >= ApiVersion::V2021_06_01
+    }
+
+    // trustBundle in the registration state
+    pub fn supports_trust_bundle(self) -> bool {
+        self >= ApiVersion::V2021_06_01
+    }
+}
+
+impl FromStr for ApiVersion {
+    type Err = &'static str;
+
+    fn from_str(version: &str) -> Result<ApiVersion, &'static str> {
+        [ApiVersion::V2019_03_31, ApiVersion::V2021_06_01]
+            .iter()
+            .copied()
+            .find(|candidate| candidate.as_str() == version)
+            .ok_or(AZ_ERROR_UNSUPPORTED_API_VERSION)
+    }
+}
+
+impl fmt::Display for ApiVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests_api_version {
+    use super::*;
+
+    #[test]
+    fn versions_round_trip() {
+        assert_eq!(ApiVersion::default().as_str(), "2019-03-31");
+        assert_eq!("2021-06-01".parse(), Ok(ApiVersion::V2021_06_01));
+        assert_eq!(
+            "2020-09-01".parse::<ApiVersion>(),
+            Err(AZ_ERROR_UNSUPPORTED_API_VERSION)
+        );
+        assert!(!ApiVersion::V2019_03_31.supports_model_id());
+        assert!(ApiVersion::LATEST.supports_trust_bundle());
+    }
+}
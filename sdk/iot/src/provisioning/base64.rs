@@ -3,6 +3,8 @@ use base64::{decode_config_slice, encode_config_slice};
 use heapless::String;
 use heapless::Vec;
 
+use super::util::{truncate, zeroize_vec};
+
 use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
 pub const AZ_ERROR_UNABLE_TO_DECODE_BASE64: &str =
     "The given input could not be converted to base64.";
@@ -32,7 +34,7 @@ where
     }
 
     let bytes_written = encode_config_slice(&input, base64::STANDARD, &mut buffer);
-    buffer.truncate(bytes_written);
+    truncate(&mut buffer, bytes_written);
     let encoded_result: Result<String<B>, core::str::Utf8Error> = String::from_utf8(buffer);
 
     if let Ok(encoded_value) = encoded_result {
@@ -69,11 +71,14 @@ where
         }
     }
 
-    match decode_config_slice(&input, base64::STANDARD, &mut buffer) {
-        Ok(bytes_written) => buffer.truncate(bytes_written),
-        Err(_) => return Err(AZ_ERROR_UNABLE_TO_DECODE_BASE64),
+    if let Ok(bytes_written) = decode_config_slice(&input, base64::STANDARD, &mut buffer) {
+        truncate(&mut buffer, bytes_written);
+        Ok(buffer)
+    } else {
+        // the input may be a key, don't leave part of it behind
+        zeroize_vec(&mut buffer);
+        Err(AZ_ERROR_UNABLE_TO_DECODE_BASE64)
     }
-    Ok(buffer)
 }
 
 #[cfg(test)]
@@ -1,80 +1,181 @@
 pub struct ClientOptions<'a> {
     pub user_agent: &'a str,
+    pub api_version: ApiVersion,
 }
 
 pub struct Client<'a> {
     pub global_device_endpoint: &'a str,
-    pub id_scope: &'a str,
-    pub registration_id: &'a str,
+    pub id_scope: IdScope<'a>,
+    pub registration_id: RegistrationId<'a>,
     pub options: ClientOptions<'a>,
 }
 
 impl<'a> Default for ClientOptions<'a> {
     #[inline]
     fn default() -> ClientOptions<'a> {
-        ClientOptions { user_agent: "" }
+        ClientOptions {
+            user_agent: "",
+            api_version: ApiVersion::default(),
+        }
     }
 }
 
 impl<'a> Client<'a> {
+    // The endpoint is only parsed when it is needed, see endpoint().
     pub fn new(
         global_device_endpoint: &'a str,
         id_scope: &'a str,
         registration_id: &'a str,
         options: Option<ClientOptions<'a>>,
-    ) -> Client<'a> {
-        Client {
+    ) -> Result<Client<'a>, &'static str> {
+        Ok(Client {
             global_device_endpoint,
-            id_scope,
-            registration_id,
+            id_scope: IdScope::new(id_scope)?,
+            registration_id: RegistrationId::new(registration_id)?,
             options: options.unwrap_or_default(),
-        }
+        })
+    }
+
+    pub fn endpoint(&self) -> Result<Endpoint<'a>, &'static str> {
+        Endpoint::parse(self.global_device_endpoint)
     }
 }
 
 //use common::error::Error;
-use super::{CLIENT_REGISTER_SUBSCRIBE_TOPIC, SERVICE_VERSION, STR_GET_IOTDPS_GET_OPERATIONSTATUS};
+use super::{
+    CLIENT_REGISTER_SUBSCRIBE_TOPIC, STR_GET_IOTDPS_GET_OPERATIONSTATUS, STR_PUT_IOTDPS_REGISTER,
+};
+
+use super::api_version::{ApiVersion, AZ_ERROR_NOT_SUPPORTED_BY_API_VERSION};
+use super::certificate::{is_base64, AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST};
+use super::raw_json::AZ_ERROR_INVALID_JSON;
+use super::serialization::DeviceRegistration;
+use super::util::write_to_string;
+use crate::endpoint::Endpoint;
+use crate::hub::client::AZ_ERROR_IOT_TOPIC_NO_MATCH;
+use crate::hub::properties::Properties;
+use crate::identifiers::{IdScope, RegistrationId};
 
 use heapless::consts::{U128, U256};
-use heapless::String;
+use heapless::{ArrayLength, String};
+use serde::Serialize;
+
+use azure_sdk_for_rust_common::error::{SpanError, AZ_ERROR_INSUFFICIENT_SPAN_SIZE};
+use azure_sdk_for_rust_common::log::{self, Classification};
+use azure_sdk_for_rust_common::span::SpanWriter;
+
+const REQUEST_ID: &str = "$rid";
+const RETRY_AFTER: &str = "retry-after";
+const MODEL_ID_PREFIX: &str = "dtmi:";
+
+pub const AZ_ERROR_INVALID_MODEL_ID: &str = "The model ID is not a valid DTMI.";
 
-use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+// A register or operation status response received on
+// $dps/registrations/res/<status>/?$rid=<request_id>[&retry-after=<seconds>]
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RegisterResponse<'a> {
+    pub status: u16,
+    pub request_id: Option<&'a str>,
+    pub retry_after_seconds: Option<u64>,
+}
 
 impl<'a> Client<'a> {
     pub fn get_client_id(&self) -> &'a str {
-        self.registration_id
+        self.registration_id.as_str()
     }
     // <id_scope>/registrations/<registration_id>/api-version=<service_version>
     pub fn get_user_name(&self) -> Result<String<U128>, &'static str> {
-        let str_registrations = Client::get_registrations();
+        self.get_user_name_with_capacity()
+    }
 
-        let mut res: String<U128> = String::new();
+    pub fn get_user_name_with_capacity<B>(&self) -> Result<String<B>, &'static str>
+    where
+        B: ArrayLength<u8>,
+    {
+        write_to_string(|buffer| self.get_user_name_into(buffer))
+    }
 
-        if res.push_str(&self.id_scope).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    // Returns the number of bytes written, or the required length when the
+    // buffer is too small.
+    pub fn get_user_name_into(&self, buffer: &mut [u8]) -> Result<usize, SpanError> {
+        let mut writer = SpanWriter::new(buffer);
+        writer.push_str(self.id_scope.as_str());
+        writer.push_str(Client::get_registrations());
+        writer.push_str(self.registration_id.as_str());
+        writer.push_str("/api-version=");
+        writer.push_str(self.options.api_version.as_str());
+        if !self.options.user_agent.is_empty() {
+            writer.push_str("&ClientVersion=");
+            writer.push_str(self.options.user_agent);
         }
-        if res.push_str(&str_registrations).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        writer.finish()
+    }
+
+    // A register request body for this client's registration ID. Set the
+    // model ID or certificate signing request before get_register_payload.
+    pub fn registration<P>(&self, payload: P) -> DeviceRegistration<'a, P> {
+        DeviceRegistration::new(payload, self.registration_id.as_str(), None)
+    }
+
+    // Refuses what the API version of the user name doesn't define, for
+    // bodies that are not written by get_register_payload.
+    pub fn check_registration<P>(
+        &self,
+        registration: &DeviceRegistration<'_, P>,
+    ) -> Result<(), &'static str> {
+        let api_version = self.options.api_version;
+        if let Some(model_id) = registration.model_id {
+            if !api_version.supports_model_id() {
+                return Err(AZ_ERROR_NOT_SUPPORTED_BY_API_VERSION);
+            }
+            if !is_valid_model_id(model_id) {
+                return Err(AZ_ERROR_INVALID_MODEL_ID);
+            }
         }
-        if res.push_str(&self.registration_id).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        if let Some(certificate_signing_request) = registration.certificate_signing_request {
+            if !api_version.supports_certificate_issuance() {
+                return Err(AZ_ERROR_NOT_SUPPORTED_BY_API_VERSION);
+            }
+            if !is_base64(certificate_signing_request) {
+                return Err(AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST);
+            }
         }
-        if res.push_str("/api-version=").is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        Ok(())
+    }
+
+    // Body of the register request, see DeviceRegistration.
+    pub fn get_register_payload<B, P>(
+        &self,
+        registration: &DeviceRegistration<'_, P>,
+    ) -> Result<String<B>, &'static str>
+    where
+        B: ArrayLength<u8>,
+        P: Serialize,
+    {
+        self.check_registration(registration)?;
+        if registration.model_id.is_some() {
+            // serde-json-core can't report a payload the model ID doesn't fit
+            // in, so it is written on its own first
+            let payload = serde_json_core::to_string::<B, _>(&registration.payload)
+                .map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+            if !(payload.starts_with('{') || payload == "\"\"" || payload == "null") {
+                return Err(AZ_ERROR_INVALID_JSON);
+            }
         }
-        if res.push_str(&SERVICE_VERSION).is_err() {
+        serde_json_core::to_string(registration).map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)
+    }
+
+    // Topic: $dps/registrations/PUT/iotdps-register/?$rid=1
+    pub fn get_register_publish_topic() -> Result<String<U128>, &'static str> {
+        let mut topic: String<U128> = String::new();
+        if topic.push_str(Client::get_dps_registrations()).is_err() {
             return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
         }
-        if !self.options.user_agent.is_empty() {
-            if res.push_str("&ClientVersion=").is_err() {
-                return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-            }
-            if res.push_str(&self.options.user_agent).is_err() {
-                return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-            }
+        if topic.push_str(STR_PUT_IOTDPS_REGISTER).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
         }
-        res.truncate(res.len());
-        Ok(res)
+        log::write(Classification::ProvisioningRequest, &topic);
+        Ok(topic)
     }
 
     // Topic: $dps/registrations/GET/iotdps-get-operationstatus/?$rid=1&operationId=%s
@@ -91,7 +192,7 @@ impl<'a> Client<'a> {
         if topic.push_str(operation_id).is_err() {
             return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
         }
-        topic.truncate(topic.len());
+        log::write(Classification::ProvisioningRequest, &topic);
         Ok(topic)
     }
     // $dps/registrations/res/
@@ -118,6 +219,51 @@ impl<'a> Client<'a> {
     pub fn get_iotdps_get_operationstatus() -> &'static str {
         return STR_GET_IOTDPS_GET_OPERATIONSTATUS;
     }
+
+    pub fn parse_received_topic(topic: &str) -> Result<RegisterResponse<'_>, &'static str> {
+        let rest = topic
+            .strip_prefix(Client::get_dps_registrations_res())
+            .ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?;
+        let mut parts = rest.splitn(2, "/?");
+        let status: u16 = parts
+            .next()
+            .and_then(|status| status.parse().ok())
+            .ok_or(AZ_ERROR_IOT_TOPIC_NO_MATCH)?;
+        let properties = Properties::new(parts.next().unwrap_or(""));
+        let classification = if status < 400 {
+            Classification::ProvisioningResponse
+        } else {
+            Classification::ProvisioningResponseError
+        };
+        log::write(classification, topic);
+        Ok(RegisterResponse {
+            status,
+            request_id: properties.get(REQUEST_ID),
+            retry_after_seconds: properties
+                .get(RETRY_AFTER)
+                .and_then(|seconds| seconds.parse().ok()),
+        })
+    }
+}
+
+// dtmi:<segment>[:<segment>]*;<version>
+fn is_valid_model_id(model_id: &str) -> bool {
+    model_id
+        .strip_prefix(MODEL_ID_PREFIX)
+        .map_or(false, |rest| {
+            let mut parts = rest.splitn(2, ';');
+            let path = parts.next().unwrap_or_default();
+            let version = parts.next().unwrap_or_default();
+            !path.is_empty()
+                && path.split(':').all(|segment| {
+                    !segment.is_empty()
+                        && segment
+                            .chars()
+                            .all(|c| c.is_ascii_alphanumeric() || c == '_')
+                })
+                && !version.is_empty()
+                && version.chars().all(|c| c.is_ascii_digit())
+        })
 }
 
 #[cfg(test)]
@@ -126,29 +272,74 @@ mod tests_new {
     #[test]
     fn global_device_endpoint_flows_through() {
         assert_eq!(
-            Client::new("gde", "", "", None).global_device_endpoint,
+            Client::new("gde", "ids", "rid", None)
+                .unwrap()
+                .global_device_endpoint,
             "gde"
         );
     }
     #[test]
+    fn endpoint_is_parsed_from_global_device_endpoint() {
+        let client = Client::new(
+            "ssl://global.azure-devices-provisioning.net:8883",
+            "ids",
+            "rid",
+            None,
+        )
+        .unwrap();
+        assert_eq!(
+            client.endpoint().map(|endpoint| endpoint.host),
+            Ok("global.azure-devices-provisioning.net")
+        );
+        assert!(Client::new("gde:port", "ids", "rid", None)
+            .unwrap()
+            .endpoint()
+            .is_err());
+    }
+    #[test]
     fn id_scope_flows_through() {
-        assert_eq!(Client::new("", "ids", "", None).id_scope, "ids");
+        assert_eq!(
+            Client::new("", "ids", "rid", None)
+                .unwrap()
+                .id_scope
+                .as_str(),
+            "ids"
+        );
     }
     #[test]
     fn registration_id_flows_through() {
-        assert_eq!(Client::new("", "", "rid", None).registration_id, "rid");
+        assert_eq!(
+            Client::new("", "ids", "rid", None)
+                .unwrap()
+                .registration_id
+                .as_str(),
+            "rid"
+        );
+    }
+    #[test]
+    fn invalid_ids_are_rejected() {
+        assert_eq!(
+            Client::new("", "", "rid", None).err(),
+            Some(crate::identifiers::AZ_ERROR_INVALID_ID_SCOPE)
+        );
+        assert_eq!(
+            Client::new("", "ids", "Device/1", None).err(),
+            Some(crate::identifiers::AZ_ERROR_INVALID_REGISTRATION_ID)
+        );
     }
     #[test]
     fn options_defaults_flows_through() {
         assert_eq!(
             Client::new(
                 "",
-                "",
-                "",
+                "ids",
+                "rid",
                 Some(ClientOptions {
-                    user_agent: "agent"
+                    user_agent: "agent",
+                    ..ClientOptions::default()
                 })
             )
+            .unwrap()
             .options
             .user_agent,
             "agent"
@@ -157,7 +348,10 @@ mod tests_new {
     #[test]
     fn options_defaults_on_none() {
         assert_eq!(
-            Client::new("", "", "", None).options.user_agent,
+            Client::new("", "ids", "rid", None)
+                .unwrap()
+                .options
+                .user_agent,
             ClientOptions::default().user_agent
         );
     }
@@ -183,4 +377,204 @@ mod tests_consts {
     fn _az_iot_provisioning_get_str_dps_registrations() {
         assert_eq!(Client::get_dps_registrations(), "$dps/registrations/");
     }
+
+    #[test]
+    fn register_publish_topic() {
+        assert_eq!(
+            Client::get_register_publish_topic().unwrap(),
+            "$dps/registrations/PUT/iotdps-register/?$rid=1"
+        );
+    }
+
+    #[test]
+    fn received_topic_is_parsed() {
+        assert_eq!(
+            Client::parse_received_topic("$dps/registrations/res/202/?$rid=1&retry-after=3"),
+            Ok(RegisterResponse {
+                status: 202,
+                request_id: Some("1"),
+                retry_after_seconds: Some(3),
+            })
+        );
+        let response = Client::parse_received_topic("$dps/registrations/res/200/?$rid=7").unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.retry_after_seconds, None);
+        assert_eq!(
+            Client::parse_received_topic("$iothub/twin/res/200/?$rid=1"),
+            Err(AZ_ERROR_IOT_TOPIC_NO_MATCH)
+        );
+        assert_eq!(
+            Client::parse_received_topic("$dps/registrations/res/abc/?$rid=1"),
+            Err(AZ_ERROR_IOT_TOPIC_NO_MATCH)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_user_name {
+    use super::*;
+    use heapless::consts::U512;
+
+    #[test]
+    fn user_name_is_built() {
+        let client = Client::new(
+            "",
+            "0ne00000001",
+            "rid",
+            Some(ClientOptions {
+                user_agent: "ua",
+                ..ClientOptions::default()
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            client.get_user_name().unwrap().as_str(),
+            "0ne00000001/registrations/rid/api-version=2019-03-31&ClientVersion=ua"
+        );
+    }
+
+    #[test]
+    fn user_name_carries_the_api_version() {
+        let client = Client::new(
+            "",
+            "0ne00000001",
+            "rid",
+            Some(ClientOptions {
+                api_version: ApiVersion::V2021_06_01,
+                ..ClientOptions::default()
+            }),
+        )
+        .unwrap();
+        assert_eq!(
+            client.get_user_name().unwrap().as_str(),
+            "0ne00000001/registrations/rid/api-version=2021-06-01"
+        );
+    }
+
+    #[test]
+    fn long_user_name_needs_a_larger_buffer() {
+        // registration ids may be up to 128 characters long
+        let registration_id = [b'r'; 128];
+        let registration_id = core::str::from_utf8(&registration_id).unwrap();
+        let client = Client::new("", "0ne00000001", registration_id, None).unwrap();
+        assert!(client.get_user_name().is_err());
+        let user_name: String<U512> = client.get_user_name_with_capacity().unwrap();
+        assert!(user_name.ends_with("/api-version=2019-03-31"));
+
+        let mut buffer = [0_u8; 64];
+        let required = match client.get_user_name_into(&mut buffer) {
+            Err(SpanError::InsufficientSize { required }) => required,
+            result => panic!("unexpected {:?}", result),
+        };
+        assert_eq!(required, user_name.len());
+        let mut buffer = [0_u8; 256];
+        assert_eq!(client.get_user_name_into(&mut buffer), Ok(required));
+        assert_eq!(&buffer[..required], user_name.as_bytes());
+    }
+}
+
+#[cfg(test)]
+mod tests_register_payload {
+    use super::*;
+    use heapless::consts::U256;
+
+    const MODEL_ID: &str = "dtmi:com:example:Thermostat;1";
+    const CERTIFICATE_SIGNING_REQUEST: &str = "TUlJQlBEQ0I=";
+
+    #[derive(Serialize)]
+    struct Allocation<'a> {
+        #[serde(rename = "hubSku")]
+        hub_sku: &'a str,
+    }
+
+    fn client(api_version: ApiVersion) -> Client<'static> {
+        Client::new(
+            "",
+            "0ne00000001",
+            "device-1",
+            Some(ClientOptions {
+                api_version,
+                ..ClientOptions::default()
+            }),
+        )
+        .unwrap()
+    }
+
+    fn payload<P>(
+        api_version: ApiVersion,
+        payload: P,
+        model_id: Option<&str>,
+        certificate_signing_request: Option<&str>,
+    ) -> Result<String<U256>, &'static str>
+    where
+        P: Serialize,
+    {
+        let client = client(api_version);
+        let mut registration = client.registration(payload);
+        registration.model_id = model_id;
+        registration.certificate_signing_request = certificate_signing_request;
+        client.get_register_payload(&registration)
+    }
+
+    #[test]
+    fn payload_for_2019_03_31() {
+        let version = ApiVersion::V2019_03_31;
+        assert_eq!(
+            payload(version, "", None, None).unwrap(),
+            "{\"payload\":\"\",\"registrationId\":\"device-1\"}"
+        );
+        let allocation = Allocation { hub_sku: "S1" };
+        assert_eq!(
+            payload(version, &allocation, None, None).unwrap(),
+            "{\"payload\":{\"hubSku\":\"S1\"},\"registrationId\":\"device-1\"}"
+        );
+        assert_eq!(
+            payload(version, &allocation, Some(MODEL_ID), None),
+            Err(AZ_ERROR_NOT_SUPPORTED_BY_API_VERSION)
+        );
+        assert_eq!(
+            payload(version, "", None, Some(CERTIFICATE_SIGNING_REQUEST)),
+            Err(AZ_ERROR_NOT_SUPPORTED_BY_API_VERSION)
+        );
+    }
+
+    #[test]
+    fn payload_for_2021_06_01() {
+        let version = ApiVersion::V2021_06_01;
+        assert_eq!(
+            payload(version, "", Some(MODEL_ID), None).unwrap(),
+            "{\"payload\":{\"modelId\":\"dtmi:com:example:Thermostat;1\"},\"registrationId\":\"device-1\"}"
+        );
+        assert_eq!(
+            payload(version, None::<Allocation<'_>>, Some(MODEL_ID), None).unwrap(),
+            "{\"payload\":{\"modelId\":\"dtmi:com:example:Thermostat;1\"},\"registrationId\":\"device-1\"}"
+        );
+        assert_eq!(
+            payload(version, Allocation { hub_sku: "S1" }, Some(MODEL_ID), None).unwrap(),
+            "{\"payload\":{\"modelId\":\"dtmi:com:example:Thermostat;1\",\"hubSku\":\"S1\"},\"registrationId\":\"device-1\"}"
+        );
+        assert_eq!(
+            payload(version, "text", Some(MODEL_ID), None),
+            Err(AZ_ERROR_INVALID_JSON)
+        );
+        assert_eq!(
+            payload(version, "", Some(MODEL_ID), Some(CERTIFICATE_SIGNING_REQUEST)).unwrap(),
+            "{\"payload\":{\"modelId\":\"dtmi:com:example:Thermostat;1\"},\"registrationId\":\"device-1\",\"certificateSigningRequest\":\"TUlJQlBEQ0I=\"}"
+        );
+        assert_eq!(
+            payload(
+                version,
+                "",
+                None,
+                Some("-----BEGIN CERTIFICATE REQUEST-----")
+            ),
+            Err(AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST)
+        );
+        for model_id in &["com:example:Thermostat;1", "dtmi:com:example", "dtmi:a\";1"] {
+            assert_eq!(
+                payload(version, "", Some(model_id), None),
+                Err(AZ_ERROR_INVALID_MODEL_ID)
+            );
+        }
+    }
 }
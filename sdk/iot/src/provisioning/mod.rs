@@ -1,13 +1,21 @@
+pub mod api_version;
 mod base64;
 pub mod certificate;
 pub mod client;
 pub mod common;
 pub mod error;
-mod percent_encode;
+pub(crate) mod percent_encode;
+pub mod policy;
+pub mod raw_json;
+pub mod retry;
 pub mod sas;
 pub mod serialization;
-mod util;
+pub mod signer;
+pub mod store;
+pub mod trust_bundle;
+pub(crate) mod util;
 
+// The default API version, see api_version::ApiVersion.
 pub const SERVICE_VERSION: &str = "2019-03-31";
 pub const CLIENT_REGISTER_SUBSCRIBE_TOPIC: &str = "$dps/registrations/res/#";
 pub const STR_PUT_IOTDPS_REGISTER: &str = "PUT/iotdps-register/?$rid=1";
@@ -1,42 +1,96 @@
-use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+use azure_sdk_for_rust_common::error::{SpanError, AZ_ERROR_INSUFFICIENT_SPAN_SIZE};
+use azure_sdk_for_rust_common::span::SpanWriter;
 use heapless::consts::U128;
-use heapless::String;
+use heapless::{ArrayLength, String};
+
+pub const AZ_ERROR_INVALID_PERCENT_ENCODING: &str =
+    "The given value is not validly percent-encoded.";
 
 pub fn encode(value: &str) -> Result<String<U128>, &'static str> {
-    let mut result: String<U128> = String::new();
-    if value.is_empty() {
-        // return 0 len buffer
-        result.truncate(0);
-        return Ok(result);
-    }
-    let extra_space_have = (128 - value.len()) / 2;
-    if extra_space_have >= value.len() {
-        // We know that there's enough space even if every character gets encoded.
-        for c in value.chars() {
-            push_char_with_needed_encoding(&mut result, c).unwrap();
+    encode_with_capacity(value)
+}
+
+pub fn encode_with_capacity<B>(value: &str) -> Result<String<B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    let mut result: String<B> = String::new();
+    encode_into(&mut result, value)?;
+    Ok(result)
+}
+
+pub fn encode_into<B>(result: &mut String<B>, value: &str) -> Result<(), &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    for b in value.bytes() {
+        let (encoded, length) = encode_byte(b);
+        // only ASCII is ever produced
+        let encoded = core::str::from_utf8(&encoded[..length]).unwrap_or_default();
+        if result.push_str(encoded).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
         }
-    } else {
-        // We may or may not have enough space, given whether the input needs much encoding or not.
-        for c in value.chars() {
-            if push_char_with_needed_encoding(&mut result, c).is_err() {
-                return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    Ok(())
+}
+
+// Returns the number of bytes written, or the required length when the buffer
+// is too small.
+pub fn encode_to_slice(value: &str, buffer: &mut [u8]) -> Result<usize, SpanError> {
+    let mut writer = SpanWriter::new(buffer);
+    write_encoded(&mut writer, value);
+    writer.finish()
+}
+
+pub(crate) fn write_encoded(writer: &mut SpanWriter<'_>, value: &str) {
+    for b in value.bytes() {
+        let (encoded, length) = encode_byte(b);
+        writer.push_bytes(&encoded[..length]);
+    }
+}
+
+pub fn decode<B>(value: &str) -> Result<String<B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    let mut bytes: heapless::Vec<u8, B> = heapless::Vec::new();
+    let mut input = value.bytes();
+    while let Some(b) = input.next() {
+        let decoded = if b == b'%' {
+            match (
+                input.next().and_then(hex_to_number),
+                input.next().and_then(hex_to_number),
+            ) {
+                (Some(upper), Some(lower)) => upper << 4 | lower,
+                _ => return Err(AZ_ERROR_INVALID_PERCENT_ENCODING),
             }
+        } else {
+            b
+        };
+        if bytes.push(decoded).is_err() {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
         }
     }
-    result.truncate(result.len());
-    Ok(result)
+    match String::from_utf8(bytes) {
+        Ok(result) => Ok(result),
+        Err(_) => Err(AZ_ERROR_INVALID_PERCENT_ENCODING),
+    }
 }
-fn push_char_with_needed_encoding(result: &mut String<U128>, c: char) -> Result<(), ()> {
-    if should_encode(c) {
-        result.push('%')?;
-        let upper = (c as u8) >> 4;
-        result.push(number_to_upper_hex(upper))?;
-        let lower = (c as u8) & 0x0F;
-        result.push(number_to_upper_hex(lower))?;
+
+// UTF-8 sequences are encoded byte by byte.
+fn encode_byte(b: u8) -> ([u8; 3], usize) {
+    if should_encode(b as char) {
+        (
+            [
+                b'%',
+                number_to_upper_hex(b >> 4) as u8,
+                number_to_upper_hex(b & 0x0F) as u8,
+            ],
+            3,
+        )
     } else {
-        result.push(c)?;
+        ([b, 0, 0], 1)
     }
-    Ok(())
 }
 fn should_encode(c: char) -> bool {
     match c {
@@ -52,6 +106,15 @@ fn number_to_upper_hex(number: u8) -> char {
     result as char
 }
 
+fn hex_to_number(c: u8) -> Option<u8> {
+    match c {
+        b'0'..=b'9' => Some(c - b'0'),
+        b'A'..=b'F' => Some(c - b'A' + 10),
+        b'a'..=b'f' => Some(c - b'a' + 10),
+        _ => None,
+    }
+}
+
 #[cfg(test)]
 mod tests_encode {
     use super::*;
@@ -60,6 +123,70 @@ mod tests_encode {
         let result = encode("http://www.example.com/#anchor").unwrap();
         assert_eq!("http%3A%2F%2Fwww.example.com%2F%23anchor", result.as_str());
     }
+
+    #[test]
+    fn long_values_are_encoded_with_a_larger_capacity() {
+        let bytes = [b'/'; 64];
+        let value = core::str::from_utf8(&bytes).unwrap();
+        assert!(encode(value).is_err());
+        let result: String<heapless::consts::U256> = encode_with_capacity(value).unwrap();
+        assert_eq!(result.len(), 64 * 3);
+    }
+
+    #[test]
+    fn encode_to_slice_reports_the_required_length() {
+        let mut buffer = [0_u8; 8];
+        assert_eq!(encode_to_slice("a b", &mut buffer), Ok(5));
+        assert_eq!(&buffer[..5], b"a%20b");
+        assert_eq!(
+            encode_to_slice("a b c", &mut buffer),
+            Err(SpanError::InsufficientSize { required: 9 })
+        );
+    }
+
+    #[test]
+    fn multi_byte_characters_are_encoded_per_byte() {
+        assert_eq!(encode("\u{e9}").unwrap().as_str(), "%C3%A9");
+    }
+
+    #[test]
+    fn encode_into_appends() {
+        let mut result: String<heapless::consts::U32> = String::new();
+        result.push_str("a=").unwrap();
+        encode_into(&mut result, "b c").unwrap();
+        assert_eq!("a=b%20c", result.as_str());
+    }
+}
+
+#[cfg(test)]
+mod tests_decode {
+    use super::*;
+    use heapless::consts::{U4, U64};
+    #[test]
+    fn decodes_what_was_encoded() {
+        let result = decode::<U64>("http%3A%2F%2Fwww.example.com%2f%23anchor").unwrap();
+        assert_eq!("http://www.example.com/#anchor", result.as_str());
+    }
+
+    #[test]
+    fn truncated_escape_is_invalid() {
+        assert_eq!(
+            decode::<U64>("abc%2").unwrap_err(),
+            AZ_ERROR_INVALID_PERCENT_ENCODING
+        );
+        assert_eq!(
+            decode::<U64>("abc%zz").unwrap_err(),
+            AZ_ERROR_INVALID_PERCENT_ENCODING
+        );
+    }
+
+    #[test]
+    fn too_small_buffer_fails() {
+        assert_eq!(
+            decode::<U4>("abcde").unwrap_err(),
+            AZ_ERROR_INSUFFICIENT_SPAN_SIZE
+        );
+    }
 }
 
 #[cfg(test)]
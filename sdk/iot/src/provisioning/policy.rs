@@ -0,0 +1,359 @@
+// Decides when a device should go back to DPS and what the outcome means for the
+// data it keeps locally.
+use super::common::ReprovisioningStatus;
+use super::error::{Error, ErrorKind};
+use super::store::{is_hub_rejection, ProvisioningRecord};
+use crate::hub::client::MethodRequest;
+use crate::mqtt::ConnectReturnCode;
+use crate::statuscode::{ErrorAction, ExtendedErrorCode};
+use azure_sdk_for_rust_common::clock::Clock;
+
+// Direct method a back end can call to ask the device to re-provision.
+pub const REPROVISION_METHOD_NAME: &str = "reprovision";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReprovisionTrigger {
+    // The hub refused the device's credentials
+    HubAuthenticationFailure,
+    // The hub asked the device to connect elsewhere
+    HubRedirect,
+    // The configured interval elapsed
+    Scheduled,
+    // A back end asked for it, see REPROVISION_METHOD_NAME
+    CloudCommand,
+}
+
+// What to do with local device state (twin cache, queued telemetry, ...) once
+// re-provisioning completed.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceDataAction {
+    Keep,
+    Reset,
+}
+
+pub struct ReprovisioningOptions {
+    pub on_hub_authentication_failure: bool,
+    pub on_hub_redirect: bool,
+    pub on_cloud_command: bool,
+    // None disables scheduled re-provisioning
+    pub interval_seconds: Option<u64>,
+}
+
+impl Default for ReprovisioningOptions {
+    #[inline]
+    fn default() -> ReprovisioningOptions {
+        ReprovisioningOptions {
+            on_hub_authentication_failure: true,
+            on_hub_redirect: true,
+            on_cloud_command: true,
+            interval_seconds: None,
+        }
+    }
+}
+
+pub struct ReprovisioningPolicy {
+    pub options: ReprovisioningOptions,
+    last_provisioned_epoch_time: u64,
+}
+
+impl ReprovisioningPolicy {
+    pub fn new(
+        last_provisioned_epoch_time: u64,
+        options: Option<ReprovisioningOptions>,
+    ) -> ReprovisioningPolicy {
+        ReprovisioningPolicy {
+            options: options.unwrap_or_default(),
+            last_provisioned_epoch_time,
+        }
+    }
+
+    pub fn last_provisioned_epoch_time(&self) -> u64 {
+        self.last_provisioned_epoch_time
+    }
+
+    pub fn is_enabled(&self, trigger: ReprovisionTrigger) -> bool {
+        match trigger {
+            ReprovisionTrigger::HubAuthenticationFailure => {
+                self.options.on_hub_authentication_failure
+            }
+            ReprovisionTrigger::HubRedirect => self.options.on_hub_redirect,
+            ReprovisionTrigger::Scheduled => self.options.interval_seconds.is_some(),
+            ReprovisionTrigger::CloudCommand => self.options.on_cloud_command,
+        }
+    }
+
+    // Filters a trigger observed by the application through the options.
+    pub fn should_reprovision(&self, trigger: ReprovisionTrigger) -> Option<ReprovisionTrigger> {
+        if self.is_enabled(trigger) {
+            Some(trigger)
+        } else {
+            None
+        }
+    }
+
+    pub fn next_scheduled_epoch_time(&self) -> Option<u64> {
+        self.options
+            .interval_seconds
+            .map(|interval| self.last_provisioned_epoch_time.saturating_add(interval))
+    }
+
+    // Meant to be called periodically from the device's main loop.
+    pub fn poll(&self, now_epoch_time: u64) -> Option<ReprovisionTrigger> {
+        match self.next_scheduled_epoch_time() {
+            Some(due) if now_epoch_time >= due => Some(ReprovisionTrigger::Scheduled),
+            _ => None,
+        }
+    }
+
+    pub fn poll_clock<C>(&self, clock: &C) -> Result<Option<ReprovisionTrigger>, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        Ok(self.poll(clock.epoch_time()?))
+    }
+
+    pub fn on_hub_connect_result(
+        &self,
+        return_code: ConnectReturnCode,
+    ) -> Option<ReprovisionTrigger> {
+        if is_hub_rejection(return_code) {
+            self.should_reprovision(ReprovisionTrigger::HubAuthenticationFailure)
+        } else {
+            None
+        }
+    }
+
+    // Error bodies (DPS or hub) that ask for new credentials count as an
+    // authentication failure.
+    pub fn on_error_code(&self, code: ExtendedErrorCode) -> Option<ReprovisionTrigger> {
+        if code.action() == ErrorAction::RefreshCredentials {
+            self.should_reprovision(ReprovisionTrigger::HubAuthenticationFailure)
+        } else {
+            None
+        }
+    }
+
+    // Error bodies from the hub. A hub that no longer knows the device (DPS
+    // moved it to another hub) or answers with a 3xx status redirects it.
+    pub fn on_hub_error_code(&self, code: ExtendedErrorCode) -> Option<ReprovisionTrigger> {
+        let status = code.status().as_u16();
+        if code == ExtendedErrorCode::DEVICE_NOT_FOUND || (300..400).contains(&status) {
+            self.should_reprovision(ReprovisionTrigger::HubRedirect)
+        } else {
+            self.on_error_code(code)
+        }
+    }
+
+    pub fn on_method_request(&self, request: &MethodRequest<'_>) -> Option<ReprovisionTrigger> {
+        if request.name == REPROVISION_METHOD_NAME {
+            self.should_reprovision(ReprovisionTrigger::CloudCommand)
+        } else {
+            None
+        }
+    }
+
+    // Records a completed re-provisioning and tells the application whether its
+    // local state is still valid for the (possibly new) hub.
+    pub fn complete(
+        &mut self,
+        now_epoch_time: u64,
+        previous: Option<&ProvisioningRecord>,
+        current: &ProvisioningRecord,
+    ) -> Result<DeviceDataAction, Error> {
+        if current.assigned_hub().is_empty() || current.device_id().is_empty() {
+            return Err(Error::from(ErrorKind::Reprovision));
+        }
+        self.last_provisioned_epoch_time = now_epoch_time;
+        Ok(device_data_action(previous, current))
+    }
+}
+
+// Local state survives unless the device moved to another hub (or identity) and
+// DPS did not migrate its data along with it.
+pub fn device_data_action(
+    previous: Option<&ProvisioningRecord>,
+    current: &ProvisioningRecord,
+) -> DeviceDataAction {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return DeviceDataAction::Keep,
+    };
+    if previous.assigned_hub() == current.assigned_hub()
+        && previous.device_id() == current.device_id()
+    {
+        return DeviceDataAction::Keep;
+    }
+    match current.reprovisioning_status() {
+        ReprovisioningStatus::DeviceDataMigrated => DeviceDataAction::Keep,
+        _ => DeviceDataAction::Reset,
+    }
+}
+
+#[cfg(test)]
+mod tests_reprovisioning_policy {
+    use super::*;
+    use azure_sdk_for_rust_common::clock::ManualClock;
+
+    fn record(hub: &str, substatus: &str) -> ProvisioningRecord {
+        ProvisioningRecord::new(hub, "device-1", "etag", substatus).unwrap()
+    }
+
+    #[test]
+    fn schedule_is_disabled_by_default() {
+        let policy = ReprovisioningPolicy::new(100, None);
+        assert_eq!(policy.next_scheduled_epoch_time(), None);
+        assert_eq!(policy.poll(u64::MAX), None);
+    }
+
+    #[test]
+    fn schedule_fires_after_the_interval() {
+        let mut policy = ReprovisioningPolicy::new(
+            100,
+            Some(ReprovisioningOptions {
+                interval_seconds: Some(50),
+                ..ReprovisioningOptions::default()
+            }),
+        );
+        assert_eq!(policy.poll(149), None);
+        assert_eq!(policy.poll(150), Some(ReprovisionTrigger::Scheduled));
+        let clock = ManualClock::from_epoch_time(149);
+        assert_eq!(policy.poll_clock(&clock), Ok(None));
+        clock.advance(1);
+        assert_eq!(
+            policy.poll_clock(&clock),
+            Ok(Some(ReprovisionTrigger::Scheduled))
+        );
+        let current = record("hub-a", "initialAssignment");
+        policy.complete(150, Some(&current), &current).unwrap();
+        assert_eq!(policy.next_scheduled_epoch_time(), Some(200));
+    }
+
+    #[test]
+    fn hub_rejection_triggers_unless_disabled() {
+        let policy = ReprovisioningPolicy::new(0, None);
+        assert_eq!(
+            policy.on_hub_connect_result(ConnectReturnCode::NotAuthorized),
+            Some(ReprovisionTrigger::HubAuthenticationFailure)
+        );
+        assert_eq!(
+            policy.on_hub_connect_result(ConnectReturnCode::ServerUnavailable),
+            None
+        );
+        let policy = ReprovisioningPolicy::new(
+            0,
+            Some(ReprovisioningOptions {
+                on_hub_authentication_failure: false,
+                ..ReprovisioningOptions::default()
+            }),
+        );
+        assert_eq!(
+            policy.on_hub_connect_result(ConnectReturnCode::NotAuthorized),
+            None
+        );
+    }
+
+    #[test]
+    fn credential_errors_trigger_reprovisioning() {
+        let policy = ReprovisioningPolicy::new(0, None);
+        assert_eq!(
+            policy.on_error_code(ExtendedErrorCode::from(401_002)),
+            Some(ReprovisionTrigger::HubAuthenticationFailure)
+        );
+        assert_eq!(policy.on_error_code(ExtendedErrorCode::THROTTLED), None);
+        assert_eq!(
+            policy.on_error_code(ExtendedErrorCode::DEVICE_NOT_FOUND),
+            None
+        );
+    }
+
+    #[test]
+    fn hub_rejection_and_redirect_trigger_reprovisioning() {
+        let policy = ReprovisioningPolicy::new(0, None);
+        assert_eq!(
+            policy.on_hub_error_code(ExtendedErrorCode::DEVICE_NOT_FOUND),
+            Some(ReprovisionTrigger::HubRedirect)
+        );
+        assert_eq!(
+            policy.on_hub_error_code(ExtendedErrorCode::from(307)),
+            Some(ReprovisionTrigger::HubRedirect)
+        );
+        assert_eq!(
+            policy.on_hub_error_code(ExtendedErrorCode::IOT_HUB_UNAUTHORIZED),
+            Some(ReprovisionTrigger::HubAuthenticationFailure)
+        );
+        assert_eq!(
+            policy.on_hub_error_code(ExtendedErrorCode::DEVICE_NOT_ONLINE),
+            None
+        );
+        let policy = ReprovisioningPolicy::new(
+            0,
+            Some(ReprovisioningOptions {
+                on_hub_redirect: false,
+                ..ReprovisioningOptions::default()
+            }),
+        );
+        assert_eq!(
+            policy.on_hub_error_code(ExtendedErrorCode::DEVICE_NOT_FOUND),
+            None
+        );
+    }
+
+    #[test]
+    fn reprovision_method_is_a_cloud_command() {
+        let policy = ReprovisioningPolicy::new(0, None);
+        let request = MethodRequest {
+            name: REPROVISION_METHOD_NAME,
+            request_id: "1",
+        };
+        assert_eq!(
+            policy.on_method_request(&request),
+            Some(ReprovisionTrigger::CloudCommand)
+        );
+        let request = MethodRequest {
+            name: "reboot",
+            request_id: "1",
+        };
+        assert_eq!(policy.on_method_request(&request), None);
+        assert_eq!(
+            policy.should_reprovision(ReprovisionTrigger::HubRedirect),
+            Some(ReprovisionTrigger::HubRedirect)
+        );
+    }
+
+    #[test]
+    fn data_is_kept_on_the_same_hub() {
+        let previous = record("hub-a", "initialAssignment");
+        assert_eq!(
+            device_data_action(Some(&previous), &record("hub-a", "deviceDataReset")),
+            DeviceDataAction::Keep
+        );
+        assert_eq!(device_data_action(None, &previous), DeviceDataAction::Keep);
+    }
+
+    #[test]
+    fn data_follows_the_substatus_on_a_new_hub() {
+        let previous = record("hub-a", "initialAssignment");
+        assert_eq!(
+            device_data_action(Some(&previous), &record("hub-b", "deviceDataMigrated")),
+            DeviceDataAction::Keep
+        );
+        assert_eq!(
+            device_data_action(Some(&previous), &record("hub-b", "deviceDataReset")),
+            DeviceDataAction::Reset
+        );
+        assert_eq!(
+            device_data_action(Some(&previous), &record("hub-b", "initialAssignment")),
+            DeviceDataAction::Reset
+        );
+    }
+
+    #[test]
+    fn incomplete_result_is_a_reprovision_error() {
+        let mut policy = ReprovisioningPolicy::new(0, None);
+        let error = policy
+            .complete(10, None, &record("", "initialAssignment"))
+            .unwrap_err();
+        assert_eq!(*error.kind(), ErrorKind::Reprovision);
+        assert_eq!(policy.last_provisioned_epoch_time(), 0);
+    }
+}
@@ -0,0 +1,408 @@
+// serde-json-core can skip a JSON value but can't hand it back, so values of
+// unknown shape (like the `info` object of an error body) are located with this
+// scanner and kept as the raw slice of the message.
+use core::fmt::{self, Write};
+use core::str::Chars;
+
+use heapless::consts::U64;
+use heapless::Vec;
+use serde::ser::{Error, Serialize, Serializer};
+
+pub const AZ_ERROR_INVALID_JSON: &str = "The given value is not valid JSON.";
+
+// A single JSON value, exactly as it appeared in the message. Strings are not
+// unescaped.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RawJson<'a>(&'a str);
+
+impl<'a> RawJson<'a> {
+    pub fn new(json: &'a str) -> Result<RawJson<'a>, &'static str> {
+        let bytes = json.as_bytes();
+        let start = skip_whitespace(bytes, 0);
+        let end = value_end(bytes, start)?;
+        if skip_whitespace(bytes, end) != bytes.len() {
+            return Err(AZ_ERROR_INVALID_JSON);
+        }
+        Ok(RawJson(&json[start..end]))
+    }
+
+    pub fn as_str(&self) -> &'a str {
+        self.0
+    }
+
+    pub fn is_object(&self) -> bool {
+        self.0.starts_with('{')
+    }
+
+    // The contents of a string value, without the quotes.
+    pub fn as_string_value(&self) -> Option<&'a str> {
+        if self.0.len() >= 2 && self.0.starts_with('"') {
+            Some(&self.0[1..self.0.len() - 1])
+        } else {
+            None
+        }
+    }
+
+    pub fn as_u64(&self) -> Option<u64> {
+        self.0.parse().ok()
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match self.0 {
+            "true" => Some(true),
+            "false" => Some(false),
+            _ => None,
+        }
+    }
+
+    // Members of an object, in order. Anything but an object has none.
+    pub fn members(&self) -> Members<'a> {
+        Members {
+            json: if self.is_object() { self.0 } else { "" },
+            position: 1,
+        }
+    }
+
+    pub fn get(&self, key: &str) -> Option<RawJson<'a>> {
+        self.members()
+            .find(|(name, _)| *name == key)
+            .map(|(_, value)| value)
+    }
+
+    // Elements of an array, in order. Anything but an array has none.
+    pub fn elements(&self) -> Elements<'a> {
+        Elements {
+            json: if self.0.starts_with('[') { self.0 } else { "" },
+            position: 1,
+        }
+    }
+}
+
+// Written through the serializer's data model, so serde_json reproduces the
+// value as received apart from whitespace. serde-json-core 0.1 has no maps and
+// can only write values without objects.
+impl<'a> Serialize for RawJson<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        match self.0 {
+            "true" => serializer.serialize_bool(true),
+            "false" => serializer.serialize_bool(false),
+            "null" => serializer.serialize_none(),
+            _ if self.is_object() => serializer.collect_map(
+                self.members()
+                    .map(|(name, value)| (JsonString(name), value)),
+            ),
+            _ if self.0.starts_with('[') => serializer.collect_seq(self.elements()),
+            _ => match self.as_string_value() {
+                Some(value) => JsonString(value).serialize(serializer),
+                None => {
+                    if let Ok(value) = self.0.parse::<u64>() {
+                        serializer.serialize_u64(value)
+                    } else if let Ok(value) = self.0.parse::<i64>() {
+                        serializer.serialize_i64(value)
+                    } else if let Ok(value) = self.0.parse::<f64>() {
+                        serializer.serialize_f64(value)
+                    } else {
+                        Err(S::Error::custom(AZ_ERROR_INVALID_JSON))
+                    }
+                }
+            },
+        }
+    }
+}
+
+// The contents of a JSON string, written unescaped.
+struct JsonString<'a>(&'a str);
+
+impl<'a> Serialize for JsonString<'a> {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.0.contains('\\') {
+            // collect_str may panic on a failing Display, so invalid escapes
+            // are found first
+            fmt::write(&mut Discard, format_args!("{self}"))
+                .map_err(|_| S::Error::custom(AZ_ERROR_INVALID_JSON))?;
+            serializer.collect_str(self)
+        } else {
+            serializer.serialize_str(self.0)
+        }
+    }
+}
+
+impl<'a> fmt::Display for JsonString<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut chars = self.0.chars();
+        while let Some(c) = chars.next() {
+            if c != '\\' {
+                f.write_char(c)?;
+                continue;
+            }
+            let unescaped = match chars.next().ok_or(fmt::Error)? {
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'n' => '\n',
+                'r' => '\r',
+                't' => '\t',
+                'u' => {
+                    let high = hex4(&mut chars)?;
+                    let code = if (0xD800..0xDC00).contains(&high) {
+                        if chars.next() != Some('\\') || chars.next() != Some('u') {
+                            return Err(fmt::Error);
+                        }
+                        let low = hex4(&mut chars)?;
+                        if !(0xDC00..0xE000).contains(&low) {
+                            return Err(fmt::Error);
+                        }
+                        0x10000 + ((high - 0xD800) << 10) + (low - 0xDC00)
+                    } else {
+                        high
+                    };
+                    char::from_u32(code).ok_or(fmt::Error)?
+                }
+                // \" \\ and \/
+                escaped => escaped,
+            };
+            f.write_char(unescaped)?;
+        }
+        Ok(())
+    }
+}
+
+struct Discard;
+
+impl Write for Discard {
+    fn write_str(&mut self, _: &str) -> fmt::Result {
+        Ok(())
+    }
+}
+
+fn hex4(chars: &mut Chars<'_>) -> Result<u32, fmt::Error> {
+    let mut value = 0;
+    for _ in 0..4 {
+        let digit = chars
+            .next()
+            .and_then(|c| c.to_digit(16))
+            .ok_or(fmt::Error)?;
+        value = value * 16 + digit;
+    }
+    Ok(value)
+}
+
+pub struct Members<'a> {
+    json: &'a str,
+    position: usize,
+}
+
+impl<'a> Iterator for Members<'a> {
+    type Item = (&'a str, RawJson<'a>);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.json.as_bytes();
+        let mut i = skip_whitespace(bytes, self.position);
+        if bytes.get(i) == Some(&b',') {
+            i = skip_whitespace(bytes, i + 1);
+        }
+        if bytes.get(i) != Some(&b'"') {
+            self.position = bytes.len();
+            return None;
+        }
+        let key_end = value_end(bytes, i).ok()?;
+        let colon = skip_whitespace(bytes, key_end);
+        if bytes.get(colon) != Some(&b':') {
+            self.position = bytes.len();
+            return None;
+        }
+        let value_start = skip_whitespace(bytes, colon + 1);
+        let end = if let Ok(end) = value_end(bytes, value_start) {
+            end
+        } else {
+            self.position = bytes.len();
+            return None;
+        };
+        self.position = end;
+        Some((
+            &self.json[i + 1..key_end - 1],
+            RawJson(&self.json[value_start..end]),
+        ))
+    }
+}
+
+pub struct Elements<'a> {
+    json: &'a str,
+    position: usize,
+}
+
+impl<'a> Iterator for Elements<'a> {
+    type Item = RawJson<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let bytes = self.json.as_bytes();
+        let mut i = skip_whitespace(bytes, self.position);
+        if bytes.get(i) == Some(&b',') {
+            i = skip_whitespace(bytes, i + 1);
+        }
+        match value_end(bytes, i) {
+            Ok(end) if bytes[i] != b']' => {
+                self.position = end;
+                Some(RawJson(&self.json[i..end]))
+            }
+            _ => {
+                self.position = bytes.len();
+                None
+            }
+        }
+    }
+}
+
+fn skip_whitespace(bytes: &[u8], mut i: usize) -> usize {
+    while bytes.get(i).map_or(false, u8::is_ascii_whitespace) {
+        i += 1;
+    }
+    i
+}
+
+// Index just past the value starting at `start`.
+fn value_end(bytes: &[u8], start: usize) -> Result<usize, &'static str> {
+    match bytes.get(start) {
+        Some(b'"') => string_end(bytes, start),
+        Some(b'{' | b'[') => {
+            // the closer each open object or array is waiting for; nesting
+            // deeper than 64 is rejected
+            let mut closers: Vec<u8, U64> = Vec::new();
+            let mut i = start;
+            while let Some(b) = bytes.get(i) {
+                match b {
+                    b'"' => {
+                        i = string_end(bytes, i)?;
+                        continue;
+                    }
+                    b'{' => closers.push(b'}').map_err(|_| AZ_ERROR_INVALID_JSON)?,
+                    b'[' => closers.push(b']').map_err(|_| AZ_ERROR_INVALID_JSON)?,
+                    b'}' | b']' => {
+                        if closers.pop() != Some(*b) {
+                            return Err(AZ_ERROR_INVALID_JSON);
+                        }
+                        if closers.is_empty() {
+                            return Ok(i + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                i += 1;
+            }
+            Err(AZ_ERROR_INVALID_JSON)
+        }
+        Some(_) => {
+            let mut i = start;
+            while let Some(b) = bytes.get(i) {
+                match b {
+                    b',' | b'}' | b']' | b':' | b'"' | b' ' | b'\t' | b'\n' | b'\r' => break,
+                    _ => i += 1,
+                }
+            }
+            if i == start {
+                Err(AZ_ERROR_INVALID_JSON)
+            } else {
+                Ok(i)
+            }
+        }
+        None => Err(AZ_ERROR_INVALID_JSON),
+    }
+}
+
+fn string_end(bytes: &[u8], start: usize) -> Result<usize, &'static str> {
+    let mut i = start + 1;
+    while let Some(b) = bytes.get(i) {
+        match b {
+            b'\\' => i += 2,
+            b'"' => return Ok(i + 1),
+            _ => i += 1,
+        }
+    }
+    Err(AZ_ERROR_INVALID_JSON)
+}
+
+#[cfg(test)]
+mod tests_raw_json {
+    use super::*;
+
+    const INFO: &str = "{ \"retryAfter\": 30, \"reason\": \"quota \\\"exceeded\\\"\", \"nested\": {\"list\": [1, {\"a\": \"}\"}]}, \"flag\": true }";
+
+    #[test]
+    fn members_are_iterated_in_order() {
+        let json = RawJson::new(INFO).unwrap();
+        let keys: heapless::Vec<&str, heapless::consts::U8> =
+            json.members().map(|(key, _)| key).collect();
+        assert_eq!(&keys[..], &["retryAfter", "reason", "nested", "flag"]);
+    }
+
+    #[test]
+    fn values_are_raw_slices() {
+        let json = RawJson::new(INFO).unwrap();
+        assert_eq!(json.get("retryAfter").unwrap().as_u64(), Some(30));
+        assert_eq!(
+            json.get("reason").unwrap().as_string_value(),
+            Some("quota \\\"exceeded\\\"")
+        );
+        assert_eq!(
+            json.get("nested").unwrap().as_str(),
+            "{\"list\": [1, {\"a\": \"}\"}]}"
+        );
+        assert_eq!(json.get("flag").unwrap().as_bool(), Some(true));
+        assert!(json.get("missing").is_none());
+    }
+
+    #[test]
+    fn incomplete_values_are_rejected() {
+        assert_eq!(
+            RawJson::new("{\"a\": [1}").unwrap_err(),
+            AZ_ERROR_INVALID_JSON
+        );
+        assert_eq!(RawJson::new("\"open").unwrap_err(), AZ_ERROR_INVALID_JSON);
+        assert_eq!(RawJson::new("{\"a\":]").unwrap_err(), AZ_ERROR_INVALID_JSON);
+        assert_eq!(
+            RawJson::new("[{\"a\": 1]}").unwrap_err(),
+            AZ_ERROR_INVALID_JSON
+        );
+        assert_eq!(RawJson::new("1 2").unwrap_err(), AZ_ERROR_INVALID_JSON);
+        assert_eq!(RawJson::new("  ").unwrap_err(), AZ_ERROR_INVALID_JSON);
+    }
+
+    #[test]
+    fn non_objects_have_no_members() {
+        assert_eq!(RawJson::new("[1, 2]").unwrap().members().count(), 0);
+    }
+
+    #[test]
+    fn elements_are_iterated_in_order() {
+        let json = RawJson::new("[ 1, {\"a\": [2]} ,\"x\"]").unwrap();
+        let mut elements = json.elements();
+        assert_eq!(elements.next().unwrap().as_str(), "1");
+        assert_eq!(elements.next().unwrap().as_str(), "{\"a\": [2]}");
+        assert_eq!(elements.next().unwrap().as_str(), "\"x\"");
+        assert!(elements.next().is_none());
+        assert_eq!(RawJson::new("[]").unwrap().elements().count(), 0);
+        assert_eq!(RawJson::new("{}").unwrap().elements().count(), 0);
+    }
+
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn serializes_as_the_value_it_holds() {
+        let json = RawJson::new(INFO).unwrap();
+        let serialized = serde_json::to_string(&json).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&serialized).unwrap(),
+            serde_json::from_str::<serde_json::Value>(INFO).unwrap()
+        );
+        let escapes = "\"\\u00e9\\ud83d\\ude00\\n\\/\"";
+        assert_eq!(
+            serde_json::to_string(&RawJson::new(escapes).unwrap()).unwrap(),
+            "\"\u{e9}\u{1f600}\\n/\""
+        );
+        assert!(serde_json::to_string(&RawJson::new("\"\\ud83d\"").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,155 @@
+// Exponential backoff between attempts of a failed operation. A longer
+// retry-after from the service always wins.
+use crate::statuscode::ExtendedErrorCode;
+use azure_sdk_for_rust_common::clock::{Clock, Deadline};
+use azure_sdk_for_rust_common::log::{self, Classification};
+
+pub struct RetryOptions {
+    pub initial_delay_seconds: u64,
+    pub max_delay_seconds: u64,
+    // None retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for RetryOptions {
+    #[inline]
+    fn default() -> RetryOptions {
+        RetryOptions {
+            initial_delay_seconds: 2,
+            max_delay_seconds: 60,
+            max_attempts: None,
+        }
+    }
+}
+
+pub struct Backoff {
+    pub options: RetryOptions,
+    attempts: u32,
+}
+
+impl Backoff {
+    pub fn new(options: Option<RetryOptions>) -> Backoff {
+        Backoff {
+            options: options.unwrap_or_default(),
+            attempts: 0,
+        }
+    }
+
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    // Call once the operation succeeded.
+    pub fn reset(&mut self) {
+        self.attempts = 0;
+    }
+
+    // Seconds to wait before the next attempt, None once the attempts are used up.
+    pub fn next_delay(&mut self, retry_after_seconds: Option<u64>) -> Option<u64> {
+        if let Some(max_attempts) = self.options.max_attempts {
+            if self.attempts >= max_attempts {
+                log::write_fmt(
+                    Classification::Retry,
+                    format_args!("giving up after {} attempts", self.attempts),
+                );
+                return None;
+            }
+        }
+        let factor = 1_u64.checked_shl(self.attempts).unwrap_or(u64::MAX);
+        let delay = self
+            .options
+            .initial_delay_seconds
+            .saturating_mul(factor)
+            .min(self.options.max_delay_seconds);
+        self.attempts = self.attempts.saturating_add(1);
+        let delay = delay.max(retry_after_seconds.unwrap_or_default());
+        log::write_fmt(
+            Classification::Retry,
+            format_args!("attempt {} in {} seconds", self.attempts + 1, delay),
+        );
+        Some(delay)
+    }
+
+    pub fn next_attempt<C>(
+        &mut self,
+        clock: &C,
+        retry_after_seconds: Option<u64>,
+    ) -> Result<Option<Deadline>, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        match self.next_delay(retry_after_seconds) {
+            Some(delay) => Deadline::after(clock, delay).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // Errors that won't go away by waiting are never retried.
+    pub fn on_error<C>(
+        &mut self,
+        clock: &C,
+        code: ExtendedErrorCode,
+        retry_after_seconds: Option<u64>,
+    ) -> Result<Option<Deadline>, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        if code.is_retriable() {
+            self.next_attempt(clock, retry_after_seconds)
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_backoff {
+    use super::*;
+    use azure_sdk_for_rust_common::clock::ManualClock;
+
+    #[test]
+    fn delays_double_up_to_the_maximum() {
+        let mut backoff = Backoff::new(None);
+        let delays: heapless::Vec<u64, heapless::consts::U8> =
+            (0..7).filter_map(|_| backoff.next_delay(None)).collect();
+        assert_eq!(&delays[..], &[2, 4, 8, 16, 32, 60, 60]);
+        backoff.reset();
+        assert_eq!(backoff.next_delay(None), Some(2));
+    }
+
+    #[test]
+    fn retry_after_wins_when_longer() {
+        let mut backoff = Backoff::new(None);
+        assert_eq!(backoff.next_delay(Some(3)), Some(3));
+        assert_eq!(backoff.next_delay(Some(1)), Some(4));
+    }
+
+    #[test]
+    fn attempts_run_out() {
+        let mut backoff = Backoff::new(Some(RetryOptions {
+            max_attempts: Some(2),
+            ..RetryOptions::default()
+        }));
+        assert!(backoff.next_delay(None).is_some());
+        assert!(backoff.next_delay(None).is_some());
+        assert_eq!(backoff.next_delay(None), None);
+    }
+
+    #[test]
+    fn next_attempt_is_a_deadline_on_the_clock() {
+        let clock = ManualClock::from_epoch_time(1000);
+        let mut backoff = Backoff::new(None);
+        let deadline = backoff
+            .on_error(&clock, ExtendedErrorCode::THROTTLED, Some(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(deadline.expires(), 1005_u64);
+        assert_eq!(deadline.is_expired(&clock), Ok(false));
+        clock.advance(5);
+        assert_eq!(deadline.is_expired(&clock), Ok(true));
+        assert_eq!(
+            backoff.on_error(&clock, ExtendedErrorCode::DEVICE_NOT_FOUND, None),
+            Ok(None)
+        );
+    }
+}
@@ -1,18 +1,19 @@
-use hmac::{Hmac, Mac, NewMac};
-use sha2::Sha256;
-// Create alias for HMAC-SHA256
-type HmacSha256 = Hmac<Sha256>;
-
+use core::fmt;
 use heapless::consts::U128;
 
-use heapless::consts::U256;
+use heapless::consts::{U256, U512};
+use heapless::ArrayLength;
 use heapless::String;
-use heapless::Vec;
+use subtle::ConstantTimeEq;
 
-use super::base64::{base64_decode, base64_encode};
-use super::util::u64_to_string;
+use super::base64::base64_encode;
+use super::signer::{SasSigner, SymmetricKeySigner};
+use super::util::{u64_to_string, write_to_string, zeroize_string};
 
-use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+use azure_sdk_for_rust_common::clock::Clock;
+use azure_sdk_for_rust_common::error::{SpanError, AZ_ERROR_INSUFFICIENT_SPAN_SIZE};
+use azure_sdk_for_rust_common::log::{self, Classification};
+use azure_sdk_for_rust_common::span::SpanWriter;
 const LF: char = '\n';
 const AMPERSAND: char = '&';
 const EQUAL_SIGN: char = '=';
@@ -21,6 +22,8 @@ const SAS_TOKEN_SR: &str = "SharedAccessSignature sr";
 const SAS_TOKEN_SE: &str = "se";
 const SAS_TOKEN_SIG: &str = "sig";
 const SAS_TOKEN_SKN: &str = "skn";
+const SAS_TOKEN_PREFIX: &str = "SharedAccessSignature ";
+pub const AZ_ERROR_INVALID_SAS_TOKEN: &str = "The given SAS token is not valid.";
 use super::percent_encode;
 
 // Concatenates:
@@ -35,74 +38,347 @@ pub fn get_password(
     token_expiration_epoch_time: u64,
     key_name: Option<&String<U128>>,
 ) -> Result<String<U256>, &'static str> {
-    let sas_signature = get_sas_get_signature(&client, token_expiration_epoch_time)?;
-    let sas_b64_encoded_hmac256_signed_signature: String<U256> =
-        get_sas_b64_encoded_hmac256_signed_signature(&sas_key, &sas_signature)?;
+    get_password_with_capacity(
+        client,
+        sas_key,
+        token_expiration_epoch_time,
+        key_name.map(String::as_str),
+    )
+}
 
-    let mut res: String<U256> = String::new();
-    if res.push_str(SAS_TOKEN_SR).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+// Same as get_password with the signature computed by the signer, for keys
+// that can't be read.
+pub fn get_password_with_signer<S>(
+    client: &super::client::Client<'_>,
+    signer: &S,
+    token_expiration_epoch_time: u64,
+    key_name: Option<&str>,
+) -> Result<String<U256>, &'static str>
+where
+    S: SasSigner + ?Sized,
+{
+    write_to_string(|buffer| {
+        get_password_into_with_signer(
+            client,
+            signer,
+            token_expiration_epoch_time,
+            key_name,
+            buffer,
+        )
+    })
+}
+
+// Same as get_password with the expiration taken from the clock. The returned
+// lifetime says when the password has to be renewed.
+pub fn get_password_with_clock<C>(
+    client: &super::client::Client<'_>,
+    sas_key: &str,
+    clock: &C,
+    lifetime_seconds: u64,
+    renew_before_seconds: u64,
+) -> Result<(String<U256>, TokenLifetime), &'static str>
+where
+    C: Clock + ?Sized,
+{
+    let lifetime = TokenLifetime::from_now(clock, lifetime_seconds, renew_before_seconds)?;
+    let password = get_password(client, sas_key, lifetime.expiration_epoch_time, None)?;
+    Ok((password, lifetime))
+}
+
+pub fn get_password_with_capacity<B>(
+    client: &super::client::Client<'_>,
+    sas_key: &str,
+    token_expiration_epoch_time: u64,
+    key_name: Option<&str>,
+) -> Result<String<B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    write_to_string(|buffer| {
+        get_password_into(
+            client,
+            sas_key,
+            token_expiration_epoch_time,
+            key_name,
+            buffer,
+        )
+    })
+}
+
+// Returns the number of bytes written, or the required length when the buffer
+// is too small.
+pub fn get_password_into(
+    client: &super::client::Client<'_>,
+    sas_key: &str,
+    token_expiration_epoch_time: u64,
+    key_name: Option<&str>,
+    buffer: &mut [u8],
+) -> Result<usize, SpanError> {
+    let signer = SymmetricKeySigner::new(sas_key)?;
+    get_password_into_with_signer(
+        client,
+        &signer,
+        token_expiration_epoch_time,
+        key_name,
+        buffer,
+    )
+}
+
+pub fn get_password_into_with_signer<S>(
+    client: &super::client::Client<'_>,
+    signer: &S,
+    token_expiration_epoch_time: u64,
+    key_name: Option<&str>,
+    buffer: &mut [u8],
+) -> Result<usize, SpanError>
+where
+    S: SasSigner + ?Sized,
+{
+    let sas_signature = get_sas_get_signature(client, token_expiration_epoch_time)?;
+    let sas_b64_encoded_hmac256_signed_signature =
+        get_sas_b64_encoded_signature(signer, &sas_signature)?;
+
+    let mut writer = SpanWriter::new(buffer);
+    writer.push_str(SAS_TOKEN_SR);
+    writer.push(EQUAL_SIGN);
+    write_resource(&mut writer, client);
+    writer.push(AMPERSAND);
+    writer.push_str(SAS_TOKEN_SIG);
+    writer.push(EQUAL_SIGN);
+    percent_encode::write_encoded(
+        &mut writer,
+        sas_b64_encoded_hmac256_signed_signature.as_str(),
+    );
+    writer.push(AMPERSAND);
+    writer.push_str(SAS_TOKEN_SE);
+    writer.push(EQUAL_SIGN);
+    writer.push_str(u64_to_string(token_expiration_epoch_time).as_str());
+    if let Some(key) = key_name {
+        writer.push(AMPERSAND);
+        writer.push_str(SAS_TOKEN_SKN);
+        writer.push(EQUAL_SIGN);
+        writer.push_str(key);
     }
-    if res.push(EQUAL_SIGN).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    let written = writer.finish()?;
+    // the signature is redacted
+    if let Ok(token) = core::str::from_utf8(&buffer[..written]) {
+        log::write(Classification::SasToken, token);
     }
-    let encoded_scope = percent_encode::encode(client.id_scope)?;
-    if res.push_str(encoded_scope.as_str()).is_err() {
+    Ok(written)
+}
+
+// url-encoded(<scope-id>)%2fregistrations%2furl-encoded(<registration-id>)
+fn write_resource(writer: &mut SpanWriter<'_>, client: &super::client::Client<'_>) {
+    percent_encode::write_encoded(writer, client.id_scope.as_str());
+    writer.push_str(SCOPE_REGISTRATIONS_STRING);
+    percent_encode::write_encoded(writer, client.registration_id.as_str());
+}
+
+// Builds "SharedAccessSignature sr=<encoded_resource>&sig=<signature>&se=<expiration-time>[&skn=<key-name>]"
+// for any resource. The signature is computed over "<encoded_resource>\n<expiration-time>".
+pub(crate) fn get_sas_token<B, S>(
+    encoded_resource: &str,
+    signer: &S,
+    token_expiration_epoch_time: u64,
+    key_name: Option<&str>,
+) -> Result<String<B>, &'static str>
+where
+    B: ArrayLength<u8>,
+    S: SasSigner + ?Sized,
+{
+    let epoch_string = u64_to_string(token_expiration_epoch_time);
+    let mut sas_signature: String<B> = String::new();
+    if sas_signature.push_str(encoded_resource).is_err()
+        || sas_signature.push(LF).is_err()
+        || sas_signature.push_str(epoch_string.as_str()).is_err()
+    {
         return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
     }
-    if res.push_str(SCOPE_REGISTRATIONS_STRING).is_err() {
+    let signature = get_sas_b64_encoded_signature(signer, sas_signature.as_str())?;
+
+    let mut res: String<B> = String::new();
+    if res.push_str(SAS_TOKEN_SR).is_err()
+        || res.push(EQUAL_SIGN).is_err()
+        || res.push_str(encoded_resource).is_err()
+        || res.push(AMPERSAND).is_err()
+        || res.push_str(SAS_TOKEN_SIG).is_err()
+        || res.push(EQUAL_SIGN).is_err()
+    {
         return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
     }
-    let encoded_reg_id = percent_encode::encode(client.registration_id)?;
-    if res.push_str(encoded_reg_id.as_str()).is_err() {
+    percent_encode::encode_into(&mut res, signature.as_str())?;
+    if res.push(AMPERSAND).is_err()
+        || res.push_str(SAS_TOKEN_SE).is_err()
+        || res.push(EQUAL_SIGN).is_err()
+        || res.push_str(epoch_string.as_str()).is_err()
+    {
         return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
     }
-    if res.push(AMPERSAND).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    if let Some(key) = key_name {
+        if res.push(AMPERSAND).is_err()
+            || res.push_str(SAS_TOKEN_SKN).is_err()
+            || res.push(EQUAL_SIGN).is_err()
+            || res.push_str(key).is_err()
+        {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
     }
-    if res.push_str(SAS_TOKEN_SIG).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    // the signature is redacted
+    log::write(Classification::SasToken, &res);
+    Ok(res)
+}
+
+// The parts of "SharedAccessSignature sr=<resource>&sig=<signature>&se=<expiration-time>[&skn=<key-name>]".
+// The resource and signature are left url-encoded.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SasToken<'a> {
+    pub resource: &'a str,
+    pub signature: &'a str,
+    pub expiration_epoch_time: u64,
+    pub key_name: Option<&'a str>,
+}
+
+impl<'a> SasToken<'a> {
+    pub fn parse(token: &'a str) -> Result<SasToken<'a>, &'static str> {
+        if !token.starts_with(SAS_TOKEN_PREFIX) {
+            return Err(AZ_ERROR_INVALID_SAS_TOKEN);
+        }
+        let mut resource = None;
+        let mut signature = None;
+        let mut expiration_epoch_time = None;
+        let mut key_name = None;
+        for pair in token[SAS_TOKEN_PREFIX.len()..].split(AMPERSAND) {
+            let mut parts = pair.splitn(2, EQUAL_SIGN);
+            match (parts.next(), parts.next()) {
+                (Some("sr"), Some(value)) => resource = Some(value),
+                (Some(SAS_TOKEN_SIG), Some(value)) => signature = Some(value),
+                (Some(SAS_TOKEN_SE), Some(value)) => match value.parse::<u64>() {
+                    Ok(epoch) => expiration_epoch_time = Some(epoch),
+                    Err(_) => return Err(AZ_ERROR_INVALID_SAS_TOKEN),
+                },
+                (Some(SAS_TOKEN_SKN), Some(value)) => key_name = Some(value),
+                _ => return Err(AZ_ERROR_INVALID_SAS_TOKEN),
+            }
+        }
+        match (resource, signature, expiration_epoch_time) {
+            (Some(resource), Some(signature), Some(expiration_epoch_time)) => Ok(SasToken {
+                resource,
+                signature,
+                expiration_epoch_time,
+                key_name,
+            }),
+            _ => Err(AZ_ERROR_INVALID_SAS_TOKEN),
+        }
     }
-    if res.push(EQUAL_SIGN).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+}
+
+impl<'a> SasToken<'a> {
+    // <scope-id>/registrations/<registration-id> or <hub_hostname>/devices/<device_id>[...]
+    pub fn decode_resource(&self) -> Result<String<U256>, &'static str> {
+        percent_encode::decode(self.resource)
     }
-    let encoded_base64_hmac_sha256_signature =
-        percent_encode::encode(sas_b64_encoded_hmac256_signed_signature.as_str())?;
-    if res
-        .push_str(encoded_base64_hmac_sha256_signature.as_str())
-        .is_err()
+
+    // Recomputes the signature over "<resource>\n<expiration-time>" with the
+    // given key. Works for DPS and hub tokens alike as the resource is signed
+    // exactly as it appears in the token.
+    pub fn verify(&self, sas_key: &str) -> Result<bool, &'static str> {
+        self.verify_with_signer(&SymmetricKeySigner::new(sas_key)?)
+    }
+
+    pub fn verify_with_signer<S>(&self, signer: &S) -> Result<bool, &'static str>
+    where
+        S: SasSigner + ?Sized,
     {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        let mut sas_signature: String<U512> = String::new();
+        if sas_signature.push_str(self.resource).is_err()
+            || sas_signature.push(LF).is_err()
+            || sas_signature
+                .push_str(u64_to_string(self.expiration_epoch_time).as_str())
+                .is_err()
+        {
+            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+        }
+        let expected = get_sas_b64_encoded_signature(signer, &sas_signature)?;
+        let actual: String<U256> = percent_encode::decode(self.signature)?;
+        Ok(expected.as_bytes().ct_eq(actual.as_bytes()).into())
     }
-    if res.push(AMPERSAND).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+
+    pub fn is_expired<C>(&self, clock: &C) -> Result<bool, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        Ok(clock.epoch_time()? >= self.expiration_epoch_time)
     }
-    if res.push_str(SAS_TOKEN_SE).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenLifetime {
+    pub expiration_epoch_time: u64,
+    // Renewing a little early leaves time to reconnect before the hub drops us.
+    pub renew_before_seconds: u64,
+}
+
+impl TokenLifetime {
+    pub fn from_now<C>(
+        clock: &C,
+        lifetime_seconds: u64,
+        renew_before_seconds: u64,
+    ) -> Result<TokenLifetime, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        Ok(TokenLifetime {
+            expiration_epoch_time: clock.epoch_time()?.saturating_add(lifetime_seconds),
+            renew_before_seconds,
+        })
     }
-    if res.push(EQUAL_SIGN).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+
+    pub fn renewal_epoch_time(&self) -> u64 {
+        self.expiration_epoch_time
+            .saturating_sub(self.renew_before_seconds)
     }
-    let epoch_string = u64_to_string(token_expiration_epoch_time);
-    if res.push_str(epoch_string.as_str()).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+
+    pub fn needs_renewal<C>(&self, clock: &C) -> Result<bool, &'static str>
+    where
+        C: Clock + ?Sized,
+    {
+        Ok(clock.epoch_time()? >= self.renewal_epoch_time())
     }
-    if let Some(key) = key_name {
-        if res.push(AMPERSAND).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-        }
-        if res.push_str(SAS_TOKEN_SKN).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-        }
-        if res.push(EQUAL_SIGN).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-        }
-        if res.push_str(key.as_str()).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-        }
+}
+
+// Device key for an enrollment group member:
+// base64(hmac-sha256(base64-decoded(<group-key>), <registration-id>))
+pub fn derive_device_key(
+    group_key: &str,
+    registration_id: &str,
+) -> Result<DerivedDeviceKey, &'static str> {
+    get_sas_b64_encoded_hmac256_signed_signature(group_key, registration_id)
+        .map(|key| DerivedDeviceKey { key })
+}
+
+// The key is wiped when it is dropped and never shown.
+pub struct DerivedDeviceKey {
+    key: String<U256>,
+}
+
+impl DerivedDeviceKey {
+    pub fn as_str(&self) -> &str {
+        self.key.as_str()
+    }
+}
+
+impl Drop for DerivedDeviceKey {
+    fn drop(&mut self) {
+        zeroize_string(&mut self.key);
+    }
+}
+
+impl fmt::Debug for DerivedDeviceKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DerivedDeviceKey")
+            .field("key", &log::REDACTED)
+            .finish()
     }
-    res.truncate(res.len());
-    Ok(res)
 }
 
 // Produces the following signature:
@@ -112,63 +388,28 @@ pub fn get_password(
 fn get_sas_get_signature(
     client: &super::client::Client<'_>,
     token_expiration_epoch_time: u64,
-) -> Result<String<U128>, &'static str> {
-    let mut res: String<U128> = String::new();
-    let encoded_scope = percent_encode::encode(client.id_scope)?;
-    if res.push_str(encoded_scope.as_str()).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-    }
-    if res.push_str(SCOPE_REGISTRATIONS_STRING).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-    }
-    let encoded_reg_id = percent_encode::encode(client.registration_id)?;
-    if res.push_str(encoded_reg_id.as_str()).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-    }
-    if res.push(LF).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-    }
-    let epoch_string = u64_to_string(token_expiration_epoch_time);
-    if res.push_str(epoch_string.as_str()).is_err() {
-        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-    }
-    res.truncate(res.len());
-    Ok(res)
+) -> Result<String<U512>, &'static str> {
+    write_to_string(|buffer| {
+        let mut writer = SpanWriter::new(buffer);
+        write_resource(&mut writer, client);
+        writer.push(LF);
+        writer.push_str(u64_to_string(token_expiration_epoch_time).as_str());
+        writer.finish()
+    })
 }
 
 fn get_sas_b64_encoded_hmac256_signed_signature(
     sas_key: &str,
-    sas_sig: &String<U128>,
+    sas_sig: &str,
 ) -> Result<String<U256>, &'static str> {
-    let decoded_sas_key = base64_decode(&sas_key)?;
-    let sas_encoded_hmac256_signed_signature = hmac_sha256_sign(&decoded_sas_key, &sas_sig)?;
-    let sas_b64_encoded_hmac256_signed_signature =
-        base64_encode(&sas_encoded_hmac256_signed_signature)?;
-    Ok(sas_b64_encoded_hmac256_signed_signature)
-}
-
-fn hmac_sha256_sign<'a>(
-    key: &Vec<u8, U256>,
-    data: &String<U128>,
-) -> Result<Vec<u8, U128>, &'a str> {
-    let mut mac = HmacSha256::new_varkey(key).expect("HMAC can take key of any size");
-    mac.update(data.as_bytes());
-
-    // `result` has type `Output` which is a thin wrapper around array of
-    // bytes for providing constant time equality check
-    let result = mac.finalize();
-
-    // To get underlying array use `into_bytes` method, but be careful, since
-    // incorrect use of the code value may permit timing attacks which defeat
-    // the security provided by the `Output`
-    let code_bytes = result.into_bytes();
-    let mut vec: Vec<u8, U128> = Vec::new();
-    for b in code_bytes.iter() {
-        if vec.push(*b).is_err() {
-            return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
-        }
-    }
-    Ok(vec)
+    get_sas_b64_encoded_signature(&SymmetricKeySigner::new(sas_key)?, sas_sig)
+}
+
+fn get_sas_b64_encoded_signature<S>(signer: &S, sas_sig: &str) -> Result<String<U256>, &'static str>
+where
+    S: SasSigner + ?Sized,
+{
+    base64_encode(signer.sign(sas_sig.as_bytes())?)
 }
 
 #[cfg(test)]
@@ -188,7 +429,8 @@ mod tests_sas_b64_encoded_hmac256_signed_signature {
             &id_scope,
             &registration_id,
             options,
-        );
+        )
+        .unwrap();
         let sas_signature = get_sas_get_signature(&client, token_expiration_epoch_time).unwrap();
         let base64_hmac_sha256_signature: String<U256> =
             get_sas_b64_encoded_hmac256_signed_signature(&sas_key, &sas_signature).unwrap();
@@ -196,3 +438,143 @@ mod tests_sas_b64_encoded_hmac256_signed_signature {
         assert_eq!(base64_hmac_sha256_signature.as_str(), expected);
     }
 }
+
+#[cfg(test)]
+mod tests_sas_token {
+    use super::*;
+    use crate::provisioning::client::Client;
+    use azure_sdk_for_rust_common::clock::ManualClock;
+    use core::sync::atomic::{AtomicBool, Ordering};
+    #[test]
+    fn password_can_be_parsed() {
+        let client = Client::new("", "eight675309", "1-1-2-3-5-8-13-21", None).unwrap();
+        let sas_key = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+        let password = get_password(&client, sas_key, 1_596_897_539, None).unwrap();
+        let token = SasToken::parse(password.as_str()).unwrap();
+        assert_eq!(
+            token.resource,
+            "eight675309%2fregistrations%2f1-1-2-3-5-8-13-21"
+        );
+        assert_eq!(
+            token.signature,
+            "npj3I09%2BJtl6VYHZM%2FH5mKMG8jn4Y3zty3dMjMkMMDs%3D"
+        );
+        assert_eq!(token.expiration_epoch_time, 1_596_897_539);
+        assert!(token.key_name.is_none());
+        assert_eq!(
+            token.decode_resource().unwrap().as_str(),
+            "eight675309/registrations/1-1-2-3-5-8-13-21"
+        );
+    }
+
+    #[test]
+    fn password_into_matches_the_fixed_capacity_variant() {
+        let client = Client::new("", "eight675309", "1-1-2-3-5-8-13-21", None).unwrap();
+        let sas_key = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+        let password = get_password(&client, sas_key, 1_596_897_539, None).unwrap();
+        let mut buffer = [0_u8; 256];
+        let written =
+            get_password_into(&client, sas_key, 1_596_897_539, None, &mut buffer).unwrap();
+        assert_eq!(&buffer[..written], password.as_bytes());
+        assert_eq!(
+            get_password_into(&client, sas_key, 1_596_897_539, None, &mut buffer[..16]),
+            Err(SpanError::InsufficientSize { required: written })
+        );
+        assert_eq!(
+            get_password_into(&client, "not base64!", 1_596_897_539, None, &mut buffer),
+            Err(SpanError::Other(
+                super::super::base64::AZ_ERROR_UNABLE_TO_DECODE_BASE64
+            ))
+        );
+    }
+
+    #[test]
+    fn clock_sets_the_expiration() {
+        let client = Client::new("", "eight675309", "1-1-2-3-5-8-13-21", None).unwrap();
+        let sas_key = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+        let clock = ManualClock::from_epoch_time(1_596_893_939);
+        let (password, lifetime) =
+            get_password_with_clock(&client, sas_key, &clock, 3600, 300).unwrap();
+        assert_eq!(
+            password,
+            get_password(&client, sas_key, 1_596_897_539, None).unwrap()
+        );
+        let token = SasToken::parse(password.as_str()).unwrap();
+        assert_eq!(token.is_expired(&clock), Ok(false));
+        clock.advance(3299);
+        assert_eq!(lifetime.needs_renewal(&clock), Ok(false));
+        clock.advance(1);
+        assert_eq!(lifetime.needs_renewal(&clock), Ok(true));
+        clock.advance(300);
+        assert_eq!(token.is_expired(&clock), Ok(true));
+    }
+
+    #[test]
+    fn password_is_verified() {
+        let client = Client::new("", "eight675309", "1-1-2-3-5-8-13-21", None).unwrap();
+        let sas_key = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+        let password = get_password(&client, sas_key, 1_596_897_539, None).unwrap();
+        let token = SasToken::parse(password.as_str()).unwrap();
+        assert_eq!(token.verify(sas_key), Ok(true));
+        assert_eq!(token.verify("b3RoZXIga2V5"), Ok(false));
+        let tampered = SasToken {
+            expiration_epoch_time: 1_596_897_540,
+            ..token
+        };
+        assert_eq!(tampered.verify(sas_key), Ok(false));
+        assert!(token.verify("not base64!").is_err());
+    }
+
+    #[test]
+    fn device_key_is_derived_from_the_group_key() {
+        // the enrollment group example from the DPS documentation
+        let group_key =
+            "8isrFI1sGsIlvvFSSFRiMfCNzv21fjbE/+ah/lSh3lF8e2YG1Te7w1KpZhJFFXJrqYKi9yegxkqIChbqOS9Egw==";
+        let key = derive_device_key(group_key, "sn-007-888-abc-mac-a1-b2-c3-d4-e5-f6").unwrap();
+        assert_eq!(key.as_str(), "Jsm0lyGpjaVYVP2g3FnmnmG9dI/9qU24wNoykUmermc=");
+        assert!(derive_device_key("not base64!", "device-1").is_err());
+    }
+
+    static LOGGED_SAS_TOKEN: AtomicBool = AtomicBool::new(false);
+
+    fn check_redacted(classification: Classification, message: &str) {
+        if classification == Classification::SasToken {
+            assert!(message.contains("&sig=***&"), "{}", message);
+            LOGGED_SAS_TOKEN.store(true, Ordering::SeqCst);
+        }
+    }
+
+    // Removes the global callback even if the test panics.
+    struct ResetCallback;
+
+    impl Drop for ResetCallback {
+        fn drop(&mut self) {
+            log::set_message_callback(None);
+        }
+    }
+
+    #[test]
+    fn logged_passwords_are_redacted() {
+        // the only test in this crate that sets the global callback
+        log::set_message_callback(Some(check_redacted));
+        let _reset = ResetCallback;
+        let client = Client::new("", "eight675309", "1-1-2-3-5-8-13-21", None).unwrap();
+        let sas_key = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+        get_password(&client, sas_key, 1_596_897_539, None).unwrap();
+        assert!(LOGGED_SAS_TOKEN.load(Ordering::SeqCst));
+    }
+
+    #[test]
+    fn key_name_is_parsed() {
+        let token =
+            SasToken::parse("SharedAccessSignature sr=a&sig=b&se=1&skn=registration").unwrap();
+        assert_eq!(token.key_name, Some("registration"));
+    }
+
+    #[test]
+    fn missing_parts_are_rejected() {
+        assert!(SasToken::parse("SharedAccessSignature sr=a&se=1").is_err());
+        assert!(SasToken::parse("SharedAccessSignature sr=a&sig=b&se=soon").is_err());
+        assert!(SasToken::parse("sr=a&sig=b&se=1").is_err());
+    }
+}
@@ -1,39 +1,255 @@
-use serde::{Deserialize, Serialize};
+use super::api_version::ApiVersion;
+use super::raw_json::{RawJson, AZ_ERROR_INVALID_JSON};
+use crate::statuscode::ExtendedErrorCode;
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+use azure_sdk_for_rust_common::timestamp::Timestamp;
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Deref;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::ser::{Error as _, Impossible, SerializeMap, SerializeStruct};
+use serde::{Deserialize, Serialize, Serializer};
+
+#[cfg(feature = "alloc")]
+pub mod owned;
 
 // https://docs.microsoft.com/en-us/rest/api/iot-dps/runtimeregistration/registerdevice#deviceregistrationresult
 
 // PUT https://global.azure-devices-provisioning.net/{idScope}/registrations/{registrationId}/register?api-version=2019-03-31
 
+// The custom allocation payload is whatever JSON the allocation webhook and the
+// device agree on. It defaults to a plain string; use a struct (or an Option of
+// one, so a missing payload still parses) to read an object.
+
 // https://docs.microsoft.com/en-us/rest/api/iot-dps/runtimeregistration/registerdevice#deviceregistration
-#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct DeviceRegistration<'a> {
+#[derive(Clone, Debug, Deserialize, PartialEq)]
+pub struct DeviceRegistration<'a, P = &'a str> {
     /// Custom allocation payload.
     #[serde(rename = "payload")]
-    pub payload: &'a str,
+    pub payload: P,
     /// Registration Id.
     #[serde(rename = "registrationId")]
     pub registration_id: &'a str,
-    #[serde(rename = "status")]
+    #[serde(rename = "tpm", default)]
     pub tpm: Option<TpmAttestation<'a>>,
+    /// Base64 encoded PKCS #10 request for an issued client certificate.
+    #[serde(rename = "certificateSigningRequest", default)]
+    pub certificate_signing_request: Option<&'a str>,
+    /// Plug and Play model ID, e.g. dtmi:com:example:Thermostat;1. It is sent
+    /// as the first member of the payload object, where it stays when parsed.
+    #[serde(skip)]
+    pub model_id: Option<&'a str>,
 }
 
-impl<'a> DeviceRegistration<'a> {
+impl<'a, P> DeviceRegistration<'a, P> {
     pub fn new(
-        payload: &'a str,
+        payload: P,
         registration_id: &'a str,
         tpm: Option<TpmAttestation<'a>>,
-    ) -> DeviceRegistration<'a> {
+    ) -> DeviceRegistration<'a, P> {
         DeviceRegistration {
             payload,
             registration_id,
             tpm,
+            certificate_signing_request: None,
+            model_id: None,
         }
     }
 }
 
+// Not derived because the model ID goes into the payload:
+// {"payload":{"modelId":"<model_id>",<members of the payload>},"registrationId":...}
+impl<'a, P> Serialize for DeviceRegistration<'a, P>
+where
+    P: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let mut state = serializer.serialize_struct("DeviceRegistration", 4)?;
+        match self.model_id {
+            Some(model_id) => state.serialize_field(
+                "payload",
+                &WithModelId {
+                    model_id,
+                    payload: &self.payload,
+                },
+            )?,
+            None => state.serialize_field("payload", &self.payload)?,
+        }
+        state.serialize_field("registrationId", self.registration_id)?;
+        match &self.tpm {
+            Some(tpm) => state.serialize_field("tpm", tpm)?,
+            None => state.skip_field("tpm")?,
+        }
+        match self.certificate_signing_request {
+            Some(request) => state.serialize_field("certificateSigningRequest", request)?,
+            None => state.skip_field("certificateSigningRequest")?,
+        }
+        state.end()
+    }
+}
+
+struct WithModelId<'r, P> {
+    model_id: &'r str,
+    payload: &'r P,
+}
+
+impl<'r, P> Serialize for WithModelId<'r, P>
+where
+    P: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        self.payload.serialize(ModelIdSerializer {
+            serializer,
+            model_id: self.model_id,
+        })
+    }
+}
+
+// Hands the payload on to the serializer with the model ID written first into
+// its struct or map. An empty payload ("", null or unit) becomes
+// {"modelId":"<model_id>"}; anything else can't hold the model ID.
+struct ModelIdSerializer<'r, S> {
+    serializer: S,
+    model_id: &'r str,
+}
+
+impl<'r, S> ModelIdSerializer<'r, S>
+where
+    S: Serializer,
+{
+    fn model_id_only(self) -> Result<S::Ok, S::Error> {
+        let mut state = self.serializer.serialize_struct("Payload", 1)?;
+        state.serialize_field("modelId", self.model_id)?;
+        state.end()
+    }
+}
+
+macro_rules! refuse {
+    ($($method:ident($($argument:ty),*) -> $ok:ty;)*) => {
+        $(
+            fn $method(self, $(_: $argument),*) -> Result<$ok, S::Error> {
+                Err(S::Error::custom(AZ_ERROR_INVALID_JSON))
+            }
+        )*
+    };
+}
+
+impl<'r, S> Serializer for ModelIdSerializer<'r, S>
+where
+    S: Serializer,
+{
+    type Ok = S::Ok;
+    type Error = S::Error;
+    type SerializeSeq = Impossible<S::Ok, S::Error>;
+    type SerializeTuple = Impossible<S::Ok, S::Error>;
+    type SerializeTupleStruct = Impossible<S::Ok, S::Error>;
+    type SerializeTupleVariant = Impossible<S::Ok, S::Error>;
+    type SerializeMap = S::SerializeMap;
+    type SerializeStruct = S::SerializeStruct;
+    type SerializeStructVariant = Impossible<S::Ok, S::Error>;
+
+    fn serialize_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<S::SerializeStruct, S::Error> {
+        let mut state = self.serializer.serialize_struct(name, len + 1)?;
+        state.serialize_field("modelId", self.model_id)?;
+        Ok(state)
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<S::SerializeMap, S::Error> {
+        let mut state = self.serializer.serialize_map(len.map(|len| len + 1))?;
+        state.serialize_entry("modelId", self.model_id)?;
+        Ok(state)
+    }
+
+    fn serialize_str(self, value: &str) -> Result<S::Ok, S::Error> {
+        if value.is_empty() {
+            self.model_id_only()
+        } else {
+            Err(S::Error::custom(AZ_ERROR_INVALID_JSON))
+        }
+    }
+
+    fn serialize_none(self) -> Result<S::Ok, S::Error> {
+        self.model_id_only()
+    }
+
+    fn serialize_unit(self) -> Result<S::Ok, S::Error> {
+        self.model_id_only()
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> Result<S::Ok, S::Error> {
+        self.model_id_only()
+    }
+
+    fn serialize_some<T>(self, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(
+        self,
+        _name: &'static str,
+        _index: u32,
+        _variant: &'static str,
+        _value: &T,
+    ) -> Result<S::Ok, S::Error>
+    where
+        T: Serialize + ?Sized,
+    {
+        Err(S::Error::custom(AZ_ERROR_INVALID_JSON))
+    }
+
+    fn collect_str<T>(self, _value: &T) -> Result<S::Ok, S::Error>
+    where
+        T: fmt::Display + ?Sized,
+    {
+        Err(S::Error::custom(AZ_ERROR_INVALID_JSON))
+    }
+
+    refuse! {
+        serialize_bool(bool) -> S::Ok;
+        serialize_i8(i8) -> S::Ok;
+        serialize_i16(i16) -> S::Ok;
+        serialize_i32(i32) -> S::Ok;
+        serialize_i64(i64) -> S::Ok;
+        serialize_u8(u8) -> S::Ok;
+        serialize_u16(u16) -> S::Ok;
+        serialize_u32(u32) -> S::Ok;
+        serialize_u64(u64) -> S::Ok;
+        serialize_f32(f32) -> S::Ok;
+        serialize_f64(f64) -> S::Ok;
+        serialize_char(char) -> S::Ok;
+        serialize_bytes(&[u8]) -> S::Ok;
+        serialize_unit_variant(&'static str, u32, &'static str) -> S::Ok;
+        serialize_seq(Option<usize>) -> Self::SerializeSeq;
+        serialize_tuple(usize) -> Self::SerializeTuple;
+        serialize_tuple_struct(&'static str, usize) -> Self::SerializeTupleStruct;
+        serialize_tuple_variant(&'static str, u32, &'static str, usize) -> Self::SerializeTupleVariant;
+        serialize_struct_variant(&'static str, u32, &'static str, usize) -> Self::SerializeStructVariant;
+    }
+}
+
 // https://docs.microsoft.com/en-us/rest/api/iot-dps/runtimeregistration/registerdevice#deviceregistrationresult
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct DeviceRegistrationResult<'a> {
+pub struct DeviceRegistrationResult<'a, P = &'a str> {
     #[serde(rename = "assignedHub", default)]
     pub assigned_hub: &'a str,
     #[serde(rename = "createdDateTimeUtc", default)]
@@ -54,7 +270,7 @@ pub struct DeviceRegistrationResult<'a> {
 
     /// Custom allocation payload returned from the webhook to the device.
     #[serde(rename = "payload", default)]
-    pub payload: &'a str,
+    pub payload: P,
 
     /// The registration ID is alphanumeric, lowercase, and may contain hyphens.
     #[serde(rename = "registrationId", default)]
@@ -85,9 +301,111 @@ pub struct DeviceRegistrationResult<'a> {
     pub tpm: Option<TpmRegistrationResult<'a>>,
     #[serde(rename = "x509", skip_serializing_if = "Option::is_none", default)]
     pub x509: Option<X509RegistrationResult<'a>>,
+
+    /// The client certificate issued for the certificate signing request,
+    /// leaf first, each certificate base64 encoded DER.
+    #[serde(
+        rename = "issuedCertificateChain",
+        skip_serializing_if = "<[_]>::is_empty",
+        default
+    )]
+    pub issued_certificate_chain: CertificateChain<'a>,
+
+    /// CA certificates for the device's trust store, see trust_bundle.
+    #[serde(
+        rename = "trustBundle",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub trust_bundle: Option<TrustBundle<'a>>,
+}
+
+// Arrays in the models have a fixed capacity. They are kept in plain arrays
+// rather than heapless::Vec, which would make the models invariant over 'a.
+macro_rules! bounded_list {
+    ($name:ident, $item:ty, $capacity:expr, $expecting:expr) => {
+        #[derive(Clone, Copy, Debug, Default, PartialEq)]
+        pub struct $name<'a> {
+            items: [$item; $capacity],
+            len: usize,
+        }
+
+        impl<'a> $name<'a> {
+            pub fn new() -> $name<'a> {
+                $name::default()
+            }
+
+            pub fn push(&mut self, item: $item) -> Result<(), &'static str> {
+                let slot = self
+                    .items
+                    .get_mut(self.len)
+                    .ok_or(AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+                *slot = item;
+                self.len += 1;
+                Ok(())
+            }
+        }
+
+        impl<'a> Deref for $name<'a> {
+            type Target = [$item];
+
+            fn deref(&self) -> &[$item] {
+                &self.items[..self.len]
+            }
+        }
+
+        impl<'a> Serialize for $name<'a> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                serializer.collect_seq(self.iter())
+            }
+        }
+
+        impl<'de: 'a, 'a> Deserialize<'de> for $name<'a> {
+            fn deserialize<D>(deserializer: D) -> Result<$name<'a>, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                struct ListVisitor<'a>(PhantomData<&'a ()>);
+
+                impl<'de: 'a, 'a> Visitor<'de> for ListVisitor<'a> {
+                    type Value = $name<'a>;
+
+                    fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+                        formatter.write_str($expecting)
+                    }
+
+                    fn visit_seq<A>(self, mut seq: A) -> Result<$name<'a>, A::Error>
+                    where
+                        A: SeqAccess<'de>,
+                    {
+                        let mut list = $name::new();
+                        while let Some(item) = seq.next_element()? {
+                            list.push(item)
+                                .map_err(|_| de::Error::invalid_length($capacity + 1, &self))?;
+                        }
+                        Ok(list)
+                    }
+                }
+
+                deserializer.deserialize_seq(ListVisitor(PhantomData))
+            }
+        }
+    };
 }
 
-impl<'a> DeviceRegistrationResult<'a> {
+// The client certificate and the CAs that issued it.
+bounded_list!(CertificateChain, &'a str, 4, "at most four certificates");
+bounded_list!(
+    TrustBundleCertificates,
+    TrustBundleCertificate<'a>,
+    10,
+    "at most ten certificates"
+);
+
+impl<'a, P> DeviceRegistrationResult<'a, P> {
     pub fn new(
         assigned_hub: &'a str,
         created_date_time_utc: &'a str,
@@ -96,14 +414,14 @@ impl<'a> DeviceRegistrationResult<'a> {
         error_message: &'a str,
         etag: &'a str,
         last_updated_date_time_utc: &'a str,
-        payload: &'a str,
+        payload: P,
         registration_id: &'a str,
         status: &'a str,
         substatus: &'a str,
         symmetric_key: Option<SymmetricKeyRegistrationResult<'a>>,
         tpm: Option<TpmRegistrationResult<'a>>,
         x509: Option<X509RegistrationResult<'a>>,
-    ) -> DeviceRegistrationResult<'a> {
+    ) -> DeviceRegistrationResult<'a, P> {
         DeviceRegistrationResult {
             assigned_hub,
             created_date_time_utc,
@@ -119,7 +437,30 @@ impl<'a> DeviceRegistrationResult<'a> {
             symmetric_key,
             tpm,
             x509,
+            issued_certificate_chain: CertificateChain::new(),
+            trust_bundle: None,
+        }
+    }
+
+    // Drops the fields the API version doesn't define, so that a client
+    // pinned to an older version acts on the same state on any service.
+    #[must_use]
+    pub fn for_api_version(mut self, api_version: ApiVersion) -> DeviceRegistrationResult<'a, P> {
+        if !api_version.supports_certificate_issuance() {
+            self.issued_certificate_chain = CertificateChain::new();
+        }
+        if !api_version.supports_trust_bundle() {
+            self.trust_bundle = None;
         }
+        self
+    }
+
+    pub fn created_date_time(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(self.created_date_time_utc)
+    }
+
+    pub fn last_updated_date_time(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(self.last_updated_date_time_utc)
     }
 }
 // https://docs.microsoft.com/en-us/rest/api/iot-dps/runtimeregistration/registerdevice#provisioningserviceerrordetails
@@ -127,8 +468,14 @@ impl<'a> DeviceRegistrationResult<'a> {
 pub struct ProvisioningServiceErrorDetails<'a> {
     #[serde(rename = "errorCode", default)]
     pub error_code: u32,
-    #[serde(rename = "info", skip_serializing_if = "Option::is_none", default)]
-    pub info: Option<&'a str>, // object
+    // An object of no fixed shape, only filled in by from_slice. It is written
+    // as the value it holds, which serde-json-core 0.1 cannot do for objects.
+    #[serde(
+        rename = "info",
+        skip_deserializing,
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub info: Option<RawJson<'a>>,
     #[serde(rename = "message", default)]
     pub message: &'a str,
     #[serde(rename = "timestampUtc", default)]
@@ -137,6 +484,24 @@ pub struct ProvisioningServiceErrorDetails<'a> {
     pub tracking_id: &'a str,
 }
 
+impl<'a> ProvisioningServiceErrorDetails<'a> {
+    pub fn from_slice(payload: &'a [u8]) -> Result<Self, &'static str> {
+        let json = core::str::from_utf8(payload).map_err(|_| AZ_ERROR_INVALID_JSON)?;
+        let mut details = serde_json_core::from_str::<ProvisioningServiceErrorDetails<'a>>(json)
+            .map_err(|_| AZ_ERROR_INVALID_JSON)?;
+        details.info = RawJson::new(json)?.get("info");
+        Ok(details)
+    }
+
+    pub fn code(&self) -> ExtendedErrorCode {
+        ExtendedErrorCode::from_u32(self.error_code)
+    }
+
+    pub fn timestamp(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(self.timestamp_utc)
+    }
+}
+
 #[cfg(test)]
 mod provisioning_service_error_details_serialization_tests {
     use super::*;
@@ -153,11 +518,36 @@ mod provisioning_service_error_details_serialization_tests {
         assert_eq!("2020-08-08T13:16:50.5067952Z", actual.timestamp_utc);
         assert_eq!("4e4fbac4-a5f1-4ad4-ae4c-2485742aadd4", actual.tracking_id);
     }
+
+    #[test]
+    fn info_object_is_captured() {
+        let source = "{\"errorCode\":429001,\"trackingId\":\"1\",\"message\":\"Throttled\",\"timestampUtc\":\"2020-08-08T13:16:50.5067952Z\",\"info\":{\"retryAfter\":\"30\",\"details\":{\"limit\":[1,2]}}}";
+        let plain = serde_json_core::from_str::<ProvisioningServiceErrorDetails<'_>>(source);
+        assert!(plain.is_ok());
+        let actual = ProvisioningServiceErrorDetails::from_slice(source.as_bytes()).unwrap();
+        assert_eq!(ExtendedErrorCode::THROTTLED, actual.code());
+        assert!(actual.code().is_retriable());
+        let info = actual.info.unwrap();
+        assert_eq!(
+            info.get("retryAfter").unwrap().as_string_value(),
+            Some("30")
+        );
+        assert_eq!(info.get("details").unwrap().as_str(), "{\"limit\":[1,2]}");
+        assert_eq!("Throttled", actual.message);
+    }
+
+    #[test]
+    fn invalid_payload_is_an_error() {
+        assert_eq!(
+            ProvisioningServiceErrorDetails::from_slice(b"{\"errorCode\":").unwrap_err(),
+            AZ_ERROR_INVALID_JSON
+        );
+    }
 }
 
 // https://docs.microsoft.com/en-us/rest/api/iot-dps/runtimeregistration/registerdevice#registrationoperationstatus
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
-pub struct RegistrationOperationStatus<'a> {
+pub struct RegistrationOperationStatus<'a, P = &'a str> {
     /// Operation ID.
     #[serde(rename = "operationId")]
     pub operation_id: &'a str,
@@ -167,18 +557,18 @@ pub struct RegistrationOperationStatus<'a> {
         skip_serializing_if = "Option::is_none",
         default
     )]
-    pub registration_state: Option<DeviceRegistrationResult<'a>>,
+    pub registration_state: Option<DeviceRegistrationResult<'a, P>>,
     /// Device enrollment status. => common::ProvisioningStatus
     #[serde(rename = "status")]
     pub status: &'a str,
 }
 
-impl<'a> RegistrationOperationStatus<'a> {
+impl<'a, P> RegistrationOperationStatus<'a, P> {
     pub fn new(
         status: &'a str,
         operation_id: &'a str,
-        registration_state: Option<DeviceRegistrationResult<'a>>,
-    ) -> RegistrationOperationStatus<'a> {
+        registration_state: Option<DeviceRegistrationResult<'a, P>>,
+    ) -> RegistrationOperationStatus<'a, P> {
         RegistrationOperationStatus {
             status,
             operation_id,
@@ -231,6 +621,28 @@ pub struct X509CertificateInfo<'a> {
     version: u32,
 }
 
+impl<'a> X509CertificateInfo<'a> {
+    pub fn subject_name(&self) -> &'a str {
+        self.subject_name
+    }
+
+    pub fn not_before(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(self.not_before_utc)
+    }
+
+    pub fn not_after(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(self.not_after_utc)
+    }
+
+    // A certificate with unreadable dates is never considered valid.
+    pub fn is_valid_at(&self, epoch_time: u64) -> bool {
+        match (self.not_before(), self.not_after()) {
+            (Ok(not_before), Ok(not_after)) => not_before <= epoch_time && not_after >= epoch_time,
+            _ => false,
+        }
+    }
+}
+
 // https://docs.microsoft.com/en-us/rest/api/iot-dps/runtimeregistration/registerdevice#x509registrationresult
 #[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
 pub struct X509RegistrationResult<'a> {
@@ -242,6 +654,105 @@ pub struct X509RegistrationResult<'a> {
     signing_certificate_info: X509CertificateInfo<'a>,
 }
 
+impl<'a> X509RegistrationResult<'a> {
+    pub fn certificate_info(&self) -> &X509CertificateInfo<'a> {
+        &self.certificate_info
+    }
+
+    pub fn signing_certificate_info(&self) -> &X509CertificateInfo<'a> {
+        &self.signing_certificate_info
+    }
+}
+
+// trustBundle in the registration state, from API version 2021-06-01
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrustBundle<'a> {
+    #[serde(rename = "certificates", default)]
+    pub certificates: TrustBundleCertificates<'a>,
+    #[serde(rename = "createdDateTime", default)]
+    pub created_date_time: &'a str,
+    /// Changes whenever the certificates do.
+    #[serde(rename = "etag", default)]
+    pub etag: &'a str,
+    #[serde(rename = "id", default)]
+    pub id: &'a str,
+    #[serde(rename = "lastModifiedDateTime", default)]
+    pub last_modified_date_time: &'a str,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrustBundleCertificate<'a> {
+    /// PEM, with its line breaks still JSON escaped when parsed by
+    /// serde-json-core; see trust_bundle for the certificate itself.
+    #[serde(rename = "certificate", default)]
+    pub certificate: &'a str,
+    #[serde(rename = "metadata", default)]
+    pub metadata: TrustBundleCertificateMetadata<'a>,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrustBundleCertificateMetadata<'a> {
+    #[serde(rename = "issuerName", default)]
+    pub issuer_name: &'a str,
+    #[serde(rename = "notAfterUtc", default)]
+    pub not_after_utc: &'a str,
+    #[serde(rename = "notBeforeUtc", default)]
+    pub not_before_utc: &'a str,
+    #[serde(rename = "sha1Thumbprint", default)]
+    pub sha1_thumbprint: &'a str,
+    #[serde(rename = "sha256Thumbprint", default)]
+    pub sha256_thumbprint: &'a str,
+    #[serde(rename = "subjectName", default)]
+    pub subject_name: &'a str,
+}
+
+#[cfg(test)]
+mod tests_timestamps {
+    use super::*;
+
+    fn certificate_info() -> X509CertificateInfo<'static> {
+        X509CertificateInfo {
+            issuer_name: "CN=issuer",
+            not_after_utc: "2021-08-04T21:39:08Z",
+            not_before_utc: "2020-08-04T21:39:08.4834929Z",
+            serial_number: "01",
+            sha1_thumbprint: "",
+            sha256_thumbprint: "",
+            subject_name: "CN=device",
+            version: 3,
+        }
+    }
+
+    #[test]
+    fn certificate_validity_is_checked_against_epoch_times() {
+        let info = certificate_info();
+        assert!(!info.is_valid_at(1_596_577_148));
+        assert!(info.is_valid_at(1_596_577_149));
+        assert!(info.is_valid_at(1_628_113_148));
+        assert!(!info.is_valid_at(1_628_113_149));
+    }
+
+    #[test]
+    fn unreadable_dates_are_never_valid() {
+        let info = X509CertificateInfo {
+            not_after_utc: "tomorrow",
+            ..certificate_info()
+        };
+        assert!(!info.is_valid_at(1_600_000_000));
+    }
+
+    #[test]
+    fn service_dates_parse() {
+        let source = "{\"registrationId\":\"test\",\"createdDateTimeUtc\":\"2020-08-04T21:39:08.4834929Z\",\"lastUpdatedDateTimeUtc\":\"2020-08-04T21:39:08.6951685Z\"}";
+        let result = serde_json_core::from_str::<DeviceRegistrationResult<'_>>(source).unwrap();
+        let created = result.created_date_time().unwrap();
+        assert!(created < result.last_updated_date_time().unwrap());
+        assert_eq!(created.epoch_time(), Some(1_596_577_148));
+        let empty = serde_json_core::from_str::<DeviceRegistrationResult<'_>>("{}").unwrap();
+        assert!(empty.created_date_time().is_err());
+    }
+}
+
 #[cfg(test)]
 mod tests_serialization_round_trip {
     use super::*;
@@ -262,6 +773,70 @@ mod tests_serialization_round_trip {
 #[cfg(test)]
 mod register_response_serialization_tests {
     use super::*;
+
+    #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+    struct Allocation<'a> {
+        #[serde(rename = "hubSku")]
+        hub_sku: &'a str,
+        #[serde(rename = "telemetryInterval")]
+        telemetry_interval: u32,
+    }
+
+    #[test]
+    fn object_payload_deserializes_into_a_typed_payload() {
+        let source = "{\"operationId\":\"1\",\"status\":\"assigned\",\"registrationState\":{\"registrationId\":\"test\",\"status\":\"assigned\",\"payload\":{\"hubSku\":\"S1\",\"telemetryInterval\":30}}}";
+        let deserialized =
+            serde_json_core::from_str::<RegistrationOperationStatus<'_, Allocation<'_>>>(source)
+                .unwrap();
+        let state = deserialized.registration_state.unwrap();
+        assert_eq!("S1", state.payload.hub_sku);
+        assert_eq!(30, state.payload.telemetry_interval);
+    }
+
+    #[test]
+    fn missing_payload_is_none_for_an_optional_payload() {
+        let source = "{\"operationId\":\"1\",\"status\":\"assigned\",\"registrationState\":{\"status\":\"assigned\"}}";
+        let deserialized = serde_json_core::from_str::<
+            RegistrationOperationStatus<'_, Option<Allocation<'_>>>,
+        >(source)
+        .unwrap();
+        assert!(deserialized.registration_state.unwrap().payload.is_none());
+    }
+    #[test]
+    fn issued_certificate_chain_deserializes() {
+        let source = "{\"operationId\":\"1\",\"status\":\"assigned\",\"registrationState\":{\"status\":\"assigned\",\"issuedCertificateChain\":[\"TGVhZg==\",\"Q0E=\"]}}";
+        let deserialized =
+            serde_json_core::from_str::<RegistrationOperationStatus<'_>>(source).unwrap();
+        let state = deserialized.registration_state.unwrap();
+        assert_eq!(&state.issued_certificate_chain[..], &["TGVhZg==", "Q0E="]);
+        let serialized = serde_json_core::to_string::<heapless::consts::U256, _>(&state).unwrap();
+        assert!(serialized.ends_with(",\"issuedCertificateChain\":[\"TGVhZg==\",\"Q0E=\"]}"));
+    }
+
+    #[test]
+    fn results_keep_the_fields_of_their_api_version() {
+        let source = "{\"status\":\"assigned\",\"issuedCertificateChain\":[\"TGVhZg==\"],\"trustBundle\":{\"certificates\":[],\"etag\":\"1\"}}";
+        let state = serde_json_core::from_str::<DeviceRegistrationResult<'_>>(source).unwrap();
+        let latest = state.clone().for_api_version(ApiVersion::V2021_06_01);
+        assert_eq!(latest, state);
+        let older = state.for_api_version(ApiVersion::V2019_03_31);
+        assert!(older.issued_certificate_chain.is_empty());
+        assert_eq!(older.trust_bundle, None);
+        assert_eq!(older.status, "assigned");
+    }
+
+    #[test]
+    fn certificate_signing_request_is_only_sent_when_set() {
+        let mut registration = DeviceRegistration::new("", "device-1", None);
+        let serialized =
+            serde_json_core::to_string::<heapless::consts::U256, _>(&registration).unwrap();
+        assert!(!serialized.contains("certificateSigningRequest"));
+        registration.certificate_signing_request = Some("TUlJQg==");
+        let serialized =
+            serde_json_core::to_string::<heapless::consts::U256, _>(&registration).unwrap();
+        assert!(serialized.ends_with(",\"certificateSigningRequest\":\"TUlJQg==\"}"));
+    }
+
     #[test]
     fn register_response_without_registration_state_deserializes() {
         let source = "{\"operationId\":\"4.214465a7b4233f53.e65f9871-d30c-47b1-8889-c8b99e24f9d1\",\"status\":\"assigned\"}";
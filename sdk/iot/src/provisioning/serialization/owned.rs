@@ -0,0 +1,802 @@
+// Owned counterparts of the borrowed models, for results that have to outlive the
+// MQTT payload they were parsed from (or move to another thread).
+use super::super::api_version::ApiVersion;
+use super::super::raw_json::RawJson;
+use crate::statuscode::ExtendedErrorCode;
+use alloc::string::String;
+use alloc::vec::Vec;
+use azure_sdk_for_rust_common::timestamp::Timestamp;
+use core::fmt;
+use serde::de::{Deserializer, Visitor};
+use serde::ser::{Error, Serializer};
+use serde::{Deserialize, Serialize};
+
+// serde-json-core only hands out borrowed strings, so every owned field goes
+// through deserialize_str instead of String's own deserialize_string.
+fn string<'de, D>(deserializer: D) -> Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct StringVisitor;
+
+    impl<'de> Visitor<'de> for StringVisitor {
+        type Value = String;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter<'_>) -> fmt::Result {
+            formatter.write_str("a string")
+        }
+
+        fn visit_str<E>(self, value: &str) -> Result<String, E>
+        where
+            E: serde::de::Error,
+        {
+            Ok(String::from(value))
+        }
+    }
+
+    deserializer.deserialize_str(StringVisitor)
+}
+
+// Writes raw JSON as the value it holds rather than as a string.
+#[allow(clippy::ref_option)]
+fn raw_json<S>(json: &Option<String>, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    RawJson::new(json.as_deref().unwrap_or("null"))
+        .map_err(S::Error::custom)?
+        .serialize(serializer)
+}
+
+// A list of strings, one deserialize_str per element.
+fn strings<'de, D>(deserializer: D) -> Result<Vec<String>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    struct Element(String);
+
+    impl<'de> Deserialize<'de> for Element {
+        fn deserialize<D>(deserializer: D) -> Result<Element, D::Error>
+        where
+            D: Deserializer<'de>,
+        {
+            string(deserializer).map(Element)
+        }
+    }
+
+    Vec::<Element>::deserialize(deserializer)
+        .map(|elements| elements.into_iter().map(|element| element.0).collect())
+}
+
+// Same problem for a payload: a String payload is read through deserialize_str,
+// any other payload type deserializes as usual.
+fn payload<'de, D, P>(deserializer: D) -> Result<P, D::Error>
+where
+    D: Deserializer<'de>,
+    P: Deserialize<'de>,
+{
+    P::deserialize(StringAsStr(deserializer))
+}
+
+struct StringAsStr<D>(D);
+
+macro_rules! forward {
+    ($($method:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $method<V>(self, $($arg: $ty,)* visitor: V) -> Result<V::Value, D::Error>
+            where
+                V: Visitor<'de>,
+            {
+                self.0.$method($($arg,)* visitor)
+            }
+        )*
+    };
+}
+
+impl<'de, D> Deserializer<'de> for StringAsStr<D>
+where
+    D: Deserializer<'de>,
+{
+    type Error = D::Error;
+
+    fn deserialize_string<V>(self, visitor: V) -> Result<V::Value, D::Error>
+    where
+        V: Visitor<'de>,
+    {
+        self.0.deserialize_str(visitor)
+    }
+
+    forward! {
+        deserialize_any();
+        deserialize_bool();
+        deserialize_i8();
+        deserialize_i16();
+        deserialize_i32();
+        deserialize_i64();
+        deserialize_u8();
+        deserialize_u16();
+        deserialize_u32();
+        deserialize_u64();
+        deserialize_f32();
+        deserialize_f64();
+        deserialize_char();
+        deserialize_str();
+        deserialize_bytes();
+        deserialize_byte_buf();
+        deserialize_option();
+        deserialize_unit();
+        deserialize_unit_struct(name: &'static str);
+        deserialize_newtype_struct(name: &'static str);
+        deserialize_seq();
+        deserialize_tuple(len: usize);
+        deserialize_tuple_struct(name: &'static str, len: usize);
+        deserialize_map();
+        deserialize_struct(name: &'static str, fields: &'static [&'static str]);
+        deserialize_enum(name: &'static str, variants: &'static [&'static str]);
+        deserialize_identifier();
+        deserialize_ignored_any();
+    }
+
+    fn is_human_readable(&self) -> bool {
+        self.0.is_human_readable()
+    }
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(bound(deserialize = "P: Deserialize<'de>"))]
+pub struct DeviceRegistration<P = String> {
+    /// Custom allocation payload.
+    #[serde(rename = "payload", deserialize_with = "payload")]
+    pub payload: P,
+    /// Registration Id.
+    #[serde(rename = "registrationId", deserialize_with = "string")]
+    pub registration_id: String,
+    #[serde(rename = "tpm", skip_serializing_if = "Option::is_none", default)]
+    pub tpm: Option<TpmAttestation>,
+    #[serde(
+        rename = "certificateSigningRequest",
+        skip_serializing_if = "Option::is_none",
+        default,
+        deserialize_with = "payload"
+    )]
+    pub certificate_signing_request: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(bound(deserialize = "P: Deserialize<'de> + Default"))]
+pub struct DeviceRegistrationResult<P = String> {
+    #[serde(rename = "assignedHub", default, deserialize_with = "string")]
+    pub assigned_hub: String,
+    #[serde(rename = "createdDateTimeUtc", default, deserialize_with = "string")]
+    pub created_date_time_utc: String,
+    #[serde(rename = "deviceId", default, deserialize_with = "string")]
+    pub device_id: String,
+
+    #[serde(rename = "errorCode", default, deserialize_with = "string")]
+    pub error_code: String,
+    #[serde(rename = "errorMessage", default, deserialize_with = "string")]
+    pub error_message: String,
+
+    #[serde(rename = "etag", default, deserialize_with = "string")]
+    pub etag: String,
+
+    #[serde(
+        rename = "lastUpdatedDateTimeUtc",
+        default,
+        deserialize_with = "string"
+    )]
+    pub last_updated_date_time_utc: String,
+
+    /// Custom allocation payload returned from the webhook to the device.
+    #[serde(rename = "payload", default, deserialize_with = "payload")]
+    pub payload: P,
+
+    #[serde(rename = "registrationId", default, deserialize_with = "string")]
+    pub registration_id: String,
+
+    #[serde(rename = "status", default, deserialize_with = "string")]
+    pub status: String,
+
+    #[serde(rename = "substatus", default, deserialize_with = "string")]
+    pub substatus: String,
+
+    #[serde(
+        rename = "symmetricKey",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub symmetric_key: Option<SymmetricKeyRegistrationResult>,
+    #[serde(rename = "tpm", skip_serializing_if = "Option::is_none", default)]
+    pub tpm: Option<TpmRegistrationResult>,
+    #[serde(rename = "x509", skip_serializing_if = "Option::is_none", default)]
+    pub x509: Option<X509RegistrationResult>,
+
+    #[serde(
+        rename = "issuedCertificateChain",
+        skip_serializing_if = "Vec::is_empty",
+        default,
+        deserialize_with = "strings"
+    )]
+    pub issued_certificate_chain: Vec<String>,
+
+    #[serde(
+        rename = "trustBundle",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub trust_bundle: Option<TrustBundle>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct ProvisioningServiceErrorDetails {
+    #[serde(rename = "errorCode", default)]
+    pub error_code: u32,
+    // Raw JSON of the info object, see info(). Its shape is not fixed, so only
+    // from_slice reads it.
+    #[serde(
+        rename = "info",
+        skip_deserializing,
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "raw_json"
+    )]
+    info: Option<String>,
+    #[serde(rename = "message", default, deserialize_with = "string")]
+    pub message: String,
+    #[serde(rename = "timestampUtc", default, deserialize_with = "string")]
+    pub timestamp_utc: String,
+    #[serde(rename = "trackingId", default, deserialize_with = "string")]
+    pub tracking_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+#[serde(bound(deserialize = "P: Deserialize<'de> + Default"))]
+pub struct RegistrationOperationStatus<P = String> {
+    /// Operation ID.
+    #[serde(rename = "operationId", deserialize_with = "string")]
+    pub operation_id: String,
+    /// Device registration status.
+    #[serde(
+        rename = "registrationState",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub registration_state: Option<DeviceRegistrationResult<P>>,
+    /// Device enrollment status. => common::ProvisioningStatus
+    #[serde(rename = "status", deserialize_with = "string")]
+    pub status: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct SymmetricKeyRegistrationResult {
+    #[serde(rename = "enrollmentGroupId", default, deserialize_with = "string")]
+    enrollment_group_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct TpmAttestation {
+    #[serde(rename = "endorsementKey", deserialize_with = "string")]
+    pub endorsement_key: String,
+    #[serde(rename = "storageRootKey", deserialize_with = "string")]
+    pub storage_root_key: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct TpmRegistrationResult {
+    #[serde(rename = "authenticationKey", default, deserialize_with = "string")]
+    authentication_key: String,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct X509CertificateInfo {
+    #[serde(rename = "issuerName", deserialize_with = "string")]
+    issuer_name: String,
+    #[serde(rename = "notAfterUtc", deserialize_with = "string")]
+    not_after_utc: String,
+    #[serde(rename = "notBeforeUtc", deserialize_with = "string")]
+    not_before_utc: String,
+    #[serde(rename = "serialNumber", deserialize_with = "string")]
+    serial_number: String,
+    #[serde(rename = "sha1Thumbprint", deserialize_with = "string")]
+    sha1_thumbprint: String,
+    #[serde(rename = "sha256Thumbprint", deserialize_with = "string")]
+    sha256_thumbprint: String,
+    #[serde(rename = "subjectName", deserialize_with = "string")]
+    subject_name: String,
+    #[serde(rename = "version")]
+    version: u32,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize, PartialEq)]
+pub struct X509RegistrationResult {
+    #[serde(rename = "certificateInfo")]
+    certificate_info: X509CertificateInfo,
+    #[serde(rename = "enrollmentGroupId", deserialize_with = "string")]
+    enrollment_group_id: String,
+    #[serde(rename = "signingCertificateInfo")]
+    signing_certificate_info: X509CertificateInfo,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrustBundle {
+    #[serde(rename = "certificates", default)]
+    pub certificates: Vec<TrustBundleCertificate>,
+    #[serde(rename = "createdDateTime", default, deserialize_with = "string")]
+    pub created_date_time: String,
+    #[serde(rename = "etag", default, deserialize_with = "string")]
+    pub etag: String,
+    #[serde(rename = "id", default, deserialize_with = "string")]
+    pub id: String,
+    #[serde(rename = "lastModifiedDateTime", default, deserialize_with = "string")]
+    pub last_modified_date_time: String,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrustBundleCertificate {
+    #[serde(rename = "certificate", default, deserialize_with = "string")]
+    pub certificate: String,
+    #[serde(rename = "metadata", default)]
+    pub metadata: TrustBundleCertificateMetadata,
+}
+
+#[derive(Clone, Debug, Default, Deserialize, Serialize, PartialEq)]
+pub struct TrustBundleCertificateMetadata {
+    #[serde(rename = "issuerName", default, deserialize_with = "string")]
+    pub issuer_name: String,
+    #[serde(rename = "notAfterUtc", default, deserialize_with = "string")]
+    pub not_after_utc: String,
+    #[serde(rename = "notBeforeUtc", default, deserialize_with = "string")]
+    pub not_before_utc: String,
+    #[serde(rename = "sha1Thumbprint", default, deserialize_with = "string")]
+    pub sha1_thumbprint: String,
+    #[serde(rename = "sha256Thumbprint", default, deserialize_with = "string")]
+    pub sha256_thumbprint: String,
+    #[serde(rename = "subjectName", default, deserialize_with = "string")]
+    pub subject_name: String,
+}
+
+impl<'a> super::DeviceRegistration<'a> {
+    pub fn into_owned(self) -> DeviceRegistration {
+        self.into_owned_with(String::from)
+    }
+}
+
+impl<'a, P> super::DeviceRegistration<'a, P> {
+    // A typed payload that still borrows from the message is converted by `f`.
+    // The model ID is only set for sending and not kept.
+    pub fn into_owned_with<Q, F>(self, f: F) -> DeviceRegistration<Q>
+    where
+        F: FnOnce(P) -> Q,
+    {
+        DeviceRegistration {
+            payload: f(self.payload),
+            registration_id: String::from(self.registration_id),
+            tpm: self.tpm.map(super::TpmAttestation::into_owned),
+            certificate_signing_request: self.certificate_signing_request.map(String::from),
+        }
+    }
+}
+
+impl<'a> super::DeviceRegistrationResult<'a> {
+    pub fn into_owned(self) -> DeviceRegistrationResult {
+        self.into_owned_with(String::from)
+    }
+}
+
+impl<'a, P> super::DeviceRegistrationResult<'a, P> {
+    pub fn into_owned_with<Q, F>(self, f: F) -> DeviceRegistrationResult<Q>
+    where
+        F: FnOnce(P) -> Q,
+    {
+        DeviceRegistrationResult {
+            assigned_hub: String::from(self.assigned_hub),
+            created_date_time_utc: String::from(self.created_date_time_utc),
+            device_id: String::from(self.device_id),
+            error_code: String::from(self.error_code),
+            error_message: String::from(self.error_message),
+            etag: String::from(self.etag),
+            last_updated_date_time_utc: String::from(self.last_updated_date_time_utc),
+            payload: f(self.payload),
+            registration_id: String::from(self.registration_id),
+            status: String::from(self.status),
+            substatus: String::from(self.substatus),
+            symmetric_key: self
+                .symmetric_key
+                .map(super::SymmetricKeyRegistrationResult::into_owned),
+            tpm: self.tpm.map(super::TpmRegistrationResult::into_owned),
+            x509: self.x509.map(super::X509RegistrationResult::into_owned),
+            issued_certificate_chain: self
+                .issued_certificate_chain
+                .iter()
+                .copied()
+                .map(String::from)
+                .collect(),
+            trust_bundle: self.trust_bundle.map(super::TrustBundle::into_owned),
+        }
+    }
+}
+
+impl<P> DeviceRegistrationResult<P> {
+    // See super::DeviceRegistrationResult::for_api_version.
+    #[must_use]
+    pub fn for_api_version(mut self, api_version: ApiVersion) -> DeviceRegistrationResult<P> {
+        if !api_version.supports_certificate_issuance() {
+            self.issued_certificate_chain.clear();
+        }
+        if !api_version.supports_trust_bundle() {
+            self.trust_bundle = None;
+        }
+        self
+    }
+
+    pub fn created_date_time(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(&self.created_date_time_utc)
+    }
+
+    pub fn last_updated_date_time(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(&self.last_updated_date_time_utc)
+    }
+}
+
+impl X509CertificateInfo {
+    // Same checks as the borrowed model.
+    pub fn as_borrowed(&self) -> super::X509CertificateInfo<'_> {
+        super::X509CertificateInfo {
+            issuer_name: &self.issuer_name,
+            not_after_utc: &self.not_after_utc,
+            not_before_utc: &self.not_before_utc,
+            serial_number: &self.serial_number,
+            sha1_thumbprint: &self.sha1_thumbprint,
+            sha256_thumbprint: &self.sha256_thumbprint,
+            subject_name: &self.subject_name,
+            version: self.version,
+        }
+    }
+
+    pub fn is_valid_at(&self, epoch_time: u64) -> bool {
+        self.as_borrowed().is_valid_at(epoch_time)
+    }
+}
+
+impl X509RegistrationResult {
+    pub fn certificate_info(&self) -> &X509CertificateInfo {
+        &self.certificate_info
+    }
+
+    pub fn signing_certificate_info(&self) -> &X509CertificateInfo {
+        &self.signing_certificate_info
+    }
+}
+
+impl ProvisioningServiceErrorDetails {
+    // Also reads info, which the derived Deserialize skips.
+    pub fn from_slice(payload: &[u8]) -> Result<Self, &'static str> {
+        super::ProvisioningServiceErrorDetails::from_slice(payload)
+            .map(super::ProvisioningServiceErrorDetails::into_owned)
+    }
+
+    pub fn info(&self) -> Option<RawJson<'_>> {
+        self.info
+            .as_deref()
+            .and_then(|info| RawJson::new(info).ok())
+    }
+
+    pub fn code(&self) -> ExtendedErrorCode {
+        ExtendedErrorCode::from_u32(self.error_code)
+    }
+
+    pub fn timestamp(&self) -> Result<Timestamp, &'static str> {
+        Timestamp::parse(&self.timestamp_utc)
+    }
+}
+
+impl<'a> super::ProvisioningServiceErrorDetails<'a> {
+    pub fn into_owned(self) -> ProvisioningServiceErrorDetails {
+        ProvisioningServiceErrorDetails {
+            error_code: self.error_code,
+            info: self.info.map(|info| String::from(info.as_str())),
+            message: String::from(self.message),
+            timestamp_utc: String::from(self.timestamp_utc),
+            tracking_id: String::from(self.tracking_id),
+        }
+    }
+}
+
+impl<'a> super::RegistrationOperationStatus<'a> {
+    pub fn into_owned(self) -> RegistrationOperationStatus {
+        self.into_owned_with(String::from)
+    }
+}
+
+impl<'a, P> super::RegistrationOperationStatus<'a, P> {
+    pub fn into_owned_with<Q, F>(self, f: F) -> RegistrationOperationStatus<Q>
+    where
+        F: FnOnce(P) -> Q,
+    {
+        RegistrationOperationStatus {
+            operation_id: String::from(self.operation_id),
+            registration_state: self
+                .registration_state
+                .map(|state| state.into_owned_with(f)),
+            status: String::from(self.status),
+        }
+    }
+}
+
+impl<'a> super::SymmetricKeyRegistrationResult<'a> {
+    pub fn into_owned(self) -> SymmetricKeyRegistrationResult {
+        SymmetricKeyRegistrationResult {
+            enrollment_group_id: String::from(self.enrollment_group_id),
+        }
+    }
+}
+
+impl<'a> super::TpmAttestation<'a> {
+    pub fn into_owned(self) -> TpmAttestation {
+        TpmAttestation {
+            endorsement_key: String::from(self.endorsement_key),
+            storage_root_key: String::from(self.storage_root_key),
+        }
+    }
+}
+
+impl<'a> super::TpmRegistrationResult<'a> {
+    pub fn into_owned(self) -> TpmRegistrationResult {
+        TpmRegistrationResult {
+            authentication_key: String::from(self.authentication_key),
+        }
+    }
+}
+
+impl<'a> super::X509CertificateInfo<'a> {
+    pub fn into_owned(self) -> X509CertificateInfo {
+        X509CertificateInfo {
+            issuer_name: String::from(self.issuer_name),
+            not_after_utc: String::from(self.not_after_utc),
+            not_before_utc: String::from(self.not_before_utc),
+            serial_number: String::from(self.serial_number),
+            sha1_thumbprint: String::from(self.sha1_thumbprint),
+            sha256_thumbprint: String::from(self.sha256_thumbprint),
+            subject_name: String::from(self.subject_name),
+            version: self.version,
+        }
+    }
+}
+
+impl<'a> super::X509RegistrationResult<'a> {
+    pub fn into_owned(self) -> X509RegistrationResult {
+        X509RegistrationResult {
+            certificate_info: self.certificate_info.into_owned(),
+            enrollment_group_id: String::from(self.enrollment_group_id),
+            signing_certificate_info: self.signing_certificate_info.into_owned(),
+        }
+    }
+}
+
+impl<'a> super::TrustBundle<'a> {
+    pub fn into_owned(self) -> TrustBundle {
+        TrustBundle {
+            certificates: self
+                .certificates
+                .iter()
+                .map(|certificate| certificate.into_owned())
+                .collect(),
+            created_date_time: String::from(self.created_date_time),
+            etag: String::from(self.etag),
+            id: String::from(self.id),
+            last_modified_date_time: String::from(self.last_modified_date_time),
+        }
+    }
+}
+
+impl TrustBundle {
+    // Fails when there are more certificates than the borrowed model holds.
+    pub fn as_borrowed(&self) -> Result<super::TrustBundle<'_>, &'static str> {
+        let mut certificates = super::TrustBundleCertificates::new();
+        for certificate in &self.certificates {
+            certificates.push(certificate.as_borrowed())?;
+        }
+        Ok(super::TrustBundle {
+            certificates,
+            created_date_time: &self.created_date_time,
+            etag: &self.etag,
+            id: &self.id,
+            last_modified_date_time: &self.last_modified_date_time,
+        })
+    }
+}
+
+impl<'a> super::TrustBundleCertificate<'a> {
+    pub fn into_owned(self) -> TrustBundleCertificate {
+        TrustBundleCertificate {
+            certificate: String::from(self.certificate),
+            metadata: TrustBundleCertificateMetadata {
+                issuer_name: String::from(self.metadata.issuer_name),
+                not_after_utc: String::from(self.metadata.not_after_utc),
+                not_before_utc: String::from(self.metadata.not_before_utc),
+                sha1_thumbprint: String::from(self.metadata.sha1_thumbprint),
+                sha256_thumbprint: String::from(self.metadata.sha256_thumbprint),
+                subject_name: String::from(self.metadata.subject_name),
+            },
+        }
+    }
+}
+
+impl TrustBundleCertificate {
+    // For the PEM and DER accessors, see trust_bundle.
+    pub fn as_borrowed(&self) -> super::TrustBundleCertificate<'_> {
+        super::TrustBundleCertificate {
+            certificate: &self.certificate,
+            metadata: super::TrustBundleCertificateMetadata {
+                issuer_name: &self.metadata.issuer_name,
+                not_after_utc: &self.metadata.not_after_utc,
+                not_before_utc: &self.metadata.not_before_utc,
+                sha1_thumbprint: &self.metadata.sha1_thumbprint,
+                sha256_thumbprint: &self.metadata.sha256_thumbprint,
+                subject_name: &self.metadata.subject_name,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_owned_serialization {
+    use super::*;
+    use heapless::consts::U1024;
+
+    const REGISTER_RESPONSE: &str = "{\"operationId\":\"4.214465a7b4233f53.e65f9871-d30c-47b1-8889-c8b99e24f9d1\",\"status\":\"assigned\",\"registrationState\":{\"registrationId\":\"test\",\"createdDateTimeUtc\":\"2020-08-04T21:39:08.4834929Z\",\"assignedHub\":\"example.azure-devices.net\",\"deviceId\":\"1-1-2-3-5-8-13\",\"status\":\"assigned\",\"substatus\":\"initialAssignment\",\"lastUpdatedDateTimeUtc\":\"2020-08-04T21:39:08.6951685Z\",\"etag\":\"ImY3MDEyN2YxLTAwMDAtMDgwMC0wMDAwLTVmMjlkNTdjMDAwMCI=\",\"symmetricKey\":{\"enrollmentGroupId\":\"group\"}}}";
+
+    #[test]
+    fn into_owned_outlives_the_payload() {
+        let owned = {
+            let payload = String::from(REGISTER_RESPONSE);
+            serde_json_core::from_str::<super::super::RegistrationOperationStatus<'_>>(&payload)
+                .unwrap()
+                .into_owned()
+        };
+        assert_eq!("assigned", owned.status);
+        let state = owned.registration_state.unwrap();
+        assert_eq!("example.azure-devices.net", state.assigned_hub);
+        assert_eq!("1-1-2-3-5-8-13", state.device_id);
+        assert_eq!("group", state.symmetric_key.unwrap().enrollment_group_id);
+    }
+
+    #[test]
+    fn deserializes_directly() {
+        let borrowed = serde_json_core::from_str::<super::super::RegistrationOperationStatus<'_>>(
+            REGISTER_RESPONSE,
+        )
+        .unwrap();
+        let owned =
+            serde_json_core::from_str::<RegistrationOperationStatus>(REGISTER_RESPONSE).unwrap();
+        assert_eq!(borrowed.into_owned(), owned);
+    }
+
+    #[test]
+    fn round_trips() {
+        let source =
+            serde_json_core::from_str::<RegistrationOperationStatus>(REGISTER_RESPONSE).unwrap();
+        let serialized = serde_json_core::to_string::<U1024, _>(&source).unwrap();
+        let copy = serde_json_core::from_str::<RegistrationOperationStatus>(&serialized).unwrap();
+        assert_eq!(source, copy);
+    }
+
+    #[test]
+    fn issued_certificate_chain_is_owned() {
+        let source = "{\"status\":\"assigned\",\"issuedCertificateChain\":[\"TGVhZg==\",\"Q0E=\"]}";
+        let borrowed =
+            serde_json_core::from_str::<super::super::DeviceRegistrationResult<'_>>(source)
+                .unwrap();
+        let owned = serde_json_core::from_str::<DeviceRegistrationResult>(source).unwrap();
+        assert_eq!(owned.issued_certificate_chain, ["TGVhZg==", "Q0E="]);
+        assert_eq!(borrowed.into_owned(), owned);
+        let owned = owned.for_api_version(ApiVersion::V2019_03_31);
+        assert!(owned.issued_certificate_chain.is_empty());
+    }
+
+    #[test]
+    fn trust_bundle_is_owned() {
+        let source = "{\"status\":\"assigned\",\"trustBundle\":{\"certificates\":[{\"certificate\":\"-----BEGIN CERTIFICATE-----\\r\\nMIIB\\r\\n-----END CERTIFICATE-----\\r\\n\",\"metadata\":{\"subjectName\":\"CN=Test CA\"}}],\"etag\":\"1\"}}";
+        let borrowed =
+            serde_json_core::from_str::<super::super::DeviceRegistrationResult<'_>>(source)
+                .unwrap();
+        let owned = serde_json_core::from_str::<DeviceRegistrationResult>(source).unwrap();
+        assert_eq!(borrowed.clone().into_owned(), owned);
+        let trust_bundle = owned.trust_bundle.unwrap();
+        assert_eq!(trust_bundle.etag, "1");
+        assert_eq!(
+            trust_bundle.certificates[0].metadata.subject_name,
+            "CN=Test CA"
+        );
+        assert_eq!(
+            trust_bundle.as_borrowed().unwrap(),
+            borrowed.trust_bundle.unwrap()
+        );
+        assert_eq!(
+            trust_bundle.certificates[0].as_borrowed().to_der().unwrap(),
+            [0x30, 0x82, 0x01]
+        );
+    }
+
+    #[test]
+    fn error_details_keep_info_when_owned() {
+        let source =
+            "{\"errorCode\":401002,\"message\":\"Unauthorized\",\"info\":{\"reason\":\"expired\"}}";
+        let owned = super::super::ProvisioningServiceErrorDetails::from_slice(source.as_bytes())
+            .unwrap()
+            .into_owned();
+        assert_eq!(
+            ProvisioningServiceErrorDetails::from_slice(source.as_bytes()).unwrap(),
+            owned
+        );
+        assert_eq!(ExtendedErrorCode::UNAUTHORIZED_ACCESS, owned.code());
+        assert_eq!(
+            owned
+                .info()
+                .unwrap()
+                .get("reason")
+                .unwrap()
+                .as_string_value(),
+            Some("expired")
+        );
+    }
+
+    // serde-json-core 0.1 cannot write objects, so info only round trips through
+    // serializers with maps.
+    #[cfg(feature = "serde_json")]
+    #[test]
+    fn error_details_round_trip_with_info() {
+        let source = "{\"errorCode\":429001,\"message\":\"Throttled\",\"info\":{\"retryAfter\":\"30\",\"note\":\"caf\\u00e9\",\"limits\":[1,-2,0.5,true,null,{}]}}";
+        let owned = ProvisioningServiceErrorDetails::from_slice(source.as_bytes()).unwrap();
+        let serialized = serde_json::to_string(&owned).unwrap();
+        let copy = ProvisioningServiceErrorDetails::from_slice(serialized.as_bytes()).unwrap();
+        assert_eq!(owned.message, copy.message);
+        let info = copy.info().unwrap();
+        assert_eq!(
+            info.get("retryAfter").unwrap().as_string_value(),
+            Some("30")
+        );
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(info.as_str()).unwrap(),
+            serde_json::from_str::<serde_json::Value>(owned.info().unwrap().as_str()).unwrap()
+        );
+
+        let without_info =
+            ProvisioningServiceErrorDetails::from_slice(b"{\"errorCode\":401002}").unwrap();
+        let serialized = serde_json::to_string(&without_info).unwrap();
+        assert!(!serialized.contains("info"));
+    }
+
+    #[derive(Debug, Default, Deserialize, PartialEq, Serialize)]
+    struct Allocation {
+        #[serde(rename = "telemetryInterval")]
+        telemetry_interval: u32,
+    }
+
+    #[test]
+    fn typed_payload_round_trips() {
+        let source = "{\"operationId\":\"1\",\"status\":\"assigned\",\"registrationState\":{\"payload\":{\"telemetryInterval\":30},\"status\":\"assigned\"}}";
+        let owned =
+            serde_json_core::from_str::<RegistrationOperationStatus<Allocation>>(source).unwrap();
+        let state = owned.registration_state.as_ref().unwrap();
+        assert_eq!(30, state.payload.telemetry_interval);
+        let serialized = serde_json_core::to_string::<U1024, _>(&owned).unwrap();
+        let copy =
+            serde_json_core::from_str::<RegistrationOperationStatus<Allocation>>(&serialized)
+                .unwrap();
+        assert_eq!(owned, copy);
+    }
+
+    #[test]
+    fn string_payload_deserializes() {
+        let source = "{\"payload\":\"custom\",\"registrationId\":\"test\"}";
+        let owned = serde_json_core::from_str::<DeviceRegistration>(source).unwrap();
+        assert_eq!("custom", owned.payload);
+    }
+
+    #[test]
+    fn is_send() {
+        fn assert_send<T: Send + 'static>() {}
+        assert_send::<RegistrationOperationStatus>();
+        assert_send::<DeviceRegistration>();
+    }
+}
@@ -0,0 +1,89 @@
+// Signing of SAS tokens. Implement SasSigner for keys held in a secure
+// element, TPM or OS keyring so that they never have to be loaded into memory;
+// SymmetricKeySigner signs in software with a base64 encoded key.
+use core::fmt;
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+use heapless::consts::U256;
+use heapless::Vec;
+
+use azure_sdk_for_rust_common::log::REDACTED;
+
+use super::base64::base64_decode;
+use super::util::zeroize_vec;
+
+type HmacSha256 = Hmac<Sha256>;
+
+pub const SHA256_DIGEST_LENGTH: usize = 32;
+
+pub trait SasSigner {
+    // HMAC-SHA256 of the data with the device or enrollment key.
+    fn sign(&self, data: &[u8]) -> Result<[u8; SHA256_DIGEST_LENGTH], &'static str>;
+}
+
+pub struct SymmetricKeySigner {
+    key: Vec<u8, U256>,
+}
+
+impl SymmetricKeySigner {
+    pub fn new(sas_key: &str) -> Result<SymmetricKeySigner, &'static str> {
+        Ok(SymmetricKeySigner {
+            key: base64_decode(sas_key)?,
+        })
+    }
+}
+
+impl Drop for SymmetricKeySigner {
+    fn drop(&mut self) {
+        zeroize_vec(&mut self.key);
+    }
+}
+
+impl fmt::Debug for SymmetricKeySigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SymmetricKeySigner")
+            .field("key", &REDACTED)
+            .finish()
+    }
+}
+
+impl SasSigner for SymmetricKeySigner {
+    fn sign(&self, data: &[u8]) -> Result<[u8; SHA256_DIGEST_LENGTH], &'static str> {
+        let mut mac = HmacSha256::new_varkey(&self.key).expect("HMAC can take key of any size");
+        mac.update(data);
+        let mut digest = [0; SHA256_DIGEST_LENGTH];
+        digest.copy_from_slice(&mac.finalize().into_bytes());
+        Ok(digest)
+    }
+}
+
+#[cfg(test)]
+mod tests_signer {
+    use super::*;
+    use crate::provisioning::base64::AZ_ERROR_UNABLE_TO_DECODE_BASE64;
+    use core::fmt::Write;
+
+    #[test]
+    fn symmetric_key_signs_with_hmac_sha256() {
+        // RFC 4231 test case 2
+        let signer = SymmetricKeySigner::new("SmVmZQ==").unwrap();
+        let digest = signer.sign(b"what do ya want for nothing?").unwrap();
+        assert_eq!(
+            digest,
+            [
+                0x5b, 0xdc, 0xc1, 0x46, 0xbf, 0x60, 0x75, 0x4e, 0x6a, 0x04, 0x24, 0x26, 0x08, 0x95,
+                0x75, 0xc7, 0x5a, 0x00, 0x3f, 0x08, 0x9d, 0x27, 0x39, 0x83, 0x9d, 0xec, 0x58, 0xb9,
+                0x64, 0xec, 0x38, 0x43
+            ]
+        );
+        let mut debug: heapless::String<heapless::consts::U64> = heapless::String::new();
+        write!(debug, "{:?}", signer).unwrap();
+        assert_eq!(debug.as_str(), "SymmetricKeySigner { key: \"***\" }");
+        assert_eq!(
+            SymmetricKeySigner::new("not base64!").err(),
+            Some(AZ_ERROR_UNABLE_TO_DECODE_BASE64)
+        );
+    }
+}
@@ -0,0 +1,620 @@
+// Keeps the last successful registration so that a device can reconnect to its
+// hub on boot without going through DPS, and re-provision once the hub no longer
+// accepts it.
+use generic_array::typenum::{Add1, Prod, Sum, Unsigned, U3, U9};
+use heapless::consts::{U128, U32};
+use heapless::{ArrayLength, String, Vec};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use super::common::{ProvisioningStatus, ReprovisioningStatus};
+use super::error::{Error, ErrorKind};
+use super::serialization::DeviceRegistrationResult;
+use crate::mqtt::ConnectReturnCode;
+
+pub const AZ_ERROR_PROVISIONING_RECORD_NOT_ASSIGNED: &str =
+    "The registration result is not for an assigned device.";
+pub const AZ_ERROR_PROVISIONING_RECORD_CORRUPT: &str = "The stored provisioning record is corrupt.";
+
+// "AZPR" followed by the format version
+const RECORD_MAGIC: [u8; 4] = *b"AZPR";
+const RECORD_VERSION: u8 = 1;
+
+type FieldCapacity = U128;
+type SubstatusCapacity = U32;
+// magic, version, three length-prefixed fields, the length-prefixed substatus
+// and a trailing crc32
+type RecordCapacity = Sum<Sum<U9, Prod<U3, Add1<FieldCapacity>>>, Add1<SubstatusCapacity>>;
+pub const MAX_RECORD_LENGTH: usize = RecordCapacity::USIZE;
+
+type RecordBuffer = Vec<u8, RecordCapacity>;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProvisioningRecord {
+    assigned_hub: String<FieldCapacity>,
+    device_id: String<FieldCapacity>,
+    etag: String<FieldCapacity>,
+    substatus: String<SubstatusCapacity>,
+}
+
+impl ProvisioningRecord {
+    pub fn new(
+        assigned_hub: &str,
+        device_id: &str,
+        etag: &str,
+        substatus: &str,
+    ) -> Result<ProvisioningRecord, &'static str> {
+        Ok(ProvisioningRecord {
+            assigned_hub: to_string(assigned_hub)?,
+            device_id: to_string(device_id)?,
+            etag: to_string(etag)?,
+            substatus: to_string(substatus)?,
+        })
+    }
+
+    // Only results with status "assigned" are worth keeping.
+    pub fn from_registration_result<P>(
+        result: &DeviceRegistrationResult<'_, P>,
+    ) -> Result<ProvisioningRecord, &'static str> {
+        match result.status.parse::<ProvisioningStatus>() {
+            Ok(ProvisioningStatus::Assigned) => ProvisioningRecord::new(
+                result.assigned_hub,
+                result.device_id,
+                result.etag,
+                result.substatus,
+            ),
+            _ => Err(AZ_ERROR_PROVISIONING_RECORD_NOT_ASSIGNED),
+        }
+    }
+
+    pub fn assigned_hub(&self) -> &str {
+        self.assigned_hub.as_str()
+    }
+
+    pub fn device_id(&self) -> &str {
+        self.device_id.as_str()
+    }
+
+    pub fn etag(&self) -> &str {
+        self.etag.as_str()
+    }
+
+    pub fn substatus(&self) -> &str {
+        self.substatus.as_str()
+    }
+
+    pub fn reprovisioning_status(&self) -> ReprovisioningStatus {
+        ReprovisioningStatus::from(self.substatus.as_str())
+    }
+
+    // AZPR | version | len | hub | len | device id | len | etag | len | substatus | crc32 (LE)
+    pub fn encode(&self) -> Result<RecordBuffer, &'static str> {
+        let mut buffer = RecordBuffer::new();
+        push(&mut buffer, &RECORD_MAGIC)?;
+        push(&mut buffer, &[RECORD_VERSION])?;
+        for field in &[
+            self.assigned_hub.as_str(),
+            self.device_id.as_str(),
+            self.etag.as_str(),
+            self.substatus.as_str(),
+        ] {
+            push(&mut buffer, &[field.len() as u8])?;
+            push(&mut buffer, field.as_bytes())?;
+        }
+        let checksum = crc32(&buffer);
+        push(&mut buffer, &checksum.to_le_bytes())?;
+        Ok(buffer)
+    }
+
+    // Trailing bytes after the checksum are ignored so that a whole flash
+    // sector can be handed in.
+    pub fn decode(bytes: &[u8]) -> Result<ProvisioningRecord, &'static str> {
+        if bytes.len() < 5 || bytes[..4] != RECORD_MAGIC || bytes[4] != RECORD_VERSION {
+            return Err(AZ_ERROR_PROVISIONING_RECORD_CORRUPT);
+        }
+        let mut offset = 5;
+        let mut fields: [&str; 4] = [""; 4];
+        for field in &mut fields {
+            let length = *bytes
+                .get(offset)
+                .ok_or(AZ_ERROR_PROVISIONING_RECORD_CORRUPT)? as usize;
+            let value = bytes
+                .get(offset + 1..offset + 1 + length)
+                .ok_or(AZ_ERROR_PROVISIONING_RECORD_CORRUPT)?;
+            *field =
+                core::str::from_utf8(value).map_err(|_| AZ_ERROR_PROVISIONING_RECORD_CORRUPT)?;
+            offset += 1 + length;
+        }
+        let checksum = bytes
+            .get(offset..offset + 4)
+            .ok_or(AZ_ERROR_PROVISIONING_RECORD_CORRUPT)?;
+        if checksum != crc32(&bytes[..offset]).to_le_bytes() {
+            return Err(AZ_ERROR_PROVISIONING_RECORD_CORRUPT);
+        }
+        ProvisioningRecord::new(fields[0], fields[1], fields[2], fields[3])
+    }
+}
+
+pub trait ProvisioningStore {
+    fn backup(&mut self, record: &ProvisioningRecord) -> Result<(), Error>;
+    // Ok(None) when nothing was backed up yet
+    fn restore(&mut self) -> Result<Option<ProvisioningRecord>, Error>;
+    fn clear(&mut self) -> Result<(), Error>;
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BootAction {
+    ConnectToHub(ProvisioningRecord),
+    Provision,
+}
+
+// Decides whether the device can go straight to its hub. A record that cannot be
+// read is treated the same as no record at all.
+pub fn boot_action<S>(store: &mut S) -> BootAction
+where
+    S: ProvisioningStore + ?Sized,
+{
+    match store.restore() {
+        Ok(Some(record)) => BootAction::ConnectToHub(record),
+        _ => BootAction::Provision,
+    }
+}
+
+// Backs up an assigned registration result. Anything else is ignored.
+pub fn backup_registration_result<S, P>(
+    store: &mut S,
+    result: &DeviceRegistrationResult<'_, P>,
+) -> Result<bool, Error>
+where
+    S: ProvisioningStore + ?Sized,
+{
+    match ProvisioningRecord::from_registration_result(result) {
+        Ok(record) => store.backup(&record).map(|()| true),
+        Err(_) => Ok(false),
+    }
+}
+
+// When the hub refuses the cached identity the record is dropped so that the
+// next attempt goes through DPS. Returns true when the device must re-provision.
+pub fn handle_hub_connect_result<S>(
+    store: &mut S,
+    return_code: ConnectReturnCode,
+) -> Result<bool, Error>
+where
+    S: ProvisioningStore + ?Sized,
+{
+    if is_hub_rejection(return_code) {
+        store.clear()?;
+        Ok(true)
+    } else {
+        Ok(false)
+    }
+}
+
+// The hub no longer accepts this identity, as opposed to being unavailable.
+pub fn is_hub_rejection(return_code: ConnectReturnCode) -> bool {
+    matches!(
+        return_code,
+        ConnectReturnCode::BadUserNameOrPassword
+            | ConnectReturnCode::NotAuthorized
+            | ConnectReturnCode::IdentifierRejected
+    )
+}
+
+// A single erasable flash sector (or any region that is erased as a whole).
+pub trait FlashSector {
+    fn capacity(&self) -> usize;
+    fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), &'static str>;
+    fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), &'static str>;
+    fn erase(&mut self) -> Result<(), &'static str>;
+}
+
+pub struct FlashSectorStore<F> {
+    sector: F,
+}
+
+impl<F> FlashSectorStore<F>
+where
+    F: FlashSector,
+{
+    pub fn new(sector: F) -> FlashSectorStore<F> {
+        FlashSectorStore { sector }
+    }
+
+    pub fn into_inner(self) -> F {
+        self.sector
+    }
+}
+
+impl<F> ProvisioningStore for FlashSectorStore<F>
+where
+    F: FlashSector,
+{
+    fn backup(&mut self, record: &ProvisioningRecord) -> Result<(), Error> {
+        let encoded = record
+            .encode()
+            .map_err(|_| Error::from(ErrorKind::CouldNotBackup))?;
+        if encoded.len() > self.sector.capacity() {
+            return Err(Error::from(ErrorKind::CouldNotBackup));
+        }
+        self.sector
+            .erase()
+            .and_then(|()| self.sector.write(0, &encoded))
+            .map_err(|_| Error::from(ErrorKind::CouldNotBackup))
+    }
+
+    fn restore(&mut self) -> Result<Option<ProvisioningRecord>, Error> {
+        let mut buffer = [0_u8; MAX_RECORD_LENGTH];
+        let length = MAX_RECORD_LENGTH.min(self.sector.capacity());
+        self.sector
+            .read(0, &mut buffer[..length])
+            .map_err(|_| Error::from(ErrorKind::CouldNotRestore))?;
+        if buffer[..4] != RECORD_MAGIC {
+            // erased or never written
+            return Ok(None);
+        }
+        ProvisioningRecord::decode(&buffer[..length])
+            .map(Some)
+            .map_err(|_| Error::from(ErrorKind::CouldNotRestore))
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.sector
+            .erase()
+            .map_err(|_| Error::from(ErrorKind::CouldNotBackup))
+    }
+}
+
+// Key-value storage such as NVS or a flash file system.
+pub trait KeyValue {
+    // Returns the number of bytes read, or None when the key does not exist.
+    fn get(&mut self, key: &str, buffer: &mut [u8]) -> Result<Option<usize>, &'static str>;
+    fn set(&mut self, key: &str, value: &[u8]) -> Result<(), &'static str>;
+    fn remove(&mut self, key: &str) -> Result<(), &'static str>;
+}
+
+pub const DEFAULT_PROVISIONING_RECORD_KEY: &str = "az-dps-record";
+
+pub struct KeyValueStore<'a, K> {
+    storage: K,
+    key: &'a str,
+}
+
+impl<'a, K> KeyValueStore<'a, K>
+where
+    K: KeyValue,
+{
+    pub fn new(storage: K, key: Option<&'a str>) -> KeyValueStore<'a, K> {
+        KeyValueStore {
+            storage,
+            key: key.unwrap_or(DEFAULT_PROVISIONING_RECORD_KEY),
+        }
+    }
+
+    pub fn into_inner(self) -> K {
+        self.storage
+    }
+}
+
+impl<'a, K> ProvisioningStore for KeyValueStore<'a, K>
+where
+    K: KeyValue,
+{
+    fn backup(&mut self, record: &ProvisioningRecord) -> Result<(), Error> {
+        let encoded = record
+            .encode()
+            .map_err(|_| Error::from(ErrorKind::CouldNotBackup))?;
+        self.storage
+            .set(self.key, &encoded)
+            .map_err(|_| Error::from(ErrorKind::CouldNotBackup))
+    }
+
+    fn restore(&mut self) -> Result<Option<ProvisioningRecord>, Error> {
+        let mut buffer = [0_u8; MAX_RECORD_LENGTH];
+        match self.storage.get(self.key, &mut buffer) {
+            Ok(Some(length)) => ProvisioningRecord::decode(&buffer[..length.min(buffer.len())])
+                .map(Some)
+                .map_err(|_| Error::from(ErrorKind::CouldNotRestore)),
+            Ok(None) => Ok(None),
+            Err(_) => Err(Error::from(ErrorKind::CouldNotRestore)),
+        }
+    }
+
+    fn clear(&mut self) -> Result<(), Error> {
+        self.storage
+            .remove(self.key)
+            .map_err(|_| Error::from(ErrorKind::CouldNotBackup))
+    }
+}
+
+#[cfg(feature = "std")]
+pub use self::file::FileStore;
+
+#[cfg(feature = "std")]
+mod file {
+    use std::fs;
+    use std::io;
+    use std::path::{Path, PathBuf};
+
+    use super::{Error, ErrorKind, ProvisioningRecord, ProvisioningStore};
+
+    // Writes go to a temporary file that is renamed over the record so that a
+    // power loss never leaves a half written record behind.
+    pub struct FileStore {
+        path: PathBuf,
+    }
+
+    impl FileStore {
+        pub fn new<P: AsRef<Path>>(path: P) -> FileStore {
+            FileStore {
+                path: path.as_ref().to_path_buf(),
+            }
+        }
+
+        pub fn path(&self) -> &Path {
+            &self.path
+        }
+
+        fn temporary_path(&self) -> PathBuf {
+            let mut path = self.path.clone().into_os_string();
+            path.push(".tmp");
+            PathBuf::from(path)
+        }
+    }
+
+    impl ProvisioningStore for FileStore {
+        fn backup(&mut self, record: &ProvisioningRecord) -> Result<(), Error> {
+            let encoded = record
+                .encode()
+                .map_err(|_| Error::from(ErrorKind::CouldNotBackup))?;
+            let temporary_path = self.temporary_path();
+            fs::write(&temporary_path, &encoded)
+                .and_then(|()| fs::rename(&temporary_path, &self.path))
+                .map_err(|_| Error::from(ErrorKind::CouldNotBackup))
+        }
+
+        fn restore(&mut self) -> Result<Option<ProvisioningRecord>, Error> {
+            match fs::read(&self.path) {
+                Ok(bytes) => ProvisioningRecord::decode(&bytes)
+                    .map(Some)
+                    .map_err(|_| Error::from(ErrorKind::CouldNotRestore)),
+                Err(error) if error.kind() == io::ErrorKind::NotFound => Ok(None),
+                Err(_) => Err(Error::from(ErrorKind::CouldNotRestore)),
+            }
+        }
+
+        fn clear(&mut self) -> Result<(), Error> {
+            match fs::remove_file(&self.path) {
+                Err(error) if error.kind() != io::ErrorKind::NotFound => {
+                    Err(Error::from(ErrorKind::CouldNotBackup))
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+}
+
+fn to_string<B>(value: &str) -> Result<String<B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    let mut result: String<B> = String::new();
+    if result.push_str(value).is_err() {
+        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    Ok(result)
+}
+
+fn push(buffer: &mut RecordBuffer, bytes: &[u8]) -> Result<(), &'static str> {
+    buffer
+        .extend_from_slice(bytes)
+        .map_err(|()| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)
+}
+
+// CRC-32 (IEEE 802.3), bitwise to avoid a lookup table
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for byte in bytes {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+#[cfg(test)]
+mod tests_provisioning_record {
+    use super::*;
+
+    fn record() -> ProvisioningRecord {
+        ProvisioningRecord::new(
+            "contoso.azure-devices.net",
+            "device-1",
+            "ImY3MDEyN2YxIg==",
+            "deviceDataMigrated",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn crc32_matches_the_check_value() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn record_round_trips() {
+        let encoded = record().encode().unwrap();
+        assert_eq!(ProvisioningRecord::decode(&encoded).unwrap(), record());
+        assert_eq!(
+            record().reprovisioning_status(),
+            ReprovisioningStatus::DeviceDataMigrated
+        );
+    }
+
+    #[test]
+    fn longest_record_round_trips() {
+        let field = "a".repeat(FieldCapacity::USIZE);
+        let substatus = "s".repeat(SubstatusCapacity::USIZE);
+        let record = ProvisioningRecord::new(&field, &field, &field, &substatus).unwrap();
+        let encoded = record.encode().unwrap();
+        assert_eq!(encoded.len(), MAX_RECORD_LENGTH);
+        assert_eq!(ProvisioningRecord::decode(&encoded).unwrap(), record);
+    }
+
+    #[test]
+    fn corrupt_record_is_rejected() {
+        let mut encoded = record().encode().unwrap();
+        encoded[7] ^= 0xFF;
+        assert_eq!(
+            ProvisioningRecord::decode(&encoded),
+            Err(AZ_ERROR_PROVISIONING_RECORD_CORRUPT)
+        );
+        assert!(ProvisioningRecord::decode(&encoded[..10]).is_err());
+        assert!(ProvisioningRecord::decode(b"").is_err());
+    }
+
+    #[test]
+    fn only_assigned_results_are_recorded() {
+        let mut result = DeviceRegistrationResult::new(
+            "contoso.azure-devices.net",
+            "",
+            "device-1",
+            "",
+            "",
+            "etag",
+            "",
+            "",
+            "device-1",
+            "assigned",
+            "initialAssignment",
+            None,
+            None,
+            None,
+        );
+        let record = ProvisioningRecord::from_registration_result(&result).unwrap();
+        assert_eq!(record.assigned_hub(), "contoso.azure-devices.net");
+        assert_eq!(record.etag(), "etag");
+        result.status = "failed";
+        assert_eq!(
+            ProvisioningRecord::from_registration_result(&result),
+            Err(AZ_ERROR_PROVISIONING_RECORD_NOT_ASSIGNED)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests_stores {
+    use super::*;
+
+    struct RamSector {
+        bytes: [u8; 1024],
+    }
+
+    impl FlashSector for RamSector {
+        fn capacity(&self) -> usize {
+            self.bytes.len()
+        }
+
+        fn read(&mut self, offset: usize, buffer: &mut [u8]) -> Result<(), &'static str> {
+            buffer.copy_from_slice(&self.bytes[offset..offset + buffer.len()]);
+            Ok(())
+        }
+
+        fn write(&mut self, offset: usize, data: &[u8]) -> Result<(), &'static str> {
+            self.bytes[offset..offset + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn erase(&mut self) -> Result<(), &'static str> {
+            self.bytes = [0xFF; 1024];
+            Ok(())
+        }
+    }
+
+    #[derive(Default)]
+    struct SingleKey {
+        value: Option<Vec<u8, RecordCapacity>>,
+    }
+
+    impl KeyValue for SingleKey {
+        fn get(&mut self, key: &str, buffer: &mut [u8]) -> Result<Option<usize>, &'static str> {
+            assert_eq!(key, DEFAULT_PROVISIONING_RECORD_KEY);
+            Ok(self.value.as_ref().map(|value| {
+                buffer[..value.len()].copy_from_slice(value);
+                value.len()
+            }))
+        }
+
+        fn set(&mut self, _: &str, value: &[u8]) -> Result<(), &'static str> {
+            self.value = Some(Vec::from_slice(value).unwrap());
+            Ok(())
+        }
+
+        fn remove(&mut self, _: &str) -> Result<(), &'static str> {
+            self.value = None;
+            Ok(())
+        }
+    }
+
+    fn record() -> ProvisioningRecord {
+        ProvisioningRecord::new("hub", "device", "etag", "initialAssignment").unwrap()
+    }
+
+    fn exercise<S: ProvisioningStore>(store: &mut S) {
+        assert_eq!(boot_action(store), BootAction::Provision);
+        store.backup(&record()).unwrap();
+        assert_eq!(boot_action(store), BootAction::ConnectToHub(record()));
+        assert!(!handle_hub_connect_result(store, ConnectReturnCode::ServerUnavailable).unwrap());
+        assert_eq!(boot_action(store), BootAction::ConnectToHub(record()));
+        assert!(handle_hub_connect_result(store, ConnectReturnCode::NotAuthorized).unwrap());
+        assert_eq!(boot_action(store), BootAction::Provision);
+    }
+
+    #[test]
+    fn flash_sector_store_backs_up_and_clears() {
+        let mut store = FlashSectorStore::new(RamSector {
+            bytes: [0xFF; 1024],
+        });
+        exercise(&mut store);
+    }
+
+    #[test]
+    fn corrupt_flash_sector_falls_back_to_provisioning() {
+        let mut store = FlashSectorStore::new(RamSector {
+            bytes: [0xFF; 1024],
+        });
+        store.backup(&record()).unwrap();
+        let mut sector = store.into_inner();
+        sector.bytes[6] ^= 0x01;
+        let mut store = FlashSectorStore::new(sector);
+        assert_eq!(
+            *store.restore().unwrap_err().kind(),
+            ErrorKind::CouldNotRestore
+        );
+        assert_eq!(boot_action(&mut store), BootAction::Provision);
+    }
+
+    #[test]
+    fn key_value_store_backs_up_and_clears() {
+        let mut store = KeyValueStore::new(SingleKey::default(), None);
+        exercise(&mut store);
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn file_store_backs_up_and_clears() {
+        let path = std::env::temp_dir().join(std::format!(
+            "az-provisioning-store-{}.bin",
+            std::process::id()
+        ));
+        let mut store = FileStore::new(&path);
+        exercise(&mut store);
+        store.backup(&record()).unwrap();
+        assert!(path.exists());
+        store.clear().unwrap();
+        assert!(!path.exists());
+        store.clear().unwrap();
+    }
+}
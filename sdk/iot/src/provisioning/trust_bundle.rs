@@ -0,0 +1,384 @@
+// The CA certificates DPS returns in the registration state's trustBundle, for
+// the device to add to its trust store. Each certificate is PEM; when the
+// registration result was parsed with serde-json-core its line breaks are
+// still the JSON escapes \r\n, so the PEM and DER here are read from either.
+use base64::{decode_config_slice, STANDARD};
+use heapless::{ArrayLength, String};
+
+use azure_sdk_for_rust_common::error::SpanError;
+use azure_sdk_for_rust_common::span::SpanWriter;
+
+use super::serialization::TrustBundleCertificate;
+use super::util::write_to_string;
+
+pub const AZ_ERROR_INVALID_TRUST_BUNDLE_CERTIFICATE: &str =
+    "The trust bundle certificate is not a valid PEM certificate.";
+pub const AZ_ERROR_UNABLE_TO_WRITE_TRUST_STORE: &str = "The trust store could not be updated.";
+
+const PEM_HEADER: &str = "-----BEGIN CERTIFICATE-----";
+const PEM_FOOTER: &str = "-----END CERTIFICATE-----";
+const PEM_LINE_LENGTH: usize = 64;
+
+impl<'a> TrustBundleCertificate<'a> {
+    // Written as PEM with \n line breaks and 64 character lines.
+    pub fn pem<B>(&self) -> Result<String<B>, &'static str>
+    where
+        B: ArrayLength<u8>,
+    {
+        write_to_string(|buffer| self.pem_into(buffer))
+    }
+
+    pub fn pem_into(&self, buffer: &mut [u8]) -> Result<usize, SpanError> {
+        let mut writer = SpanWriter::new(buffer);
+        writer.push_str(PEM_HEADER);
+        for (index, c) in base64_body(self.certificate)?.enumerate() {
+            if index % PEM_LINE_LENGTH == 0 {
+                writer.push('\n');
+            }
+            writer.push(char::from(c?));
+        }
+        writer.push('\n');
+        writer.push_str(PEM_FOOTER);
+        writer.push('\n');
+        writer.finish()
+    }
+
+    pub fn der_into(&self, buffer: &mut [u8]) -> Result<usize, SpanError> {
+        let mut writer = SpanWriter::new(buffer);
+        let mut group = [0_u8; 4];
+        let mut grouped = 0;
+        for c in base64_body(self.certificate)? {
+            group[grouped] = c?;
+            grouped += 1;
+            if grouped == group.len() {
+                let mut decoded = [0_u8; 3];
+                let len = decode_config_slice(&group, STANDARD, &mut decoded)
+                    .map_err(|_| SpanError::Other(AZ_ERROR_INVALID_TRUST_BUNDLE_CERTIFICATE))?;
+                writer.push_bytes(&decoded[..len]);
+                grouped = 0;
+            }
+        }
+        if grouped != 0 || writer.required() == 0 {
+            return Err(SpanError::Other(AZ_ERROR_INVALID_TRUST_BUNDLE_CERTIFICATE));
+        }
+        writer.finish()
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn to_pem(&self) -> Result<alloc::string::String, &'static str> {
+        let pem = write_to_vec(|buffer| self.pem_into(buffer))?;
+        alloc::string::String::from_utf8(pem).map_err(|_| AZ_ERROR_INVALID_TRUST_BUNDLE_CERTIFICATE)
+    }
+
+    #[cfg(feature = "alloc")]
+    pub fn to_der(&self) -> Result<alloc::vec::Vec<u8>, &'static str> {
+        write_to_vec(|buffer| self.der_into(buffer))
+    }
+}
+
+// Retries once with the length the writer asked for.
+#[cfg(feature = "alloc")]
+fn write_to_vec<F>(write: F) -> Result<alloc::vec::Vec<u8>, &'static str>
+where
+    F: Fn(&mut [u8]) -> Result<usize, SpanError>,
+{
+    let mut buffer = alloc::vec![0_u8; 2048];
+    let written = match write(&mut buffer) {
+        Err(SpanError::InsufficientSize { required }) => {
+            buffer.resize(required, 0);
+            write(&mut buffer)
+        }
+        result => result,
+    }
+    .map_err(|error| error.as_str())?;
+    buffer.truncate(written);
+    Ok(buffer)
+}
+
+// The base64 characters between the PEM header and footer, skipping line
+// breaks whether or not they are still escaped.
+fn base64_body(
+    certificate: &str,
+) -> Result<impl Iterator<Item = Result<u8, SpanError>> + '_, SpanError> {
+    let invalid = SpanError::Other(AZ_ERROR_INVALID_TRUST_BUNDLE_CERTIFICATE);
+    let start = certificate.find(PEM_HEADER).ok_or(invalid)? + PEM_HEADER.len();
+    let end = certificate.find(PEM_FOOTER).ok_or(invalid)?;
+    let body = certificate.get(start..end).ok_or(invalid)?;
+    let mut bytes = body.bytes();
+    Ok(core::iter::from_fn(move || loop {
+        match bytes.next()? {
+            b'\\' => match bytes.next() {
+                Some(b'n' | b'r' | b't') => {}
+                Some(b'/') => return Some(Ok(b'/')),
+                _ => return Some(Err(invalid)),
+            },
+            c if c.is_ascii_whitespace() => {}
+            c if c.is_ascii_alphanumeric() || c == b'+' || c == b'/' || c == b'=' => {
+                return Some(Ok(c))
+            }
+            _ => return Some(Err(invalid)),
+        }
+    }))
+}
+
+#[cfg(feature = "std")]
+pub use self::trust_store::{write_trust_store, TrustStoreUpdate};
+
+// A directory of <sha256 of the DER>.pem files plus the etag of the bundle
+// they came from. Every file is written to a temporary name and renamed into
+// place, and the etag last, so an interrupted update is redone next time.
+#[cfg(feature = "std")]
+mod trust_store {
+    use core::fmt::Write as _;
+    use std::collections::BTreeSet;
+    use std::fs;
+    use std::io::Write as _;
+    use std::path::Path;
+    use std::string::String;
+
+    use sha2::{Digest, Sha256};
+
+    use super::AZ_ERROR_UNABLE_TO_WRITE_TRUST_STORE;
+    use crate::provisioning::serialization::TrustBundle;
+
+    const ETAG_FILE: &str = ".etag";
+    const CERTIFICATE_EXTENSION: &str = ".pem";
+
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    pub enum TrustStoreUpdate {
+        // The etag matched the one the directory was last written with.
+        Unchanged,
+        Updated { written: usize, removed: usize },
+    }
+
+    // Certificates from an earlier bundle that are no longer in this one are
+    // removed; other files in the directory are left alone.
+    pub fn write_trust_store(
+        directory: &Path,
+        trust_bundle: &TrustBundle<'_>,
+    ) -> Result<TrustStoreUpdate, &'static str> {
+        let error = |_| AZ_ERROR_UNABLE_TO_WRITE_TRUST_STORE;
+        fs::create_dir_all(directory).map_err(error)?;
+        let etag_path = directory.join(ETAG_FILE);
+        if !trust_bundle.etag.is_empty()
+            && fs::read_to_string(&etag_path).ok().as_deref() == Some(trust_bundle.etag)
+        {
+            return Ok(TrustStoreUpdate::Unchanged);
+        }
+
+        let mut names = BTreeSet::new();
+        for certificate in trust_bundle.certificates.iter() {
+            let der = certificate.to_der()?;
+            let mut name = String::new();
+            for byte in Sha256::digest(&der) {
+                write!(name, "{:02x}", byte).map_err(|_| AZ_ERROR_UNABLE_TO_WRITE_TRUST_STORE)?;
+            }
+            name.push_str(CERTIFICATE_EXTENSION);
+            write_atomically(directory, &name, certificate.to_pem()?.as_bytes())?;
+            names.insert(name);
+        }
+
+        let mut removed = 0;
+        for entry in fs::read_dir(directory).map_err(error)? {
+            let name = entry.map_err(error)?.file_name();
+            let name = match name.to_str() {
+                Some(name) if is_certificate_file(name) && !names.contains(name) => name,
+                _ => continue,
+            };
+            fs::remove_file(directory.join(name)).map_err(error)?;
+            removed += 1;
+        }
+
+        write_atomically(directory, ETAG_FILE, trust_bundle.etag.as_bytes())?;
+        Ok(TrustStoreUpdate::Updated {
+            written: names.len(),
+            removed,
+        })
+    }
+
+    fn is_certificate_file(name: &str) -> bool {
+        name.strip_suffix(CERTIFICATE_EXTENSION)
+            .map_or(false, |hash| {
+                hash.len() == 64 && hash.bytes().all(|c| matches!(c, b'0'..=b'9' | b'a'..=b'f'))
+            })
+    }
+
+    fn write_atomically(directory: &Path, name: &str, contents: &[u8]) -> Result<(), &'static str> {
+        let error = |_| AZ_ERROR_UNABLE_TO_WRITE_TRUST_STORE;
+        let temporary = directory.join(std::format!(".{}.tmp", name));
+        let mut file = fs::File::create(&temporary).map_err(error)?;
+        file.write_all(contents).map_err(error)?;
+        file.sync_all().map_err(error)?;
+        fs::rename(&temporary, directory.join(name)).map_err(error)
+    }
+}
+
+#[cfg(test)]
+mod tests_trust_bundle {
+    use super::*;
+    use crate::provisioning::serialization::RegistrationOperationStatus;
+    use heapless::consts::U1024;
+
+    // A certificate as it appears in the service's JSON.
+    const ESCAPED: &str = "-----BEGIN CERTIFICATE-----\\r\\nMIIBFzCBvqADAgECAhQBAjANBgkqhkiG9w0BAQsFADASMRAwDgYDVQQDDAdUZXN0\\r\\nIENBMB4XDTI2MDEwMTAwMDAwMFoXDTI3MDEwMTAwMDAwMFowEjEQMA4GA1UEAwwH\\/w==\\r\\n-----END CERTIFICATE-----\\r\\n";
+
+    fn certificate(pem: &str) -> TrustBundleCertificate<'_> {
+        TrustBundleCertificate {
+            certificate: pem,
+            ..TrustBundleCertificate::default()
+        }
+    }
+
+    #[test]
+    fn escaped_and_plain_certificates_read_the_same() {
+        let escaped = certificate(ESCAPED);
+        let pem: String<U1024> = escaped.pem().unwrap();
+        assert!(pem.starts_with("-----BEGIN CERTIFICATE-----\nMIIBFzCBvqADAgEC"));
+        assert!(pem.ends_with("/w==\n-----END CERTIFICATE-----\n"));
+        assert!(pem.lines().all(|line| line.len() <= 64));
+        let plain = certificate(&pem);
+        assert_eq!(plain.pem::<U1024>().unwrap(), pem);
+
+        let mut der = [0_u8; 256];
+        let len = escaped.der_into(&mut der).unwrap();
+        assert_eq!(&der[..2], &[0x30, 0x82]);
+        let mut plain_der = [0_u8; 256];
+        assert_eq!(plain.der_into(&mut plain_der), Ok(len));
+        assert_eq!(&der[..len], &plain_der[..len]);
+        assert!(matches!(
+            escaped.der_into(&mut der[..8]),
+            Err(SpanError::InsufficientSize { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_certificates_are_rejected() {
+        let invalid = Err(SpanError::Other(AZ_ERROR_INVALID_TRUST_BUNDLE_CERTIFICATE));
+        let mut buffer = [0_u8; 64];
+        assert_eq!(certificate("MIIB").der_into(&mut buffer), invalid);
+        assert_eq!(
+            certificate("-----BEGIN CERTIFICATE-----\nMI*B\n-----END CERTIFICATE-----")
+                .der_into(&mut buffer),
+            invalid
+        );
+        assert_eq!(
+            certificate("-----BEGIN CERTIFICATE-----\nMIIBF\n-----END CERTIFICATE-----")
+                .der_into(&mut buffer),
+            invalid
+        );
+        assert_eq!(
+            certificate("-----BEGIN CERTIFICATE-----\n-----END CERTIFICATE-----")
+                .der_into(&mut buffer),
+            invalid
+        );
+    }
+
+    #[test]
+    fn trust_bundle_is_parsed_from_the_registration_state() {
+        let source = "{\"operationId\":\"1\",\"status\":\"assigned\",\"registrationState\":{\"status\":\"assigned\",\"trustBundle\":{\"certificates\":[{\"certificate\":\"-----BEGIN CERTIFICATE-----\\r\\nMIIB\\r\\n-----END CERTIFICATE-----\\r\\n\",\"metadata\":{\"subjectName\":\"CN=Test CA\",\"sha256Thumbprint\":\"00\"}}],\"id\":\"bundle\",\"createdDateTime\":\"2021-06-01T00:00:00Z\",\"lastModifiedDateTime\":\"2021-06-02T00:00:00Z\",\"etag\":\"1\"}}}";
+        let status = serde_json_core::from_str::<RegistrationOperationStatus<'_>>(source).unwrap();
+        let trust_bundle = status.registration_state.unwrap().trust_bundle.unwrap();
+        assert_eq!(trust_bundle.id, "bundle");
+        assert_eq!(trust_bundle.etag, "1");
+        assert_eq!(trust_bundle.certificates.len(), 1);
+        let certificate = trust_bundle.certificates[0];
+        assert_eq!(certificate.metadata.subject_name, "CN=Test CA");
+        let mut der = [0_u8; 3];
+        assert_eq!(certificate.der_into(&mut der), Ok(3));
+        assert_eq!(der, [0x30, 0x82, 0x01]);
+    }
+}
+
+#[cfg(all(test, feature = "std"))]
+mod tests_trust_store {
+    use super::*;
+    use crate::provisioning::serialization::{TrustBundle, TrustBundleCertificates};
+    use std::path::Path;
+    use std::{format, fs};
+
+    const FIRST: &str =
+        "-----BEGIN CERTIFICATE-----\\r\\nMIIB\\r\\n-----END CERTIFICATE-----\\r\\n";
+    const SECOND: &str = "-----BEGIN CERTIFICATE-----\nMIIC\n-----END CERTIFICATE-----\n";
+
+    fn trust_bundle<'a>(etag: &'a str, pems: &[&'a str]) -> TrustBundle<'a> {
+        let mut certificates = TrustBundleCertificates::new();
+        for pem in pems {
+            certificates
+                .push(TrustBundleCertificate {
+                    certificate: pem,
+                    ..TrustBundleCertificate::default()
+                })
+                .unwrap();
+        }
+        TrustBundle {
+            certificates,
+            etag,
+            ..TrustBundle::default()
+        }
+    }
+
+    fn pem_files(directory: &Path) -> usize {
+        fs::read_dir(directory)
+            .unwrap()
+            .filter(|entry| entry.as_ref().unwrap().path().extension() == Some("pem".as_ref()))
+            .count()
+    }
+
+    #[test]
+    fn trust_store_follows_the_etag() {
+        let directory =
+            std::env::temp_dir().join(format!("az-iot-trust-store-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&directory);
+        fs::create_dir_all(&directory).unwrap();
+        fs::write(directory.join("local.pem"), "kept").unwrap();
+
+        let first = trust_bundle("1", &[FIRST, SECOND]);
+        assert_eq!(
+            write_trust_store(&directory, &first),
+            Ok(TrustStoreUpdate::Updated {
+                written: 2,
+                removed: 0
+            })
+        );
+        assert_eq!(pem_files(&directory), 3);
+        assert_eq!(fs::read_to_string(directory.join(".etag")).unwrap(), "1");
+        assert_eq!(
+            write_trust_store(&directory, &first),
+            Ok(TrustStoreUpdate::Unchanged)
+        );
+
+        let second = trust_bundle("2", &[SECOND]);
+        assert_eq!(
+            write_trust_store(&directory, &second),
+            Ok(TrustStoreUpdate::Updated {
+                written: 1,
+                removed: 1
+            })
+        );
+        assert_eq!(pem_files(&directory), 2);
+        assert_eq!(
+            fs::read_to_string(directory.join("local.pem")).unwrap(),
+            "kept"
+        );
+        let stored = fs::read_dir(&directory)
+            .unwrap()
+            .map(|entry| entry.unwrap().path())
+            .find(|path| {
+                path.file_name().unwrap() != "local.pem"
+                    && path.extension().map_or(false, |e| e == "pem")
+            })
+            .unwrap();
+        assert_eq!(fs::read_to_string(stored).unwrap(), SECOND);
+        assert!(fs::read_dir(&directory)
+            .unwrap()
+            .all(|entry| entry.unwrap().path().extension() != Some("tmp".as_ref())));
+
+        let broken = trust_bundle("3", &["MIIB"]);
+        assert_eq!(
+            write_trust_store(&directory, &broken),
+            Err(AZ_ERROR_INVALID_TRUST_BUNDLE_CERTIFICATE)
+        );
+        assert_eq!(fs::read_to_string(directory.join(".etag")).unwrap(), "2");
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
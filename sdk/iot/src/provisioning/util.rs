@@ -1,5 +1,8 @@
 use heapless::consts::U20;
-use heapless::{String, Vec};
+use heapless::{ArrayLength, String, Vec};
+use zeroize::Zeroize;
+
+use azure_sdk_for_rust_common::error::{SpanError, AZ_ERROR_INSUFFICIENT_SPAN_SIZE};
 
 pub fn u64_to_string(value: u64) -> String<U20> {
     const ZERO: u8 = b'0';
@@ -25,6 +28,57 @@ pub fn u64_to_string(value: u64) -> String<U20> {
     res
 }
 
+// heapless 0.5 `Vec::truncate` indexes past the new length, which trips the
+// standard library's debug assertions.
+pub(crate) fn truncate<T, N>(vec: &mut Vec<T, N>, len: usize)
+where
+    N: ArrayLength<T>,
+{
+    while vec.len() > len {
+        vec.pop();
+    }
+}
+
+// Overwrites the whole capacity of a buffer that held secrets, including bytes
+// left behind by earlier truncation, and leaves it empty.
+pub(crate) fn zeroize_vec<N>(vec: &mut Vec<u8, N>)
+where
+    N: ArrayLength<u8>,
+{
+    // growing to the capacity can't fail
+    let _ = vec.resize_default(N::to_usize());
+    vec[..].zeroize();
+    truncate(vec, 0);
+}
+
+pub(crate) fn zeroize_string<N>(string: &mut String<N>)
+where
+    N: ArrayLength<u8>,
+{
+    // filling up with NULs reaches the bytes behind the current length
+    while string.push('\0').is_ok() {}
+    string.as_mut_str().zeroize();
+    // `String::clear` has the same problem as `Vec::truncate`
+    while string.pop().is_some() {}
+}
+
+// Runs a writer for caller provided buffers against a heapless buffer of
+// capacity B, so that the fixed capacity variants share its implementation.
+pub(crate) fn write_to_string<B, F>(write: F) -> Result<String<B>, &'static str>
+where
+    B: ArrayLength<u8>,
+    F: FnOnce(&mut [u8]) -> Result<usize, SpanError>,
+{
+    let mut buffer: Vec<u8, B> = Vec::new();
+    if buffer.resize_default(B::to_usize()).is_err() {
+        return Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE);
+    }
+    let written = write(&mut buffer).map_err(|error| error.as_str())?;
+    truncate(&mut buffer, written);
+    // the writers only ever produce UTF-8
+    String::from_utf8(buffer).map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)
+}
+
 #[cfg(test)]
 mod tests_u64_to_string {
     use super::*;
@@ -39,3 +93,25 @@ mod tests_u64_to_string {
         assert_eq!("0", min);
     }
 }
+
+#[cfg(test)]
+mod tests_zeroize {
+    use super::*;
+    use heapless::consts::U8;
+
+    #[test]
+    fn secrets_are_cleared() {
+        let mut vec: Vec<u8, U8> = Vec::from_slice(b"secret").unwrap();
+        truncate(&mut vec, 2);
+        zeroize_vec(&mut vec);
+        assert!(vec.is_empty());
+        vec.push(1).unwrap();
+        assert_eq!(&vec[..], &[1]);
+
+        let mut string: String<U8> = String::from("secret");
+        zeroize_string(&mut string);
+        assert_eq!(string.as_str(), "");
+        string.push_str("reused").unwrap();
+        assert_eq!(string.as_str(), "reused");
+    }
+}
@@ -1,3 +1,5 @@
+use core::convert::TryFrom;
+
 #[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Debug)]
 pub struct StatusCode(u16);
 
@@ -15,7 +17,7 @@ impl InvalidStatusCode {
 impl StatusCode {
     #[inline]
     pub fn from_u16(src: u16) -> Result<StatusCode, InvalidStatusCode> {
-        if src < 100 || src >= 600 {
+        if !(100..600).contains(&src) {
             return Err(InvalidStatusCode::new());
         }
 
@@ -34,10 +36,33 @@ impl StatusCode {
 
     #[inline]
     pub fn is_retriable(&self) -> bool {
-        self.0 == StatusCode::THROTTLED || self.0 == StatusCode::SERVER_ERROR
+        self.action() == ErrorAction::Retry
+    }
+
+    pub fn action(&self) -> ErrorAction {
+        match *self {
+            StatusCode::THROTTLED
+            | StatusCode::SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::TIMEOUT => ErrorAction::Retry,
+            StatusCode::UNAUTHORIZED => ErrorAction::RefreshCredentials,
+            _ => ErrorAction::Terminal,
+        }
     }
 }
 
+// What a device should do about a failed request.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ErrorAction {
+    // Try the same request again after backing off
+    Retry,
+    // The request will not succeed as is
+    Terminal,
+    // Renew the SAS token or certificate (or re-provision) before trying again
+    RefreshCredentials,
+}
+
 impl Default for StatusCode {
     #[inline]
     fn default() -> StatusCode {
@@ -110,6 +135,134 @@ status_codes! {
   (504, TIMEOUT);
 }
 
+// The 6-digit `errorCode` DPS and IoT Hub put in error bodies. The first three
+// digits are the HTTP status of the response.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ExtendedErrorCode {
+    code: u32,
+    name: &'static str,
+    action: ErrorAction,
+    description: &'static str,
+}
+
+impl ExtendedErrorCode {
+    // Codes missing from the catalog are described by their HTTP class.
+    pub fn from_u32(code: u32) -> ExtendedErrorCode {
+        if let Some(known) = CATALOG.iter().find(|known| known.code == code) {
+            return *known;
+        }
+        let status = ExtendedErrorCode::status_of(code);
+        ExtendedErrorCode {
+            code,
+            name: "Unknown",
+            action: status.action(),
+            description: class_description(status),
+        }
+    }
+
+    pub fn as_u32(self) -> u32 {
+        self.code
+    }
+
+    pub fn name(self) -> &'static str {
+        self.name
+    }
+
+    pub fn status(self) -> StatusCode {
+        ExtendedErrorCode::status_of(self.code)
+    }
+
+    pub fn action(self) -> ErrorAction {
+        self.action
+    }
+
+    pub fn is_retriable(self) -> bool {
+        self.action == ErrorAction::Retry
+    }
+
+    pub fn description(self) -> &'static str {
+        self.description
+    }
+
+    fn status_of(code: u32) -> StatusCode {
+        let status = if code >= 100_000 { code / 1000 } else { code };
+        u16::try_from(status)
+            .ok()
+            .and_then(|status| StatusCode::from_u16(status).ok())
+            .unwrap_or(StatusCode::UNKNOWN)
+    }
+}
+
+impl From<u32> for ExtendedErrorCode {
+    fn from(code: u32) -> Self {
+        ExtendedErrorCode::from_u32(code)
+    }
+}
+
+fn class_description(status: StatusCode) -> &'static str {
+    match status.as_u16() {
+        400 => "The body of the request is not valid.",
+        401 => "The authorization token cannot be validated.",
+        403 => "The operation is forbidden, usually because a quota was exceeded.",
+        404 => "The requested resource does not exist.",
+        409 => "The request conflicts with the current state of the resource.",
+        412 => "A precondition of the request, such as the ETag, was not met.",
+        429 => "Operations are being throttled by the service.",
+        500..=599 => "An internal error occurred in the service.",
+        _ => "Unknown error code.",
+    }
+}
+
+macro_rules! extended_error_codes {
+    (
+        $(
+            ($num:expr, $konst:ident, $name:expr, $action:ident, $description:expr);
+        )+
+    ) => {
+        impl ExtendedErrorCode {
+        $(
+            pub const $konst: ExtendedErrorCode = ExtendedErrorCode {
+                code: $num,
+                name: $name,
+                action: ErrorAction::$action,
+                description: $description,
+            };
+        )+
+        }
+
+        const CATALOG: &[ExtendedErrorCode] = &[$(ExtendedErrorCode::$konst),+];
+    }
+}
+
+extended_error_codes! {
+  // DPS and IoT Hub
+  (400_000, BAD_REQUEST, "BadRequest", Terminal, "The body of the request is not valid.");
+  (400_004, ARGUMENT_INVALID, "ArgumentInvalid", Terminal, "The request contains an invalid argument.");
+  (401_000, UNAUTHORIZED, "Unauthorized", RefreshCredentials, "The authorization token cannot be validated.");
+  (401_002, UNAUTHORIZED_ACCESS, "IotHubUnauthorizedAccess", RefreshCredentials, "The SAS token or certificate of the device is invalid or expired.");
+  (404_000, NOT_FOUND, "NotFound", Terminal, "The DPS instance or a resource such as the enrollment does not exist.");
+  (412_000, PRECONDITION_FAILED, "PreconditionFailed", Terminal, "The ETag in the request does not match the ETag of the existing resource.");
+  (429_001, THROTTLED, "ThrottlingException", Retry, "Operations are being throttled by the service.");
+  (500_000, SERVER_ERROR, "InternalServerError", Retry, "An internal error occurred in the service.");
+
+  // IoT Hub
+  (400_027, CONNECTION_CLOSED_ON_NEW_CONNECTION, "ConnectionForcefullyClosedOnNewConnection", Terminal, "Another client connected with the same device identity.");
+  (401_003, IOT_HUB_UNAUTHORIZED, "IoTHubUnauthorized", RefreshCredentials, "The credentials of the device were rejected by the hub.");
+  (403_002, IOT_HUB_QUOTA_EXCEEDED, "IoTHubQuotaExceeded", Terminal, "The daily message quota of the hub was exceeded.");
+  (403_004, DEVICE_MAXIMUM_QUEUE_DEPTH_EXCEEDED, "DeviceMaximumQueueDepthExceeded", Terminal, "Too many cloud-to-device messages are queued for the device.");
+  (404_001, DEVICE_NOT_FOUND, "DeviceNotFound", Terminal, "The device is not registered in the hub.");
+  (404_103, DEVICE_NOT_ONLINE, "DeviceNotOnline", Retry, "The device is not connected to the hub.");
+  (404_104, DEVICE_CONNECTION_CLOSED_REMOTELY, "DeviceConnectionClosedRemotely", Retry, "The device closed its connection.");
+  (409_001, DEVICE_ALREADY_EXISTS, "DeviceAlreadyExists", Terminal, "A device with the same identity already exists.");
+  (409_002, LINK_CREATION_CONFLICT, "LinkCreationConflict", Retry, "The device has more than one open link of the same kind.");
+  (409_202, CONFLICT, "Conflict", Terminal, "The request conflicts with the current state of the resource.");
+  (412_002, DEVICE_MESSAGE_LOCK_LOST, "DeviceMessageLockLost", Retry, "The lock on a cloud-to-device message was lost.");
+  (500_001, IOT_HUB_SERVER_ERROR, "ServerError", Retry, "An internal error occurred in the hub.");
+  (500_008, GENERIC_TIMEOUT, "GenericTimeout", Retry, "The operation timed out in the service.");
+  (503_003, PARTITION_NOT_FOUND, "PartitionNotFound", Retry, "The hub is temporarily unavailable.");
+  (504_101, GATEWAY_TIMEOUT, "GatewayTimeout", Retry, "The gateway timed out waiting for the device.");
+}
+
 #[cfg(test)]
 mod tests_conversions {
     use super::*;
@@ -168,4 +321,77 @@ mod tests_retriable {
     fn server_error_requests_are_retriable() {
         assert!(StatusCode::SERVER_ERROR.is_retriable());
     }
+
+    #[test]
+    fn unauthorized_requests_need_new_credentials() {
+        assert!(!StatusCode::UNAUTHORIZED.is_retriable());
+        assert_eq!(
+            StatusCode::UNAUTHORIZED.action(),
+            ErrorAction::RefreshCredentials
+        );
+        assert_eq!(StatusCode::NOT_FOUND.action(), ErrorAction::Terminal);
+    }
+}
+
+#[cfg(test)]
+mod tests_extended_error_codes {
+    use super::*;
+
+    #[test]
+    fn catalog_entries_are_found_by_code() {
+        let code = ExtendedErrorCode::from(404_103);
+        assert_eq!(code, ExtendedErrorCode::DEVICE_NOT_ONLINE);
+        assert_eq!(code.name(), "DeviceNotOnline");
+        assert_eq!(code.status(), StatusCode::NOT_FOUND);
+        assert!(code.is_retriable());
+    }
+
+    #[test]
+    fn actions_follow_the_category() {
+        assert_eq!(
+            ExtendedErrorCode::from(401_002).action(),
+            ErrorAction::RefreshCredentials
+        );
+        assert_eq!(
+            ExtendedErrorCode::from(429_001).action(),
+            ErrorAction::Retry
+        );
+        assert_eq!(
+            ExtendedErrorCode::from(400_004).action(),
+            ErrorAction::Terminal
+        );
+        assert_eq!(
+            ExtendedErrorCode::from(409_202).action(),
+            ErrorAction::Terminal
+        );
+    }
+
+    #[test]
+    fn unknown_codes_fall_back_to_their_class() {
+        let code = ExtendedErrorCode::from(503_999);
+        assert_eq!(code.name(), "Unknown");
+        assert_eq!(code.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(code.action(), ErrorAction::Retry);
+        assert_eq!(
+            ExtendedErrorCode::from(401_999).action(),
+            ErrorAction::RefreshCredentials
+        );
+        assert_eq!(ExtendedErrorCode::from(7).status(), StatusCode::UNKNOWN);
+        // 65_965 does not fit a status and must not wrap around to 429
+        assert_eq!(
+            ExtendedErrorCode::from(65_965_000).status(),
+            StatusCode::UNKNOWN
+        );
+        assert_eq!(
+            ExtendedErrorCode::from(7).description(),
+            "Unknown error code."
+        );
+    }
+
+    #[test]
+    fn catalog_codes_are_unique() {
+        for (i, a) in CATALOG.iter().enumerate() {
+            assert!(CATALOG[i + 1..].iter().all(|b| a.code != b.code));
+        }
+    }
 }
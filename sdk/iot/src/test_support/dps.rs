@@ -0,0 +1,786 @@
+// A local Device Provisioning Service endpoint.
+//
+// Connections are authenticated the same way DPS does it: the user name must be
+// <id_scope>/registrations/<registration_id>/api-version=<service_version> and the
+// password a SAS token signed with the enrollment key. Register and operation
+// status requests are answered from a script, one response per request. With a
+// certificate issuer, a certificateSigningRequest in the register request is
+// signed and the chain returned with the assignment.
+use core::fmt::Write as _;
+use std::collections::VecDeque;
+use std::format;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::string::{String, ToString};
+use std::sync::{Arc, Mutex};
+use std::vec::Vec;
+
+use heapless::consts::{U4096, U8};
+
+use super::{invalid_data, now_epoch_time, publish, serve_packets, Listener};
+use crate::mqtt::{
+    encode_connack, encode_pingresp, encode_puback, encode_suback, ClientPacket, Connect,
+    ConnectReturnCode, QoS,
+};
+use crate::provisioning::api_version::ApiVersion;
+use crate::provisioning::client::Client;
+use crate::provisioning::raw_json::RawJson;
+use crate::provisioning::sas::{get_password, SasToken};
+use crate::provisioning::serialization::{
+    DeviceRegistrationResult, ProvisioningServiceErrorDetails, RegistrationOperationStatus,
+};
+#[cfg(feature = "csr")]
+use crate::test_support::issuer::CertificateIssuer;
+
+const REGISTER_TOPIC_PREFIX: &str = "$dps/registrations/PUT/iotdps-register/?$rid=";
+const OPERATION_STATUS_TOPIC_PREFIX: &str =
+    "$dps/registrations/GET/iotdps-get-operationstatus/?$rid=";
+const TIMESTAMP: &str = "2020-08-04T21:39:08.4834929Z";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Enrollment {
+    pub registration_id: String,
+    // base64 encoded symmetric key
+    pub symmetric_key: String,
+}
+
+impl Enrollment {
+    pub fn new(registration_id: &str, symmetric_key: &str) -> Enrollment {
+        Enrollment {
+            registration_id: registration_id.to_string(),
+            symmetric_key: symmetric_key.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ScriptedResponse {
+    // 202 with status "assigning"
+    Assigning {
+        retry_after: Option<u32>,
+    },
+    // 200 with status "assigned" and the registration state
+    Assigned {
+        assigned_hub: String,
+        device_id: String,
+        substatus: String,
+    },
+    // 200 with status "failed" and the error in the registration state
+    Failed {
+        error_code: String,
+        error_message: String,
+    },
+    // 429 with retry-after
+    Throttled {
+        retry_after: u32,
+    },
+    // 401
+    Unauthorized,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DpsRequest {
+    Connect {
+        client_id: String,
+        user_name: String,
+        authorized: bool,
+    },
+    Register {
+        registration_id: String,
+        payload: String,
+    },
+    OperationStatus {
+        operation_id: String,
+    },
+}
+
+pub struct DpsSimulatorOptions {
+    pub id_scope: String,
+    pub enrollments: Vec<Enrollment>,
+    pub responses: Vec<ScriptedResponse>,
+    #[cfg(feature = "csr")]
+    pub certificate_issuer: Option<CertificateIssuer>,
+}
+
+pub struct DpsSimulator {
+    listener: Listener,
+    requests: Arc<Mutex<Vec<DpsRequest>>>,
+}
+
+struct State {
+    id_scope: String,
+    enrollments: Vec<Enrollment>,
+    responses: VecDeque<ScriptedResponse>,
+    requests: Arc<Mutex<Vec<DpsRequest>>>,
+    #[cfg(feature = "csr")]
+    certificate_issuer: Option<CertificateIssuer>,
+    // From the last register request, signed when the device is assigned
+    certificate_signing_request: Option<String>,
+}
+
+impl DpsSimulator {
+    pub fn start(options: DpsSimulatorOptions) -> io::Result<DpsSimulator> {
+        let requests = Arc::new(Mutex::new(Vec::new()));
+        let mut state = State {
+            id_scope: options.id_scope,
+            enrollments: options.enrollments,
+            responses: options.responses.into_iter().collect(),
+            requests: requests.clone(),
+            #[cfg(feature = "csr")]
+            certificate_issuer: options.certificate_issuer,
+            certificate_signing_request: None,
+        };
+        let listener = Listener::start(move |mut stream| state.serve(&mut stream))?;
+        Ok(DpsSimulator { listener, requests })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    // The value to use as the client's global device endpoint
+    pub fn endpoint(&self) -> String {
+        format!("tcp://{}", self.local_addr())
+    }
+
+    /// The requests received so far, oldest first.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a connection thread panicked while recording a request.
+    pub fn requests(&self) -> Vec<DpsRequest> {
+        self.requests.lock().unwrap().clone()
+    }
+}
+
+impl State {
+    fn serve(&mut self, stream: &mut TcpStream) -> io::Result<()> {
+        let mut registration_id = None;
+        serve_packets(stream, |stream, packet| match packet {
+            ClientPacket::Connect(connect) => {
+                registration_id = self.authenticate(&connect);
+                let return_code = if registration_id.is_some() {
+                    ConnectReturnCode::Accepted
+                } else {
+                    ConnectReturnCode::NotAuthorized
+                };
+                stream.write_all(&encode_connack(false, return_code))?;
+                Ok(registration_id.is_some())
+            }
+            ClientPacket::Subscribe { packet_id, topics } => {
+                let granted: heapless::Vec<u8, U8> =
+                    topics.iter().map(|(_, qos)| *qos as u8).collect();
+                let suback = encode_suback::<U8>(packet_id, &granted).map_err(invalid_data)?;
+                stream.write_all(&suback)?;
+                Ok(true)
+            }
+            ClientPacket::Publish(message) => {
+                if message.qos == QoS::AtLeastOnce {
+                    stream.write_all(&encode_puback(message.packet_id))?;
+                }
+                let registration_id = registration_id.as_deref().unwrap_or_default();
+                self.handle_request(stream, registration_id, message.topic, message.payload)?;
+                Ok(true)
+            }
+            ClientPacket::Pingreq => {
+                stream.write_all(&encode_pingresp())?;
+                Ok(true)
+            }
+            ClientPacket::Puback(_) => Ok(true),
+            ClientPacket::Disconnect => Ok(false),
+        })
+    }
+
+    // Returns the registration id when the user name and SAS token are valid.
+    fn authenticate(&self, connect: &Connect<'_>) -> Option<String> {
+        let user_name = connect.user_name.unwrap_or_default();
+        let password = connect
+            .password
+            .and_then(|password| core::str::from_utf8(password).ok())
+            .unwrap_or_default();
+        let registration_id = self.registration_id_from_user_name(user_name);
+        let authorized = registration_id.map_or(false, |registration_id| {
+            registration_id == connect.client_id && self.is_authorized(registration_id, password)
+        });
+        self.record(DpsRequest::Connect {
+            client_id: connect.client_id.to_string(),
+            user_name: user_name.to_string(),
+            authorized,
+        });
+        if authorized {
+            registration_id.map(ToString::to_string)
+        } else {
+            None
+        }
+    }
+
+    // <id_scope>/registrations/<registration_id>/api-version=<api_version>[&ClientVersion=<user_agent>]
+    fn registration_id_from_user_name<'a>(&self, user_name: &'a str) -> Option<&'a str> {
+        let prefix = format!("{}{}", self.id_scope, Client::get_registrations());
+        if !user_name.starts_with(prefix.as_str()) {
+            return None;
+        }
+        let mut parts = user_name[prefix.len()..].splitn(2, '/');
+        let registration_id = parts.next()?;
+        let api_version = parts.next()?.split('&').next()?;
+        if api_version
+            .strip_prefix("api-version=")?
+            .parse::<ApiVersion>()
+            .is_err()
+        {
+            return None;
+        }
+        Some(registration_id)
+    }
+
+    fn is_authorized(&self, registration_id: &str, password: &str) -> bool {
+        let enrollment = match self
+            .enrollments
+            .iter()
+            .find(|enrollment| enrollment.registration_id == registration_id)
+        {
+            Some(enrollment) => enrollment,
+            None => return false,
+        };
+        let token = match SasToken::parse(password) {
+            Ok(token) => token,
+            Err(_) => return false,
+        };
+        if token.expiration_epoch_time <= now_epoch_time() {
+            return false;
+        }
+        let client = match Client::new("", &self.id_scope, registration_id, None) {
+            Ok(client) => client,
+            Err(_) => return false,
+        };
+        match get_password(
+            &client,
+            &enrollment.symmetric_key,
+            token.expiration_epoch_time,
+            None,
+        ) {
+            Ok(expected) => expected.as_str() == password,
+            Err(_) => false,
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        stream: &mut TcpStream,
+        registration_id: &str,
+        topic: &str,
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let request_id;
+        if let Some(rid) = topic.strip_prefix(REGISTER_TOPIC_PREFIX) {
+            request_id = rid;
+            let payload = String::from_utf8_lossy(payload).to_string();
+            self.certificate_signing_request = RawJson::new(&payload)
+                .ok()
+                .and_then(|json| json.get("certificateSigningRequest"))
+                .and_then(|value| value.as_string_value())
+                .map(ToString::to_string);
+            self.record(DpsRequest::Register {
+                registration_id: registration_id.to_string(),
+                payload,
+            });
+        } else if let Some(query) = topic.strip_prefix(OPERATION_STATUS_TOPIC_PREFIX) {
+            // <rid>&operationId=<operation_id>
+            let mut parts = query.splitn(2, "&operationId=");
+            request_id = parts.next().unwrap_or_default();
+            self.record(DpsRequest::OperationStatus {
+                operation_id: parts.next().unwrap_or_default().to_string(),
+            });
+        } else {
+            return Ok(());
+        }
+        let response = self.responses.pop_front();
+        let issued_certificate_chain = match response {
+            Some(ScriptedResponse::Assigned { .. }) => self.issue(registration_id),
+            _ => Ok(Vec::new()),
+        };
+        match issued_certificate_chain {
+            Ok(chain) => respond(stream, request_id, registration_id, response, &chain),
+            Err(error) => {
+                let body = error_details(400_004, error)?;
+                let topic = format!(
+                    "{}400/?$rid={}",
+                    Client::get_dps_registrations_res(),
+                    request_id
+                );
+                publish(stream, &topic, body.as_bytes())
+            }
+        }
+    }
+
+    // Signs the pending certificate signing request, if there is one.
+    #[cfg(feature = "csr")]
+    fn issue(&self, registration_id: &str) -> Result<Vec<String>, &'static str> {
+        match (&self.certificate_issuer, &self.certificate_signing_request) {
+            (Some(issuer), Some(request)) => issuer.issue(registration_id, request),
+            _ => Ok(Vec::new()),
+        }
+    }
+
+    #[cfg(not(feature = "csr"))]
+    fn issue(&self, _registration_id: &str) -> Result<Vec<String>, &'static str> {
+        Ok(Vec::new())
+    }
+
+    fn record(&self, request: DpsRequest) {
+        self.requests.lock().unwrap().push(request);
+    }
+}
+
+fn respond(
+    stream: &mut TcpStream,
+    request_id: &str,
+    registration_id: &str,
+    response: Option<ScriptedResponse>,
+    issued_certificate_chain: &[String],
+) -> io::Result<()> {
+    let operation_id = format!("4.0000000000000000.{}", registration_id);
+    let (status_code, retry_after, body) = match response {
+        Some(ScriptedResponse::Assigning { retry_after }) => {
+            let status: RegistrationOperationStatus<'_> =
+                RegistrationOperationStatus::new("assigning", &operation_id, None);
+            (202, retry_after, to_json(&status)?)
+        }
+        Some(ScriptedResponse::Assigned {
+            assigned_hub,
+            device_id,
+            substatus,
+        }) => {
+            let mut state = registration_state(
+                registration_id,
+                "assigned",
+                &assigned_hub,
+                &device_id,
+                &substatus,
+                "",
+                "",
+            );
+            for certificate in issued_certificate_chain {
+                state
+                    .issued_certificate_chain
+                    .push(certificate)
+                    .map_err(invalid_data)?;
+            }
+            let status = RegistrationOperationStatus::new("assigned", &operation_id, Some(state));
+            (200, None, to_json(&status)?)
+        }
+        Some(ScriptedResponse::Failed {
+            error_code,
+            error_message,
+        }) => {
+            let state = registration_state(
+                registration_id,
+                "failed",
+                "",
+                "",
+                "",
+                &error_code,
+                &error_message,
+            );
+            let status = RegistrationOperationStatus::new("failed", &operation_id, Some(state));
+            (200, None, to_json(&status)?)
+        }
+        Some(ScriptedResponse::Throttled { retry_after }) => (
+            429,
+            Some(retry_after),
+            error_details(429_001, "Operations are being throttled for this tenant.")?,
+        ),
+        Some(ScriptedResponse::Unauthorized) => {
+            (401, None, error_details(401_002, "Unauthorized")?)
+        }
+        None => (
+            500,
+            None,
+            error_details(500_000, "The simulator has no scripted responses left.")?,
+        ),
+    };
+
+    let mut topic = format!(
+        "{}{}/?$rid={}",
+        Client::get_dps_registrations_res(),
+        status_code,
+        request_id
+    );
+    if let Some(retry_after) = retry_after {
+        write!(topic, "&retry-after={}", retry_after).map_err(|_| invalid_data("topic"))?;
+    }
+    publish(stream, &topic, body.as_bytes())
+}
+
+fn registration_state<'a>(
+    registration_id: &'a str,
+    status: &'a str,
+    assigned_hub: &'a str,
+    device_id: &'a str,
+    substatus: &'a str,
+    error_code: &'a str,
+    error_message: &'a str,
+) -> DeviceRegistrationResult<'a> {
+    DeviceRegistrationResult::new(
+        assigned_hub,
+        TIMESTAMP,
+        device_id,
+        error_code,
+        error_message,
+        "ImY3MDEyN2YxLTAwMDAtMDgwMC0wMDAwLTVmMjlkNTdjMDAwMCI=",
+        TIMESTAMP,
+        "",
+        registration_id,
+        status,
+        substatus,
+        None,
+        None,
+        None,
+    )
+}
+
+fn error_details(error_code: u32, message: &str) -> io::Result<String> {
+    to_json(&ProvisioningServiceErrorDetails {
+        error_code,
+        info: None,
+        message,
+        timestamp_utc: TIMESTAMP,
+        tracking_id: "00000000-0000-0000-0000-000000000000",
+    })
+}
+
+fn to_json<T: serde::Serialize>(value: &T) -> io::Result<String> {
+    serde_json_core::to_string::<U4096, _>(value)
+        .map(|json| json.as_str().to_string())
+        .map_err(|_| invalid_data("could not serialize the response"))
+}
+
+#[cfg(test)]
+mod tests_dps_simulator {
+    use super::*;
+    use crate::mqtt::{
+        decode, encode_connect, encode_disconnect, encode_publish, encode_subscribe, Packet,
+        Publish,
+    };
+    use heapless::consts::{U1024, U128, U256, U4096};
+    use std::io::Read;
+    use std::vec;
+
+    const ID_SCOPE: &str = "0ne00000001";
+    const REGISTRATION_ID: &str = "device-1";
+    const SAS_KEY: &str = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+
+    struct TestClient {
+        stream: TcpStream,
+        buffer: Vec<u8>,
+    }
+
+    impl TestClient {
+        fn connect(simulator: &DpsSimulator, sas_key: &str) -> (TestClient, Packet<U256, U4096>) {
+            let endpoint = simulator.endpoint();
+            let client = Client::new(&endpoint, ID_SCOPE, REGISTRATION_ID, None).unwrap();
+            let user_name = client.get_user_name().unwrap();
+            let password = get_password(&client, sas_key, now_epoch_time() + 3600, None).unwrap();
+            let connect = Connect::new(REGISTRATION_ID, &user_name, &password);
+            let mut test_client = TestClient {
+                stream: TcpStream::connect(simulator.local_addr()).unwrap(),
+                buffer: Vec::new(),
+            };
+            test_client.send(&encode_connect::<U256>(&connect).unwrap());
+            let connack = test_client.receive();
+            (test_client, connack)
+        }
+
+        fn send(&mut self, packet: &[u8]) {
+            self.stream.write_all(packet).unwrap();
+        }
+
+        fn publish(&mut self, topic: &str) {
+            self.publish_payload(topic, b"{}");
+        }
+
+        fn publish_payload(&mut self, topic: &str, payload: &[u8]) {
+            let publish = Publish::new(topic, payload, QoS::AtLeastOnce, 1);
+            self.send(&encode_publish::<U1024>(&publish).unwrap());
+            assert_eq!(self.receive(), Packet::Puback(1));
+        }
+
+        fn receive(&mut self) -> Packet<U256, U4096> {
+            loop {
+                if let Some((packet, consumed)) = decode(&self.buffer).unwrap() {
+                    self.buffer.drain(..consumed);
+                    return packet;
+                }
+                let mut chunk = [0_u8; 512];
+                let read = self.stream.read(&mut chunk).unwrap();
+                assert!(read > 0, "connection closed");
+                self.buffer.extend_from_slice(&chunk[..read]);
+            }
+        }
+
+        fn receive_response(&mut self) -> (String, String) {
+            match self.receive() {
+                Packet::Publish(message) => (
+                    message.topic.as_str().to_string(),
+                    String::from_utf8(message.payload.to_vec()).unwrap(),
+                ),
+                packet => panic!("unexpected packet {:?}", packet),
+            }
+        }
+    }
+
+    fn start(responses: Vec<ScriptedResponse>) -> DpsSimulator {
+        start_with_issuer(responses, None)
+    }
+
+    #[cfg(feature = "csr")]
+    fn start_with_issuer(
+        responses: Vec<ScriptedResponse>,
+        certificate_issuer: Option<CertificateIssuer>,
+    ) -> DpsSimulator {
+        DpsSimulator::start(DpsSimulatorOptions {
+            id_scope: ID_SCOPE.to_string(),
+            enrollments: vec![Enrollment::new(REGISTRATION_ID, SAS_KEY)],
+            responses,
+            certificate_issuer,
+        })
+        .unwrap()
+    }
+
+    #[cfg(not(feature = "csr"))]
+    fn start_with_issuer(responses: Vec<ScriptedResponse>, _: Option<()>) -> DpsSimulator {
+        DpsSimulator::start(DpsSimulatorOptions {
+            id_scope: ID_SCOPE.to_string(),
+            enrollments: vec![Enrollment::new(REGISTRATION_ID, SAS_KEY)],
+            responses,
+        })
+        .unwrap()
+    }
+
+    fn accepted() -> Packet<U256, U4096> {
+        Packet::Connack(crate::mqtt::Connack {
+            session_present: false,
+            return_code: ConnectReturnCode::Accepted,
+        })
+    }
+
+    #[test]
+    fn valid_sas_token_is_accepted() {
+        let simulator = start(vec![]);
+        let (_, connack) = TestClient::connect(&simulator, SAS_KEY);
+        assert_eq!(connack, accepted());
+        match &simulator.requests()[0] {
+            DpsRequest::Connect {
+                client_id,
+                authorized,
+                ..
+            } => {
+                assert_eq!(client_id, REGISTRATION_ID);
+                assert!(authorized);
+            }
+            request => panic!("unexpected request {:?}", request),
+        }
+    }
+
+    #[test]
+    fn sas_token_signed_with_another_key_is_rejected() {
+        let simulator = start(vec![]);
+        let (_, connack) =
+            TestClient::connect(&simulator, "QW5vdGhlciBrZXkgdGhhdCBpcyBub3QgZW5yb2xsZWQ=");
+        assert_eq!(
+            connack,
+            Packet::Connack(crate::mqtt::Connack {
+                session_present: false,
+                return_code: ConnectReturnCode::NotAuthorized,
+            })
+        );
+    }
+
+    #[test]
+    fn user_name_must_match_the_id_scope() {
+        let state = State {
+            id_scope: ID_SCOPE.to_string(),
+            enrollments: vec![],
+            responses: VecDeque::new(),
+            requests: Arc::new(Mutex::new(Vec::new())),
+            #[cfg(feature = "csr")]
+            certificate_issuer: None,
+            certificate_signing_request: None,
+        };
+        assert_eq!(
+            state.registration_id_from_user_name(
+                "0ne00000001/registrations/device-1/api-version=2019-03-31&ClientVersion=a"
+            ),
+            Some(REGISTRATION_ID)
+        );
+        assert_eq!(
+            state.registration_id_from_user_name(
+                "0ne00000001/registrations/device-1/api-version=2021-06-01"
+            ),
+            Some(REGISTRATION_ID)
+        );
+        assert_eq!(
+            state.registration_id_from_user_name(
+                "0ne00000002/registrations/device-1/api-version=2019-03-31"
+            ),
+            None
+        );
+        assert_eq!(
+            state.registration_id_from_user_name(
+                "0ne00000001/registrations/device-1/api-version=2018-11-01"
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn scripted_responses_are_returned_in_order() {
+        let simulator = start(vec![
+            ScriptedResponse::Throttled { retry_after: 5 },
+            ScriptedResponse::Assigning {
+                retry_after: Some(3),
+            },
+            ScriptedResponse::Unauthorized,
+            ScriptedResponse::Assigned {
+                assigned_hub: "example.azure-devices.net".to_string(),
+                device_id: REGISTRATION_ID.to_string(),
+                substatus: "initialAssignment".to_string(),
+            },
+        ]);
+        let (mut client, _) = TestClient::connect(&simulator, SAS_KEY);
+        let subscribe = encode_subscribe::<U128>(
+            1,
+            &[(Client::get_provisioning_service_topics(), QoS::AtLeastOnce)],
+        )
+        .unwrap();
+        client.send(&subscribe);
+        match client.receive() {
+            Packet::Suback(suback) => assert_eq!(&suback.return_codes[..], &[1]),
+            packet => panic!("unexpected packet {:?}", packet),
+        }
+
+        let register_topic = Client::get_register_publish_topic().unwrap();
+        client.publish(&register_topic);
+        let (topic, body) = client.receive_response();
+        assert_eq!(topic, "$dps/registrations/res/429/?$rid=1&retry-after=5");
+        let error =
+            serde_json_core::from_str::<ProvisioningServiceErrorDetails<'_>>(&body).unwrap();
+        assert_eq!(error.error_code, 429_001);
+
+        client.publish(&register_topic);
+        let (topic, body) = client.receive_response();
+        assert_eq!(topic, "$dps/registrations/res/202/?$rid=1&retry-after=3");
+        let status = serde_json_core::from_str::<RegistrationOperationStatus<'_>>(&body).unwrap();
+        assert_eq!(status.status, "assigning");
+
+        let status_topic = Client::query_status_get_publish_topic(status.operation_id).unwrap();
+        client.publish(&status_topic);
+        let (topic, _) = client.receive_response();
+        assert_eq!(topic, "$dps/registrations/res/401/?$rid=1");
+
+        client.publish(&status_topic);
+        let (topic, body) = client.receive_response();
+        assert_eq!(topic, "$dps/registrations/res/200/?$rid=1");
+        let status = serde_json_core::from_str::<RegistrationOperationStatus<'_>>(&body).unwrap();
+        assert_eq!(status.status, "assigned");
+        let state = status.registration_state.unwrap();
+        assert_eq!(state.assigned_hub, "example.azure-devices.net");
+        assert_eq!(state.substatus, "initialAssignment");
+
+        client.send(&encode_disconnect());
+        let requests = simulator.requests();
+        assert_eq!(
+            requests[1],
+            DpsRequest::Register {
+                registration_id: REGISTRATION_ID.to_string(),
+                payload: "{}".to_string()
+            }
+        );
+        assert_eq!(
+            requests[3],
+            DpsRequest::OperationStatus {
+                operation_id: "4.0000000000000000.device-1".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn failed_registration_reports_the_error() {
+        let simulator = start(vec![ScriptedResponse::Failed {
+            error_code: "400209".to_string(),
+            error_message: "Custom allocation failed".to_string(),
+        }]);
+        let (mut client, _) = TestClient::connect(&simulator, SAS_KEY);
+        client.publish(&Client::get_register_publish_topic().unwrap());
+        let (_, body) = client.receive_response();
+        let status = serde_json_core::from_str::<RegistrationOperationStatus<'_>>(&body).unwrap();
+        assert_eq!(status.status, "failed");
+        let state = status.registration_state.unwrap();
+        assert_eq!(state.error_code, "400209");
+        assert_eq!(state.error_message, "Custom allocation failed");
+    }
+
+    #[cfg(feature = "csr")]
+    #[test]
+    fn certificate_signing_requests_are_signed() {
+        use crate::identifiers::RegistrationId;
+        use crate::provisioning::api_version::ApiVersion;
+        use crate::provisioning::certificate::{DeviceKey, IssuedCertificate};
+        use crate::provisioning::client::ClientOptions;
+
+        let assigned = || ScriptedResponse::Assigned {
+            assigned_hub: "example.azure-devices.net".to_string(),
+            device_id: REGISTRATION_ID.to_string(),
+            substatus: "initialAssignment".to_string(),
+        };
+        let issuer = CertificateIssuer::new("Test Issuing CA").unwrap();
+        let simulator = start_with_issuer(vec![assigned(), assigned()], Some(issuer));
+        let (mut client, _) = TestClient::connect(&simulator, SAS_KEY);
+        let provisioning_client = Client::new(
+            "",
+            ID_SCOPE,
+            REGISTRATION_ID,
+            Some(ClientOptions {
+                api_version: ApiVersion::V2021_06_01,
+                ..ClientOptions::default()
+            }),
+        )
+        .unwrap();
+        let registration_id = RegistrationId::new(REGISTRATION_ID).unwrap();
+        let key = DeviceKey::generate().unwrap();
+        let request = key.certificate_signing_request(&registration_id).unwrap();
+        let mut registration = provisioning_client.registration("");
+        registration.certificate_signing_request = Some(&request);
+        let payload: heapless::String<U1024> = provisioning_client
+            .get_register_payload(&registration)
+            .unwrap();
+        let register_topic = Client::get_register_publish_topic().unwrap();
+        client.publish_payload(&register_topic, payload.as_bytes());
+        let (topic, body) = client.receive_response();
+        assert_eq!(topic, "$dps/registrations/res/200/?$rid=1");
+        let status = serde_json_core::from_str::<RegistrationOperationStatus<'_>>(&body).unwrap();
+        let state = status.registration_state.unwrap();
+        assert_eq!(state.issued_certificate_chain.len(), 2);
+        let issued = IssuedCertificate::from_chain(
+            &state.issued_certificate_chain,
+            &key,
+            &registration_id,
+            now_epoch_time(),
+        )
+        .unwrap();
+        assert_eq!(issued.certificate_chain_der().len(), 2);
+
+        // DPS only signs requests for the registration ID
+        let other = key
+            .certificate_signing_request(&RegistrationId::new("device-2").unwrap())
+            .unwrap();
+        let mut registration = provisioning_client.registration("");
+        registration.certificate_signing_request = Some(&other);
+        let payload: heapless::String<U1024> = provisioning_client
+            .get_register_payload(&registration)
+            .unwrap();
+        client.publish_payload(&register_topic, payload.as_bytes());
+        let (topic, _) = client.receive_response();
+        assert_eq!(topic, "$dps/registrations/res/400/?$rid=1");
+    }
+}
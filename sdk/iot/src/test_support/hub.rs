@@ -0,0 +1,865 @@
+// A local IoT Hub endpoint.
+//
+// Devices authenticate with a SAS token signed with their symmetric key, the
+// same way the hub does it. Telemetry is recorded with its decoded property bag
+// and tests can push cloud-to-device messages, desired property patches and
+// direct method calls to a connected device. Twin GET and reported PATCH
+// requests are served from an in-memory twin that is versioned like the hub's.
+use std::collections::HashMap;
+use std::format;
+use std::io::{self, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::string::{String, ToString};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use std::vec::Vec;
+
+use heapless::consts::{U256, U8};
+use serde_json::{json, Map, Value};
+
+use super::{invalid_data, now_epoch_time, publish, serve_packets, Listener};
+use crate::hub::client::{Client, ClientOptions};
+use crate::hub::properties::Properties;
+use crate::hub::sas::get_password;
+use crate::hub::{SERVICE_VERSION, TWIN_GET_TOPIC, TWIN_PATCH_REPORTED_TOPIC};
+use crate::identifiers::ModuleId;
+use crate::mqtt::{
+    encode_connack, encode_pingresp, encode_puback, encode_suback, ClientPacket, Connect,
+    ConnectReturnCode, QoS,
+};
+use crate::provisioning::percent_encode;
+use crate::provisioning::sas::SasToken;
+
+const DEVICES: &str = "devices/";
+const MESSAGES_EVENTS: &str = "/messages/events/";
+const METHODS_RESPONSE_PREFIX: &str = "$iothub/methods/res/";
+const VERSION: &str = "$version";
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct HubDevice {
+    pub device_id: String,
+    // base64 encoded symmetric key
+    pub symmetric_key: String,
+}
+
+impl HubDevice {
+    pub fn new(device_id: &str, symmetric_key: &str) -> HubDevice {
+        HubDevice {
+            device_id: device_id.to_string(),
+            symmetric_key: symmetric_key.to_string(),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Telemetry {
+    pub device_id: String,
+    pub module_id: Option<String>,
+    // decoded names and values, in the order they appeared in the topic
+    pub properties: Vec<(String, String)>,
+    pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct MethodResponse {
+    pub device_id: String,
+    pub request_id: String,
+    pub status: u16,
+    pub payload: Vec<u8>,
+}
+
+pub struct HubSimulatorOptions {
+    pub hostname: String,
+    pub devices: Vec<HubDevice>,
+}
+
+pub struct HubSimulator {
+    listener: Listener,
+    hostname: String,
+    shared: Arc<Shared>,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+#[derive(Default)]
+struct State {
+    // writes to a connected device go through its stream clone so that packets
+    // sent by the test and by the connection handler never interleave
+    connections: HashMap<String, TcpStream>,
+    subscriptions: HashMap<String, Vec<String>>,
+    telemetry: Vec<Telemetry>,
+    method_responses: Vec<MethodResponse>,
+    twins: HashMap<String, Twin>,
+    next_request_id: u64,
+}
+
+struct Twin {
+    desired: Value,
+    desired_version: u64,
+    reported: Value,
+    reported_version: u64,
+}
+
+impl Default for Twin {
+    fn default() -> Twin {
+        Twin {
+            desired: Value::Object(Map::new()),
+            desired_version: 1,
+            reported: Value::Object(Map::new()),
+            reported_version: 1,
+        }
+    }
+}
+
+impl Twin {
+    fn document(&self) -> Value {
+        json!({
+            "desired": with_version(&self.desired, self.desired_version),
+            "reported": with_version(&self.reported, self.reported_version),
+        })
+    }
+}
+
+impl HubSimulator {
+    pub fn start(options: HubSimulatorOptions) -> io::Result<HubSimulator> {
+        let shared = Arc::new(Shared {
+            state: Mutex::new(State::default()),
+            changed: Condvar::new(),
+        });
+        let connection = Connection {
+            hostname: options.hostname.clone(),
+            devices: options.devices,
+            shared: shared.clone(),
+        };
+        let listener = Listener::start(move |mut stream| connection.serve(&mut stream))?;
+        Ok(HubSimulator {
+            listener,
+            hostname: options.hostname,
+            shared,
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    // The hostname devices must use in their user name and SAS token
+    pub fn hostname(&self) -> &str {
+        &self.hostname
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("tcp://{}", self.local_addr())
+    }
+
+    pub fn telemetry(&self) -> Vec<Telemetry> {
+        self.shared.lock().telemetry.clone()
+    }
+
+    // Waits until at least `count` messages were received, returning what was
+    // received by the time the timeout elapsed.
+    pub fn wait_for_telemetry(&self, count: usize, timeout: Duration) -> Vec<Telemetry> {
+        self.shared
+            .wait_until(timeout, |state| state.telemetry.len() >= count)
+            .telemetry
+            .clone()
+    }
+
+    // Waits until the device subscribed to exactly the given topic filter.
+    pub fn wait_for_subscription(&self, device_id: &str, filter: &str, timeout: Duration) -> bool {
+        self.shared
+            .wait_until(timeout, |state| state.is_subscribed(device_id, filter))
+            .is_subscribed(device_id, filter)
+    }
+
+    // Publishes on devices/<device_id>/messages/devicebound/<properties>
+    pub fn send_c2d(
+        &self,
+        device_id: &str,
+        properties: &[(&str, &str)],
+        payload: &[u8],
+    ) -> io::Result<()> {
+        let topic = format!(
+            "{}{}/messages/devicebound/{}",
+            DEVICES,
+            device_id,
+            encode_properties(properties)?
+        );
+        self.shared.lock().publish_to(device_id, &topic, payload)
+    }
+
+    // Merges the patch into the desired properties and sends it to the device on
+    // $iothub/twin/PATCH/properties/desired/?$version=<version>. Returns the new
+    // desired properties version.
+    pub fn patch_desired(&self, device_id: &str, patch: &Value) -> io::Result<u64> {
+        let mut state = self.shared.lock();
+        let twin = state.twins.entry(device_id.to_string()).or_default();
+        merge_patch(&mut twin.desired, patch);
+        twin.desired_version += 1;
+        let version = twin.desired_version;
+        let topic = format!(
+            "$iothub/twin/PATCH/properties/desired/?$version={}",
+            version
+        );
+        let body = with_version(patch, version).to_string();
+        state.publish_to(device_id, &topic, body.as_bytes())?;
+        Ok(version)
+    }
+
+    // Sends a direct method call on $iothub/methods/POST/<name>/?$rid=<request_id>
+    // and returns the request id to wait on.
+    pub fn invoke_method(&self, device_id: &str, name: &str, payload: &[u8]) -> io::Result<String> {
+        let mut state = self.shared.lock();
+        state.next_request_id += 1;
+        let request_id = format!("{:x}", state.next_request_id);
+        let topic = format!("$iothub/methods/POST/{}/?$rid={}", name, request_id);
+        state.publish_to(device_id, &topic, payload)?;
+        Ok(request_id)
+    }
+
+    pub fn wait_for_method_response(
+        &self,
+        request_id: &str,
+        timeout: Duration,
+    ) -> Option<MethodResponse> {
+        let find = |state: &State| {
+            state
+                .method_responses
+                .iter()
+                .find(|response| response.request_id == request_id)
+                .cloned()
+        };
+        let state = self
+            .shared
+            .wait_until(timeout, |state| find(state).is_some());
+        find(&state)
+    }
+
+    // The full twin document: {"desired": {...}, "reported": {...}}
+    pub fn twin(&self, device_id: &str) -> Value {
+        self.shared
+            .lock()
+            .twins
+            .get(device_id)
+            .map_or_else(|| Twin::default().document(), Twin::document)
+    }
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap()
+    }
+
+    fn wait_until<F>(&self, timeout: Duration, mut done: F) -> MutexGuard<'_, State>
+    where
+        F: FnMut(&State) -> bool,
+    {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        while !done(&state) {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            state = self.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+        state
+    }
+
+    fn update<F>(&self, change: F)
+    where
+        F: FnOnce(&mut State),
+    {
+        change(&mut self.lock());
+        self.changed.notify_all();
+    }
+}
+
+impl State {
+    fn connection(&mut self, device_id: &str) -> io::Result<&mut TcpStream> {
+        self.connections.get_mut(device_id).ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "the device is not connected")
+        })
+    }
+
+    fn write_to(&mut self, device_id: &str, packet: &[u8]) -> io::Result<()> {
+        self.connection(device_id)?.write_all(packet)
+    }
+
+    fn publish_to(&mut self, device_id: &str, topic: &str, payload: &[u8]) -> io::Result<()> {
+        publish(self.connection(device_id)?, topic, payload)
+    }
+
+    fn is_subscribed(&self, device_id: &str, filter: &str) -> bool {
+        self.subscriptions
+            .get(device_id)
+            .map_or(false, |filters| filters.iter().any(|f| f == filter))
+    }
+}
+
+struct Connection {
+    hostname: String,
+    devices: Vec<HubDevice>,
+    shared: Arc<Shared>,
+}
+
+impl Connection {
+    fn serve(&self, stream: &mut TcpStream) -> io::Result<()> {
+        let mut device_id: Option<String> = None;
+        let result = serve_packets(stream, |stream, packet| match packet {
+            ClientPacket::Connect(connect) => {
+                device_id = self.authenticate(&connect);
+                let return_code = if device_id.is_some() {
+                    ConnectReturnCode::Accepted
+                } else {
+                    ConnectReturnCode::NotAuthorized
+                };
+                stream.write_all(&encode_connack(false, return_code))?;
+                if let Some(device_id) = &device_id {
+                    let connection = stream.try_clone()?;
+                    self.shared.update(|state| {
+                        state.connections.insert(device_id.clone(), connection);
+                        state.subscriptions.remove(device_id);
+                    });
+                }
+                Ok(device_id.is_some())
+            }
+            ClientPacket::Subscribe { packet_id, topics } => {
+                let device_id = device_id.as_deref().unwrap_or_default();
+                let granted: heapless::Vec<u8, U8> =
+                    topics.iter().map(|(_, qos)| *qos as u8).collect();
+                let suback = encode_suback::<U8>(packet_id, &granted).map_err(invalid_data)?;
+                self.shared.lock().write_to(device_id, &suback)?;
+                self.shared.update(|state| {
+                    let filters = state
+                        .subscriptions
+                        .entry(device_id.to_string())
+                        .or_default();
+                    filters.extend(topics.iter().map(|(topic, _)| topic.to_string()));
+                });
+                Ok(true)
+            }
+            ClientPacket::Publish(message) => {
+                let device_id = device_id.as_deref().unwrap_or_default();
+                if message.qos == QoS::AtLeastOnce {
+                    self.shared
+                        .lock()
+                        .write_to(device_id, &encode_puback(message.packet_id))?;
+                }
+                self.handle_publish(device_id, message.topic, message.payload)?;
+                Ok(true)
+            }
+            ClientPacket::Pingreq => {
+                let device_id = device_id.as_deref().unwrap_or_default();
+                self.shared.lock().write_to(device_id, &encode_pingresp())?;
+                Ok(true)
+            }
+            ClientPacket::Puback(_) => Ok(true),
+            ClientPacket::Disconnect => Ok(false),
+        });
+        if let Some(device_id) = device_id {
+            self.shared.update(|state| {
+                state.connections.remove(&device_id);
+                state.subscriptions.remove(&device_id);
+            });
+        }
+        result
+    }
+
+    // Returns the device id when the user name and SAS token are valid.
+    fn authenticate(&self, connect: &Connect<'_>) -> Option<String> {
+        let user_name = connect.user_name.unwrap_or_default();
+        let password = connect
+            .password
+            .and_then(|password| core::str::from_utf8(password).ok())
+            .unwrap_or_default();
+        let mut client_id = connect.client_id.splitn(2, '/');
+        let device_id = client_id.next().unwrap_or_default();
+        let module_id = client_id.next().map(ModuleId::new).transpose().ok()?;
+        let client = Client::new(
+            &self.hostname,
+            device_id,
+            Some(ClientOptions {
+                module_id,
+                user_agent: "",
+            }),
+        )
+        .ok()?;
+        if !self.is_valid_user_name(&client, user_name) || !self.is_authorized(&client, password) {
+            return None;
+        }
+        Some(device_id.to_string())
+    }
+
+    // <hostname>/<client_id>/?api-version=<service_version>[&DeviceClientType=<user_agent>]
+    fn is_valid_user_name(&self, client: &Client<'_>, user_name: &str) -> bool {
+        let expected = match client.get_client_id() {
+            Ok(client_id) => format!("{}/{}/?", self.hostname, client_id),
+            Err(_) => return false,
+        };
+        match user_name.strip_prefix(expected.as_str()) {
+            Some(query) => Properties::new(query).get("api-version") == Some(SERVICE_VERSION),
+            None => false,
+        }
+    }
+
+    fn is_authorized(&self, client: &Client<'_>, password: &str) -> bool {
+        let device = match self
+            .devices
+            .iter()
+            .find(|device| device.device_id == client.device_id.as_str())
+        {
+            Some(device) => device,
+            None => return false,
+        };
+        let token = match SasToken::parse(password) {
+            Ok(token) => token,
+            Err(_) => return false,
+        };
+        if token.expiration_epoch_time <= now_epoch_time() {
+            return false;
+        }
+        match get_password(
+            client,
+            &device.symmetric_key,
+            token.expiration_epoch_time,
+            token.key_name,
+        ) {
+            Ok(expected) => expected.as_str() == password,
+            Err(_) => false,
+        }
+    }
+
+    fn handle_publish(&self, device_id: &str, topic: &str, payload: &[u8]) -> io::Result<()> {
+        if let Some(rid) = topic.strip_prefix(TWIN_GET_TOPIC) {
+            let mut state = self.shared.lock();
+            let document = state
+                .twins
+                .get(device_id)
+                .map_or_else(|| Twin::default().document(), Twin::document);
+            let topic = format!("$iothub/twin/res/200/?$rid={}", rid);
+            return state.publish_to(device_id, &topic, document.to_string().as_bytes());
+        }
+        if let Some(rid) = topic.strip_prefix(TWIN_PATCH_REPORTED_TOPIC) {
+            return self.patch_reported(device_id, rid, payload);
+        }
+        if let Some(rest) = topic.strip_prefix(METHODS_RESPONSE_PREFIX) {
+            // <status>/?$rid=<request_id>
+            let mut parts = rest.splitn(2, "/?");
+            let status = parts.next().and_then(|status| status.parse().ok());
+            let request_id = parts
+                .next()
+                .and_then(|query| Properties::new(query).get("$rid"));
+            let (status, request_id) = match (status, request_id) {
+                (Some(status), Some(request_id)) => (status, request_id),
+                _ => return Err(invalid_data("malformed method response topic")),
+            };
+            let response = MethodResponse {
+                device_id: device_id.to_string(),
+                request_id: request_id.to_string(),
+                status,
+                payload: payload.to_vec(),
+            };
+            self.shared
+                .update(|state| state.method_responses.push(response));
+            return Ok(());
+        }
+        let telemetry = parse_telemetry(device_id, topic, payload)?;
+        self.shared.update(|state| state.telemetry.push(telemetry));
+        Ok(())
+    }
+
+    fn patch_reported(&self, device_id: &str, rid: &str, payload: &[u8]) -> io::Result<()> {
+        let mut state = self.shared.lock();
+        let topic = match serde_json::from_slice::<Value>(payload) {
+            Ok(patch) if patch.is_object() => {
+                let twin = state.twins.entry(device_id.to_string()).or_default();
+                merge_patch(&mut twin.reported, &patch);
+                twin.reported_version += 1;
+                format!(
+                    "$iothub/twin/res/204/?$rid={}&$version={}",
+                    rid, twin.reported_version
+                )
+            }
+            _ => format!("$iothub/twin/res/400/?$rid={}", rid),
+        };
+        state.publish_to(device_id, &topic, b"")
+    }
+}
+
+// devices/<device_id>[/modules/<module_id>]/messages/events/<properties>
+fn parse_telemetry(device_id: &str, topic: &str, payload: &[u8]) -> io::Result<Telemetry> {
+    let rest = topic
+        .strip_prefix(DEVICES)
+        .and_then(|rest| rest.strip_prefix(device_id))
+        .ok_or_else(|| invalid_data("publish on a topic of another device"))?;
+    let (module_id, properties) = if let Some(properties) = rest.strip_prefix(MESSAGES_EVENTS) {
+        (None, properties)
+    } else {
+        let rest = rest
+            .strip_prefix("/modules/")
+            .ok_or_else(|| invalid_data("unsupported topic"))?;
+        let mut parts = rest.splitn(2, MESSAGES_EVENTS);
+        let module_id = parts.next().unwrap_or_default();
+        let properties = parts
+            .next()
+            .ok_or_else(|| invalid_data("unsupported topic"))?;
+        (Some(module_id.to_string()), properties)
+    };
+    let properties = Properties::new(properties)
+        .iter()
+        .map(|(name, value)| Ok((decode(name)?, decode(value)?)))
+        .collect::<io::Result<Vec<_>>>()?;
+    Ok(Telemetry {
+        device_id: device_id.to_string(),
+        module_id,
+        properties,
+        payload: payload.to_vec(),
+    })
+}
+
+fn decode(value: &str) -> io::Result<String> {
+    percent_encode::decode::<U256>(value)
+        .map(|decoded| decoded.as_str().to_string())
+        .map_err(invalid_data)
+}
+
+fn encode_properties(properties: &[(&str, &str)]) -> io::Result<String> {
+    let mut encoded = String::new();
+    for (name, value) in properties {
+        if !encoded.is_empty() {
+            encoded.push('&');
+        }
+        let mut buffer: heapless::String<U256> = heapless::String::new();
+        percent_encode::encode_into(&mut buffer, name).map_err(invalid_data)?;
+        if buffer.push('=').is_err() {
+            return Err(invalid_data("property is too long"));
+        }
+        percent_encode::encode_into(&mut buffer, value).map_err(invalid_data)?;
+        encoded.push_str(&buffer);
+    }
+    Ok(encoded)
+}
+
+fn with_version(properties: &Value, version: u64) -> Value {
+    let mut properties = properties.clone();
+    if let Value::Object(map) = &mut properties {
+        map.insert(VERSION.to_string(), Value::from(version));
+    }
+    properties
+}
+
+// JSON merge patch (RFC 7386): null removes a property, objects are merged
+// recursively and anything else replaces the existing value.
+fn merge_patch(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        _ => {
+            *target = patch.clone();
+            return;
+        }
+    };
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    if let Value::Object(target) = target {
+        for (name, value) in patch {
+            if name == VERSION {
+                continue;
+            }
+            if value.is_null() {
+                target.remove(name);
+            } else {
+                merge_patch(target.entry(name.clone()).or_insert(Value::Null), value);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests_hub_simulator {
+    use super::*;
+    use crate::hub::client::TwinResponseType;
+    use crate::hub::properties::PropertiesBuilder;
+    use crate::mqtt::{
+        decode, encode_connect, encode_disconnect, encode_publish, encode_subscribe, Connack,
+        Packet, Publish,
+    };
+    use heapless::consts::{U1024, U128};
+    use std::io::Read;
+    use std::vec;
+
+    const HOSTNAME: &str = "simulated.azure-devices.net";
+    const DEVICE_ID: &str = "device-1";
+    const SAS_KEY: &str = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    struct TestClient {
+        stream: TcpStream,
+        buffer: Vec<u8>,
+    }
+
+    impl TestClient {
+        fn connect(
+            simulator: &HubSimulator,
+            module_id: Option<&str>,
+            sas_key: &str,
+        ) -> (TestClient, Packet<U256, U1024>) {
+            let client = Client::new(
+                simulator.hostname(),
+                DEVICE_ID,
+                Some(ClientOptions {
+                    module_id: module_id.map(|module_id| ModuleId::new(module_id).unwrap()),
+                    user_agent: "",
+                }),
+            )
+            .unwrap();
+            let client_id = client.get_client_id().unwrap();
+            let user_name = client.get_user_name().unwrap();
+            let password = get_password(&client, sas_key, now_epoch_time() + 3600, None).unwrap();
+            let connect = Connect::new(&client_id, &user_name, &password);
+            let mut test_client = TestClient {
+                stream: TcpStream::connect(simulator.local_addr()).unwrap(),
+                buffer: Vec::new(),
+            };
+            test_client.send(&encode_connect::<U1024>(&connect).unwrap());
+            let connack = test_client.receive();
+            (test_client, connack)
+        }
+
+        fn send(&mut self, packet: &[u8]) {
+            self.stream.write_all(packet).unwrap();
+        }
+
+        fn subscribe(&mut self, filter: &str) {
+            self.send(&encode_subscribe::<U128>(1, &[(filter, QoS::AtMostOnce)]).unwrap());
+            match self.receive() {
+                Packet::Suback(suback) => assert!(suback.is_success()),
+                packet => panic!("unexpected packet {:?}", packet),
+            }
+        }
+
+        fn publish(&mut self, topic: &str, payload: &[u8]) {
+            let publish = Publish::new(topic, payload, QoS::AtLeastOnce, 1);
+            self.send(&encode_publish::<U1024>(&publish).unwrap());
+            assert_eq!(self.receive(), Packet::Puback(1));
+        }
+
+        fn receive(&mut self) -> Packet<U256, U1024> {
+            loop {
+                if let Some((packet, consumed)) = decode(&self.buffer).unwrap() {
+                    self.buffer.drain(..consumed);
+                    return packet;
+                }
+                let mut chunk = [0_u8; 512];
+                let read = self.stream.read(&mut chunk).unwrap();
+                assert!(read > 0, "connection closed");
+                self.buffer.extend_from_slice(&chunk[..read]);
+            }
+        }
+
+        fn receive_message(&mut self) -> (String, String) {
+            match self.receive() {
+                Packet::Publish(message) => (
+                    message.topic.as_str().to_string(),
+                    String::from_utf8(message.payload.to_vec()).unwrap(),
+                ),
+                packet => panic!("unexpected packet {:?}", packet),
+            }
+        }
+    }
+
+    fn start() -> HubSimulator {
+        HubSimulator::start(HubSimulatorOptions {
+            hostname: HOSTNAME.to_string(),
+            devices: vec![HubDevice::new(DEVICE_ID, SAS_KEY)],
+        })
+        .unwrap()
+    }
+
+    fn client() -> Client<'static> {
+        Client::new(HOSTNAME, DEVICE_ID, None).unwrap()
+    }
+
+    fn connect(simulator: &HubSimulator) -> TestClient {
+        let (client, connack) = TestClient::connect(simulator, None, SAS_KEY);
+        assert_eq!(
+            connack,
+            Packet::Connack(Connack {
+                session_present: false,
+                return_code: ConnectReturnCode::Accepted,
+            })
+        );
+        client
+    }
+
+    #[test]
+    fn unknown_key_is_rejected() {
+        let simulator = start();
+        let (_, connack) = TestClient::connect(
+            &simulator,
+            None,
+            "QW5vdGhlciBrZXkgdGhhdCBpcyBub3QgZW5yb2xsZWQ=",
+        );
+        assert_eq!(
+            connack,
+            Packet::Connack(Connack {
+                session_present: false,
+                return_code: ConnectReturnCode::NotAuthorized,
+            })
+        );
+    }
+
+    #[test]
+    fn telemetry_is_recorded_with_properties() {
+        let simulator = start();
+        let mut device = connect(&simulator);
+        let mut properties: PropertiesBuilder<U128> = PropertiesBuilder::new();
+        properties.append("alert level", "high/critical").unwrap();
+        properties.append("$.ct", "application/json").unwrap();
+        let topic = client()
+            .telemetry_publish_topic(Some(&properties.as_properties()))
+            .unwrap();
+        device.publish(&topic, b"{\"temperature\":21}");
+
+        let telemetry = simulator.wait_for_telemetry(1, TIMEOUT);
+        assert_eq!(
+            telemetry,
+            vec![Telemetry {
+                device_id: DEVICE_ID.to_string(),
+                module_id: None,
+                properties: vec![
+                    ("alert level".to_string(), "high/critical".to_string()),
+                    ("$.ct".to_string(), "application/json".to_string()),
+                ],
+                payload: b"{\"temperature\":21}".to_vec(),
+            }]
+        );
+    }
+
+    #[test]
+    fn module_telemetry_records_the_module() {
+        let simulator = start();
+        let (mut device, _) = TestClient::connect(&simulator, Some("sensor"), SAS_KEY);
+        device.publish("devices/device-1/modules/sensor/messages/events/", b"1");
+        let telemetry = simulator.wait_for_telemetry(1, TIMEOUT);
+        assert_eq!(telemetry[0].module_id.as_deref(), Some("sensor"));
+    }
+
+    #[test]
+    fn c2d_messages_are_delivered() {
+        let simulator = start();
+        let mut device = connect(&simulator);
+        let filter = client().c2d_subscribe_topic().unwrap();
+        device.subscribe(&filter);
+        simulator
+            .send_c2d(DEVICE_ID, &[("key", "a value")], b"hello")
+            .unwrap();
+
+        let (topic, body) = device.receive_message();
+        let request = client().c2d_parse_received_topic(&topic).unwrap();
+        assert_eq!(request.properties.get("key"), Some("a%20value"));
+        assert_eq!(body, "hello");
+    }
+
+    #[test]
+    fn c2d_to_a_disconnected_device_fails() {
+        let simulator = start();
+        assert!(simulator.send_c2d(DEVICE_ID, &[], b"hello").is_err());
+    }
+
+    #[test]
+    fn direct_methods_are_round_tripped() {
+        let simulator = start();
+        let mut device = connect(&simulator);
+        device.subscribe(crate::hub::METHODS_SUBSCRIBE_TOPIC);
+        let request_id = simulator
+            .invoke_method(DEVICE_ID, "reboot", b"{\"delay\":0}")
+            .unwrap();
+
+        let (topic, body) = device.receive_message();
+        let request = Client::methods_parse_received_topic(&topic).unwrap();
+        assert_eq!(request.name, "reboot");
+        assert_eq!(body, "{\"delay\":0}");
+        let response_topic =
+            Client::methods_response_publish_topic(request.request_id, 200).unwrap();
+        device.publish(&response_topic, b"{\"ok\":true}");
+
+        let response = simulator
+            .wait_for_method_response(&request_id, TIMEOUT)
+            .unwrap();
+        assert_eq!(response.status, 200);
+        assert_eq!(response.payload, b"{\"ok\":true}".to_vec());
+    }
+
+    #[test]
+    fn desired_patches_are_versioned() {
+        let simulator = start();
+        let mut device = connect(&simulator);
+        device.subscribe(crate::hub::TWIN_PATCH_SUBSCRIBE_TOPIC);
+        let version = simulator
+            .patch_desired(DEVICE_ID, &json!({"interval": 30, "mode": "eco"}))
+            .unwrap();
+        assert_eq!(version, 2);
+
+        let (topic, body) = device.receive_message();
+        let response = Client::twin_parse_received_topic(&topic).unwrap();
+        assert_eq!(response.response_type, TwinResponseType::DesiredProperties);
+        assert_eq!(response.version, Some("2"));
+        let patch: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(patch, json!({"interval": 30, "mode": "eco", "$version": 2}));
+
+        simulator
+            .patch_desired(DEVICE_ID, &json!({"mode": null}))
+            .unwrap();
+        assert_eq!(
+            simulator.twin(DEVICE_ID)["desired"],
+            json!({"interval": 30, "$version": 3})
+        );
+    }
+
+    #[test]
+    fn twin_get_and_reported_patch_are_served() {
+        let simulator = start();
+        let mut device = connect(&simulator);
+        device.subscribe(crate::hub::TWIN_RESPONSE_SUBSCRIBE_TOPIC);
+
+        let patch_topic = Client::twin_patch_publish_topic("1").unwrap();
+        device.publish(&patch_topic, b"{\"firmware\":{\"version\":\"1.2\"}}");
+        let (topic, _) = device.receive_message();
+        let response = Client::twin_parse_received_topic(&topic).unwrap();
+        assert_eq!(response.response_type, TwinResponseType::ReportedProperties);
+        assert_eq!(response.request_id, Some("1"));
+        assert_eq!(response.version, Some("2"));
+
+        device.publish(&patch_topic, b"not json");
+        let (topic, _) = device.receive_message();
+        assert_eq!(topic, "$iothub/twin/res/400/?$rid=1");
+
+        let get_topic = Client::twin_document_get_publish_topic("2").unwrap();
+        device.publish(&get_topic, b"");
+        let (topic, body) = device.receive_message();
+        let response = Client::twin_parse_received_topic(&topic).unwrap();
+        assert_eq!(response.response_type, TwinResponseType::Get);
+        assert_eq!(response.status, 200);
+        let document: Value = serde_json::from_str(&body).unwrap();
+        assert_eq!(
+            document,
+            json!({
+                "desired": {"$version": 1},
+                "reported": {"firmware": {"version": "1.2"}, "$version": 2},
+            })
+        );
+        device.send(&encode_disconnect());
+    }
+
+    #[test]
+    fn merge_patch_follows_rfc_7386() {
+        let mut target = json!({"a": "b", "c": {"d": "e", "f": "g"}});
+        merge_patch(&mut target, &json!({"a": "z", "c": {"f": null}}));
+        assert_eq!(target, json!({"a": "z", "c": {"d": "e"}}));
+    }
+}
@@ -0,0 +1,85 @@
+// A test CA that signs certificate signing requests the way DPS does for
+// client certificate issuance: the request has to be for the registration ID
+// and the chain comes back leaf first, base64 encoded DER.
+use std::string::String;
+use std::vec;
+use std::vec::Vec;
+
+use rcgen::{
+    BasicConstraints, Certificate, CertificateParams, CertificateSigningRequest, DnType,
+    ExtendedKeyUsagePurpose, IsCa, KeyUsagePurpose, PKCS_ECDSA_P256_SHA256,
+};
+use x509_parser::certification_request::X509CertificationRequest;
+use x509_parser::prelude::FromDer;
+
+use crate::provisioning::certificate::AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST;
+
+pub struct CertificateIssuer {
+    certificate: Certificate,
+    certificate_base64: String,
+}
+
+impl CertificateIssuer {
+    pub fn new(common_name: &str) -> Result<CertificateIssuer, &'static str> {
+        let mut params = CertificateParams::new(Vec::new());
+        params.alg = &PKCS_ECDSA_P256_SHA256;
+        params
+            .distinguished_name
+            .push(DnType::CommonName, common_name);
+        params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+        params.key_usages = vec![KeyUsagePurpose::KeyCertSign, KeyUsagePurpose::CrlSign];
+        let certificate =
+            Certificate::from_params(params).map_err(|_| "The test CA could not be created.")?;
+        let certificate_base64 = certificate
+            .serialize_der()
+            .map(base64::encode)
+            .map_err(|_| "The test CA could not be created.")?;
+        Ok(CertificateIssuer {
+            certificate,
+            certificate_base64,
+        })
+    }
+
+    pub fn certificate_base64(&self) -> &str {
+        &self.certificate_base64
+    }
+
+    pub fn certificate_pem(&self) -> String {
+        self.certificate.serialize_pem().unwrap_or_default()
+    }
+
+    // The issued chain for a base64 encoded request whose common name is the
+    // registration ID: the client certificate followed by this CA.
+    pub fn issue(
+        &self,
+        registration_id: &str,
+        certificate_signing_request: &str,
+    ) -> Result<Vec<String>, &'static str> {
+        let der = base64::decode(certificate_signing_request)
+            .map_err(|_| AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST)?;
+        let common_name = match X509CertificationRequest::from_der(&der) {
+            Ok((_, request)) if request.verify_signature().is_ok() => request
+                .certification_request_info
+                .subject
+                .iter_common_name()
+                .next()
+                .and_then(|name| name.as_str().ok())
+                .map(String::from),
+            _ => None,
+        };
+        if common_name.as_deref() != Some(registration_id) {
+            return Err(AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST);
+        }
+        let mut request = CertificateSigningRequest::from_der(&der)
+            .map_err(|_| AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST)?;
+        request.params.key_usages = vec![KeyUsagePurpose::DigitalSignature];
+        request.params.extended_key_usages = vec![ExtendedKeyUsagePurpose::ClientAuth];
+        let certificate = request
+            .serialize_der_with_signer(&self.certificate)
+            .map_err(|_| AZ_ERROR_INVALID_CERTIFICATE_SIGNING_REQUEST)?;
+        Ok(vec![
+            base64::encode(certificate),
+            self.certificate_base64.clone(),
+        ])
+    }
+}
@@ -0,0 +1,117 @@
+// Local stand-ins for the Azure services so that device code can be exercised
+// without network access. They speak MQTT 3.1.1 over plain TCP using the
+// crate's own codec and are only meant for tests; websocket puts them behind
+// a WebSocket endpoint.
+pub mod dps;
+pub mod hub;
+#[cfg(feature = "csr")]
+pub mod issuer;
+pub mod signer;
+#[cfg(feature = "websocket")]
+pub mod websocket;
+
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::vec::Vec;
+
+use azure_sdk_for_rust_common::clock::{Clock, SystemClock};
+use heapless::consts::U4096;
+
+use crate::mqtt::{decode_client_packet, encode_publish, ClientPacket, Publish, QoS};
+
+// Accepts connections on a loopback port until dropped. Connections are served
+// one at a time, in the order they were accepted.
+pub(crate) struct Listener {
+    address: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Listener {
+    pub(crate) fn start<F>(mut serve: F) -> io::Result<Listener>
+    where
+        F: FnMut(TcpStream) -> io::Result<()> + Send + 'static,
+    {
+        let listener = TcpListener::bind("127.0.0.1:0")?;
+        let address = listener.local_addr()?;
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let stopping = shutdown.clone();
+        let thread = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if stopping.load(Ordering::SeqCst) {
+                    return;
+                }
+                if let Ok(stream) = stream {
+                    // A misbehaving client only ends its own connection.
+                    let _ = serve(stream);
+                }
+            }
+        });
+        Ok(Listener {
+            address,
+            shutdown,
+            thread: Some(thread),
+        })
+    }
+
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.address
+    }
+}
+
+impl Drop for Listener {
+    fn drop(&mut self) {
+        self.shutdown.store(true, Ordering::SeqCst);
+        // wake up the blocking accept
+        let _ = TcpStream::connect(self.address);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+// Reads client packets from the stream and hands them to the handler until the
+// client disconnects or the handler returns false.
+pub(crate) fn serve_packets<F>(stream: &mut TcpStream, mut handler: F) -> io::Result<()>
+where
+    F: FnMut(&mut TcpStream, ClientPacket<'_>) -> io::Result<bool>,
+{
+    let mut buffer: Vec<u8> = Vec::new();
+    let mut chunk = [0_u8; 1024];
+    loop {
+        loop {
+            let consumed = match decode_client_packet(&buffer).map_err(invalid_data)? {
+                Some((packet, consumed)) => {
+                    if !handler(stream, packet)? {
+                        return Ok(());
+                    }
+                    consumed
+                }
+                None => break,
+            };
+            buffer.drain(..consumed);
+        }
+        let read = stream.read(&mut chunk)?;
+        if read == 0 {
+            return Ok(());
+        }
+        buffer.extend_from_slice(&chunk[..read]);
+    }
+}
+
+pub(crate) fn publish(stream: &mut TcpStream, topic: &str, payload: &[u8]) -> io::Result<()> {
+    let packet = encode_publish::<U4096>(&Publish::new(topic, payload, QoS::AtMostOnce, 0))
+        .map_err(invalid_data)?;
+    stream.write_all(&packet)
+}
+
+pub(crate) fn invalid_data(error: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+pub(crate) fn now_epoch_time() -> u64 {
+    SystemClock.epoch_time().unwrap_or_default()
+}
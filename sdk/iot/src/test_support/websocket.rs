@@ -0,0 +1,121 @@
+// Stands in for the services' WebSocket endpoint: accepts the upgrade on
+// /$iothub/websocket and relays the MQTT bytes to one of the TCP stand-ins, so
+// DpsSimulator and HubSimulator can be reached the way port 443 reaches them.
+use std::format;
+use std::io::{self, Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::string::String;
+use std::time::Duration;
+
+use super::Listener;
+use crate::endpoint::WEBSOCKET_PATH;
+use crate::websocket::WebSocketStream;
+
+// How long each side is waited on before checking the other
+const POLL_INTERVAL: Duration = Duration::from_millis(5);
+
+pub struct WebSocketGateway {
+    listener: Listener,
+}
+
+impl WebSocketGateway {
+    // Relays to the stand-in listening on upstream, e.g. DpsSimulator::local_addr
+    pub fn start(upstream: SocketAddr) -> io::Result<WebSocketGateway> {
+        let listener = Listener::start(move |stream| relay(stream, upstream))?;
+        Ok(WebSocketGateway { listener })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.listener.local_addr()
+    }
+
+    pub fn endpoint(&self) -> String {
+        format!("ws://{}{}", self.local_addr(), WEBSOCKET_PATH)
+    }
+}
+
+fn relay(stream: TcpStream, upstream: SocketAddr) -> io::Result<()> {
+    let mut client = WebSocketStream::accept(stream)?;
+    let mut service = TcpStream::connect(upstream)?;
+    client.get_ref().set_read_timeout(Some(POLL_INTERVAL))?;
+    service.set_read_timeout(Some(POLL_INTERVAL))?;
+    let mut chunk = [0_u8; 4096];
+    loop {
+        match client.read(&mut chunk) {
+            Ok(0) => return Ok(()),
+            Ok(read) => service.write_all(&chunk[..read])?,
+            Err(error) if is_timeout(&error) => {}
+            Err(error) => return Err(error),
+        }
+        match service.read(&mut chunk) {
+            Ok(0) => return client.close(),
+            Ok(read) => client.write_all(&chunk[..read])?,
+            Err(error) if is_timeout(&error) => {}
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+fn is_timeout(error: &io::Error) -> bool {
+    error.kind() == io::ErrorKind::WouldBlock || error.kind() == io::ErrorKind::TimedOut
+}
+
+#[cfg(test)]
+mod tests_websocket_gateway {
+    use super::*;
+    use crate::endpoint::Endpoint;
+    use crate::hub::client::Client;
+    use crate::hub::sas::get_password;
+    use crate::mqtt::{decode, encode_connect, Connack, Connect, ConnectReturnCode, Packet};
+    use crate::test_support::hub::{HubDevice, HubSimulator, HubSimulatorOptions};
+    use crate::test_support::now_epoch_time;
+    use heapless::consts::{U1024, U256};
+    use std::string::ToString;
+    use std::vec;
+    use std::vec::Vec;
+
+    const HOSTNAME: &str = "simulated.azure-devices.net";
+    const DEVICE_ID: &str = "device-1";
+    const SAS_KEY: &str = "VGhpcyB0aGluZyBhbGwgdGhpbmdzIGl0IGRldm91cnM=";
+
+    #[test]
+    fn hub_is_reached_over_websocket() {
+        let simulator = HubSimulator::start(HubSimulatorOptions {
+            hostname: HOSTNAME.to_string(),
+            devices: vec![HubDevice::new(DEVICE_ID, SAS_KEY)],
+        })
+        .unwrap();
+        let gateway = WebSocketGateway::start(simulator.local_addr()).unwrap();
+        let endpoint = gateway.endpoint();
+        let endpoint = Endpoint::parse(&endpoint).unwrap();
+        let stream = TcpStream::connect(gateway.local_addr()).unwrap();
+        let mut websocket = WebSocketStream::connect(stream, &endpoint).unwrap();
+
+        let client = Client::new(HOSTNAME, DEVICE_ID, None).unwrap();
+        let client_id = client.get_client_id().unwrap();
+        let user_name = client.get_user_name().unwrap();
+        let password = get_password(&client, SAS_KEY, now_epoch_time() + 3600, None).unwrap();
+        let connect = Connect::new(&client_id, &user_name, &password);
+        websocket
+            .write_all(&encode_connect::<U1024>(&connect).unwrap())
+            .unwrap();
+
+        let mut buffer = Vec::new();
+        let connack = loop {
+            if let Some((packet, _)) = decode::<U256, U1024>(&buffer).unwrap() {
+                break packet;
+            }
+            let mut chunk = [0_u8; 64];
+            let read = websocket.read(&mut chunk).unwrap();
+            assert!(read > 0, "connection closed");
+            buffer.extend_from_slice(&chunk[..read]);
+        };
+        assert_eq!(
+            connack,
+            Packet::Connack(Connack {
+                session_present: false,
+                return_code: ConnectReturnCode::Accepted,
+            })
+        );
+    }
+}
@@ -0,0 +1,199 @@
+// TLS setup for the MQTT connection to DPS or IoT Hub: the device identity
+// from an X509Credential, the CAs the service certificate must chain to, and
+// the host name that has to be sent as SNI for the service to answer at all.
+#[cfg(feature = "native-tls")]
+pub mod native_tls;
+#[cfg(feature = "rustls")]
+pub mod rustls;
+#[cfg(test)]
+mod test_certificates;
+
+use std::fs;
+use std::vec::Vec;
+
+use rustls_pemfile::Item;
+use zeroize::Zeroize;
+
+use crate::provisioning::common::X509Credential;
+
+pub const AZ_ERROR_TLS_UNABLE_TO_READ_FILE: &str = "The certificate or key file could not be read.";
+pub const AZ_ERROR_TLS_INVALID_PEM: &str = "The given value is not valid PEM.";
+pub const AZ_ERROR_TLS_NO_CERTIFICATES: &str = "No certificates were found.";
+pub const AZ_ERROR_TLS_NO_PRIVATE_KEY: &str = "No private key was found.";
+pub const AZ_ERROR_TLS_INVALID_CERTIFICATE: &str = "A certificate or private key was rejected.";
+pub const AZ_ERROR_TLS_INVALID_HOST_NAME: &str = "The host name cannot be used for TLS.";
+pub const AZ_ERROR_TLS_HANDSHAKE_FAILED: &str = "The TLS handshake failed.";
+
+const PEM_BEGIN: &str = "-----BEGIN";
+
+// The roots the DPS and IoT Hub certificates chain to, by common name.
+#[cfg(feature = "azure-roots")]
+const AZURE_ROOT_CA_NAMES: [&str; 5] = [
+    "DigiCert Global Root G2",
+    "DigiCert Global Root CA",
+    "Baltimore CyberTrust Root",
+    "Microsoft RSA Root Certificate Authority 2017",
+    "Microsoft ECC Root Certificate Authority 2017",
+];
+
+// The device certificate chain and its private key, kept as PEM.
+pub struct ClientIdentity {
+    certificate_chain: Vec<u8>,
+    private_key: Vec<u8>,
+}
+
+impl ClientIdentity {
+    // The chain starts with the device certificate; the key is PKCS #8, or
+    // PKCS #1 or SEC1 for rustls.
+    pub fn from_pem(certificate_chain: &[u8], private_key: &[u8]) -> Result<Self, &'static str> {
+        let identity = ClientIdentity {
+            certificate_chain: certificate_chain.to_vec(),
+            private_key: private_key.to_vec(),
+        };
+        identity.certificate_chain_der()?;
+        identity.private_key_der()?;
+        Ok(identity)
+    }
+
+    // The credential's certificate and key are either PEM or paths to PEM
+    // files, as in AZ_IOT_DEVICE_X509_CERT_PEM_FILE.
+    pub fn from_credential(credential: &X509Credential) -> Result<Self, &'static str> {
+        let certificate_chain = load_pem(credential.identity_cert())?;
+        let mut private_key = load_pem(credential.identity_private_key())?;
+        let identity = ClientIdentity::from_pem(&certificate_chain, &private_key);
+        private_key.zeroize();
+        identity
+    }
+
+    pub fn certificate_chain_pem(&self) -> &[u8] {
+        &self.certificate_chain
+    }
+
+    pub fn private_key_pem(&self) -> &[u8] {
+        &self.private_key
+    }
+
+    pub fn certificate_chain_der(&self) -> Result<Vec<Vec<u8>>, &'static str> {
+        certificates_der(&self.certificate_chain)
+    }
+
+    pub fn private_key_der(&self) -> Result<Item, &'static str> {
+        pem_items(&self.private_key)?
+            .into_iter()
+            .find(|item| matches!(item, Item::PKCS8Key(_) | Item::RSAKey(_) | Item::ECKey(_)))
+            .ok_or(AZ_ERROR_TLS_NO_PRIVATE_KEY)
+    }
+}
+
+impl Drop for ClientIdentity {
+    fn drop(&mut self) {
+        self.private_key.zeroize();
+    }
+}
+
+// The CAs that the service certificate has to chain to.
+pub enum TrustBundle {
+    // Azure's public roots. native-tls can only add certificates, so it uses
+    // the system roots instead.
+    #[cfg(feature = "azure-roots")]
+    AzureRoots,
+    // PEM certificates, e.g. the AZ_IOT_DEVICE_X509_TRUST_PEM_FILE bundle.
+    Pem(Vec<u8>),
+}
+
+impl TrustBundle {
+    // Either PEM or the path to a PEM file.
+    pub fn load(path_or_pem: &str) -> Result<Self, &'static str> {
+        let pem = load_pem(path_or_pem)?;
+        certificates_der(&pem)?;
+        Ok(TrustBundle::Pem(pem))
+    }
+}
+
+#[cfg(feature = "azure-roots")]
+pub fn azure_root_anchors() -> impl Iterator<Item = &'static webpki_roots::TrustAnchor<'static>> {
+    webpki_roots::TLS_SERVER_ROOTS.iter().filter(|anchor| {
+        AZURE_ROOT_CA_NAMES
+            .iter()
+            .any(|name| contains(anchor.subject, name.as_bytes()))
+    })
+}
+
+#[cfg(feature = "azure-roots")]
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack
+        .windows(needle.len())
+        .any(|window| window == needle)
+}
+
+fn load_pem(path_or_pem: &str) -> Result<Vec<u8>, &'static str> {
+    if path_or_pem.trim_start().starts_with(PEM_BEGIN) {
+        Ok(path_or_pem.as_bytes().to_vec())
+    } else {
+        fs::read(path_or_pem).map_err(|_| AZ_ERROR_TLS_UNABLE_TO_READ_FILE)
+    }
+}
+
+fn pem_items(pem: &[u8]) -> Result<Vec<Item>, &'static str> {
+    let mut reader = pem;
+    rustls_pemfile::read_all(&mut reader).map_err(|_| AZ_ERROR_TLS_INVALID_PEM)
+}
+
+fn certificates_der(pem: &[u8]) -> Result<Vec<Vec<u8>>, &'static str> {
+    let certificates: Vec<Vec<u8>> = pem_items(pem)?
+        .into_iter()
+        .filter_map(|item| match item {
+            Item::X509Certificate(der) => Some(der),
+            _ => None,
+        })
+        .collect();
+    if certificates.is_empty() {
+        return Err(AZ_ERROR_TLS_NO_CERTIFICATES);
+    }
+    Ok(certificates)
+}
+
+#[cfg(test)]
+mod tests_tls {
+    use super::test_certificates::*;
+    use super::*;
+    use heapless::String;
+
+    #[test]
+    fn identities_are_read_from_credentials() {
+        let credential = X509Credential::new(
+            String::from("/nonexistent/device.pem"),
+            String::from(DEVICE_PRIVATE_KEY),
+        );
+        assert_eq!(
+            ClientIdentity::from_credential(&credential).err(),
+            Some(AZ_ERROR_TLS_UNABLE_TO_READ_FILE)
+        );
+
+        let identity =
+            ClientIdentity::from_pem(DEVICE_CERTIFICATE.as_bytes(), DEVICE_PRIVATE_KEY.as_bytes())
+                .unwrap();
+        assert_eq!(identity.certificate_chain_der().unwrap().len(), 1);
+        assert!(matches!(identity.private_key_der(), Ok(Item::PKCS8Key(_))));
+        assert_eq!(
+            ClientIdentity::from_pem(DEVICE_PRIVATE_KEY.as_bytes(), DEVICE_PRIVATE_KEY.as_bytes())
+                .err(),
+            Some(AZ_ERROR_TLS_NO_CERTIFICATES)
+        );
+        assert_eq!(
+            ClientIdentity::from_pem(DEVICE_CERTIFICATE.as_bytes(), CA_CERTIFICATE.as_bytes())
+                .err(),
+            Some(AZ_ERROR_TLS_NO_PRIVATE_KEY)
+        );
+        assert_eq!(
+            TrustBundle::load("-----BEGIN CERTIFICATE-----\n").err(),
+            Some(AZ_ERROR_TLS_INVALID_PEM)
+        );
+    }
+
+    #[cfg(feature = "azure-roots")]
+    #[test]
+    fn azure_roots_are_built_in() {
+        assert_eq!(azure_root_anchors().count(), AZURE_ROOT_CA_NAMES.len());
+    }
+}
@@ -0,0 +1,134 @@
+// native-tls (OpenSSL, Schannel or Secure Transport) connector, e.g.
+// let connector = connector(Some(&identity), &trust)?;
+// let stream = connect(&connector, client.global_device_endpoint, tcp)?;
+use std::io::{Read, Write};
+
+use ::native_tls::{Certificate, Identity, TlsConnector, TlsStream};
+
+use crate::endpoint::Endpoint;
+
+use super::{
+    ClientIdentity, TrustBundle, AZ_ERROR_TLS_HANDSHAKE_FAILED, AZ_ERROR_TLS_INVALID_CERTIFICATE,
+};
+
+// The identity's key has to be PKCS #8. A PEM trust bundle replaces the system
+// roots rather than adding to them.
+pub fn connector(
+    identity: Option<&ClientIdentity>,
+    trust: &TrustBundle,
+) -> Result<TlsConnector, &'static str> {
+    let mut builder = TlsConnector::builder();
+    if let Some(identity) = identity {
+        builder.identity(
+            Identity::from_pkcs8(identity.certificate_chain_pem(), identity.private_key_pem())
+                .map_err(|_| AZ_ERROR_TLS_INVALID_CERTIFICATE)?,
+        );
+    }
+    match trust {
+        #[cfg(feature = "azure-roots")]
+        TrustBundle::AzureRoots => {}
+        TrustBundle::Pem(pem) => {
+            builder.disable_built_in_roots(true);
+            for certificate in super::certificates_der(pem)? {
+                builder.add_root_certificate(
+                    Certificate::from_der(&certificate)
+                        .map_err(|_| AZ_ERROR_TLS_INVALID_CERTIFICATE)?,
+                );
+            }
+        }
+    }
+    builder
+        .build()
+        .map_err(|_| AZ_ERROR_TLS_INVALID_CERTIFICATE)
+}
+
+// Runs the handshake over a blocking stream with SNI set to the endpoint's
+// host.
+pub fn connect<S>(
+    connector: &TlsConnector,
+    endpoint: &str,
+    stream: S,
+) -> Result<TlsStream<S>, &'static str>
+where
+    S: Read + Write,
+{
+    connector
+        .connect(Endpoint::parse(endpoint)?.host, stream)
+        .map_err(|_| AZ_ERROR_TLS_HANDSHAKE_FAILED)
+}
+
+#[cfg(all(test, feature = "rustls"))]
+mod tests_native_tls {
+    use super::super::test_certificates::*;
+    use super::*;
+    use ::rustls::server::AllowAnyAuthenticatedClient;
+    use ::rustls::{RootCertStore, ServerConfig, ServerConnection, StreamOwned};
+    use std::net::{TcpListener, TcpStream};
+    use std::string::String;
+    use std::sync::Arc;
+    use std::thread;
+
+    fn certificates(pem: &str) -> std::vec::Vec<::rustls::Certificate> {
+        super::super::certificates_der(pem.as_bytes())
+            .unwrap()
+            .into_iter()
+            .map(::rustls::Certificate)
+            .collect()
+    }
+
+    // Accepts one connection and answers with the SNI it was sent.
+    fn serve(listener: TcpListener) -> thread::JoinHandle<()> {
+        let mut roots = RootCertStore::empty();
+        roots.add(&certificates(CA_CERTIFICATE)[0]).unwrap();
+        let key =
+            ClientIdentity::from_pem(SERVER_CERTIFICATE.as_bytes(), SERVER_PRIVATE_KEY.as_bytes())
+                .unwrap()
+                .private_key_der();
+        let key = match key {
+            Ok(rustls_pemfile::Item::PKCS8Key(der)) => ::rustls::PrivateKey(der),
+            _ => unreachable!(),
+        };
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            .with_single_cert(certificates(SERVER_CERTIFICATE), key)
+            .unwrap();
+        thread::spawn(move || {
+            let (tcp, _) = listener.accept().unwrap();
+            let connection = ServerConnection::new(Arc::new(config)).unwrap();
+            let mut stream = StreamOwned::new(connection, tcp);
+            let mut request = [0; 1];
+            stream.read_exact(&mut request).unwrap();
+            let server_name = String::from(stream.conn.server_name().unwrap_or_default());
+            stream.write_all(server_name.as_bytes()).unwrap();
+            stream.conn.send_close_notify();
+            stream.flush().unwrap();
+        })
+    }
+
+    #[test]
+    fn device_certificate_and_sni_reach_the_service() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = serve(listener);
+
+        let identity =
+            ClientIdentity::from_pem(DEVICE_CERTIFICATE.as_bytes(), DEVICE_PRIVATE_KEY.as_bytes())
+                .unwrap();
+        let connector =
+            connector(Some(&identity), &TrustBundle::Pem(CA_CERTIFICATE.into())).unwrap();
+        // the certificate is verified against the DPS host, not the address
+        let tcp = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        let mut stream = connect(
+            &connector,
+            "ssl://global.azure-devices-provisioning.net:8883",
+            tcp,
+        )
+        .unwrap();
+        stream.write_all(b"?").unwrap();
+        let mut server_name = String::new();
+        stream.read_to_string(&mut server_name).unwrap();
+        server.join().unwrap();
+        assert_eq!(server_name, "global.azure-devices-provisioning.net");
+    }
+}
@@ -0,0 +1,183 @@
+// rustls client configuration, e.g.
+// let config = client_config(Some(&identity), &TrustBundle::AzureRoots)?;
+// let connection = client_connection(Arc::new(config), client.global_device_endpoint)?;
+// let stream = rustls::StreamOwned::new(connection, tcp);
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::vec::Vec;
+
+use ::rustls::{
+    Certificate, ClientConfig, ClientConnection, PrivateKey, RootCertStore, ServerName,
+};
+use rustls_pemfile::Item;
+
+use crate::endpoint::Endpoint;
+
+use super::{
+    ClientIdentity, TrustBundle, AZ_ERROR_TLS_HANDSHAKE_FAILED, AZ_ERROR_TLS_INVALID_CERTIFICATE,
+    AZ_ERROR_TLS_NO_PRIVATE_KEY,
+};
+
+// Without an identity the device authenticates with a SAS token instead.
+pub fn client_config(
+    identity: Option<&ClientIdentity>,
+    trust: &TrustBundle,
+) -> Result<ClientConfig, &'static str> {
+    let builder = ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(root_store(trust)?);
+    match identity {
+        Some(identity) => builder
+            .with_client_auth_cert(certificate_chain(identity)?, private_key(identity)?)
+            .map_err(|_| AZ_ERROR_TLS_INVALID_CERTIFICATE),
+        None => Ok(builder.with_no_client_auth()),
+    }
+}
+
+// Starts the handshake with SNI set to the endpoint's host.
+pub fn client_connection(
+    config: Arc<ClientConfig>,
+    endpoint: &str,
+) -> Result<ClientConnection, &'static str> {
+    let server_name = ServerName::try_from(Endpoint::parse(endpoint)?.host)
+        .map_err(|_| super::AZ_ERROR_TLS_INVALID_HOST_NAME)?;
+    ClientConnection::new(config, server_name).map_err(|_| AZ_ERROR_TLS_HANDSHAKE_FAILED)
+}
+
+fn root_store(trust: &TrustBundle) -> Result<RootCertStore, &'static str> {
+    let mut roots = RootCertStore::empty();
+    match trust {
+        #[cfg(feature = "azure-roots")]
+        TrustBundle::AzureRoots => {
+            roots.add_trust_anchors(super::azure_root_anchors().map(|anchor| {
+                ::rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                    anchor.subject,
+                    anchor.spki,
+                    anchor.name_constraints,
+                )
+            }));
+        }
+        TrustBundle::Pem(pem) => {
+            for certificate in super::certificates_der(pem)? {
+                roots
+                    .add(&Certificate(certificate))
+                    .map_err(|_| AZ_ERROR_TLS_INVALID_CERTIFICATE)?;
+            }
+        }
+    }
+    Ok(roots)
+}
+
+fn certificate_chain(identity: &ClientIdentity) -> Result<Vec<Certificate>, &'static str> {
+    Ok(identity
+        .certificate_chain_der()?
+        .into_iter()
+        .map(Certificate)
+        .collect())
+}
+
+fn private_key(identity: &ClientIdentity) -> Result<PrivateKey, &'static str> {
+    match identity.private_key_der()? {
+        Item::PKCS8Key(der) | Item::RSAKey(der) | Item::ECKey(der) => Ok(PrivateKey(der)),
+        _ => Err(AZ_ERROR_TLS_NO_PRIVATE_KEY),
+    }
+}
+
+#[cfg(test)]
+mod tests_rustls {
+    use super::super::test_certificates::*;
+    use super::*;
+    use ::rustls::server::AllowAnyAuthenticatedClient;
+    use ::rustls::{Connection, ServerConfig, ServerConnection};
+
+    fn server() -> ServerConnection {
+        let roots = root_store(&TrustBundle::Pem(CA_CERTIFICATE.into())).unwrap();
+        let identity =
+            ClientIdentity::from_pem(SERVER_CERTIFICATE.as_bytes(), SERVER_PRIVATE_KEY.as_bytes())
+                .unwrap();
+        let config = ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(AllowAnyAuthenticatedClient::new(roots).boxed())
+            .with_single_cert(
+                certificate_chain(&identity).unwrap(),
+                private_key(&identity).unwrap(),
+            )
+            .unwrap();
+        ServerConnection::new(Arc::new(config)).unwrap()
+    }
+
+    fn transfer(from: &mut Connection, to: &mut Connection) {
+        let mut buffer = Vec::new();
+        while from.wants_write() {
+            from.write_tls(&mut buffer).unwrap();
+        }
+        let mut data = &buffer[..];
+        while !data.is_empty() {
+            to.read_tls(&mut data).unwrap();
+            to.process_new_packets().unwrap();
+        }
+    }
+
+    #[test]
+    fn device_certificate_and_sni_reach_the_service() {
+        let identity =
+            ClientIdentity::from_pem(DEVICE_CERTIFICATE.as_bytes(), DEVICE_PRIVATE_KEY.as_bytes())
+                .unwrap();
+        let config =
+            client_config(Some(&identity), &TrustBundle::Pem(CA_CERTIFICATE.into())).unwrap();
+        let mut client = Connection::Client(
+            client_connection(
+                Arc::new(config),
+                "ssl://global.azure-devices-provisioning.net:8883",
+            )
+            .unwrap(),
+        );
+        let mut server = Connection::Server(server());
+        while client.is_handshaking() || server.is_handshaking() {
+            transfer(&mut client, &mut server);
+            transfer(&mut server, &mut client);
+        }
+        if let Connection::Server(server) = &server {
+            assert_eq!(
+                server.server_name(),
+                Some("global.azure-devices-provisioning.net")
+            );
+            assert_eq!(server.peer_certificates().map(<[_]>::len), Some(1));
+        }
+    }
+
+    #[test]
+    fn untrusted_services_are_rejected() {
+        let config = client_config(None, &TrustBundle::Pem(SERVER_CERTIFICATE.into())).unwrap();
+        let mut client = Connection::Client(
+            client_connection(Arc::new(config), "global.azure-devices-provisioning.net").unwrap(),
+        );
+        let mut server = Connection::Server(server());
+        let mut buffer = Vec::new();
+        while client.wants_write() {
+            client.write_tls(&mut buffer).unwrap();
+        }
+        server.read_tls(&mut &buffer[..]).unwrap();
+        server.process_new_packets().unwrap();
+        buffer.clear();
+        while server.wants_write() {
+            server.write_tls(&mut buffer).unwrap();
+        }
+        client.read_tls(&mut &buffer[..]).unwrap();
+        assert!(client.process_new_packets().is_err());
+    }
+
+    #[cfg(feature = "azure-roots")]
+    #[test]
+    fn azure_roots_configure_a_client() {
+        assert!(client_config(None, &TrustBundle::AzureRoots).is_ok());
+        assert_eq!(
+            client_connection(
+                Arc::new(client_config(None, &TrustBundle::AzureRoots).unwrap()),
+                "ssl://:8883"
+            )
+            .err(),
+            Some(crate::endpoint::AZ_ERROR_ENDPOINT_INVALID_HOST)
+        );
+    }
+}
@@ -0,0 +1,305 @@
+use core::convert::TryFrom;
+
+use heapless::{ArrayLength, Vec};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use super::{AZ_ERROR_WEBSOCKET_MALFORMED_FRAME, AZ_ERROR_WEBSOCKET_UNSUPPORTED_FRAME};
+
+// Two bytes, an eight byte extended length and a four byte mask
+pub const MAX_FRAME_HEADER_LENGTH: usize = 14;
+
+const FIN: u8 = 0x80;
+const RESERVED: u8 = 0x70;
+const MASKED: u8 = 0x80;
+// Payload length values announcing a 16 or 64 bit extended length
+const EXTENDED_16: u8 = 126;
+const EXTENDED_64: u8 = 127;
+// Control frames are never fragmented and carry at most 125 bytes.
+const MAX_CONTROL_PAYLOAD_LENGTH: usize = 125;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Opcode {
+    Continuation = 0,
+    Text = 1,
+    Binary = 2,
+    Close = 8,
+    Ping = 9,
+    Pong = 10,
+}
+
+impl Opcode {
+    fn from_u8(value: u8) -> Result<Opcode, &'static str> {
+        match value {
+            0 => Ok(Opcode::Continuation),
+            1 => Ok(Opcode::Text),
+            2 => Ok(Opcode::Binary),
+            8 => Ok(Opcode::Close),
+            9 => Ok(Opcode::Ping),
+            10 => Ok(Opcode::Pong),
+            _ => Err(AZ_ERROR_WEBSOCKET_UNSUPPORTED_FRAME),
+        }
+    }
+
+    pub fn is_control(self) -> bool {
+        self as u8 >= 8
+    }
+}
+
+#[derive(Debug, PartialEq)]
+pub struct Frame<'a> {
+    // False if more fragments of the message follow in continuation frames
+    pub fin: bool,
+    pub opcode: Opcode,
+    // Clients must mask their frames and servers must not.
+    pub masked: bool,
+    pub payload: &'a [u8],
+}
+
+// The header of a single, unfragmented frame. With a mask, as clients must
+// send, the payload has to be masked with apply_mask before it is written.
+pub fn encode_frame_header(
+    opcode: Opcode,
+    payload_length: usize,
+    mask: Option<[u8; 4]>,
+) -> ([u8; MAX_FRAME_HEADER_LENGTH], usize) {
+    let mut header = [0_u8; MAX_FRAME_HEADER_LENGTH];
+    header[0] = FIN | opcode as u8;
+    let mask_bit = if mask.is_some() { MASKED } else { 0 };
+    let mut length = if payload_length < usize::from(EXTENDED_16) {
+        header[1] = mask_bit | payload_length as u8;
+        2
+    } else if let Ok(payload_length) = u16::try_from(payload_length) {
+        header[1] = mask_bit | EXTENDED_16;
+        header[2..4].copy_from_slice(&payload_length.to_be_bytes());
+        4
+    } else {
+        header[1] = mask_bit | EXTENDED_64;
+        header[2..10].copy_from_slice(&(payload_length as u64).to_be_bytes());
+        10
+    };
+    if let Some(mask) = mask {
+        header[length..length + 4].copy_from_slice(&mask);
+        length += 4;
+    }
+    (header, length)
+}
+
+// Masking is its own inverse, so this both masks and unmasks.
+pub fn apply_mask(payload: &mut [u8], mask: [u8; 4]) {
+    for (index, byte) in payload.iter_mut().enumerate() {
+        *byte ^= mask[index % 4];
+    }
+}
+
+pub fn encode_frame<B>(
+    opcode: Opcode,
+    payload: &[u8],
+    mask: Option<[u8; 4]>,
+) -> Result<Vec<u8, B>, &'static str>
+where
+    B: ArrayLength<u8>,
+{
+    if opcode.is_control() && payload.len() > MAX_CONTROL_PAYLOAD_LENGTH {
+        return Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME);
+    }
+    let (header, header_length) = encode_frame_header(opcode, payload.len(), mask);
+    let mut buffer: Vec<u8, B> = Vec::new();
+    buffer
+        .extend_from_slice(&header[..header_length])
+        .map_err(|()| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+    buffer
+        .extend_from_slice(payload)
+        .map_err(|()| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+    if let Some(mask) = mask {
+        apply_mask(&mut buffer[header_length..], mask);
+    }
+    Ok(buffer)
+}
+
+// Returns the payload length the frame at the start of the buffer announces,
+// or None if not enough bytes have been received to know it yet, so that a
+// frame can be refused before its payload is buffered.
+pub fn payload_length(buffer: &[u8]) -> Result<Option<usize>, &'static str> {
+    Ok(parse_length(buffer)?.map(|(payload_length, _)| payload_length))
+}
+
+// The payload length and the length of the header up to the mask.
+fn parse_length(buffer: &[u8]) -> Result<Option<(usize, usize)>, &'static str> {
+    let length = match buffer.get(1) {
+        Some(length) => length & !MASKED,
+        None => return Ok(None),
+    };
+    match length {
+        EXTENDED_16 => Ok(buffer
+            .get(2..4)
+            .map(|bytes| (usize::from(u16::from_be_bytes([bytes[0], bytes[1]])), 4))),
+        EXTENDED_64 => match buffer.get(2..10) {
+            Some(bytes) => {
+                let mut length = [0_u8; 8];
+                length.copy_from_slice(bytes);
+                let length = u64::from_be_bytes(length);
+                if length >> 63 != 0 || length > usize::MAX as u64 {
+                    return Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME);
+                }
+                Ok(Some((length as usize, 10)))
+            }
+            None => Ok(None),
+        },
+        length => Ok(Some((usize::from(length), 2))),
+    }
+}
+
+// Decodes the frame at the start of the buffer, unmasking its payload in
+// place. Returns the frame and the number of bytes it used, or None if the
+// frame has not been completely received; a decoded frame must be consumed
+// since decoding it again would mask the payload a second time.
+pub fn decode_frame(buffer: &mut [u8]) -> Result<Option<(Frame<'_>, usize)>, &'static str> {
+    if buffer.len() < 2 {
+        return Ok(None);
+    }
+    if buffer[0] & RESERVED != 0 {
+        return Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME);
+    }
+    let fin = buffer[0] & FIN != 0;
+    let opcode = Opcode::from_u8(buffer[0] & 0x0F)?;
+    let masked = buffer[1] & MASKED != 0;
+    let (payload_length, mut header_length) = match parse_length(buffer)? {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    if opcode.is_control() && (!fin || payload_length > MAX_CONTROL_PAYLOAD_LENGTH) {
+        return Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME);
+    }
+    let mut mask = None;
+    if masked {
+        match buffer.get(header_length..header_length + 4) {
+            Some(bytes) => mask = Some([bytes[0], bytes[1], bytes[2], bytes[3]]),
+            None => return Ok(None),
+        }
+        header_length += 4;
+    }
+    let total_length = match header_length.checked_add(payload_length) {
+        Some(total_length) => total_length,
+        None => return Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME),
+    };
+    if buffer.len() < total_length {
+        return Ok(None);
+    }
+    let payload = &mut buffer[header_length..total_length];
+    if let Some(mask) = mask {
+        apply_mask(payload, mask);
+    }
+    Ok(Some((
+        Frame {
+            fin,
+            opcode,
+            masked,
+            payload,
+        },
+        total_length,
+    )))
+}
+
+#[cfg(test)]
+mod tests_frame {
+    use super::*;
+    use heapless::consts::{U16, U512};
+
+    const MASK: [u8; 4] = [0x37, 0xfa, 0x21, 0x3d];
+
+    #[test]
+    fn masked_frames_match_the_rfc_example() {
+        let frame: Vec<u8, U16> = encode_frame(Opcode::Text, b"Hello", Some(MASK)).unwrap();
+        let expected: &[u8] = &[
+            0x81, 0x85, 0x37, 0xfa, 0x21, 0x3d, 0x7f, 0x9f, 0x4d, 0x51, 0x58,
+        ];
+        assert_eq!(&frame[..], expected);
+
+        let mut buffer = [0_u8; 11];
+        buffer.copy_from_slice(expected);
+        let (frame, length) = decode_frame(&mut buffer).unwrap().unwrap();
+        assert_eq!(length, 11);
+        assert_eq!(
+            frame,
+            Frame {
+                fin: true,
+                opcode: Opcode::Text,
+                masked: true,
+                payload: b"Hello",
+            }
+        );
+    }
+
+    #[test]
+    fn extended_lengths_are_encoded() {
+        let (header, length) = encode_frame_header(Opcode::Binary, 256, None);
+        assert_eq!(&header[..length], &[0x82, 0x7E, 0x01, 0x00]);
+        let (header, length) = encode_frame_header(Opcode::Binary, 65536, Some(MASK));
+        assert_eq!(
+            &header[..length],
+            &[0x82, 0xFF, 0, 0, 0, 0, 0, 1, 0, 0, 0x37, 0xfa, 0x21, 0x3d]
+        );
+
+        let payload = [0xA5_u8; 300];
+        let mut frame: Vec<u8, U512> = encode_frame(Opcode::Binary, &payload, None).unwrap();
+        let (decoded, length) = decode_frame(&mut frame).unwrap().unwrap();
+        assert_eq!(length, 304);
+        assert!(!decoded.masked);
+        assert_eq!(decoded.payload, &payload[..]);
+    }
+
+    #[test]
+    fn partial_frames_wait_for_more_bytes() {
+        let mut frame: Vec<u8, U512> =
+            encode_frame(Opcode::Binary, &[1_u8; 200], Some(MASK)).unwrap();
+        for length in &[0, 1, 3, 7, 203] {
+            assert_eq!(decode_frame(&mut frame[..*length]), Ok(None));
+        }
+        assert!(decode_frame(&mut frame).unwrap().is_some());
+    }
+
+    #[test]
+    fn payload_lengths_are_known_from_the_header() {
+        assert_eq!(payload_length(&[0x82]), Ok(None));
+        assert_eq!(payload_length(&[0x82, 0x85]), Ok(Some(5)));
+        assert_eq!(payload_length(&[0x82, 0x7E, 0x01]), Ok(None));
+        assert_eq!(payload_length(&[0x82, 0x7E, 0x01, 0x00]), Ok(Some(256)));
+        assert_eq!(
+            payload_length(&[0x82, 0x7F, 0, 0, 0, 0, 0, 1, 0, 0]),
+            Ok(Some(65536))
+        );
+    }
+
+    #[test]
+    fn invalid_frames_are_rejected() {
+        // reserved bit set
+        assert_eq!(
+            decode_frame(&mut [0xC2, 0x00]),
+            Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME)
+        );
+        // opcode 3 is reserved
+        assert_eq!(
+            decode_frame(&mut [0x83, 0x00]),
+            Err(AZ_ERROR_WEBSOCKET_UNSUPPORTED_FRAME)
+        );
+        // fragmented ping
+        assert_eq!(
+            decode_frame(&mut [0x09, 0x00]),
+            Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME)
+        );
+        // 64 bit length with the most significant bit set
+        assert_eq!(
+            decode_frame(&mut [0x82, 0x7F, 0x80, 0, 0, 0, 0, 0, 0, 0]),
+            Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME)
+        );
+        assert_eq!(
+            encode_frame::<U512>(Opcode::Ping, &[0_u8; 126], None),
+            Err(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME)
+        );
+        assert_eq!(
+            encode_frame::<U16>(Opcode::Binary, &[0_u8; 16], None),
+            Err(AZ_ERROR_INSUFFICIENT_SPAN_SIZE)
+        );
+    }
+}
@@ -0,0 +1,316 @@
+// The HTTP/1.1 upgrade that opens the connection. The client sends a random
+// base64 key and the server proves it understood the request by answering
+// with the SHA-1 of that key and a fixed GUID.
+use core::fmt::Write;
+use core::str;
+
+use heapless::consts::U28;
+use heapless::{ArrayLength, String};
+use sha1::{Digest, Sha1};
+
+use azure_sdk_for_rust_common::error::AZ_ERROR_INSUFFICIENT_SPAN_SIZE;
+
+use super::{AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED, SUBPROTOCOL, WEBSOCKET_VERSION};
+use crate::endpoint::{Endpoint, AZ_ERROR_ENDPOINT_UNSUPPORTED_SCHEME, WEBSOCKET_PATH};
+
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const END_OF_HEAD: &[u8] = b"\r\n\r\n";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct UpgradeRequest<'a> {
+    pub path: &'a str,
+    pub host: &'a str,
+    pub key: &'a str,
+}
+
+// Sec-WebSocket-Key for 16 random bytes from the caller.
+pub fn encode_key(nonce: &[u8; 16]) -> String<U28> {
+    encode_base64(nonce)
+}
+
+// Sec-WebSocket-Accept for a Sec-WebSocket-Key.
+pub fn accept_key(key: &str) -> String<U28> {
+    let mut hasher = Sha1::new();
+    hasher.update(key.as_bytes());
+    hasher.update(ACCEPT_GUID.as_bytes());
+    encode_base64(&hasher.finalize())
+}
+
+// GET <path> for a ws:// or wss:// endpoint, /$iothub/websocket if it has no
+// path.
+pub fn encode_upgrade_request<N>(
+    endpoint: &Endpoint<'_>,
+    key: &str,
+) -> Result<String<N>, &'static str>
+where
+    N: ArrayLength<u8>,
+{
+    if !endpoint.scheme.is_websocket() {
+        return Err(AZ_ERROR_ENDPOINT_UNSUPPORTED_SCHEME);
+    }
+    let path = if endpoint.path.is_empty() {
+        WEBSOCKET_PATH
+    } else {
+        endpoint.path
+    };
+    let mut request = String::new();
+    write!(request, "GET {} HTTP/1.1\r\nHost: {}", path, endpoint.host)
+        .map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+    if endpoint.port != endpoint.scheme.default_port() {
+        write!(request, ":{}", endpoint.port).map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+    }
+    write!(
+        request,
+        "\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Key: {}\r\nSec-WebSocket-Version: {}\r\nSec-WebSocket-Protocol: {}\r\n\r\n",
+        key, WEBSOCKET_VERSION, SUBPROTOCOL
+    )
+    .map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+    Ok(request)
+}
+
+// Checks the server's response to the request sent with the key. Returns the
+// length of the response head, after which the frames start, or None if the
+// head has not been completely received.
+pub fn parse_upgrade_response(buffer: &[u8], key: &str) -> Result<Option<usize>, &'static str> {
+    let (head, length) = match parse_head(buffer)? {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    let mut status_line = head.start_line.split(' ');
+    let accepted = status_line.next() == Some("HTTP/1.1")
+        && status_line.next() == Some("101")
+        && head.is_upgrade()
+        && head.accept == Some(accept_key(key).as_str())
+        && head.protocol == Some(SUBPROTOCOL);
+    if accepted {
+        Ok(Some(length))
+    } else {
+        Err(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED)
+    }
+}
+
+// Server side, for the local stand-ins: the request has to offer the mqtt
+// subprotocol. Returns the request and its length, or None if it has not been
+// completely received.
+pub fn parse_upgrade_request(
+    buffer: &[u8],
+) -> Result<Option<(UpgradeRequest<'_>, usize)>, &'static str> {
+    let (head, length) = match parse_head(buffer)? {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    let mut request_line = head.start_line.split(' ');
+    let path = match (
+        request_line.next(),
+        request_line.next(),
+        request_line.next(),
+    ) {
+        (Some("GET"), Some(path), Some("HTTP/1.1")) => path,
+        _ => return Err(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED),
+    };
+    let offers_mqtt = head
+        .protocol
+        .map_or(false, |protocols| has_token(protocols, SUBPROTOCOL));
+    match (head.host, head.key) {
+        (Some(host), Some(key))
+            if head.is_upgrade() && head.version == Some(WEBSOCKET_VERSION) && offers_mqtt =>
+        {
+            Ok(Some((UpgradeRequest { path, host, key }, length)))
+        }
+        _ => Err(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED),
+    }
+}
+
+pub fn encode_upgrade_response<N>(key: &str) -> Result<String<N>, &'static str>
+where
+    N: ArrayLength<u8>,
+{
+    let mut response = String::new();
+    write!(
+        response,
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\nSec-WebSocket-Accept: {}\r\nSec-WebSocket-Protocol: {}\r\n\r\n",
+        accept_key(key),
+        SUBPROTOCOL
+    )
+    .map_err(|_| AZ_ERROR_INSUFFICIENT_SPAN_SIZE)?;
+    Ok(response)
+}
+
+// The headers either side of the upgrade looks at; others are ignored.
+#[derive(Default)]
+struct Head<'a> {
+    start_line: &'a str,
+    host: Option<&'a str>,
+    upgrade: Option<&'a str>,
+    connection: Option<&'a str>,
+    key: Option<&'a str>,
+    accept: Option<&'a str>,
+    version: Option<&'a str>,
+    protocol: Option<&'a str>,
+}
+
+impl<'a> Head<'a> {
+    fn is_upgrade(&self) -> bool {
+        self.upgrade
+            .map_or(false, |upgrade| upgrade.eq_ignore_ascii_case("websocket"))
+            && self
+                .connection
+                .map_or(false, |connection| has_token(connection, "upgrade"))
+    }
+}
+
+fn parse_head(buffer: &[u8]) -> Result<Option<(Head<'_>, usize)>, &'static str> {
+    let end = match buffer
+        .windows(END_OF_HEAD.len())
+        .position(|window| window == END_OF_HEAD)
+    {
+        Some(end) => end,
+        None => return Ok(None),
+    };
+    let text = str::from_utf8(&buffer[..end]).map_err(|_| AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED)?;
+    let mut lines = text.split("\r\n");
+    let mut head = Head {
+        start_line: lines.next().unwrap_or_default(),
+        ..Head::default()
+    };
+    for line in lines {
+        let separator = line.find(':').ok_or(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED)?;
+        let name = &line[..separator];
+        let value = Some(line[separator + 1..].trim());
+        for (header, field) in [
+            ("host", &mut head.host),
+            ("upgrade", &mut head.upgrade),
+            ("connection", &mut head.connection),
+            ("sec-websocket-key", &mut head.key),
+            ("sec-websocket-accept", &mut head.accept),
+            ("sec-websocket-version", &mut head.version),
+            ("sec-websocket-protocol", &mut head.protocol),
+        ] {
+            if name.eq_ignore_ascii_case(header) {
+                *field = value;
+            }
+        }
+    }
+    Ok(Some((head, end + END_OF_HEAD.len())))
+}
+
+// Comma separated header values such as Connection: keep-alive, Upgrade
+fn has_token(value: &str, token: &str) -> bool {
+    value
+        .split(',')
+        .any(|candidate| candidate.trim().eq_ignore_ascii_case(token))
+}
+
+fn encode_base64(bytes: &[u8]) -> String<U28> {
+    // 20 bytes of SHA-1 encode to exactly 28 characters
+    let mut encoded = [0_u8; 28];
+    let length = base64::encode_config_slice(bytes, base64::STANDARD, &mut encoded);
+    let mut result = String::new();
+    // base64 is ASCII and fits
+    let _ = result.push_str(str::from_utf8(&encoded[..length]).unwrap_or_default());
+    result
+}
+
+#[cfg(test)]
+mod tests_handshake {
+    use super::*;
+    use heapless::consts::U512;
+
+    // From the opening handshake example in RFC 6455
+    const KEY: &str = "dGhlIHNhbXBsZSBub25jZQ==";
+    const ACCEPT: &str = "s3pPLMBiTxaQ9kYGzzhZRbK+xOo=";
+
+    #[test]
+    fn keys_match_the_rfc_example() {
+        assert_eq!(encode_key(b"the sample nonce").as_str(), KEY);
+        assert_eq!(accept_key(KEY).as_str(), ACCEPT);
+    }
+
+    #[test]
+    fn upgrade_request_is_encoded() {
+        let endpoint = Endpoint::parse("global.azure-devices-provisioning.net")
+            .unwrap()
+            .websocket();
+        let request: String<U512> = encode_upgrade_request(&endpoint, KEY).unwrap();
+        assert_eq!(
+            request.as_str(),
+            "GET /$iothub/websocket HTTP/1.1\r\n\
+             Host: global.azure-devices-provisioning.net\r\n\
+             Upgrade: websocket\r\n\
+             Connection: Upgrade\r\n\
+             Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+             Sec-WebSocket-Version: 13\r\n\
+             Sec-WebSocket-Protocol: mqtt\r\n\r\n"
+        );
+
+        let local = Endpoint::parse("ws://127.0.0.1:8080").unwrap();
+        let request: String<U512> = encode_upgrade_request(&local, KEY).unwrap();
+        assert!(request.starts_with("GET /$iothub/websocket HTTP/1.1\r\nHost: 127.0.0.1:8080\r\n"));
+
+        let (parsed, length) = parse_upgrade_request(request.as_bytes()).unwrap().unwrap();
+        assert_eq!(length, request.len());
+        assert_eq!(
+            parsed,
+            UpgradeRequest {
+                path: WEBSOCKET_PATH,
+                host: "127.0.0.1:8080",
+                key: KEY,
+            }
+        );
+
+        assert_eq!(
+            encode_upgrade_request::<U512>(&endpoint.mqtts(), KEY),
+            Err(AZ_ERROR_ENDPOINT_UNSUPPORTED_SCHEME)
+        );
+    }
+
+    #[test]
+    fn upgrade_response_is_checked() {
+        let response: String<U512> = encode_upgrade_response(KEY).unwrap();
+        assert_eq!(
+            parse_upgrade_response(response.as_bytes(), KEY),
+            Ok(Some(response.len()))
+        );
+        assert_eq!(
+            parse_upgrade_response(&response.as_bytes()[..response.len() - 1], KEY),
+            Ok(None)
+        );
+        assert_eq!(
+            parse_upgrade_response(response.as_bytes(), "b3RoZXIgc2FtcGxlIG5vbmNl"),
+            Err(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED)
+        );
+
+        let mixed_case: &[u8] = b"HTTP/1.1 101 Switching Protocols\r\nupgrade: WebSocket\r\n\
+                          CONNECTION: keep-alive, upgrade\r\n\
+                          sec-websocket-accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\
+                          Sec-WebSocket-Protocol: mqtt\r\n\r\n\x82\x00";
+        assert_eq!(
+            parse_upgrade_response(mixed_case, KEY),
+            Ok(Some(mixed_case.len() - 2))
+        );
+
+        let without_protocol = "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\n\
+                                Connection: Upgrade\r\n\
+                                Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n\r\n";
+        assert_eq!(
+            parse_upgrade_response(without_protocol.as_bytes(), KEY),
+            Err(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED)
+        );
+        assert_eq!(
+            parse_upgrade_response(b"HTTP/1.1 401 Unauthorized\r\n\r\n", KEY),
+            Err(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED)
+        );
+    }
+
+    #[test]
+    fn requests_without_mqtt_are_refused() {
+        let request = "GET /$iothub/websocket HTTP/1.1\r\nHost: example.net\r\n\
+                       Upgrade: websocket\r\nConnection: Upgrade\r\n\
+                       Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\n\
+                       Sec-WebSocket-Version: 13\r\nSec-WebSocket-Protocol: chat\r\n\r\n";
+        assert_eq!(
+            parse_upgrade_request(request.as_bytes()),
+            Err(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED)
+        );
+    }
+}
@@ -0,0 +1,34 @@
+// MQTT over WebSocket (RFC 6455) for networks that only allow outbound 443.
+// https://tools.ietf.org/html/rfc6455
+//
+// DPS and IoT Hub accept an HTTP upgrade to /$iothub/websocket with the mqtt
+// subprotocol; MQTT packets then travel in binary frames. A packet may span
+// frames and a frame may hold several packets, so the frame payloads are read
+// as a plain byte stream and fed to the MQTT codec.
+mod frame;
+mod handshake;
+#[cfg(feature = "std")]
+mod stream;
+
+pub use frame::{
+    apply_mask, decode_frame, encode_frame, encode_frame_header, payload_length, Frame, Opcode,
+    MAX_FRAME_HEADER_LENGTH,
+};
+pub use handshake::{
+    accept_key, encode_key, encode_upgrade_request, encode_upgrade_response, parse_upgrade_request,
+    parse_upgrade_response, UpgradeRequest,
+};
+#[cfg(feature = "std")]
+pub use stream::{WebSocketStream, DEFAULT_MAX_PAYLOAD_LENGTH};
+
+pub const AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED: &str = "The WebSocket upgrade was not accepted.";
+pub const AZ_ERROR_WEBSOCKET_MALFORMED_FRAME: &str = "The WebSocket frame is malformed.";
+pub const AZ_ERROR_WEBSOCKET_UNSUPPORTED_FRAME: &str = "The WebSocket frame type is not supported.";
+pub const AZ_ERROR_WEBSOCKET_FRAME_TOO_LARGE: &str =
+    "The WebSocket frame is larger than the stream accepts.";
+pub const AZ_ERROR_WEBSOCKET_NO_RANDOM: &str =
+    "Random bytes for the WebSocket key and masks are not available.";
+
+// Sec-WebSocket-Protocol required by both services
+pub const SUBPROTOCOL: &str = "mqtt";
+pub const WEBSOCKET_VERSION: &str = "13";
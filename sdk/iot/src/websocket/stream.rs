@@ -0,0 +1,462 @@
+// A byte stream over binary frames, so that the crate's codec or any MQTT
+// client that takes a Read + Write transport can run over WebSocket. Each
+// write is sent as one frame; pings are answered while reading.
+use std::io::{self, Read, Write};
+use std::vec::Vec;
+
+use heapless::consts::U512;
+use heapless::String;
+
+use super::{
+    apply_mask, decode_frame, encode_frame_header, encode_key, encode_upgrade_request,
+    encode_upgrade_response, parse_upgrade_request, parse_upgrade_response, payload_length, Opcode,
+    AZ_ERROR_WEBSOCKET_FRAME_TOO_LARGE, AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED,
+    AZ_ERROR_WEBSOCKET_MALFORMED_FRAME, AZ_ERROR_WEBSOCKET_NO_RANDOM,
+    AZ_ERROR_WEBSOCKET_UNSUPPORTED_FRAME,
+};
+use crate::endpoint::Endpoint;
+use crate::mqtt::MAX_REMAINING_LENGTH;
+
+// Neither side sends much more than the headers the upgrade needs.
+const MAX_HANDSHAKE_LENGTH: usize = 8192;
+
+// The largest MQTT packet: a five byte fixed header and its remaining length.
+pub const DEFAULT_MAX_PAYLOAD_LENGTH: usize = 5 + MAX_REMAINING_LENGTH;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Role {
+    Client,
+    Server,
+}
+
+pub struct WebSocketStream<S> {
+    stream: S,
+    role: Role,
+    // Received bytes that do not form a complete frame yet
+    incoming: Vec<u8>,
+    // Payload of the last data frame, read from position
+    payload: Vec<u8>,
+    position: usize,
+    // Frames announcing a longer payload are refused before it is buffered.
+    max_payload_length: usize,
+    // A binary frame without fin was received and continuation frames follow
+    message_in_progress: bool,
+    close_sent: bool,
+    close_received: bool,
+}
+
+impl<S> WebSocketStream<S>
+where
+    S: Read + Write,
+{
+    // Upgrades a connection (TLS for wss://) to the endpoint.
+    pub fn connect(mut stream: S, endpoint: &Endpoint<'_>) -> io::Result<WebSocketStream<S>> {
+        let mut nonce = [0_u8; 16];
+        fill_random(&mut nonce)?;
+        let key = encode_key(&nonce);
+        let request: String<U512> = encode_upgrade_request(endpoint, &key).map_err(invalid_data)?;
+        stream.write_all(request.as_bytes())?;
+        stream.flush()?;
+        let mut incoming = Vec::new();
+        loop {
+            if let Some(length) = parse_upgrade_response(&incoming, &key).map_err(invalid_data)? {
+                incoming.drain(..length);
+                return Ok(WebSocketStream::new(stream, Role::Client, incoming));
+            }
+            read_handshake(&mut stream, &mut incoming)?;
+        }
+    }
+
+    // Server side of connect, for stand-ins; a request that is not an mqtt
+    // upgrade is answered with 400.
+    pub fn accept(mut stream: S) -> io::Result<WebSocketStream<S>> {
+        let mut incoming = Vec::new();
+        loop {
+            match parse_upgrade_request(&incoming) {
+                Ok(Some((request, length))) => {
+                    let response: String<U512> =
+                        encode_upgrade_response(request.key).map_err(invalid_data)?;
+                    stream.write_all(response.as_bytes())?;
+                    stream.flush()?;
+                    incoming.drain(..length);
+                    return Ok(WebSocketStream::new(stream, Role::Server, incoming));
+                }
+                Ok(None) => read_handshake(&mut stream, &mut incoming)?,
+                Err(error) => {
+                    stream.write_all(b"HTTP/1.1 400 Bad Request\r\nContent-Length: 0\r\n\r\n")?;
+                    return Err(invalid_data(error));
+                }
+            }
+        }
+    }
+
+    fn new(stream: S, role: Role, incoming: Vec<u8>) -> WebSocketStream<S> {
+        WebSocketStream {
+            stream,
+            role,
+            incoming,
+            payload: Vec::new(),
+            position: 0,
+            max_payload_length: DEFAULT_MAX_PAYLOAD_LENGTH,
+            message_in_progress: false,
+            close_sent: false,
+            close_received: false,
+        }
+    }
+
+    pub fn set_max_payload_length(&mut self, max_payload_length: usize) {
+        self.max_payload_length = max_payload_length;
+    }
+
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    // Starts the closing handshake; reads return end of stream once the peer
+    // has answered.
+    pub fn close(&mut self) -> io::Result<()> {
+        if !self.close_sent {
+            self.close_sent = true;
+            write_frame(&mut self.stream, self.role, Opcode::Close, &[])?;
+            self.stream.flush()?;
+        }
+        Ok(())
+    }
+
+    // Handles the frame at the start of incoming, if complete. Returns false
+    // if more bytes are needed.
+    fn process_frame(&mut self) -> io::Result<bool> {
+        if let Some(payload_length) = payload_length(&self.incoming).map_err(invalid_data)? {
+            if payload_length > self.max_payload_length {
+                return Err(invalid_data(AZ_ERROR_WEBSOCKET_FRAME_TOO_LARGE));
+            }
+        }
+        let (frame, length) = match decode_frame(&mut self.incoming).map_err(invalid_data)? {
+            Some(decoded) => decoded,
+            None => return Ok(false),
+        };
+        // only client frames are masked
+        if frame.masked != (self.role == Role::Server) {
+            return Err(invalid_data(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME));
+        }
+        match frame.opcode {
+            Opcode::Binary | Opcode::Continuation => {
+                // a message's fragments can't be interleaved with another
+                // message (RFC 6455 section 5.4)
+                if self.message_in_progress != (frame.opcode == Opcode::Continuation) {
+                    return Err(invalid_data(AZ_ERROR_WEBSOCKET_MALFORMED_FRAME));
+                }
+                self.message_in_progress = !frame.fin;
+                self.payload.clear();
+                self.payload.extend_from_slice(frame.payload);
+                self.position = 0;
+            }
+            Opcode::Ping => {
+                let mut ping = [0_u8; 125];
+                let ping = &mut ping[..frame.payload.len()];
+                ping.copy_from_slice(frame.payload);
+                write_frame(&mut self.stream, self.role, Opcode::Pong, ping)?;
+            }
+            Opcode::Pong => {}
+            Opcode::Close => {
+                self.close_received = true;
+                if !self.close_sent {
+                    self.close_sent = true;
+                    write_frame(&mut self.stream, self.role, Opcode::Close, &[])?;
+                }
+            }
+            Opcode::Text => return Err(invalid_data(AZ_ERROR_WEBSOCKET_UNSUPPORTED_FRAME)),
+        }
+        self.incoming.drain(..length);
+        Ok(true)
+    }
+}
+
+impl<S> Read for WebSocketStream<S>
+where
+    S: Read + Write,
+{
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            if self.position < self.payload.len() {
+                let available = &self.payload[self.position..];
+                let length = available.len().min(buf.len());
+                buf[..length].copy_from_slice(&available[..length]);
+                self.position += length;
+                return Ok(length);
+            }
+            if self.close_received {
+                return Ok(0);
+            }
+            if !self.process_frame()? {
+                // a timeout leaves the partial frame in incoming for the next read
+                let mut chunk = [0_u8; 4096];
+                let read = self.stream.read(&mut chunk)?;
+                if read == 0 {
+                    if self.incoming.is_empty() {
+                        return Ok(0);
+                    }
+                    // the connection ended within a frame
+                    return Err(io::Error::new(
+                        io::ErrorKind::UnexpectedEof,
+                        AZ_ERROR_WEBSOCKET_MALFORMED_FRAME,
+                    ));
+                }
+                self.incoming.extend_from_slice(&chunk[..read]);
+            }
+        }
+    }
+}
+
+impl<S> Write for WebSocketStream<S>
+where
+    S: Read + Write,
+{
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        write_frame(&mut self.stream, self.role, Opcode::Binary, buf)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
+
+// Written with a single write so that TLS does not split the frame.
+fn write_frame<S>(stream: &mut S, role: Role, opcode: Opcode, payload: &[u8]) -> io::Result<()>
+where
+    S: Write,
+{
+    let mask = match role {
+        Role::Client => {
+            let mut mask = [0_u8; 4];
+            fill_random(&mut mask)?;
+            Some(mask)
+        }
+        Role::Server => None,
+    };
+    let (header, header_length) = encode_frame_header(opcode, payload.len(), mask);
+    let mut frame = Vec::with_capacity(header_length + payload.len());
+    frame.extend_from_slice(&header[..header_length]);
+    frame.extend_from_slice(payload);
+    if let Some(mask) = mask {
+        apply_mask(&mut frame[header_length..], mask);
+    }
+    stream.write_all(&frame)
+}
+
+fn read_handshake<S>(stream: &mut S, incoming: &mut Vec<u8>) -> io::Result<()>
+where
+    S: Read,
+{
+    if incoming.len() > MAX_HANDSHAKE_LENGTH {
+        return Err(invalid_data(AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED));
+    }
+    let mut chunk = [0_u8; 1024];
+    let read = stream.read(&mut chunk)?;
+    if read == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            AZ_ERROR_WEBSOCKET_HANDSHAKE_FAILED,
+        ));
+    }
+    incoming.extend_from_slice(&chunk[..read]);
+    Ok(())
+}
+
+// Keys and masks have to be unpredictable to intermediaries, so they come
+// from the operating system's generator.
+fn fill_random(bytes: &mut [u8]) -> io::Result<()> {
+    getrandom::getrandom(bytes)
+        .map_err(|_| io::Error::new(io::ErrorKind::Other, AZ_ERROR_WEBSOCKET_NO_RANDOM))
+}
+
+fn invalid_data(error: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
+
+#[cfg(test)]
+mod tests_websocket_stream {
+    use super::*;
+    use crate::mqtt::{decode, encode_pingreq, encode_pingresp, Packet};
+    use heapless::consts::{U16, U64};
+    use std::net::{TcpListener, TcpStream};
+    use std::thread;
+
+    // Answers each PINGREQ with a PINGRESP split across two frames, after
+    // pinging the client.
+    fn start_broker() -> (std::net::SocketAddr, thread::JoinHandle<()>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let broker = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut server = WebSocketStream::accept(stream).unwrap();
+            let mut packet = [0_u8; 2];
+            while server.read_exact(&mut packet).is_ok() {
+                assert_eq!(packet, encode_pingreq());
+                write_frame(&mut server.stream, Role::Server, Opcode::Ping, b"hi").unwrap();
+                let pingresp = encode_pingresp();
+                server.write_all(&pingresp[..1]).unwrap();
+                server.write_all(&pingresp[1..]).unwrap();
+            }
+        });
+        (address, broker)
+    }
+
+    #[test]
+    fn mqtt_packets_round_trip() {
+        let (address, broker) = start_broker();
+        let uri = std::format!("ws://{}", address);
+        let endpoint = Endpoint::parse(&uri).unwrap();
+        let stream = TcpStream::connect(address).unwrap();
+        let mut client = WebSocketStream::connect(stream, &endpoint).unwrap();
+        for _ in 0..2 {
+            client.write_all(&encode_pingreq()).unwrap();
+            let mut buffer = [0_u8; 2];
+            client.read_exact(&mut buffer).unwrap();
+            assert_eq!(
+                decode::<U16, U64>(&buffer).unwrap(),
+                Some((Packet::Pingresp, 2))
+            );
+        }
+        client.close().unwrap();
+        // the server answers the close before the end of the stream
+        assert_eq!(client.read(&mut [0_u8; 2]).unwrap(), 0);
+        broker.join().unwrap();
+    }
+
+    #[test]
+    fn streams_ending_within_a_frame_are_truncated() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let mut server = WebSocketStream::accept(stream).unwrap();
+            // a binary frame announcing five bytes, of which one is sent
+            server.stream.write_all(&[0x82, 0x05, 0x10]).unwrap();
+        });
+        let uri = std::format!("ws://{}", address);
+        let endpoint = Endpoint::parse(&uri).unwrap();
+        let stream = TcpStream::connect(address).unwrap();
+        let mut client = WebSocketStream::connect(stream, &endpoint).unwrap();
+        server.join().unwrap();
+        let error = client.read(&mut [0_u8; 8]).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    // Reads from a script of frames and keeps what is written.
+    struct Wire {
+        input: io::Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for Wire {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for Wire {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn client(input: &[u8]) -> WebSocketStream<Wire> {
+        let wire = Wire {
+            input: io::Cursor::new(input.to_vec()),
+            output: Vec::new(),
+        };
+        WebSocketStream::new(wire, Role::Client, Vec::new())
+    }
+
+    fn error_of(result: io::Result<usize>) -> std::string::String {
+        std::format!("{}", result.unwrap_err())
+    }
+
+    #[test]
+    fn oversized_frames_are_refused_before_buffering() {
+        // a binary frame announcing 2^62 bytes
+        let mut stream = client(&[0x82, 0x7F, 0x40, 0, 0, 0, 0, 0, 0, 0, 1, 2, 3]);
+        assert_eq!(
+            error_of(stream.read(&mut [0_u8; 8])),
+            AZ_ERROR_WEBSOCKET_FRAME_TOO_LARGE
+        );
+        assert!(stream.incoming.len() <= 13);
+
+        let mut stream = client(&[0x82, 0x04, 1, 2, 3, 4, 0x82, 0x05, 1, 2, 3, 4, 5]);
+        stream.set_max_payload_length(4);
+        let mut buffer = [0_u8; 4];
+        stream.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [1, 2, 3, 4]);
+        assert_eq!(
+            error_of(stream.read(&mut buffer)),
+            AZ_ERROR_WEBSOCKET_FRAME_TOO_LARGE
+        );
+    }
+
+    #[test]
+    fn fragmented_messages_are_read_in_order() {
+        // binary without fin, a ping in between and the final continuation
+        let mut stream = client(&[0x02, 0x02, b'a', b'b', 0x89, 0x00, 0x80, 0x01, b'c']);
+        let mut buffer = std::vec::Vec::new();
+        stream.read_to_end(&mut buffer).unwrap();
+        assert_eq!(buffer, b"abc");
+    }
+
+    #[test]
+    fn stray_or_interleaved_continuations_are_malformed() {
+        let mut stream = client(&[0x80, 0x01, b'a']);
+        assert_eq!(
+            error_of(stream.read(&mut [0_u8; 8])),
+            AZ_ERROR_WEBSOCKET_MALFORMED_FRAME
+        );
+        // a new message before the last one was finished
+        let mut stream = client(&[0x02, 0x01, b'a', 0x82, 0x01, b'b']);
+        let mut buffer = [0_u8; 8];
+        assert_eq!(stream.read(&mut buffer).unwrap(), 1);
+        assert_eq!(
+            error_of(stream.read(&mut buffer)),
+            AZ_ERROR_WEBSOCKET_MALFORMED_FRAME
+        );
+    }
+
+    #[test]
+    fn masks_differ_between_frames() {
+        let mut frames = Vec::new();
+        write_frame(&mut frames, Role::Client, Opcode::Binary, &[0_u8; 4]).unwrap();
+        write_frame(&mut frames, Role::Client, Opcode::Binary, &[0_u8; 4]).unwrap();
+        // header, mask and a zero payload that shows the mask again
+        assert_eq!(frames[2..6], frames[6..10]);
+        assert_ne!(frames[2..6], frames[12..16]);
+    }
+
+    #[test]
+    fn other_upgrades_are_refused() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            WebSocketStream::accept(stream).err().unwrap().kind()
+        });
+        let mut stream = TcpStream::connect(address).unwrap();
+        stream
+            .write_all(b"GET / HTTP/1.1\r\nHost: example.net\r\n\r\n")
+            .unwrap();
+        let mut response = std::string::String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 400"));
+        assert_eq!(server.join().unwrap(), io::ErrorKind::InvalidData);
+    }
+}